@@ -0,0 +1,73 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.keytrans;
+
+import java.util.Optional;
+import org.signal.libsignal.internal.CalledFromNative;
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.internal.NativeHandleGuard;
+
+/**
+ * Outcome of checking key transparency gossip received from a peer.
+ *
+ * @see org.signal.libsignal.net.GossipService#processIncomingGossip
+ */
+public class GossipStatus extends NativeHandleGuard.SimpleOwner {
+  /** This enum must be kept in sync with the Rust version. */
+  public enum Kind {
+    /** The peer's tree head is newer than ours, and has been adopted. */
+    ADVANCED,
+    /** The peer's tree head is the same as ours. */
+    UP_TO_DATE,
+    /** The peer is behind us; see {@link #getCatchUp}. */
+    PEER_BEHIND,
+    /** The peer's tree head is inconsistent with ours; see {@link #getEquivocationProof}. */
+    INCONSISTENT,
+    /** The gossip could not be parsed, or its tree head wasn't signed by the service. */
+    INVALID,
+    /** The gossip was too old or too far in the future, and was not adopted. */
+    STALE,
+    /** The gossip could not be checked against our distinguished tree head. */
+    UNVERIFIED,
+    /** There was no gossip. */
+    MISSING,
+    /** The gossip was checked, but our updated tree head could not be saved. */
+    NOT_SAVED,
+  }
+
+  @Override
+  protected void release(long nativeHandle) {
+    Native.GossipStatus_Destroy(nativeHandle);
+  }
+
+  @CalledFromNative
+  public GossipStatus(long nativeHandle) {
+    super(nativeHandle);
+  }
+
+  public Kind getKind() {
+    return Kind.values()[guardedMap(Native::GossipStatus_GetKind)];
+  }
+
+  /** Gossip to send back to a peer that is behind, if the kind is {@link Kind#PEER_BEHIND}. */
+  public Optional<byte[]> getCatchUp() {
+    return nonEmpty(guardedMap(Native::GossipStatus_GetCatchUp));
+  }
+
+  /**
+   * Evidence that the service showed the peer a tree inconsistent with ours, if the kind is {@link
+   * Kind#INCONSISTENT}.
+   *
+   * <p>It can be reported to an auditor or shown to the user.
+   */
+  public Optional<byte[]> getEquivocationProof() {
+    return nonEmpty(guardedMap(Native::GossipStatus_GetEquivocationProof));
+  }
+
+  private static Optional<byte[]> nonEmpty(byte[] bytes) {
+    return bytes.length == 0 ? Optional.empty() : Optional.of(bytes);
+  }
+}
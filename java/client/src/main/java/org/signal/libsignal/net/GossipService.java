@@ -0,0 +1,157 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.net;
+
+import static org.signal.libsignal.internal.FilterExceptions.filterExceptions;
+
+import java.time.Instant;
+import java.util.Optional;
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.internal.NativeHandleGuard;
+import org.signal.libsignal.keytrans.GossipStatus;
+import org.signal.libsignal.protocol.DuplicateMessageException;
+import org.signal.libsignal.protocol.InvalidMessageException;
+import org.signal.libsignal.protocol.InvalidVersionException;
+import org.signal.libsignal.protocol.NoSessionException;
+import org.signal.libsignal.protocol.SignalProtocolAddress;
+import org.signal.libsignal.protocol.UntrustedIdentityException;
+import org.signal.libsignal.protocol.message.CiphertextMessage;
+import org.signal.libsignal.protocol.message.SignalMessage;
+import org.signal.libsignal.protocol.state.GossipStore;
+import org.signal.libsignal.protocol.state.IdentityKeyStore;
+import org.signal.libsignal.protocol.state.SessionStore;
+
+/**
+ * Gossips key transparency tree heads with peers, to detect the service showing different clients
+ * inconsistent trees.
+ *
+ * <p>The state is kept in a {@link GossipStore}, which is read and, if anything changed, written
+ * back on every call.
+ *
+ * <p>The distinguished tree head is only ever advanced by {@link #setDistinguishedTreeHead}, with a
+ * head obtained from {@link KeyTransparencyClient#updateDistinguished}.
+ */
+public class GossipService {
+  private final Network.Environment environment;
+  private final GossipStore store;
+
+  public GossipService(Network.Environment environment, GossipStore store) {
+    this.environment = environment;
+    this.store = store;
+  }
+
+  /**
+   * Adopts a distinguished tree head obtained from the key transparency service.
+   *
+   * @param distinguishedTreeHead the serialized head, as saved by {@link
+   *     KeyTransparencyClient#updateDistinguished}.
+   * @throws IllegalArgumentException if the head is malformed or was not signed by the service.
+   */
+  public void setDistinguishedTreeHead(byte[] distinguishedTreeHead) {
+    filterExceptions(
+        () ->
+            Native.GossipService_SetDistinguishedTreeHead(
+                environment.value, store, distinguishedTreeHead));
+  }
+
+  /** Gossip to attach to outgoing messages, if there is a tree head to gossip about. */
+  public Optional<byte[]> outgoingGossip() {
+    return outgoingGossip(Instant.now());
+  }
+
+  public Optional<byte[]> outgoingGossip(Instant now) {
+    byte[] gossip =
+        filterExceptions(
+            () ->
+                Native.GossipService_OutgoingGossip(environment.value, store, now.toEpochMilli()));
+    return gossip.length == 0 ? Optional.empty() : Optional.of(gossip);
+  }
+
+  /** Checks gossip received out of band from {@code peer} against our tree heads. */
+  public GossipStatus processIncomingGossip(SignalProtocolAddress peer, byte[] gossip) {
+    return processIncomingGossip(peer, gossip, Instant.now());
+  }
+
+  public GossipStatus processIncomingGossip(
+      SignalProtocolAddress peer, byte[] gossip, Instant now) {
+    try (NativeHandleGuard peerGuard = new NativeHandleGuard(peer)) {
+      return new GossipStatus(
+          filterExceptions(
+              () ->
+                  Native.GossipService_ProcessIncomingGossip(
+                      environment.value,
+                      store,
+                      peerGuard.nativeHandle(),
+                      gossip,
+                      now.toEpochMilli())));
+    }
+  }
+
+  /**
+   * Like {@link org.signal.libsignal.protocol.SessionCipher#encrypt}, but attaches gossip for the
+   * recipient to the message.
+   */
+  public CiphertextMessage encrypt(
+      byte[] paddedMessage,
+      SignalProtocolAddress remoteAddress,
+      SessionStore sessionStore,
+      IdentityKeyStore identityKeyStore,
+      Instant now)
+      throws NoSessionException, UntrustedIdentityException {
+    try (NativeHandleGuard remoteAddressGuard = new NativeHandleGuard(remoteAddress)) {
+      return filterExceptions(
+          NoSessionException.class,
+          UntrustedIdentityException.class,
+          () ->
+              Native.SessionCipher_EncryptMessageWithGossip(
+                  paddedMessage,
+                  remoteAddressGuard.nativeHandle(),
+                  sessionStore,
+                  identityKeyStore,
+                  environment.value,
+                  store,
+                  now.toEpochMilli()));
+    }
+  }
+
+  /**
+   * Like {@link org.signal.libsignal.protocol.SessionCipher#decrypt(SignalMessage)}, but also
+   * processes any gossip the message carries.
+   *
+   * <p>The outcome of processing the gossip is not reported; use {@link #processIncomingGossip} for
+   * that.
+   */
+  public byte[] decrypt(
+      SignalMessage ciphertext,
+      SignalProtocolAddress remoteAddress,
+      SessionStore sessionStore,
+      IdentityKeyStore identityKeyStore,
+      Instant now)
+      throws InvalidMessageException,
+          InvalidVersionException,
+          DuplicateMessageException,
+          NoSessionException,
+          UntrustedIdentityException {
+    try (NativeHandleGuard ciphertextGuard = new NativeHandleGuard(ciphertext);
+        NativeHandleGuard remoteAddressGuard = new NativeHandleGuard(remoteAddress); ) {
+      return filterExceptions(
+          InvalidMessageException.class,
+          InvalidVersionException.class,
+          DuplicateMessageException.class,
+          NoSessionException.class,
+          UntrustedIdentityException.class,
+          () ->
+              Native.SessionCipher_DecryptSignalMessageWithGossip(
+                  ciphertextGuard.nativeHandle(),
+                  remoteAddressGuard.nativeHandle(),
+                  sessionStore,
+                  identityKeyStore,
+                  environment.value,
+                  store,
+                  now.toEpochMilli()));
+    }
+  }
+}
@@ -0,0 +1,42 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.net;
+
+import static org.junit.Assert.*;
+
+import java.util.Optional;
+import org.junit.Test;
+import org.signal.libsignal.protocol.state.KtState;
+import org.signal.libsignal.protocol.state.impl.InMemoryGossipStore;
+
+public class GossipServiceTest {
+  @Test
+  public void freshStateHasNoTreeHeads() {
+    KtState state = new KtState();
+    assertEquals(Optional.empty(), state.getLastTreeHead());
+    assertEquals(Optional.empty(), state.getLastDistinguishedTreeHead());
+
+    KtState roundTripped = new KtState(state.serialize());
+    assertArrayEquals(state.serialize(), roundTripped.serialize());
+  }
+
+  @Test
+  public void nothingToGossipWithoutTreeHead() {
+    InMemoryGossipStore store = new InMemoryGossipStore();
+    GossipService service = new GossipService(Network.Environment.STAGING, store);
+    assertEquals(Optional.empty(), service.outgoingGossip());
+    assertNull(store.loadState());
+  }
+
+  @Test
+  public void rejectsMalformedDistinguishedTreeHead() {
+    InMemoryGossipStore store = new InMemoryGossipStore();
+    GossipService service = new GossipService(Network.Environment.STAGING, store);
+    assertThrows(
+        IllegalArgumentException.class, () -> service.setDistinguishedTreeHead(new byte[] {1, 2}));
+    assertNull(store.loadState());
+  }
+}
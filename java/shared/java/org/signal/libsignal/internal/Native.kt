@@ -14,6 +14,7 @@ import org.signal.libsignal.protocol.groups.state.SenderKeyStore
 import org.signal.libsignal.protocol.logging.Log
 import org.signal.libsignal.protocol.logging.SignalProtocolLogger
 import org.signal.libsignal.protocol.message.CiphertextMessage
+import org.signal.libsignal.protocol.state.GossipStore
 import org.signal.libsignal.protocol.state.IdentityKeyStore
 import org.signal.libsignal.protocol.state.KyberPreKeyStore
 import org.signal.libsignal.protocol.state.PreKeyStore
@@ -480,6 +481,22 @@ internal object Native {
   @JvmStatic
   public external fun GenericServerSecretParams_GetPublicParams(paramsBytes: ByteArray): ByteArray
 
+  @JvmStatic @Throws(Exception::class)
+  public external fun GossipService_OutgoingGossip(environment: Int, store: GossipStore, now: Long): ByteArray
+  @JvmStatic @Throws(Exception::class)
+  public external fun GossipService_ProcessIncomingGossip(environment: Int, store: GossipStore, peer: ObjectHandle, gossip: ByteArray, now: Long): ObjectHandle
+  @JvmStatic @Throws(Exception::class)
+  public external fun GossipService_SetDistinguishedTreeHead(environment: Int, store: GossipStore, distinguishedTreeHead: ByteArray): Unit
+
+  @JvmStatic
+  public external fun GossipStatus_Destroy(handle: ObjectHandle): Unit
+  @JvmStatic
+  public external fun GossipStatus_GetCatchUp(status: ObjectHandle): ByteArray
+  @JvmStatic
+  public external fun GossipStatus_GetEquivocationProof(status: ObjectHandle): ByteArray
+  @JvmStatic
+  public external fun GossipStatus_GetKind(status: ObjectHandle): Int
+
   @JvmStatic @Throws(Exception::class)
   public external fun GroupCipher_DecryptMessage(sender: ObjectHandle, message: ByteArray, store: SenderKeyStore): ByteArray
   @JvmStatic @Throws(Exception::class)
@@ -617,6 +634,19 @@ internal object Native {
   @JvmStatic
   public external fun KeyTransparency_UsernameHashSearchKey(hash: ByteArray): ByteArray
 
+  @JvmStatic @Throws(Exception::class)
+  public external fun KtState_Deserialize(data: ByteArray): ObjectHandle
+  @JvmStatic
+  public external fun KtState_Destroy(handle: ObjectHandle): Unit
+  @JvmStatic
+  public external fun KtState_GetLastDistinguishedTreeHead(state: ObjectHandle): ByteArray
+  @JvmStatic
+  public external fun KtState_GetLastTreeHead(state: ObjectHandle): ByteArray
+  @JvmStatic
+  public external fun KtState_New(): ObjectHandle
+  @JvmStatic @Throws(Exception::class)
+  public external fun KtState_Serialize(obj: ObjectHandle): ByteArray
+
   @JvmStatic
   public external fun KyberKeyPair_Destroy(handle: ObjectHandle): Unit
   @JvmStatic
@@ -1165,7 +1195,11 @@ internal object Native {
   @JvmStatic @Throws(Exception::class)
   public external fun SessionCipher_DecryptSignalMessage(message: ObjectHandle, protocolAddress: ObjectHandle, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore): ByteArray
   @JvmStatic @Throws(Exception::class)
+  public external fun SessionCipher_DecryptSignalMessageWithGossip(message: ObjectHandle, protocolAddress: ObjectHandle, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, environment: Int, gossipStore: GossipStore, now: Long): ByteArray
+  @JvmStatic @Throws(Exception::class)
   public external fun SessionCipher_EncryptMessage(ptext: ByteArray, protocolAddress: ObjectHandle, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, now: Long): CiphertextMessage
+  @JvmStatic @Throws(Exception::class)
+  public external fun SessionCipher_EncryptMessageWithGossip(ptext: ByteArray, protocolAddress: ObjectHandle, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, environment: Int, gossipStore: GossipStore, now: Long): CiphertextMessage
 
   @JvmStatic @Throws(Exception::class)
   public external fun SessionRecord_ArchiveCurrentState(sessionRecord: ObjectHandle): Unit
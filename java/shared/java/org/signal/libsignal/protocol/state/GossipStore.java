@@ -0,0 +1,27 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol.state;
+
+import org.signal.libsignal.internal.CalledFromNative;
+
+/** Persists the {@link KtState} used to gossip key transparency tree heads with peers. */
+@CalledFromNative
+public interface GossipStore {
+  /**
+   * Returns a copy of the stored state, or {@code null} if none has been saved yet.
+   *
+   * <p>As with {@link SessionStore#loadSession}, changes to the returned state must not affect the
+   * stored state until {@link #saveState} is called.
+   */
+  public KtState loadState();
+
+  /**
+   * Replaces the stored state.
+   *
+   * <p>The state is only saved when it changes, and it is never saved partially.
+   */
+  public void saveState(KtState state);
+}
@@ -0,0 +1,61 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol.state;
+
+import static org.signal.libsignal.internal.FilterExceptions.filterExceptions;
+
+import java.util.Optional;
+import org.signal.libsignal.internal.CalledFromNative;
+import org.signal.libsignal.internal.Native;
+import org.signal.libsignal.internal.NativeHandleGuard;
+
+/**
+ * The key transparency tree heads a client has verified or learned from its peers through gossip.
+ *
+ * <p>Its contents are opaque to the app; it only needs to be persisted through a {@link
+ * GossipStore}.
+ */
+public class KtState extends NativeHandleGuard.SimpleOwner {
+  @Override
+  protected void release(long nativeHandle) {
+    Native.KtState_Destroy(nativeHandle);
+  }
+
+  /** Creates a state with no tree heads, as used before the first distinguished tree head. */
+  public KtState() {
+    super(Native.KtState_New());
+  }
+
+  @CalledFromNative
+  public KtState(long nativeHandle) {
+    super(nativeHandle);
+  }
+
+  /**
+   * @throws IllegalArgumentException if the serialized state is invalid
+   */
+  public KtState(byte[] serialized) {
+    super(filterExceptions(() -> Native.KtState_Deserialize(serialized)));
+  }
+
+  public byte[] serialize() {
+    return filterExceptions(() -> guardedMapChecked(Native::KtState_Serialize));
+  }
+
+  /** The newest tree head this client has verified, if any. */
+  public Optional<byte[]> getLastTreeHead() {
+    return nonEmpty(guardedMap(Native::KtState_GetLastTreeHead));
+  }
+
+  /** The tree head this client last got from the key transparency service, if any. */
+  public Optional<byte[]> getLastDistinguishedTreeHead() {
+    return nonEmpty(guardedMap(Native::KtState_GetLastDistinguishedTreeHead));
+  }
+
+  private static Optional<byte[]> nonEmpty(byte[] bytes) {
+    return bytes.length == 0 ? Optional.empty() : Optional.of(bytes);
+  }
+}
@@ -0,0 +1,29 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol.state.impl;
+
+import org.signal.libsignal.protocol.state.GossipStore;
+import org.signal.libsignal.protocol.state.KtState;
+
+public class InMemoryGossipStore implements GossipStore {
+
+  private byte[] state = null;
+
+  public InMemoryGossipStore() {}
+
+  @Override
+  public synchronized KtState loadState() {
+    if (state == null) {
+      return null;
+    }
+    return new KtState(state);
+  }
+
+  @Override
+  public synchronized void saveState(KtState state) {
+    this.state = state.serialize();
+  }
+}
@@ -122,6 +122,11 @@ export type SenderKeyStore = {
   ) => Promise<SenderKeyRecord | null>;
 };
 
+export type GossipStore = {
+  _getState: () => Promise<KtState | null>;
+  _saveState: (state: KtState) => Promise<void>;
+};
+
 export type InputStream = {
   _read: (amount: number) => Promise<Uint8Array>;
   _skip: (amount: number) => Promise<void>;
@@ -218,6 +223,17 @@ type NativeFunctions = {
   PublicKey_HpkeSeal: (pk: Wrapper<PublicKey>, plaintext: Uint8Array, info: Uint8Array, associatedData: Uint8Array) => Uint8Array;
   PrivateKey_HpkeOpen: (sk: Wrapper<PrivateKey>, ciphertext: Uint8Array, info: Uint8Array, associatedData: Uint8Array) => Uint8Array;
   HKDF_DeriveSecrets: (outputLength: number, ikm: Uint8Array, label: Uint8Array | null, salt: Uint8Array | null) => Uint8Array;
+  KtState_New: () => KtState;
+  KtState_Deserialize: (data: Uint8Array) => KtState;
+  KtState_Serialize: (obj: Wrapper<KtState>) => Uint8Array;
+  KtState_GetLastTreeHead: (state: Wrapper<KtState>) => Uint8Array;
+  KtState_GetLastDistinguishedTreeHead: (state: Wrapper<KtState>) => Uint8Array;
+  GossipService_SetDistinguishedTreeHead: (environment: number, store: GossipStore, distinguishedTreeHead: Uint8Array) => Promise<void>;
+  GossipService_OutgoingGossip: (environment: number, store: GossipStore, now: Timestamp) => Promise<Uint8Array>;
+  GossipService_ProcessIncomingGossip: (environment: number, store: GossipStore, peer: Wrapper<ProtocolAddress>, gossip: Uint8Array, now: Timestamp) => Promise<GossipStatus>;
+  GossipStatus_GetKind: (status: Wrapper<GossipStatus>) => number;
+  GossipStatus_GetCatchUp: (status: Wrapper<GossipStatus>) => Uint8Array;
+  GossipStatus_GetEquivocationProof: (status: Wrapper<GossipStatus>) => Uint8Array;
   ServiceId_ServiceIdBinary: (value: Uint8Array) => Uint8Array;
   ServiceId_ServiceIdString: (value: Uint8Array) => string;
   ServiceId_ServiceIdLog: (value: Uint8Array) => string;
@@ -375,7 +391,9 @@ type NativeFunctions = {
   SealedSenderDecryptionResult_Message: (obj: Wrapper<SealedSenderDecryptionResult>) => Uint8Array;
   SessionBuilder_ProcessPreKeyBundle: (bundle: Wrapper<PreKeyBundle>, protocolAddress: Wrapper<ProtocolAddress>, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, now: Timestamp) => Promise<void>;
   SessionCipher_EncryptMessage: (ptext: Uint8Array, protocolAddress: Wrapper<ProtocolAddress>, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, now: Timestamp) => Promise<CiphertextMessage>;
+  SessionCipher_EncryptMessageWithGossip: (ptext: Uint8Array, protocolAddress: Wrapper<ProtocolAddress>, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, environment: number, gossipStore: GossipStore, now: Timestamp) => Promise<CiphertextMessage>;
   SessionCipher_DecryptSignalMessage: (message: Wrapper<SignalMessage>, protocolAddress: Wrapper<ProtocolAddress>, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore) => Promise<Uint8Array>;
+  SessionCipher_DecryptSignalMessageWithGossip: (message: Wrapper<SignalMessage>, protocolAddress: Wrapper<ProtocolAddress>, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, environment: number, gossipStore: GossipStore, now: Timestamp) => Promise<Uint8Array>;
   SessionCipher_DecryptPreKeySignalMessage: (message: Wrapper<PreKeySignalMessage>, protocolAddress: Wrapper<ProtocolAddress>, sessionStore: SessionStore, identityKeyStore: IdentityKeyStore, prekeyStore: PreKeyStore, signedPrekeyStore: SignedPreKeyStore, kyberPrekeyStore: KyberPreKeyStore) => Promise<Uint8Array>;
   SealedSender_Encrypt: (destination: Wrapper<ProtocolAddress>, content: Wrapper<UnidentifiedSenderMessageContent>, identityKeyStore: IdentityKeyStore) => Promise<Uint8Array>;
   SealedSender_MultiRecipientEncrypt: (recipients: Wrapper<ProtocolAddress>[], recipientSessions: Wrapper<SessionRecord>[], excludedRecipients: Uint8Array, content: Wrapper<UnidentifiedSenderMessageContent>, identityKeyStore: IdentityKeyStore) => Promise<Uint8Array>;
@@ -764,6 +782,17 @@ const { registerErrors,
   PublicKey_HpkeSeal,
   PrivateKey_HpkeOpen,
   HKDF_DeriveSecrets,
+  KtState_New,
+  KtState_Deserialize,
+  KtState_Serialize,
+  KtState_GetLastTreeHead,
+  KtState_GetLastDistinguishedTreeHead,
+  GossipService_SetDistinguishedTreeHead,
+  GossipService_OutgoingGossip,
+  GossipService_ProcessIncomingGossip,
+  GossipStatus_GetKind,
+  GossipStatus_GetCatchUp,
+  GossipStatus_GetEquivocationProof,
   ServiceId_ServiceIdBinary,
   ServiceId_ServiceIdString,
   ServiceId_ServiceIdLog,
@@ -921,7 +950,9 @@ const { registerErrors,
   SealedSenderDecryptionResult_Message,
   SessionBuilder_ProcessPreKeyBundle,
   SessionCipher_EncryptMessage,
+  SessionCipher_EncryptMessageWithGossip,
   SessionCipher_DecryptSignalMessage,
+  SessionCipher_DecryptSignalMessageWithGossip,
   SessionCipher_DecryptPreKeySignalMessage,
   SealedSender_Encrypt,
   SealedSender_MultiRecipientEncrypt,
@@ -1312,6 +1343,17 @@ export { registerErrors,
   PublicKey_HpkeSeal,
   PrivateKey_HpkeOpen,
   HKDF_DeriveSecrets,
+  KtState_New,
+  KtState_Deserialize,
+  KtState_Serialize,
+  KtState_GetLastTreeHead,
+  KtState_GetLastDistinguishedTreeHead,
+  GossipService_SetDistinguishedTreeHead,
+  GossipService_OutgoingGossip,
+  GossipService_ProcessIncomingGossip,
+  GossipStatus_GetKind,
+  GossipStatus_GetCatchUp,
+  GossipStatus_GetEquivocationProof,
   ServiceId_ServiceIdBinary,
   ServiceId_ServiceIdString,
   ServiceId_ServiceIdLog,
@@ -1469,7 +1511,9 @@ export { registerErrors,
   SealedSenderDecryptionResult_Message,
   SessionBuilder_ProcessPreKeyBundle,
   SessionCipher_EncryptMessage,
+  SessionCipher_EncryptMessageWithGossip,
   SessionCipher_DecryptSignalMessage,
+  SessionCipher_DecryptSignalMessageWithGossip,
   SessionCipher_DecryptPreKeySignalMessage,
   SealedSender_Encrypt,
   SealedSender_MultiRecipientEncrypt,
@@ -1895,6 +1939,8 @@ export interface SealedSenderDecryptionResult { readonly __type: unique symbol;
 export interface KyberKeyPair { readonly __type: unique symbol; }
 export interface KyberPublicKey { readonly __type: unique symbol; }
 export interface KyberSecretKey { readonly __type: unique symbol; }
+export interface KtState { readonly __type: unique symbol; }
+export interface GossipStatus { readonly __type: unique symbol; }
 export interface SgxClientState { readonly __type: unique symbol; }
 export interface ExpiringProfileKeyCredential { readonly __type: unique symbol; }
 export interface ExpiringProfileKeyCredentialResponse { readonly __type: unique symbol; }
@@ -736,6 +736,56 @@ export class SenderKeyRecord {
   }
 }
 
+/**
+ * The key transparency tree heads a client has verified or learned from its
+ * peers through gossip.
+ *
+ * Its contents are opaque to the app; it only needs to be persisted through a
+ * {@link GossipStore}.
+ */
+export class KtState {
+  readonly _nativeHandle: Native.KtState;
+
+  static _fromNativeHandle(nativeHandle: Native.KtState): KtState {
+    return new KtState(nativeHandle);
+  }
+
+  private constructor(nativeHandle: Native.KtState) {
+    this._nativeHandle = nativeHandle;
+  }
+
+  /**
+   * Creates a state with no tree heads, as used before the first
+   * distinguished tree head.
+   */
+  static new(): KtState {
+    return new KtState(Native.KtState_New());
+  }
+
+  static deserialize(buffer: Uint8Array): KtState {
+    return new KtState(Native.KtState_Deserialize(buffer));
+  }
+
+  serialize(): Uint8Array {
+    return Native.KtState_Serialize(this);
+  }
+
+  /** The newest tree head this client has verified, if any. */
+  lastTreeHead(): Uint8Array | null {
+    const head = Native.KtState_GetLastTreeHead(this);
+    return head.length === 0 ? null : head;
+  }
+
+  /**
+   * The tree head this client last got from the key transparency service, if
+   * any.
+   */
+  lastDistinguishedTreeHead(): Uint8Array | null {
+    const head = Native.KtState_GetLastDistinguishedTreeHead(this);
+    return head.length === 0 ? null : head;
+  }
+}
+
 export class SenderCertificate {
   readonly _nativeHandle: Native.SenderCertificate;
 
@@ -1249,6 +1299,25 @@ export abstract class SenderKeyStore implements Native.SenderKeyStore {
   ): Promise<SenderKeyRecord | null>;
 }
 
+export abstract class GossipStore implements Native.GossipStore {
+  async _getState(): Promise<Native.KtState | null> {
+    const state = await this.getState();
+    if (state == null) {
+      return null;
+    } else {
+      return state._nativeHandle;
+    }
+  }
+  async _saveState(state: Native.KtState): Promise<void> {
+    return this.saveState(KtState._fromNativeHandle(state));
+  }
+
+  /** Returns the stored state, or null if none has been saved yet. */
+  abstract getState(): Promise<KtState | null>;
+  /** Replaces the stored state. It is only saved when it changes. */
+  abstract saveState(state: KtState): Promise<void>;
+}
+
 export async function groupEncrypt(
   sender: ProtocolAddress,
   distributionId: Uuid,
@@ -22,6 +22,7 @@ import { SvrB } from './net/SvrB.js';
 import { BridgedStringMap, newNativeHandle } from './internal.js';
 export * from './net/CDSI.js';
 export * from './net/Chat.js';
+export * from './net/Gossip.js';
 export * from './net/chat/UnauthMessagesService.js';
 export * from './net/chat/UnauthUsernamesService.js';
 export * from './net/Registration.js';
@@ -0,0 +1,190 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import * as Native from '../Native.js';
+import { ProtocolAddress } from '../Address.js';
+import {
+  CiphertextMessage,
+  GossipStore,
+  IdentityKeyStore,
+  SessionStore,
+  SignalMessage,
+} from '../index.js';
+import { Environment } from '../net.js';
+
+// This needs to be kept in sync with the Rust version of the enum.
+export enum GossipStatusKind {
+  /** The peer's tree head is newer than ours, and has been adopted. */
+  Advanced = 0,
+  /** The peer's tree head is the same as ours. */
+  UpToDate = 1,
+  /** The peer is behind us; see {@link GossipStatus.catchUp}. */
+  PeerBehind = 2,
+  /**
+   * The peer's tree head is inconsistent with ours; see
+   * {@link GossipStatus.equivocationProof}.
+   */
+  Inconsistent = 3,
+  /**
+   * The gossip could not be parsed, or its tree head wasn't signed by the
+   * service.
+   */
+  Invalid = 4,
+  /** The gossip was too old or too far in the future, and was not adopted. */
+  Stale = 5,
+  /** The gossip could not be checked against our distinguished tree head. */
+  Unverified = 6,
+  /** There was no gossip. */
+  Missing = 7,
+  /** The gossip was checked, but our updated tree head could not be saved. */
+  NotSaved = 8,
+}
+
+/** Outcome of checking key transparency gossip received from a peer. */
+export class GossipStatus {
+  readonly _nativeHandle: Native.GossipStatus;
+
+  private constructor(nativeHandle: Native.GossipStatus) {
+    this._nativeHandle = nativeHandle;
+  }
+
+  static _fromNativeHandle(nativeHandle: Native.GossipStatus): GossipStatus {
+    return new GossipStatus(nativeHandle);
+  }
+
+  kind(): GossipStatusKind {
+    return Native.GossipStatus_GetKind(this) as GossipStatusKind;
+  }
+
+  /**
+   * Gossip to send back to a peer that is behind, if the kind is
+   * {@link GossipStatusKind.PeerBehind}.
+   */
+  catchUp(): Uint8Array | null {
+    const catchUp = Native.GossipStatus_GetCatchUp(this);
+    return catchUp.length === 0 ? null : catchUp;
+  }
+
+  /**
+   * Evidence that the service showed the peer a tree inconsistent with ours,
+   * if the kind is {@link GossipStatusKind.Inconsistent}.
+   *
+   * It can be reported to an auditor or shown to the user.
+   */
+  equivocationProof(): Uint8Array | null {
+    const proof = Native.GossipStatus_GetEquivocationProof(this);
+    return proof.length === 0 ? null : proof;
+  }
+}
+
+/**
+ * Gossips key transparency tree heads with peers, to detect the service
+ * showing different clients inconsistent trees.
+ *
+ * The state is kept in a {@link GossipStore}, which is read and, if anything
+ * changed, written back on every call.
+ *
+ * The distinguished tree head is only ever advanced by
+ * {@link setDistinguishedTreeHead}, with the head a key transparency client
+ * saved to its store.
+ */
+export class GossipService {
+  constructor(
+    private readonly environment: Environment,
+    private readonly store: GossipStore
+  ) {}
+
+  /**
+   * Adopts a distinguished tree head obtained from the key transparency
+   * service.
+   *
+   * Throws if the head is malformed or was not signed by the service.
+   */
+  setDistinguishedTreeHead(distinguishedTreeHead: Uint8Array): Promise<void> {
+    return Native.GossipService_SetDistinguishedTreeHead(
+      this.environment,
+      this.store,
+      distinguishedTreeHead
+    );
+  }
+
+  /**
+   * Gossip to attach to outgoing messages, if there is a tree head to gossip
+   * about.
+   */
+  async outgoingGossip(now: Date = new Date()): Promise<Uint8Array | null> {
+    const gossip = await Native.GossipService_OutgoingGossip(
+      this.environment,
+      this.store,
+      now.getTime()
+    );
+    return gossip.length === 0 ? null : gossip;
+  }
+
+  /** Checks gossip received out of band from `peer` against our tree heads. */
+  async processIncomingGossip(
+    peer: ProtocolAddress,
+    gossip: Uint8Array,
+    now: Date = new Date()
+  ): Promise<GossipStatus> {
+    return GossipStatus._fromNativeHandle(
+      await Native.GossipService_ProcessIncomingGossip(
+        this.environment,
+        this.store,
+        peer,
+        gossip,
+        now.getTime()
+      )
+    );
+  }
+
+  /**
+   * Like `signalEncrypt`, but attaches gossip for the recipient to the
+   * message.
+   */
+  async encrypt(
+    message: Uint8Array,
+    address: ProtocolAddress,
+    sessionStore: SessionStore,
+    identityStore: IdentityKeyStore,
+    now: Date = new Date()
+  ): Promise<CiphertextMessage> {
+    return CiphertextMessage._fromNativeHandle(
+      await Native.SessionCipher_EncryptMessageWithGossip(
+        message,
+        address,
+        sessionStore,
+        identityStore,
+        this.environment,
+        this.store,
+        now.getTime()
+      )
+    );
+  }
+
+  /**
+   * Like `signalDecrypt`, but also processes any gossip the message carries.
+   *
+   * The outcome of processing the gossip is not reported; use
+   * {@link processIncomingGossip} for that.
+   */
+  decrypt(
+    message: SignalMessage,
+    address: ProtocolAddress,
+    sessionStore: SessionStore,
+    identityStore: IdentityKeyStore,
+    now: Date = new Date()
+  ): Promise<Uint8Array> {
+    return Native.SessionCipher_DecryptSignalMessageWithGossip(
+      message,
+      address,
+      sessionStore,
+      identityStore,
+      this.environment,
+      this.store,
+      now.getTime()
+    );
+  }
+}
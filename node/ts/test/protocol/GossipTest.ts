@@ -0,0 +1,42 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import * as SignalClient from '../../index.js';
+import * as util from '../util.js';
+import { Environment, GossipService } from '../../net.js';
+import { InMemoryGossipStore } from './TestStores.js';
+
+import { assert, use } from 'chai';
+import chaiAsPromised from 'chai-as-promised';
+
+use(chaiAsPromised);
+util.initLogger();
+
+describe('GossipService', () => {
+  it('starts with no tree heads', () => {
+    const state = SignalClient.KtState.new();
+    assert.isNull(state.lastTreeHead());
+    assert.isNull(state.lastDistinguishedTreeHead());
+
+    const roundTripped = SignalClient.KtState.deserialize(state.serialize());
+    assert.deepEqual(roundTripped.serialize(), state.serialize());
+  });
+
+  it('has nothing to gossip without a tree head', async () => {
+    const store = new InMemoryGossipStore();
+    const service = new GossipService(Environment.Staging, store);
+    assert.isNull(await service.outgoingGossip());
+    assert.isNull(await store.getState());
+  });
+
+  it('rejects a malformed distinguished tree head', async () => {
+    const store = new InMemoryGossipStore();
+    const service = new GossipService(Environment.Staging, store);
+    await assert.isRejected(
+      service.setDistinguishedTreeHead(Uint8Array.of(1, 2))
+    );
+    assert.isNull(await store.getState());
+  });
+});
@@ -191,6 +191,16 @@ export class InMemorySenderKeyStore extends SignalClient.SenderKeyStore {
   }
 }
 
+export class InMemoryGossipStore extends SignalClient.GossipStore {
+  private state: SignalClient.KtState | null = null;
+  async saveState(state: SignalClient.KtState): Promise<void> {
+    this.state = state;
+  }
+  async getState(): Promise<SignalClient.KtState | null> {
+    return this.state;
+  }
+}
+
 export default class TestStores {
   sender: InMemorySenderKeyStore;
   prekey: InMemoryPreKeyStore;
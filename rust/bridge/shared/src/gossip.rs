@@ -0,0 +1,186 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use libsignal_bridge_macros::*;
+use libsignal_bridge_types::net::Environment;
+use libsignal_bridge_types::protocol::FfiGossipStatusKind;
+use libsignal_bridge_types::support::AsType;
+use libsignal_keytrans::{KeyTransparency, LastTreeHead, StoredTreeHead};
+use libsignal_protocol::error::Result;
+use libsignal_protocol::*;
+use prost::Message;
+
+use crate::support::*;
+use crate::*;
+
+bridge_handle_fns!(KtState);
+bridge_handle_fns!(GossipStatus);
+
+// Gossip bridge functions return byte arrays rather than optionals, so that all three bridges can
+// share them; as with the gossip carried on messages, an empty array means "none".
+
+fn gossip_error_to_protocol(operation: &'static str, error: GossipError) -> SignalProtocolError {
+    match error {
+        GossipError::Storage(_) | GossipError::UnrecognizedStateVersion(_) => {
+            SignalProtocolError::ApplicationCallbackError(operation, Box::new(error))
+        }
+        error => SignalProtocolError::InvalidArgument(format!("{operation}: {error}")),
+    }
+}
+
+fn encode_tree_head(head: Option<&LastTreeHead>) -> Vec<u8> {
+    head.cloned()
+        .map(|head| StoredTreeHead::from(head).encode_to_vec())
+        .unwrap_or_default()
+}
+
+/// A [`GossipService`] running on a snapshot of the state in an app-provided [`GossipStore`].
+///
+/// App stores can only be borrowed for the duration of a single bridge call, so each call loads
+/// the state, runs the service on an in-memory copy, and writes the state back if it changed.
+pub(crate) struct BridgedGossipService {
+    pub(crate) service: GossipService,
+    loaded: KtState,
+}
+
+impl BridgedGossipService {
+    pub(crate) async fn load(environment: Environment, store: &dyn GossipStore) -> Result<Self> {
+        let loaded = store
+            .load_state()
+            .await
+            .map_err(|e| gossip_error_to_protocol("load_state", e))?
+            .unwrap_or_else(KtState::empty);
+
+        let mut snapshot = InMemGossipStore::new();
+        snapshot
+            .save_state(&loaded)
+            .await
+            .expect("in-memory store cannot fail");
+        let kt = KeyTransparency {
+            config: environment.env().keytrans_config.into(),
+        };
+        let service = GossipService::new(kt, Box::new(snapshot))
+            .await
+            .expect("in-memory store cannot fail");
+
+        Ok(Self { service, loaded })
+    }
+
+    pub(crate) async fn save(self, store: &mut dyn GossipStore) -> Result<()> {
+        if *self.service.state() == self.loaded {
+            return Ok(());
+        }
+        store
+            .save_state(self.service.state())
+            .await
+            .map_err(|e| gossip_error_to_protocol("save_state", e))
+    }
+}
+
+#[bridge_fn]
+fn KtState_New() -> KtState {
+    KtState::empty()
+}
+
+#[bridge_fn]
+fn KtState_Deserialize(data: &[u8]) -> Result<KtState> {
+    KtState::deserialize(data).map_err(|e| gossip_error_to_protocol("KtState_Deserialize", e))
+}
+
+bridge_get!(KtState::serialize as Serialize -> Vec<u8>);
+
+#[bridge_fn]
+fn KtState_GetLastTreeHead(state: &KtState) -> Vec<u8> {
+    encode_tree_head(state.last_tree_head())
+}
+
+#[bridge_fn]
+fn KtState_GetLastDistinguishedTreeHead(state: &KtState) -> Vec<u8> {
+    encode_tree_head(state.last_distinguished_tree_head())
+}
+
+/// Adopts a distinguished tree head obtained from the key transparency service, such as the result
+/// of `KeyTransparency_Distinguished`.
+///
+/// The head comes back from the app, so its operator signature is checked again before it is
+/// trusted. This is the only way apps advance the distinguished head: monitor responses are never
+/// exposed to them, so [`GossipService::run_monitor_once`] is not bridged.
+#[bridge_fn]
+async fn GossipService_SetDistinguishedTreeHead(
+    environment: AsType<Environment, u8>,
+    store: &mut dyn GossipStore,
+    distinguished_tree_head: &[u8],
+) -> Result<()> {
+    let head = StoredTreeHead::decode(distinguished_tree_head)
+        .ok()
+        .and_then(StoredTreeHead::into_last_tree_head)
+        .ok_or_else(|| {
+            SignalProtocolError::InvalidArgument("invalid distinguished tree head".to_owned())
+        })?;
+
+    let environment = environment.into_inner();
+    KeyTransparency {
+        config: environment.env().keytrans_config.into(),
+    }
+    .verify_tree_head_signature(&head)
+    .map_err(|e| SignalProtocolError::InvalidArgument(format!("distinguished tree head: {e}")))?;
+
+    let mut bridged = BridgedGossipService::load(environment, &*store).await?;
+    bridged
+        .service
+        .set_distinguished_tree_head(head)
+        .await
+        .expect("in-memory store cannot fail");
+    bridged.save(store).await
+}
+
+#[bridge_fn]
+async fn GossipService_OutgoingGossip(
+    environment: AsType<Environment, u8>,
+    store: &mut dyn GossipStore,
+    now: Timestamp,
+) -> Result<Vec<u8>> {
+    let bridged = BridgedGossipService::load(environment.into_inner(), &*store).await?;
+    let gossip = bridged
+        .service
+        .outgoing_gossip(now.into())
+        .map_err(|e| gossip_error_to_protocol("outgoing_gossip", e))?;
+    Ok(gossip.unwrap_or_default())
+}
+
+#[bridge_fn]
+async fn GossipService_ProcessIncomingGossip(
+    environment: AsType<Environment, u8>,
+    store: &mut dyn GossipStore,
+    peer: &ProtocolAddress,
+    gossip: &[u8],
+    now: Timestamp,
+) -> Result<GossipStatus> {
+    let mut bridged = BridgedGossipService::load(environment.into_inner(), &*store).await?;
+    let status = process_incoming_gossip(&mut bridged.service, peer, gossip, now.into()).await?;
+    bridged.save(store).await?;
+    Ok(status)
+}
+
+#[bridge_fn]
+fn GossipStatus_GetKind(status: &GossipStatus) -> u8 {
+    FfiGossipStatusKind::from(status) as u8
+}
+
+#[bridge_fn]
+fn GossipStatus_GetCatchUp(status: &GossipStatus) -> Vec<u8> {
+    match status {
+        GossipStatus::Verified(GossipOutcome::PeerBehind { catch_up }) => catch_up.clone(),
+        _ => vec![],
+    }
+}
+
+#[bridge_fn]
+fn GossipStatus_GetEquivocationProof(status: &GossipStatus) -> Vec<u8> {
+    match status {
+        GossipStatus::Inconsistent(proof) => proof.serialize(),
+        _ => vec![],
+    }
+}
@@ -24,6 +24,7 @@ pub use libsignal_bridge_types::{node, node_register};
 pub mod logging;
 
 pub mod crypto;
+pub mod gossip;
 pub mod protocol;
 
 // Desktop does not make use of device transfer certificates
@@ -9,14 +9,16 @@ use futures_util::FutureExt;
 use libsignal_bridge_macros::*;
 #[cfg(feature = "jni")]
 use libsignal_bridge_types::jni;
+use libsignal_bridge_types::net::Environment;
+use libsignal_bridge_types::support::AsType;
 use libsignal_core::InvalidDeviceId;
 use libsignal_protocol::error::Result;
 use libsignal_protocol::*;
-use libsignal_gossip::gossip_service::gossip_test;
 use rand::TryRngCore as _;
 use static_assertions::const_assert_eq;
 use uuid::Uuid;
 
+use crate::gossip::BridgedGossipService;
 use crate::support::*;
 use crate::*;
 
@@ -1090,20 +1092,20 @@ async fn SessionCipher_EncryptMessageWithGossip(
     protocol_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
     identity_key_store: &mut dyn IdentityKeyStore,
+    environment: AsType<Environment, u8>,
+    gossip_store: &mut dyn GossipStore,
     now: Timestamp,
 ) -> Result<CiphertextMessage> {
     let mut csprng = rand::rngs::OsRng.unwrap_err();
-
-    let gossip = gossip_test::create_test_gossip();
-    let gossip_bytes = gossip.encode().unwrap_or_default();
+    let bridged = BridgedGossipService::load(environment.into_inner(), &*gossip_store).await?;
     message_encrypt_with_gossip(
         ptext,
         protocol_address,
         session_store,
         identity_key_store,
+        &bridged.service,
         now.into(),
         &mut csprng,
-        &gossip_bytes,
     )
     .await
 }
@@ -1126,22 +1128,34 @@ async fn SessionCipher_DecryptSignalMessage(
     .await
 }
 
+/// Like `SessionCipher_DecryptSignalMessage`, but also checks any gossip attached to the message.
+///
+/// Only the plaintext is returned; a newly trusted tree head is saved to `gossip_store`, and a
+/// gossip failure never fails the decryption.
 #[bridge_fn(ffi = "decrypt_message_with_gossip")]
 async fn SessionCipher_DecryptSignalMessageWithGossip(
     message: &SignalMessage,
     protocol_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
     identity_key_store: &mut dyn IdentityKeyStore,
+    environment: AsType<Environment, u8>,
+    gossip_store: &mut dyn GossipStore,
+    now: Timestamp,
 ) -> Result<Vec<u8>> {
     let mut csprng = rand::rngs::OsRng.unwrap_err();
-    message_decrypt_signal_with_gossip(
+    let mut bridged = BridgedGossipService::load(environment.into_inner(), &*gossip_store).await?;
+    let (ptext, _status) = message_decrypt_signal_with_gossip(
         message,
         protocol_address,
         session_store,
         identity_key_store,
+        &mut bridged.service,
+        now.into(),
         &mut csprng,
     )
-    .await
+    .await?;
+    bridged.save(gossip_store).await?;
+    Ok(ptext)
 }
 
 #[bridge_fn(ffi = "decrypt_pre_key_message")]
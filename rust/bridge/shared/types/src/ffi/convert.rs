@@ -502,6 +502,7 @@ bridge_trait!(SenderKeyStore);
 bridge_trait!(SessionStore);
 bridge_trait!(SignedPreKeyStore);
 bridge_trait!(KyberPreKeyStore);
+bridge_trait!(GossipStore);
 bridge_trait!(InputStream);
 bridge_trait!(SyncInputStream);
 
@@ -401,6 +401,7 @@ impl IntoFfiError for SignalProtocolError {
             Self::InvalidProtobufEncoding => SignalErrorCode::ProtobufError,
             Self::CiphertextMessageTooShort(_)
             | Self::InvalidMessage(_, _)
+            | Self::ReusedBaseKey(_, _)
             | Self::InvalidSealedSenderMessage(_)
             | Self::BadKEMCiphertextLength(_, _) => SignalErrorCode::InvalidMessage,
             Self::LegacyCiphertextVersion(_) => SignalErrorCode::LegacyCiphertextVersion,
@@ -423,12 +424,16 @@ impl IntoFfiError for SignalProtocolError {
             Self::NoSenderKeyState { .. } | Self::SessionNotFound(_) => {
                 SignalErrorCode::SessionNotFound
             }
-            Self::InvalidSessionStructure(_) => SignalErrorCode::InvalidSession,
+            Self::InvalidSessionStructure(_) | Self::InvalidSessionArchive(_) => {
+                SignalErrorCode::InvalidSession
+            }
+            Self::StaleSessionArchive(_) => SignalErrorCode::InvalidState,
             Self::DuplicatedMessage(_, _) => SignalErrorCode::DuplicatedMessage,
             Self::FfiBindingError(_) => SignalErrorCode::InternalError,
             Self::ApplicationCallbackError(_, _) => SignalErrorCode::CallbackError,
             Self::SealedSenderSelfSend => SignalErrorCode::SealedSenderSelfSend,
             Self::UnknownSealedSenderServerCertificateId(_) => SignalErrorCode::VerificationFailure,
+            Self::Io(_) => SignalErrorCode::IoError,
         };
 
         SimpleError::new(code, self.to_string()).into()
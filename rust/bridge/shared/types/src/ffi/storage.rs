@@ -466,3 +466,40 @@ impl SenderKeyStore for &FfiSenderKeyStoreStruct {
         Ok(Some(*record))
     }
 }
+
+type LoadGossipState = extern "C" fn(store_ctx: *mut c_void, *mut MutPointer<KtState>) -> c_int;
+type SaveGossipState = extern "C" fn(store_ctx: *mut c_void, ConstPointer<KtState>) -> c_int;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FfiGossipStoreStruct {
+    ctx: *mut c_void,
+    load_state: LoadGossipState,
+    save_state: SaveGossipState,
+}
+
+#[async_trait(?Send)]
+impl GossipStore for &FfiGossipStoreStruct {
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError> {
+        let mut state = MutPointer::null();
+        let result = (self.load_state)(self.ctx, &mut state);
+
+        CallbackError::check(result)
+            .map_err(|e| GossipError::Storage(format!("load_state: {e}")))?;
+
+        let state = state.into_inner();
+        if state.is_null() {
+            return Ok(None);
+        }
+
+        let state = unsafe { Box::from_raw(state) };
+
+        Ok(Some(*state))
+    }
+
+    async fn save_state(&mut self, state: &KtState) -> Result<(), GossipError> {
+        let result = (self.save_state)(self.ctx, state.into());
+
+        CallbackError::check(result).map_err(|e| GossipError::Storage(format!("save_state: {e}")))
+    }
+}
@@ -274,7 +274,10 @@ impl JniError for SignalProtocolError {
                 .map(Into::into);
             }
 
-            SignalProtocolError::InvalidState(_, _) => ClassName("java.lang.IllegalStateException"),
+            SignalProtocolError::InvalidState(_, _)
+            | SignalProtocolError::StaleSessionArchive(_) => {
+                ClassName("java.lang.IllegalStateException")
+            }
 
             SignalProtocolError::InvalidProtocolAddress {
                 name: _,
@@ -311,11 +314,13 @@ impl JniError for SignalProtocolError {
                 ClassName("org.signal.libsignal.protocol.NoSessionException")
             }
 
-            SignalProtocolError::InvalidSessionStructure(_) => {
+            SignalProtocolError::InvalidSessionStructure(_)
+            | SignalProtocolError::InvalidSessionArchive(_) => {
                 ClassName("org.signal.libsignal.protocol.InvalidSessionException")
             }
 
             SignalProtocolError::InvalidMessage(..)
+            | SignalProtocolError::ReusedBaseKey(..)
             | SignalProtocolError::CiphertextMessageTooShort(_)
             | SignalProtocolError::InvalidProtobufEncoding
             | SignalProtocolError::InvalidSealedSenderMessage(_)
@@ -331,6 +336,8 @@ impl JniError for SignalProtocolError {
             SignalProtocolError::LegacyCiphertextVersion(_) => {
                 ClassName("org.signal.libsignal.protocol.LegacyMessageException")
             }
+
+            SignalProtocolError::Io(_) => ClassName("java.io.IOException"),
         };
 
         make_single_message_throwable(env, &self.to_string(), class_name)
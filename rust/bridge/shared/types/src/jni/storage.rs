@@ -16,6 +16,7 @@ pub type JavaSignedPreKeyStore<'a> = JObject<'a>;
 pub type JavaKyberPreKeyStore<'a> = JObject<'a>;
 pub type JavaSessionStore<'a> = JObject<'a>;
 pub type JavaSenderKeyStore<'a> = JObject<'a>;
+pub type JavaGossipStore<'a> = JObject<'a>;
 
 pub struct JniIdentityKeyStore<'a> {
     env: RefCell<EnvHandle<'a>>,
@@ -738,3 +739,69 @@ impl SenderKeyStore for JniSenderKeyStore<'_> {
             .map_err(BridgeOrProtocolError::from)?)
     }
 }
+
+pub struct JniGossipStore<'a> {
+    env: RefCell<EnvHandle<'a>>,
+    store: &'a JObject<'a>,
+}
+
+impl<'a> JniGossipStore<'a> {
+    pub fn new<'context: 'a>(
+        env: &mut JNIEnv<'context>,
+        store: &'a JObject<'a>,
+    ) -> Result<Self, BridgeLayerError> {
+        check_jobject_type(
+            env,
+            store,
+            ClassName("org.signal.libsignal.protocol.state.GossipStore"),
+        )?;
+        Ok(Self {
+            env: EnvHandle::new(env).into(),
+            store,
+        })
+    }
+}
+
+impl JniGossipStore<'_> {
+    fn do_load_state(&self) -> Result<Option<KtState>, BridgeLayerError> {
+        self.env
+            .borrow_mut()
+            .with_local_frame(8, "loadState", |env| {
+                let callback_args = jni_args!(() -> org.signal.libsignal.protocol.state.KtState);
+                get_object_with_native_handle(env, self.store, callback_args, "loadState")
+            })
+    }
+
+    fn do_save_state(&mut self, state: &KtState) -> Result<(), BridgeLayerError> {
+        self.env
+            .borrow_mut()
+            .with_local_frame(8, "saveState", |env| {
+                let state_handle = state.clone().convert_into(env)?;
+                let state_jobject = jobject_from_native_handle(
+                    env,
+                    ClassName("org.signal.libsignal.protocol.state.KtState"),
+                    state_handle,
+                )?;
+
+                let callback_args = jni_args!((
+                    state_jobject => org.signal.libsignal.protocol.state.KtState,
+                ) -> void);
+                call_method_checked(env, self.store, "saveState", callback_args)?;
+
+                Ok(())
+            })
+    }
+}
+
+#[async_trait(? Send)]
+impl GossipStore for JniGossipStore<'_> {
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError> {
+        self.do_load_state()
+            .map_err(|e| GossipError::Storage(format!("loadState: {e}")))
+    }
+
+    async fn save_state(&mut self, state: &KtState) -> Result<(), GossipError> {
+        self.do_save_state(state)
+            .map_err(|e| GossipError::Storage(format!("saveState: {e}")))
+    }
+}
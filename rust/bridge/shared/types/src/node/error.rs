@@ -172,6 +172,13 @@ impl SignalNodeError for SignalProtocolError {
                 operation_name,
                 no_extra_properties,
             ),
+            SignalProtocolError::Io(..) => new_js_error(
+                cx,
+                Some(IO_ERROR),
+                &message,
+                operation_name,
+                no_extra_properties,
+            ),
             SignalProtocolError::UntrustedIdentity(addr) => {
                 let make_extra_props = |cx: &mut C| {
                     let props = cx.empty_object();
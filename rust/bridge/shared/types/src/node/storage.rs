@@ -787,3 +787,89 @@ impl SenderKeyStore for NodeSenderKeyStore {
             .map_err(|s| js_error_to_rust("saveSenderKey", s))
     }
 }
+
+pub struct NodeGossipStore {
+    js_channel: Channel,
+    store_object: Arc<Root<JsObject>>,
+}
+
+impl NodeGossipStore {
+    pub(crate) fn new(cx: &mut FunctionContext, store: Handle<JsObject>) -> Self {
+        Self {
+            js_channel: cx.channel(),
+            store_object: Arc::new(store.root(cx)),
+        }
+    }
+
+    async fn do_get_state(&self) -> Result<Option<KtState>, String> {
+        let store_object_shared = self.store_object.clone();
+        JsFuture::get_promise(&self.js_channel, move |cx| {
+            let store_object = store_object_shared.to_inner(cx);
+            let result = call_method(cx, store_object, "_getState", [])?;
+            let result = result.downcast_or_throw(cx)?;
+            store_object_shared.finalize(cx);
+            Ok(result)
+        })
+        .then(|cx, result| match result {
+            Ok(value) => match value.downcast::<DefaultJsBox<KtState>, _>(cx) {
+                Ok(obj) => Ok(Some((***obj).clone())),
+                Err(_) => {
+                    if value.is_a::<JsNull, _>(cx) {
+                        Ok(None)
+                    } else {
+                        Err("result must be an object".to_owned())
+                    }
+                }
+            },
+            Err(error) => Err(error
+                .to_string(cx)
+                .expect("can convert to string")
+                .value(cx)),
+        })
+        .await
+    }
+
+    async fn do_save_state(&self, state: KtState) -> Result<(), String> {
+        let store_object_shared = self.store_object.clone();
+        JsFuture::get_promise(&self.js_channel, move |cx| {
+            let store_object = store_object_shared.to_inner(cx);
+            let state: Handle<JsValue> = state.convert_into(cx)?;
+            let result =
+                call_method(cx, store_object, "_saveState", [state])?.downcast_or_throw(cx)?;
+            store_object_shared.finalize(cx);
+            Ok(result)
+        })
+        .then(|cx, result| match result {
+            Ok(value) => match value.downcast::<JsUndefined, _>(cx) {
+                Ok(_) => Ok(()),
+                Err(_) => Err("unexpected result from _saveState".into()),
+            },
+            Err(error) => Err(error
+                .to_string(cx)
+                .expect("can convert to string")
+                .value(cx)),
+        })
+        .await
+    }
+}
+
+impl Finalize for NodeGossipStore {
+    fn finalize<'a, C: Context<'a>>(self, cx: &mut C) {
+        self.store_object.finalize(cx)
+    }
+}
+
+#[async_trait(?Send)]
+impl GossipStore for NodeGossipStore {
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError> {
+        self.do_get_state()
+            .await
+            .map_err(|s| GossipError::Storage(format!("getState: {s}")))
+    }
+
+    async fn save_state(&mut self, state: &KtState) -> Result<(), GossipError> {
+        self.do_save_state(state.clone())
+            .await
+            .map_err(|s| GossipError::Storage(format!("saveState: {s}")))
+    }
+}
@@ -39,6 +39,8 @@ bridge_as_handle!(SealedSenderDecryptionResult, ffi = false, jni = false);
 bridge_as_handle!(KyberKeyPair);
 bridge_as_handle!(KyberPublicKey);
 bridge_as_handle!(KyberSecretKey);
+bridge_as_handle!(KtState);
+bridge_as_handle!(GossipStatus);
 
 pub use libsignal_protocol::Timestamp;
 
@@ -88,3 +90,35 @@ const_assert_eq!(
     FfiCiphertextMessageType::Plaintext as u8,
     CiphertextMessageType::Plaintext as u8
 );
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum FfiGossipStatusKind {
+    Advanced = 0,
+    UpToDate = 1,
+    PeerBehind = 2,
+    Inconsistent = 3,
+    Invalid = 4,
+    Stale = 5,
+    Unverified = 6,
+    Missing = 7,
+    NotSaved = 8,
+}
+
+impl From<&GossipStatus> for FfiGossipStatusKind {
+    fn from(status: &GossipStatus) -> Self {
+        match status {
+            GossipStatus::Verified(GossipOutcome::Advanced) => Self::Advanced,
+            GossipStatus::Verified(GossipOutcome::UpToDate) => Self::UpToDate,
+            GossipStatus::Verified(GossipOutcome::PeerBehind { .. }) => Self::PeerBehind,
+            // check_gossip reports these outcomes as Unverified, but be thorough.
+            GossipStatus::Verified(GossipOutcome::Bootstrapped | GossipOutcome::Unverified)
+            | GossipStatus::Unverified => Self::Unverified,
+            GossipStatus::Inconsistent(_) => Self::Inconsistent,
+            GossipStatus::Invalid => Self::Invalid,
+            GossipStatus::Stale => Self::Stale,
+            GossipStatus::Missing => Self::Missing,
+            GossipStatus::NotSaved(_) => Self::NotSaved,
+        }
+    }
+}
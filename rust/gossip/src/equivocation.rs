@@ -0,0 +1,153 @@
+use libsignal_keytrans::{LastTreeHead, StoredTreeHead};
+use prost::Message;
+
+use crate::gossip::{GossipError, proto};
+
+/// Version byte prepended to every serialized [`EquivocationProof`].
+pub(crate) const EQUIVOCATION_PROOF_CURRENT_VERSION: u8 = 1;
+
+/// Evidence that the key transparency log has shown us and a peer views of the tree that cannot
+/// both be true.
+///
+/// The proof is self-contained: it carries both signed tree heads and their roots exactly as they
+/// were received, along with the consistency proof that failed to link them, so it can be handed
+/// to an auditor (who is expected to check the signatures) or shown to the user.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EquivocationProof {
+    peer_address: String,
+    local_tree_head: LastTreeHead,
+    peer_tree_head: LastTreeHead,
+    consistency_proof: Vec<Vec<u8>>,
+}
+
+impl EquivocationProof {
+    pub fn new(
+        peer_address: String,
+        local_tree_head: LastTreeHead,
+        peer_tree_head: LastTreeHead,
+        consistency_proof: Vec<Vec<u8>>,
+    ) -> Self {
+        Self {
+            peer_address,
+            local_tree_head,
+            peer_tree_head,
+            consistency_proof,
+        }
+    }
+
+    /// The peer whose gossip conflicted with our state.
+    pub fn peer_address(&self) -> &str {
+        &self.peer_address
+    }
+
+    /// Our trusted tree head (and root) that the peer's head was checked against.
+    pub fn local_tree_head(&self) -> &LastTreeHead {
+        &self.local_tree_head
+    }
+
+    /// The tree head (and root) the peer gossiped to us.
+    pub fn peer_tree_head(&self) -> &LastTreeHead {
+        &self.peer_tree_head
+    }
+
+    /// The consistency proof supplied by the peer, which failed to link the two heads.
+    ///
+    /// Empty when both heads are for the same tree size, in which case the differing roots are
+    /// evidence enough.
+    pub fn consistency_proof(&self) -> &[Vec<u8>] {
+        &self.consistency_proof
+    }
+
+    /// Serializes the proof as a version byte followed by an `EquivocationProof` protobuf.
+    pub fn serialize(&self) -> Vec<u8> {
+        let stored = proto::EquivocationProof {
+            peer_address: self.peer_address.clone(),
+            local_tree_head: StoredTreeHead::from(self.local_tree_head.clone()).encode_to_vec(),
+            peer_tree_head: StoredTreeHead::from(self.peer_tree_head.clone()).encode_to_vec(),
+            consistency_proof: self.consistency_proof.clone(),
+        };
+
+        let mut out = Vec::with_capacity(1 + stored.encoded_len());
+        out.push(EQUIVOCATION_PROOF_CURRENT_VERSION);
+        stored
+            .encode(&mut out)
+            .expect("can always append to a buffer");
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, GossipError> {
+        fn decode_head(bytes: &[u8]) -> Result<LastTreeHead, GossipError> {
+            StoredTreeHead::decode(bytes)
+                .ok()
+                .and_then(StoredTreeHead::into_last_tree_head)
+                .ok_or(GossipError::Invalid)
+        }
+
+        let (&version, rest) = data.split_first().ok_or(GossipError::Invalid)?;
+        if version != EQUIVOCATION_PROOF_CURRENT_VERSION {
+            return Err(GossipError::UnrecognizedProofVersion(version));
+        }
+
+        let stored = proto::EquivocationProof::decode(rest).map_err(|_| GossipError::Invalid)?;
+        Ok(Self {
+            peer_address: stored.peer_address,
+            local_tree_head: decode_head(&stored.local_tree_head)?,
+            peer_tree_head: decode_head(&stored.peer_tree_head)?,
+            consistency_proof: stored.consistency_proof,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libsignal_keytrans::TreeHead;
+
+    use super::*;
+
+    fn test_proof() -> EquivocationProof {
+        let head = |tree_size, root| {
+            (
+                TreeHead {
+                    tree_size,
+                    timestamp: 1669123456789,
+                    signatures: vec![],
+                },
+                root,
+            )
+        };
+        EquivocationProof::new(
+            "peer.1".to_owned(),
+            head(10, [0xAA; 32]),
+            head(20, [0xBB; 32]),
+            vec![vec![0x11; 32], vec![0x22; 32]],
+        )
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let proof = test_proof();
+        let serialized = proof.serialize();
+        assert_eq!(serialized[0], EQUIVOCATION_PROOF_CURRENT_VERSION);
+        assert_eq!(EquivocationProof::deserialize(&serialized), Ok(proof));
+    }
+
+    #[test]
+    fn deserialize_rejects_bad_input() {
+        let mut serialized = test_proof().serialize();
+        serialized[0] = EQUIVOCATION_PROOF_CURRENT_VERSION + 1;
+        assert_eq!(
+            EquivocationProof::deserialize(&serialized),
+            Err(GossipError::UnrecognizedProofVersion(
+                EQUIVOCATION_PROOF_CURRENT_VERSION + 1
+            ))
+        );
+        assert_eq!(
+            EquivocationProof::deserialize(&[]),
+            Err(GossipError::Invalid)
+        );
+        assert_eq!(
+            EquivocationProof::deserialize(&[EQUIVOCATION_PROOF_CURRENT_VERSION]),
+            Err(GossipError::Invalid)
+        );
+    }
+}
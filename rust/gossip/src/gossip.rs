@@ -1,106 +1,253 @@
-use libsignal_keytrans::{
-    FullTreeHead, LastTreeHead, KeyTransparency, TreeRoot
-};
+use std::time::{Duration, SystemTime};
 
+use libsignal_keytrans::{FullTreeHead, Signature, TreeRoot};
 use prost::Message;
 
-use std::time::SystemTime;
+use crate::EquivocationProof;
+
 pub mod proto {
     tonic::include_proto!("gossip");
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, displaydoc::Display)]
 pub enum GossipError {
+    /// gossip data was malformed
     Invalid,
-    Inconsistent
+    /// gossip tree head is inconsistent with the local state
+    Inconsistent(Box<EquivocationProof>),
+    /// no trusted tree head to check gossip against
+    Uninitialized,
+    /// gossip is older than the freshness window
+    Stale,
+    /// gossip timestamp is too far in the future
+    FutureTimestamp,
+    /// gossip tree head timestamp is older than the trusted tree head
+    TimestampRegression,
+    /// key transparency verification failed: {0}
+    VerificationFailed(String),
+    /// unrecognized gossip version <{0}>
+    UnrecognizedVersion(u8),
+    /// compact gossip refers to a tree head we have not seen
+    UnknownTreeHead,
+    /// only {0} of the {1} required auditors vouch for the gossiped tree head
+    InsufficientAuditors(usize, usize),
+    /// unrecognized stored state version <{0}>
+    UnrecognizedStateVersion(u8),
+    /// unrecognized equivocation proof version <{0}>
+    UnrecognizedProofVersion(u8),
+    /// gossip storage failed: {0}
+    Storage(String),
 }
 
+impl std::error::Error for GossipError {}
+
+/// Version byte prepended to every encoded [`GossipMessage`].
+pub(crate) const GOSSIP_CURRENT_VERSION: u8 = 1;
+
+/// A tree head, along with the consistency proof needed to check it, as sent to a peer.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Gossip {
-    pub full_tree_head : FullTreeHead,  // consists consistency proof 
+    pub full_tree_head: FullTreeHead, // consists consistency proof
     pub tree_root: TreeRoot,
     pub timestamp: SystemTime,
+    /// The size of the sender's distinguished tree head, which the consistency proof in
+    /// `full_tree_head` starts from.
+    pub distinguished_tree_size: Option<u64>,
+}
+
+/// Identifies a tree head without its consistency proof.
+///
+/// Sent in place of a full [`Gossip`] to a peer that has already seen an equal or newer head.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactGossip {
+    pub tree_size: u64,
+    pub tree_root: TreeRoot,
+    pub signatures: Vec<Signature>,
+    /// The tree head's timestamp, which its signatures cover.
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GossipMessage {
+    Full(Gossip),
+    Compact(CompactGossip),
+}
+
+/// The tree head a peer last gossiped to us, which they are therefore known to have seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcknowledgedTreeHead {
+    pub tree_size: u64,
+    pub tree_root: TreeRoot,
 }
 
 impl Gossip {
-    pub fn new(
-        full_tree_head: FullTreeHead,
-        tree_root: TreeRoot,
-        timestamp: SystemTime,
-    ) -> Self {
+    pub fn new(full_tree_head: FullTreeHead, tree_root: TreeRoot, timestamp: SystemTime) -> Self {
         Self {
             full_tree_head,
             tree_root,
             timestamp,
+            distinguished_tree_size: None,
         }
     }
 
-    pub fn encode(&self) -> Result<Vec<u8>, GossipError> {
-        let mut th_bytes = Vec::new();
-        self.full_tree_head
-            .encode(&mut th_bytes)
-            .map_err(|_| GossipError::Invalid)?;
-
-        let proto = proto::Gossip {
-            full_tree_head: th_bytes,
-            tree_root: self.tree_root.to_vec(),
-            timestamp: self.timestamp.duration_since(SystemTime::UNIX_EPOCH).map_err(|_| GossipError::Invalid)?.as_millis() as i64,
-        };
+    /// Records that the consistency proof starts from a distinguished tree head of size
+    /// `tree_size`.
+    pub fn with_distinguished_tree_size(mut self, tree_size: u64) -> Self {
+        self.distinguished_tree_size = Some(tree_size);
+        self
+    }
 
-        let mut out = Vec::with_capacity(proto.encoded_len());
-        proto
-            .encode(&mut out)
-            .map_err(|_| GossipError::Invalid)?;
-        Ok(out)
+    pub fn encode(&self) -> Result<Vec<u8>, GossipError> {
+        Ok(encode_message(proto::gossip_message::Head::Full(
+            self.to_proto()?,
+        )))
     }
 
+    /// Decodes gossip that must be in the full form.
     pub fn decode(data: &[u8]) -> Result<Self, GossipError> {
-        let proto: proto::Gossip = proto::Gossip::decode(data).map_err(|_| GossipError::Invalid)?;
+        match GossipMessage::decode(data)? {
+            GossipMessage::Full(gossip) => Ok(gossip),
+            GossipMessage::Compact(_) => Err(GossipError::Invalid),
+        }
+    }
 
-        let full_tree_head =
-            FullTreeHead::decode(proto.full_tree_head.as_slice()).map_err(|_| GossipError::Invalid)?;
+    fn to_proto(&self) -> Result<proto::Gossip, GossipError> {
+        Ok(proto::Gossip {
+            full_tree_head: self.full_tree_head.encode_to_vec(),
+            tree_root: self.tree_root.to_vec(),
+            timestamp: self
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()
+                .and_then(|since_epoch| i64::try_from(since_epoch.as_millis()).ok())
+                .ok_or(GossipError::Invalid)?,
+            distinguished_tree_size: self.distinguished_tree_size.unwrap_or(0),
+        })
+    }
 
-        let proto_root = proto.tree_root.as_slice(); 
+    fn from_proto(proto: proto::Gossip) -> Result<Self, GossipError> {
+        let full_tree_head = FullTreeHead::decode(proto.full_tree_head.as_slice())
+            .map_err(|_| GossipError::Invalid)?;
 
-        if proto_root.len() != 32 {
-            return Err(GossipError::Invalid);
-        }
+        let tree_root = decode_root(&proto.tree_root)?;
 
-        let mut tree_root = [0u8; 32];
-        tree_root.copy_from_slice(proto_root);
+        let timestamp = u64::try_from(proto.timestamp)
+            .ok()
+            .and_then(|millis| SystemTime::UNIX_EPOCH.checked_add(Duration::from_millis(millis)))
+            .ok_or(GossipError::Invalid)?;
 
         Ok(Self {
             full_tree_head,
             tree_root,
-            timestamp: SystemTime::now(),
+            timestamp,
+            distinguished_tree_size: Some(proto.distinguished_tree_size).filter(|&size| size != 0),
         })
     }
 }
 
+impl CompactGossip {
+    pub fn encode(&self) -> Vec<u8> {
+        encode_message(proto::gossip_message::Head::Compact(proto::CompactGossip {
+            tree_size: self.tree_size,
+            tree_root: self.tree_root.to_vec(),
+            signatures: self.signatures.iter().map(Message::encode_to_vec).collect(),
+            timestamp: self.timestamp,
+        }))
+    }
+
+    fn from_proto(proto: proto::CompactGossip) -> Result<Self, GossipError> {
+        let signatures = proto
+            .signatures
+            .iter()
+            .map(|bytes| Signature::decode(bytes.as_slice()))
+            .collect::<Result<_, _>>()
+            .map_err(|_| GossipError::Invalid)?;
+        Ok(Self {
+            tree_size: proto.tree_size,
+            tree_root: decode_root(&proto.tree_root)?,
+            signatures,
+            timestamp: proto.timestamp,
+        })
+    }
+}
+
+impl GossipMessage {
+    pub fn encode(&self) -> Result<Vec<u8>, GossipError> {
+        match self {
+            Self::Full(gossip) => gossip.encode(),
+            Self::Compact(compact) => Ok(compact.encode()),
+        }
+    }
+
+    /// Decodes either form of gossip, as produced by [`Gossip::encode`] or
+    /// [`CompactGossip::encode`].
+    pub fn decode(data: &[u8]) -> Result<Self, GossipError> {
+        let (&version, rest) = data.split_first().ok_or(GossipError::Invalid)?;
+        if version != GOSSIP_CURRENT_VERSION {
+            return Err(GossipError::UnrecognizedVersion(version));
+        }
+
+        let message = proto::GossipMessage::decode(rest).map_err(|_| GossipError::Invalid)?;
+        match message.head.ok_or(GossipError::Invalid)? {
+            proto::gossip_message::Head::Full(gossip) => Gossip::from_proto(gossip).map(Self::Full),
+            proto::gossip_message::Head::Compact(compact) => {
+                CompactGossip::from_proto(compact).map(Self::Compact)
+            }
+        }
+    }
+
+    /// The tree head the sender of this gossip has seen.
+    pub fn acknowledged_head(&self) -> Result<AcknowledgedTreeHead, GossipError> {
+        match self {
+            Self::Full(gossip) => Ok(AcknowledgedTreeHead {
+                tree_size: gossip
+                    .full_tree_head
+                    .tree_head
+                    .as_ref()
+                    .ok_or(GossipError::Invalid)?
+                    .tree_size,
+                tree_root: gossip.tree_root,
+            }),
+            Self::Compact(compact) => Ok(AcknowledgedTreeHead {
+                tree_size: compact.tree_size,
+                tree_root: compact.tree_root,
+            }),
+        }
+    }
+}
+
+fn encode_message(head: proto::gossip_message::Head) -> Vec<u8> {
+    let message = proto::GossipMessage { head: Some(head) };
+    let mut out = Vec::with_capacity(1 + message.encoded_len());
+    out.push(GOSSIP_CURRENT_VERSION);
+    message
+        .encode(&mut out)
+        .expect("can always append to a buffer");
+    out
+}
+
+fn decode_root(bytes: &[u8]) -> Result<TreeRoot, GossipError> {
+    bytes.try_into().map_err(|_| GossipError::Invalid)
+}
+
 #[cfg(test)]
 mod tests {
+    use libsignal_keytrans::TreeHead;
+
     use super::*;
-    use libsignal_keytrans::{TreeHead, Signature, TreeRoot};
 
     fn create_test_full_tree_head() -> FullTreeHead {
         FullTreeHead {
             tree_head: Some(TreeHead {
                 tree_size: 12345,
                 timestamp: 1669123456789,
-                signatures: vec![
-                    Signature {
-                        auditor_public_key: vec![0x01, 0x02, 0x03, 0x04],
-                        signature: vec![0xaa, 0xbb, 0xcc, 0xdd, 0xee],
-                    },
-                ],
+                signatures: vec![Signature {
+                    auditor_public_key: vec![0x01, 0x02, 0x03, 0x04],
+                    signature: vec![0xaa, 0xbb, 0xcc, 0xdd, 0xee],
+                }],
             }),
-            last: vec![
-                vec![0x10, 0x20, 0x30, 0x40],
-                vec![0x50, 0x60, 0x70, 0x80],
-            ],
-            distinguished: vec![
-                vec![0xa1, 0xa2, 0xa3, 0xa4],
-            ],
+            last: vec![vec![0x10, 0x20, 0x30, 0x40], vec![0x50, 0x60, 0x70, 0x80]],
+            distinguished: vec![vec![0xa1, 0xa2, 0xa3, 0xa4]],
             full_auditor_tree_heads: vec![],
         }
     }
@@ -114,17 +261,101 @@ mod tests {
         // Create test data
         let tree_head = create_test_full_tree_head();
         let tree_root = create_test_tree_root();
-        let timestamp = SystemTime::now();
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1669123456789);
 
-        let original_gossip = Gossip::new(tree_head, tree_root, timestamp);
+        let original_gossip =
+            Gossip::new(tree_head, tree_root, timestamp).with_distinguished_tree_size(12000);
         let encoded = original_gossip.encode().expect("encoding should succeed");
-        
+
         assert!(!encoded.is_empty(), "encoded data should not be empty");
-        assert!(encoded.len() > 10, "encoded data should be substantial size");
+        assert!(
+            encoded.len() > 10,
+            "encoded data should be substantial size"
+        );
 
         let decoded_gossip = Gossip::decode(&encoded).expect("decoding should succeed");
 
-        assert_eq!(decoded_gossip.full_tree_head, original_gossip.full_tree_head, "full_tree_head should match");
-        assert_eq!(decoded_gossip.tree_root, original_gossip.tree_root, "tree_root should match");
+        assert_eq!(
+            decoded_gossip.full_tree_head, original_gossip.full_tree_head,
+            "full_tree_head should match"
+        );
+        assert_eq!(
+            decoded_gossip.tree_root, original_gossip.tree_root,
+            "tree_root should match"
+        );
+        assert_eq!(
+            decoded_gossip.timestamp, original_gossip.timestamp,
+            "timestamp should match"
+        );
+        assert_eq!(
+            decoded_gossip.distinguished_tree_size, original_gossip.distinguished_tree_size,
+            "distinguished_tree_size should match"
+        );
+    }
+
+    fn create_test_compact_gossip() -> CompactGossip {
+        let full_tree_head = create_test_full_tree_head();
+        let tree_head = full_tree_head.tree_head.expect("has a head");
+        CompactGossip {
+            tree_size: tree_head.tree_size,
+            tree_root: create_test_tree_root(),
+            signatures: tree_head.signatures,
+            timestamp: tree_head.timestamp,
+        }
+    }
+
+    #[test]
+    fn test_compact_encoding() {
+        let compact = create_test_compact_gossip();
+        let encoded = compact.encode();
+        assert_eq!(encoded[0], GOSSIP_CURRENT_VERSION);
+        assert_eq!(
+            GossipMessage::decode(&encoded),
+            Ok(GossipMessage::Compact(compact.clone()))
+        );
+
+        let full = Gossip::new(
+            create_test_full_tree_head(),
+            create_test_tree_root(),
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1669123456789),
+        )
+        .encode()
+        .expect("encoding should succeed");
+        assert!(
+            encoded.len() < full.len(),
+            "compact form should be smaller than the full form"
+        );
+
+        // The full-only decoder doesn't accept the compact form.
+        assert_eq!(Gossip::decode(&encoded), Err(GossipError::Invalid));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_acknowledged_head() {
+        let expected = AcknowledgedTreeHead {
+            tree_size: 12345,
+            tree_root: create_test_tree_root(),
+        };
+
+        let full = GossipMessage::Full(Gossip::new(
+            create_test_full_tree_head(),
+            create_test_tree_root(),
+            SystemTime::now(),
+        ));
+        assert_eq!(full.acknowledged_head(), Ok(expected));
+
+        let compact = GossipMessage::Compact(create_test_compact_gossip());
+        assert_eq!(compact.acknowledged_head(), Ok(expected));
+    }
+
+    #[test]
+    fn test_decoding_rejects_unknown_version() {
+        let mut encoded = create_test_compact_gossip().encode();
+        encoded[0] = GOSSIP_CURRENT_VERSION + 1;
+        assert_eq!(
+            GossipMessage::decode(&encoded),
+            Err(GossipError::UnrecognizedVersion(GOSSIP_CURRENT_VERSION + 1))
+        );
+        assert_eq!(GossipMessage::decode(&[]), Err(GossipError::Invalid));
+    }
+}
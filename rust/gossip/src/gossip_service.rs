@@ -1,36 +1,142 @@
-use libsignal_keytrans::{KeyTransparency, LastTreeHead, FullTreeHead, TreeHead, TreeRoot, Signature};
-use crate::gossip::Gossip;     
-use crate::gossip::GossipError;
-use std::time::SystemTime;
+use std::cmp::Ordering;
+use std::time::{Duration, SystemTime};
 
-use crate::gossip_storage::{KtState, load_state, save_state};
+use libsignal_keytrans::{
+    FullAuditorTreeHead, FullTreeHead, KeyTransparency, LastTreeHead, TreeHead,
+};
+
+use crate::equivocation::EquivocationProof;
+use crate::gossip::{AcknowledgedTreeHead, CompactGossip, Gossip, GossipError, GossipMessage};
+use crate::gossip_storage::{GossipStore, KtState};
 
 pub struct GossipService {
     kt: KeyTransparency,
     state: KtState,
+    store: Box<dyn GossipStore>,
+    freshness: FreshnessPolicy,
+    auditors: AuditorPolicy,
+    bootstrap_from_peers: bool,
+}
+
+/// What [`GossipService::process_incoming_gossip`] made of a peer's tree head.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GossipOutcome {
+    /// The peer's head is newer than ours, and has been adopted.
+    Advanced,
+    /// The peer's head is the same as ours.
+    UpToDate,
+    /// The peer is behind us; `catch_up` is gossip to send back to them.
+    PeerBehind { catch_up: Vec<u8> },
+    /// There was no distinguished head to verify against, so the peer's head was adopted
+    /// unverified (see [`GossipService::allow_bootstrap_from_peers`]).
+    Bootstrapped,
+    /// The peer's head is newer than ours, but its consistency proof starts from a different
+    /// distinguished head than ours, so it couldn't be checked and hasn't been adopted.
+    Unverified,
+}
+
+/// A peer's gossip that [`GossipService::check_incoming_gossip`] accepted, along with the update
+/// to our state it calls for, which hasn't been saved yet.
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct CheckedGossip {
+    outcome: GossipOutcome,
+    based_on: KtState,
+    new_state: Option<KtState>,
+}
+
+impl CheckedGossip {
+    fn unchanged(outcome: GossipOutcome, state: &KtState) -> Self {
+        Self {
+            outcome,
+            based_on: state.clone(),
+            new_state: None,
+        }
+    }
+
+    /// What the peer's head turned out to be, relative to ours.
+    pub fn outcome(&self) -> &GossipOutcome {
+        &self.outcome
+    }
 }
 
+/// How recent gossip must be for a [`GossipService`] to accept it.
+///
+/// Both the time the peer sent the gossip and the timestamp of the tree head it carries are
+/// checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FreshnessPolicy {
+    /// The oldest a timestamp may be, relative to the current time.
+    pub max_age: Duration,
+    /// How far ahead of the current time a timestamp may be, to allow for clock skew.
+    pub max_clock_skew: Duration,
+}
+
+impl FreshnessPolicy {
+    fn check(&self, timestamp: SystemTime, now: SystemTime) -> Result<(), GossipError> {
+        match now.duration_since(timestamp) {
+            Ok(age) if age > self.max_age => Err(GossipError::Stale),
+            Err(e) if e.duration() > self.max_clock_skew => Err(GossipError::FutureTimestamp),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for FreshnessPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+            max_clock_skew: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// How many of the deployment's third-party auditors must vouch for a tree head before a
+/// [`GossipService`] adopts it from a peer.
+///
+/// An auditor vouches for a head if the gossip carrying it includes a tree head signed by that
+/// auditor and consistent with the peer's head. Requiring this means a log operator showing some
+/// clients a forked head can't spread it by gossip unless enough auditors have seen the fork too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditorPolicy {
+    /// How many distinct auditors must vouch for a head; the default of zero requires none.
+    ///
+    /// If this is more than the number of auditors in the key transparency configuration, no
+    /// head will be adopted from peers.
+    pub required: usize,
+}
+
+impl AuditorPolicy {
+    /// Requires at least `required` of the configured auditors to vouch for a head.
+    pub fn at_least(required: usize) -> Self {
+        Self { required }
+    }
+}
+
+/// Keys and signing helpers for a test key transparency deployment, which must never be trusted
+/// outside of tests.
+#[cfg(any(test, feature = "test-util"))]
 pub mod gossip_test {
+    use ed25519_dalek::{Signer as _, SigningKey};
+    use libsignal_keytrans::{
+        AuditorTreeHead, DeploymentMode, PublicConfig, Signature, TreeRoot, VerifyingKey,
+        VerifyingKeys, VrfPublicKey,
+    };
+
     use super::*;
+
     pub fn create_test_full_tree_head() -> FullTreeHead {
         FullTreeHead {
             tree_head: Some(TreeHead {
                 tree_size: 12345,
                 timestamp: 1669123456789,
-                signatures: vec![
-                    Signature {
-                        auditor_public_key: vec![0x01, 0x02, 0x03, 0x04],
-                        signature: vec![0xaa, 0xbb, 0xcc, 0xdd, 0xee],
-                    },
-                ],
+                signatures: vec![Signature {
+                    auditor_public_key: vec![0x01, 0x02, 0x03, 0x04],
+                    signature: vec![0xaa, 0xbb, 0xcc, 0xdd, 0xee],
+                }],
             }),
-            last: vec![
-                vec![0x10, 0x20, 0x30, 0x40],
-                vec![0x50, 0x60, 0x70, 0x80],
-            ],
-            distinguished: vec![
-                vec![0xa1, 0xa2, 0xa3, 0xa4],
-            ],
+            last: vec![vec![0x10, 0x20, 0x30, 0x40], vec![0x50, 0x60, 0x70, 0x80]],
+            distinguished: vec![vec![0xa1, 0xa2, 0xa3, 0xa4]],
             full_auditor_tree_heads: vec![],
         }
     }
@@ -40,60 +146,1257 @@ pub mod gossip_test {
     }
 
     pub fn create_test_gossip() -> Gossip {
-        Gossip {
-            full_tree_head: create_test_full_tree_head(),
-            tree_root: create_test_tree_root(),
-            timestamp: SystemTime::now(),
+        Gossip::new(
+            create_test_full_tree_head(),
+            create_test_tree_root(),
+            SystemTime::now(),
+        )
+    }
+
+    /// The key the service operator in [`create_test_key_transparency`] signs tree heads with.
+    pub fn test_operator_key() -> SigningKey {
+        SigningKey::from_bytes(&[0xA0; 32])
+    }
+
+    /// The keys of the auditors in [`create_test_key_transparency`].
+    pub fn test_auditor_keys() -> [SigningKey; 3] {
+        [1, 2, 3].map(|seed| SigningKey::from_bytes(&[seed; 32]))
+    }
+
+    pub fn create_test_key_transparency() -> KeyTransparency {
+        const VRF_KEY: [u8; 32] = [
+            0xec, 0x3a, 0x26, 0x82, 0x37, 0xcf, 0x5c, 0x47, 0x11, 0x5c, 0xf2, 0x22, 0x40, 0x5d,
+            0x5f, 0x90, 0xcc, 0x63, 0x3e, 0xbe, 0x05, 0xca, 0xf8, 0x2c, 0x0d, 0xd5, 0xac, 0xf9,
+            0xd3, 0x41, 0xda, 0xdb,
+        ];
+        KeyTransparency {
+            config: PublicConfig {
+                mode: DeploymentMode::ThirdPartyAuditing(VerifyingKeys::from(
+                    test_auditor_keys().iter().map(SigningKey::verifying_key),
+                )),
+                signature_key: test_operator_key().verifying_key(),
+                vrf_key: VrfPublicKey::try_from(VRF_KEY).expect("valid test key"),
+            },
+        }
+    }
+
+    /// The tree head serialization from the key transparency spec, as signed for `auditor_key`
+    /// in the deployment from [`create_test_key_transparency`].
+    fn to_be_signed(
+        auditor_key: &VerifyingKey,
+        tree_size: u64,
+        timestamp: i64,
+        root: &TreeRoot,
+    ) -> Vec<u8> {
+        let kt = create_test_key_transparency();
+        let mut to_be_signed = vec![0, 0, 3];
+        for key in [
+            kt.config.signature_key.as_bytes(),
+            kt.config.vrf_key.as_bytes(),
+            auditor_key.as_bytes(),
+        ] {
+            let len = u16::try_from(key.len()).expect("short key");
+            to_be_signed.extend_from_slice(&len.to_be_bytes());
+            to_be_signed.extend_from_slice(key);
+        }
+        to_be_signed.extend_from_slice(&tree_size.to_be_bytes());
+        to_be_signed.extend_from_slice(&timestamp.to_be_bytes());
+        to_be_signed.extend_from_slice(root);
+        to_be_signed
+    }
+
+    /// Replaces the signatures on `tree_head` with the test operator's, for each test auditor.
+    pub fn sign_test_tree_head(tree_head: &mut TreeHead, root: &TreeRoot) {
+        let operator = test_operator_key();
+        tree_head.signatures = test_auditor_keys()
+            .iter()
+            .map(|auditor| {
+                let auditor_key = auditor.verifying_key();
+                let message =
+                    to_be_signed(&auditor_key, tree_head.tree_size, tree_head.timestamp, root);
+                Signature {
+                    auditor_public_key: auditor_key.as_bytes().to_vec(),
+                    signature: operator.sign(&message).to_vec(),
+                }
+            })
+            .collect();
+    }
+
+    /// `auditor`'s signature on `head`, which it has seen in full.
+    pub fn create_test_auditor_tree_head(
+        auditor: &SigningKey,
+        head: &LastTreeHead,
+    ) -> FullAuditorTreeHead {
+        let (tree_head, root) = head;
+        let auditor_key = auditor.verifying_key();
+        let message = to_be_signed(&auditor_key, tree_head.tree_size, tree_head.timestamp, root);
+        FullAuditorTreeHead {
+            tree_head: Some(AuditorTreeHead {
+                tree_size: tree_head.tree_size,
+                timestamp: tree_head.timestamp,
+                signature: auditor.sign(&message).to_vec(),
+            }),
+            root_value: None,
+            consistency: vec![],
+            public_key: auditor_key.as_bytes().to_vec(),
         }
     }
 }
 
 impl GossipService {
-    pub fn new(kt: KeyTransparency) -> Self {
-        let state = load_state();
-        Self { kt, state }
+    /// Creates a service backed by `store`, resuming from whatever state it last saved.
+    pub async fn new(
+        kt: KeyTransparency,
+        store: Box<dyn GossipStore>,
+    ) -> Result<Self, GossipError> {
+        let state = store.load_state().await?.unwrap_or_else(KtState::empty);
+        Ok(Self {
+            kt,
+            state,
+            store,
+            freshness: FreshnessPolicy::default(),
+            auditors: AuditorPolicy::default(),
+            bootstrap_from_peers: false,
+        })
+    }
+
+    /// Lets the service adopt tree heads from peers before it has a distinguished head of its own.
+    ///
+    /// Such heads can't be verified, only compared with one another, so this should only be used
+    /// until the first successful [`Self::run_monitor_once`].
+    pub fn allow_bootstrap_from_peers(mut self) -> Self {
+        self.bootstrap_from_peers = true;
+        self
+    }
+
+    /// Replaces the default [`FreshnessPolicy`] applied to incoming gossip.
+    pub fn with_freshness_policy(mut self, freshness: FreshnessPolicy) -> Self {
+        self.freshness = freshness;
+        self
+    }
+
+    /// Replaces the default [`AuditorPolicy`], which doesn't require any auditors to vouch for
+    /// heads adopted from peers.
+    pub fn with_auditor_policy(mut self, auditors: AuditorPolicy) -> Self {
+        self.auditors = auditors;
+        self
     }
 
     pub fn state(&self) -> &KtState {
         &self.state
     }
 
-    pub fn process_incoming_gossip(&mut self, bytes: &[u8]) -> Result<(), GossipError> {
-        let gossip = Gossip::decode(bytes)?;
-        let local_last = self.state.last_tree_head();
-        let local_distinguished = self.state.last_distinguished_tree_head();
+    /// Encodes our current trusted tree head as gossip to attach to an outgoing message.
+    ///
+    /// Returns `None` if there is no trusted head to share yet.
+    pub fn outgoing_gossip(&self, now: SystemTime) -> Result<Option<Vec<u8>>, GossipError> {
+        self.state
+            .last_tree_head()
+            .map(|head| self.encode_gossip(head, now))
+            .transpose()
+    }
+
+    /// Like [`Self::outgoing_gossip`], but only identifies our head by its size, root, and
+    /// signatures if the peer has `acknowledged` that very head, rather than sending it in full.
+    ///
+    /// A head the same size as the acknowledged one but with a different root is still sent in
+    /// full, so the peer gets everything it needs to notice the fork. So is a head older than the
+    /// acknowledged one: the peer doesn't keep the heads it has moved on from, so could only
+    /// answer a compact form of it with catch-up gossip, without checking that it was ever part
+    /// of its log.
+    pub fn outgoing_gossip_for_peer(
+        &self,
+        acknowledged: Option<&AcknowledgedTreeHead>,
+        now: SystemTime,
+    ) -> Result<Option<Vec<u8>>, GossipError> {
+        let Some(head) = self.state.last_tree_head() else {
+            return Ok(None);
+        };
+        let (tree_head, tree_root) = head;
+        let already_seen = acknowledged.is_some_and(|acknowledged| {
+            acknowledged.tree_size == tree_head.tree_size && acknowledged.tree_root == *tree_root
+        });
+        if !already_seen {
+            return self.encode_gossip(head, now).map(Some);
+        }
+        let compact = CompactGossip {
+            tree_size: tree_head.tree_size,
+            tree_root: *tree_root,
+            signatures: tree_head.signatures.clone(),
+            timestamp: tree_head.timestamp,
+        };
+        Ok(Some(compact.encode()))
+    }
 
-        if let Some(ld) = local_distinguished {
-            self.kt
-                .verify_distinguished(&gossip.full_tree_head, local_last, ld)
-                .map_err(|_| GossipError::Invalid)?;
+    fn encode_gossip(&self, head: &LastTreeHead, now: SystemTime) -> Result<Vec<u8>, GossipError> {
+        let (tree_head, tree_root) = head;
+        // Auditor heads are only known to vouch for our last tree head.
+        let full_auditor_tree_heads = if self.state.last_tree_head() == Some(head) {
+            self.state.auditor_tree_heads().to_vec()
         } else {
-            return Err(GossipError::Invalid);
+            vec![]
+        };
+        let full_tree_head = FullTreeHead {
+            tree_head: Some(tree_head.clone()),
+            distinguished: self.state.last_tree_head_consistency().to_vec(),
+            full_auditor_tree_heads,
+            ..Default::default()
+        };
+        let mut gossip = Gossip::new(full_tree_head, *tree_root, now);
+        if let Some((distinguished, _)) = self.state.last_distinguished_tree_head() {
+            gossip = gossip.with_distinguished_tree_size(distinguished.tree_size);
         }
+        gossip.encode()
+    }
+
+    /// Checks gossip received from a peer against our trusted state.
+    ///
+    /// The peer's tree head is checked against our distinguished head using the consistency
+    /// proof it carries, just as peers check ours when we gossip. If the proof starts from a
+    /// different distinguished head, a newer peer head is [`GossipOutcome::Unverified`] and left
+    /// alone. Otherwise, what happens next depends on how the peer's head compares to our own:
+    ///
+    /// - if it is newer, it is adopted, as long as it passes the service's [`FreshnessPolicy`] at
+    ///   `now`, isn't timestamped earlier than ours, and enough auditors vouch for it to satisfy
+    ///   the service's [`AuditorPolicy`];
+    /// - if it is the same size, its root must match ours, and any newer auditor heads vouching
+    ///   for it are kept;
+    /// - if it is older, we keep our head and return gossip for the peer to catch up with.
+    ///
+    /// Compact gossip carries no consistency proof or send time, so it can only be compared with
+    /// our head, and fails with [`GossipError::UnknownTreeHead`] if it is newer.
+    ///
+    /// Either way, the peer's head must be signed by the service operator, or this fails with
+    /// [`GossipError::Invalid`] before it is compared with anything.
+    ///
+    /// Fails with [`GossipError::Inconsistent`] if the peer's head cannot be reconciled with
+    /// ours, carrying an [`EquivocationProof`] naming `peer_address`. A consistency proof that
+    /// doesn't verify fails with [`GossipError::VerificationFailed`] instead, since it only shows
+    /// that the peer sent a bad proof. Without a distinguished
+    /// head, fails with [`GossipError::Uninitialized`] unless bootstrapping has been enabled with
+    /// [`Self::allow_bootstrap_from_peers`].
+    pub async fn process_incoming_gossip(
+        &mut self,
+        peer_address: &str,
+        bytes: &[u8],
+        now: SystemTime,
+    ) -> Result<GossipOutcome, GossipError> {
+        let checked = self.check_incoming_gossip(peer_address, bytes, now)?;
+        self.save_checked_gossip(checked).await
+    }
+
+    /// Like [`Self::process_incoming_gossip`], but leaves saving whatever the gossip taught us
+    /// to a later [`Self::save_checked_gossip`] call.
+    ///
+    /// This lets a caller persist its own updates for the message that carried the gossip first,
+    /// and only advance the gossip state once those have been committed.
+    pub fn check_incoming_gossip(
+        &self,
+        peer_address: &str,
+        bytes: &[u8],
+        now: SystemTime,
+    ) -> Result<CheckedGossip, GossipError> {
+        let message = GossipMessage::decode(bytes)?;
 
-        if let Some(tree_head) = gossip.full_tree_head.tree_head.clone() {
-            self.state.set_last_tree_head((tree_head, gossip.tree_root));
-            save_state(&self.state);
+        if !self.state.has_distinguished_tree_head() && !self.bootstrap_from_peers {
+            return Err(GossipError::Uninitialized);
         }
 
-        Ok(())
+        match message {
+            GossipMessage::Full(gossip) => self.check_full_gossip(peer_address, gossip, now),
+            GossipMessage::Compact(compact) => self
+                .check_compact_gossip(peer_address, compact, now)
+                .map(|outcome| CheckedGossip::unchanged(outcome, &self.state)),
+        }
+    }
+
+    /// Saves the update to our state that `checked` calls for, if any, returning its outcome.
+    ///
+    /// Fails with [`GossipError::Stale`] without saving anything if our state has changed since
+    /// the gossip was checked, since the update was worked out from the old state.
+    pub async fn save_checked_gossip(
+        &mut self,
+        checked: CheckedGossip,
+    ) -> Result<GossipOutcome, GossipError> {
+        let CheckedGossip {
+            outcome,
+            based_on,
+            new_state,
+        } = checked;
+        if let Some(new_state) = new_state {
+            if based_on != self.state {
+                return Err(GossipError::Stale);
+            }
+            self.update_state(new_state).await?;
+        }
+        Ok(outcome)
+    }
+
+    fn check_full_gossip(
+        &self,
+        peer_address: &str,
+        gossip: Gossip,
+        now: SystemTime,
+    ) -> Result<CheckedGossip, GossipError> {
+        let peer_head = gossip
+            .full_tree_head
+            .tree_head
+            .clone()
+            .ok_or(GossipError::Invalid)?;
+        let peer_last: LastTreeHead = (peer_head, gossip.tree_root);
+        self.verify_signature(&peer_last)?;
+        let peer_head_time = u64::try_from(peer_last.0.timestamp)
+            .ok()
+            .and_then(|millis| SystemTime::UNIX_EPOCH.checked_add(Duration::from_millis(millis)))
+            .ok_or(GossipError::Invalid)?;
+        let peer_consistency = &gossip.full_tree_head.distinguished;
+
+        let distinguished = self.state.last_distinguished_tree_head().cloned();
+
+        self.freshness.check(gossip.timestamp, now)?;
+
+        let Some(local) = self
+            .state
+            .last_tree_head()
+            .or(distinguished.as_ref())
+            .cloned()
+        else {
+            // Bootstrapping with nothing to compare against: take the peer's word for it.
+            self.freshness.check(peer_head_time, now)?;
+            let auditor_heads =
+                self.auditor_heads_vouching_for(&gossip.full_tree_head, &peer_last)?;
+            return Ok(self.adopting(
+                GossipOutcome::Bootstrapped,
+                peer_last,
+                vec![],
+                auditor_heads,
+            ));
+        };
+
+        let equivocation = |local: &LastTreeHead, consistency_proof: Vec<Vec<u8>>| {
+            GossipError::Inconsistent(Box::new(EquivocationProof::new(
+                peer_address.to_owned(),
+                local.clone(),
+                peer_last.clone(),
+                consistency_proof,
+            )))
+        };
+
+        // A peer head older than the distinguished head can't be checked against it, since the
+        // proof would have to go the other way; sending it our head is all we can do. Nor can
+        // one whose proof starts from a different distinguished head than ours, which honest
+        // peers that last fetched from the log at different times will send; it can still be
+        // compared with our own head, but not adopted.
+        if let Some(distinguished) = &distinguished {
+            let d_size = distinguished.0.tree_size;
+            let peer_size = peer_last.0.tree_size;
+            if peer_size == d_size
+                || (peer_size > d_size && gossip.distinguished_tree_size == Some(d_size))
+            {
+                if peer_size == d_size {
+                    // Two signed heads of the same size with different roots are a fork.
+                    if peer_last.1 != distinguished.1 {
+                        return Err(equivocation(distinguished, vec![]));
+                    }
+                } else {
+                    // A proof that doesn't check out says nothing about the log, only about the
+                    // peer, who could have sent anything; it is dropped rather than reported.
+                    self.kt
+                        .verify_distinguished(
+                            &gossip.full_tree_head,
+                            Some(&peer_last),
+                            distinguished,
+                        )
+                        .map_err(|e| GossipError::VerificationFailed(e.to_string()))?;
+                }
+            } else if peer_size > local.0.tree_size {
+                return Ok(CheckedGossip::unchanged(
+                    GossipOutcome::Unverified,
+                    &self.state,
+                ));
+            }
+        }
+
+        match peer_last.0.tree_size.cmp(&local.0.tree_size) {
+            Ordering::Equal if peer_last.1 == local.1 => {
+                let auditor_heads = self.vouching_auditor_heads(&gossip.full_tree_head, &local);
+                Ok(CheckedGossip {
+                    outcome: GossipOutcome::UpToDate,
+                    based_on: self.state.clone(),
+                    new_state: self.with_merged_auditor_heads(auditor_heads),
+                })
+            }
+            Ordering::Equal => Err(equivocation(&local, vec![])),
+            Ordering::Less => Ok(CheckedGossip::unchanged(
+                GossipOutcome::PeerBehind {
+                    catch_up: self.encode_gossip(&local, now)?,
+                },
+                &self.state,
+            )),
+            Ordering::Greater => {
+                self.freshness.check(peer_head_time, now)?;
+                if peer_last.0.timestamp < local.0.timestamp {
+                    return Err(GossipError::TimestampRegression);
+                }
+                let auditor_heads =
+                    self.auditor_heads_vouching_for(&gossip.full_tree_head, &peer_last)?;
+                let consistency = if distinguished.is_some() {
+                    peer_consistency.clone()
+                } else {
+                    vec![]
+                };
+                let outcome = if distinguished.is_some() {
+                    GossipOutcome::Advanced
+                } else {
+                    GossipOutcome::Bootstrapped
+                };
+                Ok(self.adopting(outcome, peer_last, consistency, auditor_heads))
+            }
+        }
+    }
+
+    fn check_compact_gossip(
+        &self,
+        peer_address: &str,
+        compact: CompactGossip,
+        now: SystemTime,
+    ) -> Result<GossipOutcome, GossipError> {
+        let peer_last: LastTreeHead = (
+            TreeHead {
+                tree_size: compact.tree_size,
+                timestamp: compact.timestamp,
+                signatures: compact.signatures,
+            },
+            compact.tree_root,
+        );
+        self.verify_signature(&peer_last)?;
+
+        let local = self
+            .state
+            .last_tree_head()
+            .or(self.state.last_distinguished_tree_head())
+            .ok_or(GossipError::UnknownTreeHead)?;
+
+        match peer_last.0.tree_size.cmp(&local.0.tree_size) {
+            Ordering::Equal if peer_last.1 == local.1 => Ok(GossipOutcome::UpToDate),
+            Ordering::Equal => Err(GossipError::Inconsistent(Box::new(EquivocationProof::new(
+                peer_address.to_owned(),
+                local.clone(),
+                peer_last,
+                vec![],
+            )))),
+            // Peers only send a head compactly once we've gossiped it to them, so this is a head
+            // we have since moved on from. We don't keep those to compare against, but the full
+            // gossip lets the peer check our head against its own.
+            Ordering::Less => Ok(GossipOutcome::PeerBehind {
+                catch_up: self.encode_gossip(local, now)?,
+            }),
+            Ordering::Greater => Err(GossipError::UnknownTreeHead),
+        }
+    }
+
+    /// Checks that the service operator signed a peer's `head`, before anything else is done
+    /// with it.
+    fn verify_signature(&self, head: &LastTreeHead) -> Result<(), GossipError> {
+        self.kt
+            .verify_tree_head_signature(head)
+            .map_err(|_| GossipError::Invalid)
+    }
+
+    /// The tree heads in `full_tree_head` from configured auditors that vouch for `head`, at most
+    /// one per auditor.
+    fn vouching_auditor_heads(
+        &self,
+        full_tree_head: &FullTreeHead,
+        head: &LastTreeHead,
+    ) -> Vec<FullAuditorTreeHead> {
+        self.kt
+            .auditor_keys()
+            .filter_map(|key| {
+                let auditor_head = full_tree_head.select_auditor_tree_head(key)?;
+                self.kt
+                    .verify_auditor_tree_head(key, auditor_head, head)
+                    .ok()?;
+                Some(auditor_head.clone())
+            })
+            .collect()
+    }
+
+    /// Like [`Self::vouching_auditor_heads`], but fails if there are too few of them to satisfy
+    /// our [`AuditorPolicy`].
+    fn auditor_heads_vouching_for(
+        &self,
+        full_tree_head: &FullTreeHead,
+        head: &LastTreeHead,
+    ) -> Result<Vec<FullAuditorTreeHead>, GossipError> {
+        let vouching = self.vouching_auditor_heads(full_tree_head, head);
+        if vouching.len() < self.auditors.required {
+            return Err(GossipError::InsufficientAuditors(
+                vouching.len(),
+                self.auditors.required,
+            ));
+        }
+        Ok(vouching)
     }
 
-    pub fn run_monitor_once(
+    /// Keeps the newer of our own and the given head for each auditor.
+    ///
+    /// All of `heads` must vouch for our last tree head.
+    async fn merge_auditor_heads(
+        &mut self,
+        heads: Vec<FullAuditorTreeHead>,
+    ) -> Result<(), GossipError> {
+        match self.with_merged_auditor_heads(heads) {
+            Some(new_state) => self.update_state(new_state).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Our state with the newer of our own and the given head kept for each auditor, or `None`
+    /// if that doesn't change anything.
+    fn with_merged_auditor_heads(&self, heads: Vec<FullAuditorTreeHead>) -> Option<KtState> {
+        fn position(head: &FullAuditorTreeHead) -> Option<(u64, i64)> {
+            head.tree_head
+                .as_ref()
+                .map(|head| (head.tree_size, head.timestamp))
+        }
+
+        let mut merged = self.state.auditor_tree_heads().to_vec();
+        for head in heads {
+            match merged
+                .iter_mut()
+                .find(|existing| existing.public_key == head.public_key)
+            {
+                Some(existing) if position(existing) < position(&head) => *existing = head,
+                Some(_) => {}
+                None => merged.push(head),
+            }
+        }
+        if merged == self.state.auditor_tree_heads() {
+            return None;
+        }
+
+        let mut new_state = self.state.clone();
+        new_state.set_auditor_tree_heads(merged);
+        Some(new_state)
+    }
+
+    fn adopting(
+        &self,
+        outcome: GossipOutcome,
+        head: LastTreeHead,
+        consistency: Vec<Vec<u8>>,
+        auditor_heads: Vec<FullAuditorTreeHead>,
+    ) -> CheckedGossip {
+        let mut new_state = self.state.clone();
+        new_state.set_last_tree_head(head);
+        new_state.set_last_tree_head_consistency(consistency);
+        new_state.set_auditor_tree_heads(auditor_heads);
+        CheckedGossip {
+            outcome,
+            based_on: self.state.clone(),
+            new_state: Some(new_state),
+        }
+    }
+
+    pub async fn run_monitor_once(
         &mut self,
         req: &libsignal_keytrans::MonitorRequest,
         resp: &libsignal_keytrans::MonitorResponse,
-        ctx: libsignal_keytrans::MonitorContext,
+        ctx: libsignal_keytrans::MonitorContext<'_>,
         now: SystemTime,
-    ) -> Result<(), libsignal_keytrans::Error> {
-        let update = self.kt.verify_monitor(req, resp, ctx, now)?;
+    ) -> Result<(), GossipError> {
+        let update = self
+            .kt
+            .verify_monitor(req, resp, ctx, now)
+            .map_err(|e| GossipError::VerificationFailed(e.to_string()))?;
 
-        let new_last: LastTreeHead = (update.tree_head, update.tree_root);
+        let head = (update.tree_head, update.tree_root);
+        // The response's auditor heads were checked along with the rest of it.
+        let auditor_heads = resp
+            .tree_head
+            .as_ref()
+            .map(|full_tree_head| self.vouching_auditor_heads(full_tree_head, &head))
+            .unwrap_or_default();
+        self.set_distinguished_tree_head(head).await?;
+        self.merge_auditor_heads(auditor_heads).await
+    }
 
-        self.state.set_last_tree_head(new_last.clone());
-        self.state.set_last_distinguished_tree_head(new_last);
-        save_state(&self.state);
+    /// Trusts `head` as both our distinguished and our last tree head.
+    ///
+    /// This is for heads that have already been verified, such as the result of a key
+    /// transparency distinguished request; [`Self::run_monitor_once`] does the verification
+    /// itself.
+    pub async fn set_distinguished_tree_head(
+        &mut self,
+        head: LastTreeHead,
+    ) -> Result<(), GossipError> {
+        let mut new_state = self.state.clone();
+        if self.state.last_tree_head() != Some(&head) {
+            new_state.set_auditor_tree_heads(vec![]);
+        }
+        new_state.set_last_tree_head(head.clone());
+        new_state.set_last_distinguished_tree_head(head);
+        new_state.set_last_tree_head_consistency(vec![]);
+        self.update_state(new_state).await
+    }
 
+    /// Persists `new_state`, only adopting it in memory once the store has accepted it.
+    async fn update_state(&mut self, new_state: KtState) -> Result<(), GossipError> {
+        self.store.save_state(&new_state).await?;
+        self.state = new_state;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use futures_util::FutureExt;
+    use libsignal_keytrans::TreeRoot;
+    use sha2::{Digest, Sha256};
+
+    use super::gossip_test::*;
+    use super::*;
+    use crate::{GossipStore, InMemGossipStore};
+
+    const HEAD_TIMESTAMP: i64 = 1669123456789;
+
+    fn head(tree_size: u64, root: TreeRoot) -> LastTreeHead {
+        head_at(tree_size, root, HEAD_TIMESTAMP)
+    }
+
+    fn head_at(tree_size: u64, root: TreeRoot, timestamp: i64) -> LastTreeHead {
+        let mut tree_head = TreeHead {
+            tree_size,
+            timestamp,
+            signatures: vec![],
+        };
+        sign_test_tree_head(&mut tree_head, &root);
+        (tree_head, root)
+    }
+
+    /// Shortly after the test heads were produced.
+    fn test_now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(HEAD_TIMESTAMP as u64 + 60_000)
+    }
+
+    const SMALL_ROOT: TreeRoot = [1; 32];
+    const RIGHT_SUBTREE: TreeRoot = [5; 32];
+
+    /// The root of an 8-entry log whose first 4 entries have [`SMALL_ROOT`].
+    ///
+    /// The consistency proof between the two is just `[RIGHT_SUBTREE]`.
+    fn large_root() -> TreeRoot {
+        const INTERIOR: [u8; 1] = [1];
+        Sha256::new()
+            .chain_update(INTERIOR)
+            .chain_update(SMALL_ROOT)
+            .chain_update(INTERIOR)
+            .chain_update(RIGHT_SUBTREE)
+            .finalize()
+            .into()
+    }
+
+    fn small_state() -> KtState {
+        let mut state = KtState::empty();
+        state.set_last_tree_head(head(4, SMALL_ROOT));
+        state.set_last_distinguished_tree_head(head(4, SMALL_ROOT));
+        state
+    }
+
+    fn large_state() -> KtState {
+        let mut state = small_state();
+        state.set_last_tree_head(head_at(8, large_root(), HEAD_TIMESTAMP + 1));
+        state.set_last_tree_head_consistency(vec![RIGHT_SUBTREE.to_vec()]);
+        state
+    }
+
+    fn service_with_state(state: &KtState) -> GossipService {
+        let mut store = InMemGossipStore::new();
+        store
+            .save_state(state)
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+        GossipService::new(create_test_key_transparency(), Box::new(store))
+            .now_or_never()
+            .expect("sync")
+            .expect("can load")
+    }
+
+    fn gossip_from(service: &GossipService) -> Vec<u8> {
+        service
+            .outgoing_gossip(test_now())
+            .expect("can encode")
+            .expect("has a head")
+    }
+
+    fn gossip_of(head: LastTreeHead, sent_at: SystemTime) -> Vec<u8> {
+        let (tree_head, tree_root) = head;
+        Gossip::new(
+            FullTreeHead {
+                tree_head: Some(tree_head),
+                ..Default::default()
+            },
+            tree_root,
+            sent_at,
+        )
+        .encode()
+        .expect("can encode")
+    }
+
+    fn process(
+        service: &mut GossipService,
+        gossip: &[u8],
+        now: SystemTime,
+    ) -> Result<GossipOutcome, GossipError> {
+        service
+            .process_incoming_gossip("peer.1", gossip, now)
+            .now_or_never()
+            .expect("sync")
+    }
+
+    #[test]
+    fn equal_heads_are_up_to_date() {
+        let sender = service_with_state(&small_state());
+        let mut receiver = service_with_state(&small_state());
+
+        assert_eq!(
+            process(&mut receiver, &gossip_from(&sender), test_now()),
+            Ok(GossipOutcome::UpToDate)
+        );
+        assert_eq!(receiver.state(), &small_state());
+    }
+
+    #[test]
+    fn newer_head_is_adopted_and_passed_on() {
+        let sender = service_with_state(&large_state());
+        let mut receiver = service_with_state(&small_state());
+
+        assert_eq!(
+            process(&mut receiver, &gossip_from(&sender), test_now()),
+            Ok(GossipOutcome::Advanced)
+        );
+        assert_eq!(receiver.state(), &large_state());
+
+        // The receiver keeps the consistency proof, so its own gossip is verifiable in turn.
+        let mut third = service_with_state(&small_state());
+        assert_eq!(
+            process(&mut third, &gossip_from(&receiver), test_now()),
+            Ok(GossipOutcome::Advanced)
+        );
+        assert_eq!(third.state(), &large_state());
+    }
+
+    #[test]
+    fn checked_gossip_is_only_adopted_once_saved() {
+        let sender = service_with_state(&large_state());
+        let mut receiver = service_with_state(&small_state());
+
+        let checked = receiver
+            .check_incoming_gossip("peer.1", &gossip_from(&sender), test_now())
+            .expect("valid");
+        assert_eq!(checked.outcome(), &GossipOutcome::Advanced);
+        assert_eq!(receiver.state(), &small_state());
+
+        assert_eq!(
+            receiver
+                .save_checked_gossip(checked)
+                .now_or_never()
+                .expect("sync"),
+            Ok(GossipOutcome::Advanced)
+        );
+        assert_eq!(receiver.state(), &large_state());
+    }
+
+    #[test]
+    fn checked_gossip_is_not_saved_over_a_newer_state() {
+        let sender = service_with_state(&large_state());
+        let mut receiver = service_with_state(&small_state());
+
+        let checked = receiver
+            .check_incoming_gossip("peer.1", &gossip_from(&sender), test_now())
+            .expect("valid");
+        receiver
+            .set_distinguished_tree_head(head_at(8, large_root(), HEAD_TIMESTAMP + 1))
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+        let state = receiver.state().clone();
+
+        assert_eq!(
+            receiver
+                .save_checked_gossip(checked)
+                .now_or_never()
+                .expect("sync"),
+            Err(GossipError::Stale)
+        );
+        assert_eq!(receiver.state(), &state);
+    }
+
+    #[test]
+    fn peer_behind_gets_catch_up_gossip() {
+        let mut ahead = service_with_state(&large_state());
+        let mut behind = service_with_state(&small_state());
+
+        let outcome = process(&mut ahead, &gossip_from(&behind), test_now());
+        let Ok(GossipOutcome::PeerBehind { catch_up }) = outcome else {
+            panic!("unexpected outcome: {outcome:?}");
+        };
+        // We keep the larger head...
+        assert_eq!(ahead.state(), &large_state());
+
+        // ...and the peer can verify and adopt it from the catch-up gossip.
+        assert_eq!(
+            process(&mut behind, &catch_up, test_now()),
+            Ok(GossipOutcome::Advanced)
+        );
+        assert_eq!(behind.state(), &large_state());
+    }
+
+    #[test]
+    fn peers_with_different_distinguished_heads_are_not_inconsistent() {
+        // The sender fetched the large head from the log directly, so has no proof from the small
+        // one.
+        let mut fetched_large = KtState::empty();
+        let large_head = large_state().last_tree_head().cloned().expect("has a head");
+        fetched_large.set_last_tree_head(large_head.clone());
+        fetched_large.set_last_distinguished_tree_head(large_head);
+        let sender = service_with_state(&fetched_large);
+
+        // A receiver behind the sender can't check its head...
+        let mut behind = service_with_state(&small_state());
+        assert_eq!(
+            process(&mut behind, &gossip_from(&sender), test_now()),
+            Ok(GossipOutcome::Unverified)
+        );
+        assert_eq!(behind.state(), &small_state());
+
+        // ...but one that has already adopted the same head by gossip can compare it with its own.
+        let mut caught_up = service_with_state(&large_state());
+        assert_eq!(
+            process(&mut caught_up, &gossip_from(&sender), test_now()),
+            Ok(GossipOutcome::UpToDate)
+        );
+
+        // Going the other way, heads no larger than our distinguished head are checked against it
+        // directly.
+        let mut ahead = service_with_state(&fetched_large);
+        assert!(matches!(
+            process(
+                &mut ahead,
+                &gossip_from(&service_with_state(&small_state())),
+                test_now()
+            ),
+            Ok(GossipOutcome::PeerBehind { .. })
+        ));
+        assert_eq!(
+            process(
+                &mut ahead,
+                &gossip_from(&service_with_state(&large_state())),
+                test_now()
+            ),
+            Ok(GossipOutcome::UpToDate)
+        );
+        assert_eq!(ahead.state(), &fetched_large);
+    }
+
+    #[test]
+    fn conflicting_root_is_inconsistent() {
+        let mut receiver = service_with_state(&small_state());
+
+        let mut forked = small_state();
+        forked.set_last_tree_head(head(4, [2; 32]));
+        let sender = service_with_state(&forked);
+
+        let err = process(&mut receiver, &gossip_from(&sender), test_now())
+            .expect_err("should be inconsistent");
+        let GossipError::Inconsistent(proof) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(proof.peer_address(), "peer.1");
+        assert_eq!(
+            Some(proof.local_tree_head()),
+            small_state().last_tree_head()
+        );
+        assert_eq!(Some(proof.peer_tree_head()), forked.last_tree_head());
+        assert!(proof.consistency_proof().is_empty());
+        assert_eq!(receiver.state(), &small_state());
+    }
+
+    #[test]
+    fn failed_consistency_proof_is_not_an_equivocation() {
+        let mut receiver = service_with_state(&small_state());
+
+        let (peer_head, peer_root) = head(8, [2; 32]);
+        let gossip = Gossip::new(
+            FullTreeHead {
+                tree_head: Some(peer_head),
+                distinguished: vec![vec![3; 32]],
+                ..Default::default()
+            },
+            peer_root,
+            test_now(),
+        )
+        .with_distinguished_tree_size(4)
+        .encode()
+        .expect("can encode");
+
+        assert!(matches!(
+            process(&mut receiver, &gossip, test_now()),
+            Err(GossipError::VerificationFailed(_))
+        ));
+        assert_eq!(receiver.state(), &small_state());
+    }
+
+    #[test]
+    fn unsigned_heads_are_rejected_before_comparison() {
+        let mut receiver = service_with_state(&large_state());
+        let unsigned = |(mut tree_head, root): LastTreeHead| {
+            tree_head.signatures.clear();
+            (tree_head, root)
+        };
+        let mut wrongly_signed = head(8, [2; 32]);
+        wrongly_signed.1 = large_root();
+
+        // Whether they would otherwise be up to date, newer, or a fork...
+        for peer_head in [
+            unsigned(head_at(8, large_root(), HEAD_TIMESTAMP + 1)),
+            unsigned(head(16, [2; 32])),
+            unsigned(head(8, [2; 32])),
+            wrongly_signed,
+        ] {
+            assert_eq!(
+                process(
+                    &mut receiver,
+                    &gossip_of(peer_head.clone(), test_now()),
+                    test_now()
+                ),
+                Err(GossipError::Invalid)
+            );
+            // ...and whichever form they arrive in.
+            let (tree_head, tree_root) = peer_head;
+            let compact = CompactGossip {
+                tree_size: tree_head.tree_size,
+                tree_root,
+                signatures: tree_head.signatures,
+                timestamp: tree_head.timestamp,
+            };
+            assert_eq!(
+                process(&mut receiver, &compact.encode(), test_now()),
+                Err(GossipError::Invalid)
+            );
+        }
+        assert_eq!(receiver.state(), &large_state());
+    }
+
+    #[test]
+    fn decoded_timestamp_is_checked_for_freshness() {
+        let mut receiver =
+            service_with_state(&small_state()).with_freshness_policy(FreshnessPolicy {
+                max_age: Duration::from_secs(60 * 60),
+                max_clock_skew: Duration::from_secs(60),
+            });
+        let later = test_now() + Duration::from_secs(2 * 60 * 60);
+
+        // Gossip that was sent too long ago.
+        let gossip = gossip_of(head(4, SMALL_ROOT), test_now());
+        assert_eq!(
+            process(&mut receiver, &gossip, test_now()),
+            Ok(GossipOutcome::UpToDate)
+        );
+        assert_eq!(
+            process(&mut receiver, &gossip, later),
+            Err(GossipError::Stale)
+        );
+
+        // Freshly sent gossip carrying a newer head that is nevertheless too old to adopt.
+        let replayed_head = service_with_state(&large_state())
+            .outgoing_gossip(later)
+            .expect("can encode")
+            .expect("has a head");
+        assert_eq!(
+            process(&mut receiver, &replayed_head, later),
+            Err(GossipError::Stale)
+        );
+
+        // Gossip sent too far in the future.
+        let ahead = gossip_of(
+            head(4, SMALL_ROOT),
+            test_now() + Duration::from_secs(5 * 60),
+        );
+        assert_eq!(
+            process(&mut receiver, &ahead, test_now()),
+            Err(GossipError::FutureTimestamp)
+        );
+        assert_eq!(receiver.state(), &small_state());
+    }
+
+    #[test]
+    fn newer_tree_head_timestamp_must_not_go_backwards() {
+        let mut sender = large_state();
+        sender.set_last_tree_head(head_at(8, large_root(), HEAD_TIMESTAMP - 1));
+        let mut receiver = service_with_state(&small_state());
+
+        assert_eq!(
+            process(
+                &mut receiver,
+                &gossip_from(&service_with_state(&sender)),
+                test_now()
+            ),
+            Err(GossipError::TimestampRegression)
+        );
+        assert_eq!(receiver.state(), &small_state());
+    }
+
+    #[test]
+    fn bootstrapping_is_opt_in() {
+        let mut strict = service_with_state(&KtState::empty());
+        assert_eq!(strict.outgoing_gossip(test_now()), Ok(None));
+        assert_eq!(
+            process(
+                &mut strict,
+                &gossip_of(head(4, SMALL_ROOT), test_now()),
+                test_now()
+            ),
+            Err(GossipError::Uninitialized)
+        );
+
+        let mut bootstrapping = service_with_state(&KtState::empty()).allow_bootstrap_from_peers();
+        assert_eq!(
+            process(
+                &mut bootstrapping,
+                &gossip_of(head(4, SMALL_ROOT), test_now()),
+                test_now()
+            ),
+            Ok(GossipOutcome::Bootstrapped)
+        );
+        assert_eq!(
+            bootstrapping.state().last_tree_head(),
+            Some(&head(4, SMALL_ROOT))
+        );
+        assert_eq!(bootstrapping.state().last_distinguished_tree_head(), None);
+
+        // Later heads are still compared against the bootstrapped one.
+        assert_eq!(
+            process(
+                &mut bootstrapping,
+                &gossip_of(head(4, SMALL_ROOT), test_now()),
+                test_now()
+            ),
+            Ok(GossipOutcome::UpToDate)
+        );
+        assert!(matches!(
+            process(
+                &mut bootstrapping,
+                &gossip_of(head(4, [2; 32]), test_now()),
+                test_now()
+            ),
+            Err(GossipError::Inconsistent(_))
+        ));
+        assert_eq!(
+            process(
+                &mut bootstrapping,
+                &gossip_of(head(8, [2; 32]), test_now()),
+                test_now()
+            ),
+            Ok(GossipOutcome::Bootstrapped)
+        );
+    }
+
+    #[test]
+    fn verified_distinguished_head_is_trusted() {
+        let mut service = service_with_state(&KtState::empty());
+        service
+            .set_distinguished_tree_head(head(4, SMALL_ROOT))
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+        assert_eq!(service.state(), &small_state());
+
+        // Later gossip is checked against it.
+        assert_eq!(
+            process(
+                &mut service,
+                &gossip_from(&service_with_state(&large_state())),
+                test_now()
+            ),
+            Ok(GossipOutcome::Advanced)
+        );
+    }
+
+    fn audited_service(state: &KtState, auditors: AuditorPolicy) -> GossipService {
+        service_with_state(state).with_auditor_policy(auditors)
+    }
+
+    #[test]
+    fn auditor_policy_decides_whether_heads_are_adopted() {
+        let auditors = test_auditor_keys();
+        let large_head = large_state().last_tree_head().cloned().expect("has a head");
+        let vouched_for_by = |auditors: &[SigningKey]| {
+            let mut state = large_state();
+            state.set_auditor_tree_heads(
+                auditors
+                    .iter()
+                    .map(|auditor| create_test_auditor_tree_head(auditor, &large_head))
+                    .collect(),
+            );
+            audited_service(&state, AuditorPolicy::default())
+        };
+        let mut receiver = audited_service(&small_state(), AuditorPolicy::at_least(2));
+
+        // One auditor isn't enough...
+        assert_eq!(
+            process(
+                &mut receiver,
+                &gossip_from(&vouched_for_by(&auditors[..1])),
+                test_now()
+            ),
+            Err(GossipError::InsufficientAuditors(1, 2))
+        );
+        assert_eq!(receiver.state(), &small_state());
+
+        // ...and neither an unconfigured auditor nor one that saw a different head counts.
+        let mut forged = large_state();
+        forged.set_auditor_tree_heads(vec![
+            create_test_auditor_tree_head(&auditors[0], &large_head),
+            create_test_auditor_tree_head(&auditors[1], &head(8, [9; 32])),
+            create_test_auditor_tree_head(&SigningKey::from_bytes(&[4; 32]), &large_head),
+        ]);
+        assert_eq!(
+            process(
+                &mut receiver,
+                &gossip_from(&audited_service(&forged, AuditorPolicy::default())),
+                test_now()
+            ),
+            Err(GossipError::InsufficientAuditors(1, 2))
+        );
+
+        // Two auditors are, and their heads are kept and passed on.
+        assert_eq!(
+            process(
+                &mut receiver,
+                &gossip_from(&vouched_for_by(&auditors[1..])),
+                test_now()
+            ),
+            Ok(GossipOutcome::Advanced)
+        );
+        assert_eq!(receiver.state().auditor_tree_heads().len(), 2);
+        let mut third = audited_service(&small_state(), AuditorPolicy::at_least(2));
+        assert_eq!(
+            process(&mut third, &gossip_from(&receiver), test_now()),
+            Ok(GossipOutcome::Advanced)
+        );
+    }
+
+    #[test]
+    fn auditor_heads_for_our_head_are_collected() {
+        let auditors = &test_auditor_keys()[..2];
+        let small_head = small_state().last_tree_head().cloned().expect("has a head");
+
+        let mut receiver = service_with_state(&small_state());
+        for auditor in auditors {
+            let mut state = small_state();
+            state.set_auditor_tree_heads(vec![create_test_auditor_tree_head(auditor, &small_head)]);
+            let sender = service_with_state(&state);
+            assert_eq!(
+                process(&mut receiver, &gossip_from(&sender), test_now()),
+                Ok(GossipOutcome::UpToDate)
+            );
+        }
+
+        let expected: Vec<_> = auditors
+            .iter()
+            .map(|auditor| create_test_auditor_tree_head(auditor, &small_head))
+            .collect();
+        assert_eq!(receiver.state().auditor_tree_heads(), expected);
+        assert!(
+            receiver
+                .state()
+                .auditor_tree_head(auditors[1].verifying_key().as_bytes())
+                .is_some()
+        );
+    }
+
+    fn acknowledged(head: &LastTreeHead) -> AcknowledgedTreeHead {
+        AcknowledgedTreeHead {
+            tree_size: head.0.tree_size,
+            tree_root: head.1,
+        }
+    }
+
+    #[test]
+    fn acknowledged_head_is_sent_compactly() {
+        let sender = service_with_state(&large_state());
+        let full = gossip_from(&sender);
+        let outgoing = |acknowledged: Option<AcknowledgedTreeHead>| {
+            sender
+                .outgoing_gossip_for_peer(acknowledged.as_ref(), test_now())
+                .expect("can encode")
+                .expect("has a head")
+        };
+
+        // Nothing acknowledged yet, or only an older head.
+        assert_eq!(outgoing(None), full);
+        assert_eq!(outgoing(Some(acknowledged(&head(4, SMALL_ROOT)))), full);
+
+        // The same head.
+        let large_head = large_state().last_tree_head().cloned().expect("has a head");
+        let compact = outgoing(Some(acknowledged(&large_head)));
+        assert!(compact.len() < full.len());
+        assert_eq!(
+            GossipMessage::decode(&compact).and_then(|m| m.acknowledged_head()),
+            Ok(acknowledged(&large_head))
+        );
+
+        // A newer head, which the peer couldn't check an older compact head against.
+        assert_eq!(outgoing(Some(acknowledged(&head(16, [2; 32])))), full);
+
+        // A head of the same size with a different root is a fork, so the peer needs it in full.
+        assert_eq!(outgoing(Some(acknowledged(&head(8, [2; 32])))), full);
+    }
+
+    #[test]
+    fn compact_gossip_is_compared_with_our_head() {
+        let compact = |head: LastTreeHead| {
+            CompactGossip {
+                tree_size: head.0.tree_size,
+                tree_root: head.1,
+                signatures: head.0.signatures,
+                timestamp: head.0.timestamp,
+            }
+            .encode()
+        };
+        let mut receiver = service_with_state(&large_state());
+        let large_head = large_state().last_tree_head().cloned().expect("has a head");
+
+        assert_eq!(
+            process(&mut receiver, &compact(large_head), test_now()),
+            Ok(GossipOutcome::UpToDate)
+        );
+
+        let outcome = process(&mut receiver, &compact(head(4, SMALL_ROOT)), test_now());
+        let Ok(GossipOutcome::PeerBehind { catch_up }) = outcome else {
+            panic!("unexpected outcome: {outcome:?}");
+        };
+        assert!(matches!(
+            GossipMessage::decode(&catch_up),
+            Ok(GossipMessage::Full(_))
+        ));
+
+        let err = process(&mut receiver, &compact(head(8, [2; 32])), test_now())
+            .expect_err("should be inconsistent");
+        let GossipError::Inconsistent(proof) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(proof.peer_tree_head(), &head(8, [2; 32]));
+
+        // Without a consistency proof, a newer head can't be adopted.
+        assert_eq!(
+            process(&mut receiver, &compact(head(16, [2; 32])), test_now()),
+            Err(GossipError::UnknownTreeHead)
+        );
+        assert_eq!(receiver.state(), &large_state());
+    }
+}
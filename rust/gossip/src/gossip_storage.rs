@@ -1,8 +1,8 @@
 /*
 Gossip Service
 
-This service should 
-1. periodically call kt.verify_monitor() 
+This service should
+1. periodically call kt.verify_monitor()
 2. check incoming gossip's consistency using kt.verify_distinguished() and update last_distinguished_tree_head if OK
 
 FullTreeHead = tree head incoming from gossip to verify
@@ -10,14 +10,25 @@ last_tree_head = current trusted head (updated after valid gossip responses)
 last_distinguished_tree_head = last head directly signed by server (never updated by gossip)
 */
 
-use libsignal_keytrans::{
-    LastTreeHead
-};
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
 
-#[derive(Clone, Debug)]
+use async_trait::async_trait;
+use libsignal_keytrans::{FullAuditorTreeHead, LastTreeHead, StoredTreeHead};
+use prost::Message;
+
+use crate::gossip::{GossipError, proto};
+
+/// Version byte prepended to every serialized [`KtState`].
+pub(crate) const KT_STATE_CURRENT_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct KtState {
     last_tree_head: Option<LastTreeHead>, // (TreeHead, TreeRoot)
     last_distinguished_tree_head: Option<LastTreeHead>,
+    last_tree_head_consistency: Vec<Vec<u8>>,
+    auditor_tree_heads: Vec<FullAuditorTreeHead>,
 }
 
 impl KtState {
@@ -25,6 +36,8 @@ impl KtState {
         KtState {
             last_tree_head: None,
             last_distinguished_tree_head: None,
+            last_tree_head_consistency: Vec::new(),
+            auditor_tree_heads: Vec::new(),
         }
     }
 
@@ -44,6 +57,36 @@ impl KtState {
         self.last_distinguished_tree_head = Some(tree_head);
     }
 
+    /// The consistency proof from the distinguished tree head to the last tree head, which is
+    /// passed along with our head so that peers can verify it.
+    ///
+    /// Empty if the two heads are the same size.
+    pub fn last_tree_head_consistency(&self) -> &[Vec<u8>] {
+        &self.last_tree_head_consistency
+    }
+
+    pub fn set_last_tree_head_consistency(&mut self, proof: Vec<Vec<u8>>) {
+        self.last_tree_head_consistency = proof;
+    }
+
+    /// The latest tree head from each third-party auditor that vouches for the last tree head,
+    /// which is passed along with our head so that peers can apply their
+    /// [`AuditorPolicy`](crate::AuditorPolicy) to it.
+    pub fn auditor_tree_heads(&self) -> &[FullAuditorTreeHead] {
+        &self.auditor_tree_heads
+    }
+
+    /// The entry in [`Self::auditor_tree_heads`] for the auditor with the given public key.
+    pub fn auditor_tree_head(&self, auditor_public_key: &[u8]) -> Option<&FullAuditorTreeHead> {
+        self.auditor_tree_heads
+            .iter()
+            .find(|head| head.public_key == auditor_public_key)
+    }
+
+    pub fn set_auditor_tree_heads(&mut self, heads: Vec<FullAuditorTreeHead>) {
+        self.auditor_tree_heads = heads;
+    }
+
     pub fn has_tree_head(&self) -> bool {
         self.last_tree_head.is_some()
     }
@@ -55,13 +98,262 @@ impl KtState {
     pub fn is_initialized(&self) -> bool {
         self.has_tree_head() && self.has_distinguished_tree_head()
     }
+
+    /// Serializes the state as a version byte followed by a `StoredKtState` protobuf.
+    pub fn serialize(&self) -> Vec<u8> {
+        fn encode_head(head: &Option<LastTreeHead>) -> Vec<u8> {
+            head.clone()
+                .map(|head| StoredTreeHead::from(head).encode_to_vec())
+                .unwrap_or_default()
+        }
+
+        let stored = proto::StoredKtState {
+            last_tree_head: encode_head(&self.last_tree_head),
+            last_distinguished_tree_head: encode_head(&self.last_distinguished_tree_head),
+            last_tree_head_consistency: self.last_tree_head_consistency.clone(),
+            auditor_tree_heads: self
+                .auditor_tree_heads
+                .iter()
+                .map(Message::encode_to_vec)
+                .collect(),
+        };
+
+        let mut out = Vec::with_capacity(1 + stored.encoded_len());
+        out.push(KT_STATE_CURRENT_VERSION);
+        stored
+            .encode(&mut out)
+            .expect("can always append to a buffer");
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, GossipError> {
+        fn decode_head(bytes: &[u8]) -> Result<Option<LastTreeHead>, GossipError> {
+            if bytes.is_empty() {
+                return Ok(None);
+            }
+            let stored = StoredTreeHead::decode(bytes).map_err(|_| GossipError::Invalid)?;
+            stored
+                .into_last_tree_head()
+                .map(Some)
+                .ok_or(GossipError::Invalid)
+        }
+
+        let (&version, rest) = data.split_first().ok_or(GossipError::Invalid)?;
+        if version != KT_STATE_CURRENT_VERSION {
+            return Err(GossipError::UnrecognizedStateVersion(version));
+        }
+
+        let stored = proto::StoredKtState::decode(rest).map_err(|_| GossipError::Invalid)?;
+        Ok(Self {
+            last_tree_head: decode_head(&stored.last_tree_head)?,
+            last_distinguished_tree_head: decode_head(&stored.last_distinguished_tree_head)?,
+            last_tree_head_consistency: stored.last_tree_head_consistency,
+            auditor_tree_heads: stored
+                .auditor_tree_heads
+                .iter()
+                .map(|bytes| {
+                    FullAuditorTreeHead::decode(bytes.as_slice()).map_err(|_| GossipError::Invalid)
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Default for KtState {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// Interface for persisting the [`KtState`] tracked by a
+/// [`GossipService`](crate::GossipService), which may be in-memory, on-disk, etc.
+#[async_trait(?Send)]
+pub trait GossipStore {
+    /// Look up the previously saved state, if any.
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError>;
+
+    /// Replace the saved state with `state`.
+    async fn save_state(&mut self, state: &KtState) -> Result<(), GossipError>;
+}
+
+/// Reference implementation of [`GossipStore`].
+///
+/// This implementation is purely in-memory, and therefore most likely useful for testing.
+#[derive(Clone, Debug, Default)]
+pub struct InMemGossipStore {
+    state: Option<KtState>,
+}
+
+impl InMemGossipStore {
+    /// Create an empty gossip store.
+    pub fn new() -> Self {
+        Self { state: None }
+    }
 }
 
-pub fn save_state(_state: &KtState) {
-    // TODO: save somewhere
+#[async_trait(?Send)]
+impl GossipStore for InMemGossipStore {
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError> {
+        Ok(self.state.clone())
+    }
+
+    async fn save_state(&mut self, state: &KtState) -> Result<(), GossipError> {
+        self.state = Some(state.clone());
+        Ok(())
+    }
+}
+
+/// A [`GossipStore`] that keeps the serialized [`KtState`] in a single file.
+///
+/// Writes go to a sibling temporary file that is then renamed over the original, so a crash
+/// mid-write leaves the previous state intact.
+#[derive(Clone, Debug)]
+pub struct FileGossipStore {
+    path: PathBuf,
+}
+
+impl FileGossipStore {
+    /// Create a store backed by the file at `path`. The file does not need to exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".tmp");
+        self.path.with_file_name(file_name)
+    }
+}
+
+#[async_trait(?Send)]
+impl GossipStore for FileGossipStore {
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError> {
+        match fs::read(&self.path) {
+            Ok(bytes) => KtState::deserialize(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(GossipError::Storage(e.to_string())),
+        }
+    }
+
+    async fn save_state(&mut self, state: &KtState) -> Result<(), GossipError> {
+        let temp_path = self.temp_path();
+        let write_temp = || -> std::io::Result<()> {
+            let mut file = fs::File::create(&temp_path)?;
+            file.write_all(&state.serialize())?;
+            file.sync_all()
+        };
+        write_temp()
+            .and_then(|()| fs::rename(&temp_path, &self.path))
+            .map_err(|e| GossipError::Storage(e.to_string()))
+    }
 }
 
-pub fn load_state() -> KtState {
-    // TODO: read from from that somewhere
-    KtState::empty()
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use libsignal_keytrans::{AuditorTreeHead, TreeHead};
+
+    use super::*;
+
+    fn test_state() -> KtState {
+        let head = |tree_size, root| {
+            (
+                TreeHead {
+                    tree_size,
+                    timestamp: 1669123456789,
+                    signatures: vec![],
+                },
+                root,
+            )
+        };
+        let mut state = KtState::empty();
+        state.set_last_tree_head(head(20, [0xAA; 32]));
+        state.set_last_distinguished_tree_head(head(10, [0xBB; 32]));
+        state.set_last_tree_head_consistency(vec![vec![0xCC; 32]]);
+        state.set_auditor_tree_heads(vec![FullAuditorTreeHead {
+            tree_head: Some(AuditorTreeHead {
+                tree_size: 18,
+                timestamp: 1669123456000,
+                signature: vec![0xDD; 64],
+            }),
+            root_value: Some(vec![0xEE; 32]),
+            consistency: vec![vec![0xFF; 32]],
+            public_key: vec![0x11; 32],
+        }]);
+        state
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let state = test_state();
+        let serialized = state.serialize();
+        assert_eq!(serialized[0], KT_STATE_CURRENT_VERSION);
+        assert_eq!(KtState::deserialize(&serialized), Ok(state));
+
+        let empty = KtState::empty();
+        assert_eq!(KtState::deserialize(&empty.serialize()), Ok(empty));
+    }
+
+    #[test]
+    fn deserialize_rejects_unknown_version() {
+        let mut serialized = test_state().serialize();
+        serialized[0] = KT_STATE_CURRENT_VERSION + 1;
+        assert_eq!(
+            KtState::deserialize(&serialized),
+            Err(GossipError::UnrecognizedStateVersion(
+                KT_STATE_CURRENT_VERSION + 1
+            ))
+        );
+        assert_eq!(KtState::deserialize(&[]), Err(GossipError::Invalid));
+    }
+
+    #[test]
+    fn in_memory_store() {
+        let mut store = InMemGossipStore::new();
+        assert_eq!(store.load_state().now_or_never().expect("sync"), Ok(None));
+
+        let state = test_state();
+        store
+            .save_state(&state)
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+        assert_eq!(
+            store.load_state().now_or_never().expect("sync"),
+            Ok(Some(state))
+        );
+    }
+
+    #[test]
+    fn file_store() {
+        let path = std::env::temp_dir().join(format!(
+            "libsignal-gossip-file-store-test-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileGossipStore::new(&path);
+        assert_eq!(store.load_state().now_or_never().expect("sync"), Ok(None));
+
+        let state = test_state();
+        store
+            .save_state(&state)
+            .now_or_never()
+            .expect("sync")
+            .expect("can save");
+
+        // A fresh store pointed at the same file sees the saved state.
+        let reopened = FileGossipStore::new(&path);
+        assert_eq!(
+            reopened.load_state().now_or_never().expect("sync"),
+            Ok(Some(state))
+        );
+        assert!(!store.temp_path().exists());
+
+        fs::remove_file(&path).expect("can clean up");
+    }
 }
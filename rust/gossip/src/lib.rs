@@ -12,6 +12,9 @@ pub use gossip::*;
 mod gossip_storage;
 pub use gossip_storage::*;
 
+mod equivocation;
+pub use equivocation::*;
+
 pub mod gossip_service;
 pub use gossip_service::*;
 
@@ -98,7 +101,7 @@ pub use gossip_service::*;
 //     ) -> Option<Gossip> {
 //         let kt = self.kt.as_ref()?;
 //         let full = self.storage.load_full_tree_head(kt)?;
-        
+
 //         let gossip = Gossip::new(full, protocol_address, Vec::new(), now);
 //         Some(gossip)
 //     }
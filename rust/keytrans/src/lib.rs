@@ -21,18 +21,19 @@ use std::time::SystemTime;
 pub use ed25519_dalek::VerifyingKey;
 use itertools::Itertools;
 pub use proto::{
-    ChatMonitorResponse, CondensedTreeSearchResponse,
+    AuditorTreeHead, ChatMonitorResponse, CondensedTreeSearchResponse,
     DistinguishedResponse as ChatDistinguishedResponse, FullAuditorTreeHead, FullTreeHead,
     MonitorKey, MonitorProof, MonitorRequest, MonitorResponse,
     SearchResponse as ChatSearchResponse, Signature, StoredAccountData, StoredMonitoringData,
     StoredTreeHead, TreeHead, UpdateRequest, UpdateResponse,
 };
 pub use verify::Error;
-use verify::{verify_distinguished, verify_monitor, verify_search};
+use verify::{
+    verify_auditor_tree_head, verify_distinguished, verify_monitor, verify_search,
+    verify_tree_head_signatures,
+};
 pub use vrf::PublicKey as VrfPublicKey;
 
-use crate::proto::AuditorTreeHead;
-
 #[derive(PartialEq, Clone)]
 pub struct VerifyingKeys(Vec<VerifyingKey>);
 
@@ -242,6 +243,59 @@ impl KeyTransparency {
         verify_distinguished(full_tree_head, last_tree_head, last_distinguished_tree_head)
     }
 
+    /// Checks that `tree_head` was signed by the service operator.
+    ///
+    /// This is the signature check made on the tree heads in search and monitor responses, for
+    /// tree heads that arrive some other way. Nothing else about the tree head is checked.
+    pub fn verify_tree_head_signature(
+        &self,
+        tree_head: &LastTreeHead,
+    ) -> Result<(), verify::Error> {
+        let (tree_head, root) = tree_head;
+        verify_tree_head_signatures(&self.config, tree_head, root)
+    }
+
+    /// The keys of the third-party auditors this deployment is configured with.
+    ///
+    /// Empty unless the deployment mode is [`DeploymentMode::ThirdPartyAuditing`].
+    pub fn auditor_keys(&self) -> impl ExactSizeIterator<Item = &VerifyingKey> {
+        match &self.config.mode {
+            DeploymentMode::ThirdPartyAuditing(keys) => keys.iter(),
+            DeploymentMode::ContactMonitoring | DeploymentMode::ThirdPartyManagement(_) => {
+                EMPTY_KEYS.iter()
+            }
+        }
+    }
+
+    /// Checks that `auditor_tree_head` was signed by the configured auditor `auditor_key`, and
+    /// is consistent with the service operator's `tree_head`.
+    ///
+    /// Unlike the checks made on auditor tree heads in search and monitor responses, this does
+    /// not check how recent the auditor's tree head is.
+    pub fn verify_auditor_tree_head(
+        &self,
+        auditor_key: &VerifyingKey,
+        auditor_tree_head: &FullAuditorTreeHead,
+        tree_head: &LastTreeHead,
+    ) -> Result<(), verify::Error> {
+        if !self.auditor_keys().contains(auditor_key) {
+            return Err(Error::BadData("unknown auditor key".to_string()));
+        }
+        if auditor_tree_head.public_key.as_slice() != auditor_key.as_bytes().as_slice() {
+            return Err(Error::BadData(
+                "auditor tree head is for a different auditor".to_string(),
+            ));
+        }
+        let (tree_head, root) = tree_head;
+        verify_auditor_tree_head(
+            &self.config,
+            auditor_key,
+            auditor_tree_head,
+            tree_head,
+            root,
+        )
+    }
+
     /// Checks that the output of a Monitor operation is valid and updates the
     /// client's stored data.
     pub fn verify_monitor<'a>(
@@ -566,4 +620,119 @@ mod test {
     fn find_matching_works(xs: &[i32], ys: &[i32]) -> Option<Vec<i32>> {
         find_matching(xs.iter(), ys.iter(), |a, b| a * 10 == *b, |a, b| a + b)
     }
+
+    #[test]
+    fn verify_auditor_tree_head_works() {
+        use ed25519_dalek::{Signer as _, SigningKey};
+
+        const VRF_KEY: [u8; 32] = [
+            0xec, 0x3a, 0x26, 0x82, 0x37, 0xcf, 0x5c, 0x47, 0x11, 0x5c, 0xf2, 0x22, 0x40, 0x5d,
+            0x5f, 0x90, 0xcc, 0x63, 0x3e, 0xbe, 0x05, 0xca, 0xf8, 0x2c, 0x0d, 0xd5, 0xac, 0xf9,
+            0xd3, 0x41, 0xda, 0xdb,
+        ];
+
+        let auditor = SigningKey::from_bytes(&[1; 32]);
+        let auditor_key = auditor.verifying_key();
+        let stranger_key = SigningKey::from_bytes(&[2; 32]).verifying_key();
+        let kt = KeyTransparency {
+            config: PublicConfig {
+                mode: DeploymentMode::ThirdPartyAuditing(VerifyingKeys::from([auditor_key])),
+                signature_key: SigningKey::from_bytes(&[3; 32]).verifying_key(),
+                vrf_key: VrfPublicKey::try_from(VRF_KEY).expect("valid test key"),
+            },
+        };
+
+        let root = [0xAA; 32];
+        let service_head = TreeHead {
+            tree_size: 10,
+            timestamp: 1669123456789,
+            signatures: vec![],
+        };
+        let mut auditor_head = AuditorTreeHead {
+            tree_size: 10,
+            timestamp: 1669123456000,
+            signature: vec![],
+        };
+        let to_be_signed = auditor_head.to_signable_header(&root, &kt.config, Some(&auditor_key));
+        auditor_head.signature = auditor.sign(&to_be_signed).to_vec();
+        let full_auditor_head = FullAuditorTreeHead {
+            tree_head: Some(auditor_head),
+            root_value: None,
+            consistency: vec![],
+            public_key: auditor_key.as_bytes().to_vec(),
+        };
+
+        kt.verify_auditor_tree_head(
+            &auditor_key,
+            &full_auditor_head,
+            &(service_head.clone(), root),
+        )
+        .expect("valid");
+        kt.verify_auditor_tree_head(
+            &auditor_key,
+            &full_auditor_head,
+            &(service_head.clone(), [0xBB; 32]),
+        )
+        .expect_err("signed a different root");
+        kt.verify_auditor_tree_head(&stranger_key, &full_auditor_head, &(service_head, root))
+            .expect_err("not a configured auditor");
+    }
+
+    #[test]
+    fn verify_tree_head_signature_works() {
+        use ed25519_dalek::{Signer as _, SigningKey};
+
+        const VRF_KEY: [u8; 32] = [
+            0xec, 0x3a, 0x26, 0x82, 0x37, 0xcf, 0x5c, 0x47, 0x11, 0x5c, 0xf2, 0x22, 0x40, 0x5d,
+            0x5f, 0x90, 0xcc, 0x63, 0x3e, 0xbe, 0x05, 0xca, 0xf8, 0x2c, 0x0d, 0xd5, 0xac, 0xf9,
+            0xd3, 0x41, 0xda, 0xdb,
+        ];
+
+        let operator = SigningKey::from_bytes(&[3; 32]);
+        let auditor_keys = [1, 2].map(|seed| SigningKey::from_bytes(&[seed; 32]).verifying_key());
+        let kt = KeyTransparency {
+            config: PublicConfig {
+                mode: DeploymentMode::ThirdPartyAuditing(VerifyingKeys::from(auditor_keys)),
+                signature_key: operator.verifying_key(),
+                vrf_key: VrfPublicKey::try_from(VRF_KEY).expect("valid test key"),
+            },
+        };
+
+        let root = [0xAA; 32];
+        let unsigned = TreeHead {
+            tree_size: 10,
+            timestamp: 1669123456789,
+            signatures: vec![],
+        };
+        let signatures = auditor_keys
+            .iter()
+            .map(|key| {
+                let single = SingleSignatureTreeHead(unsigned.clone());
+                let to_be_signed = single.to_signable_header(&root, &kt.config, Some(key));
+                Signature {
+                    auditor_public_key: key.as_bytes().to_vec(),
+                    signature: operator.sign(&to_be_signed).to_vec(),
+                }
+            })
+            .collect_vec();
+        let signed = TreeHead {
+            signatures: signatures.clone(),
+            ..unsigned.clone()
+        };
+
+        kt.verify_tree_head_signature(&(signed.clone(), root))
+            .expect("valid");
+        kt.verify_tree_head_signature(&(signed, [0xBB; 32]))
+            .expect_err("signed a different root");
+        kt.verify_tree_head_signature(&(unsigned.clone(), root))
+            .expect_err("unsigned");
+        kt.verify_tree_head_signature(&(
+            TreeHead {
+                signatures: signatures[..1].to_vec(),
+                ..unsigned
+            },
+            root,
+        ))
+        .expect_err("missing a signature for one auditor");
+    }
 }
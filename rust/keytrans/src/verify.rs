@@ -15,8 +15,8 @@ use crate::implicit::{full_monitoring_path, monitoring_path};
 use crate::log::{evaluate_batch_proof, verify_consistency_proof};
 use crate::prefix::{MalformedProof, evaluate as evaluate_prefix};
 use crate::proto::{
-    CondensedTreeSearchResponse, FullTreeHead, MonitorKey, MonitorProof, MonitorRequest,
-    MonitorResponse, ProofStep, TreeHead,
+    CondensedTreeSearchResponse, FullAuditorTreeHead, FullTreeHead, MonitorKey, MonitorProof,
+    MonitorRequest, MonitorResponse, ProofStep, TreeHead,
 };
 use crate::{
     DeploymentMode, FullSearchResponse, LastTreeHead, MonitorContext, MonitorStateUpdate,
@@ -124,6 +124,24 @@ fn verify_tree_head_signature(
         .map_err(|_| Error::VerificationFailed("failed to verify tree head signature".to_string()))
 }
 
+/// Checks the service operator's signatures on a tree head, one for each of the keys associated
+/// with the deployment mode.
+pub(crate) fn verify_tree_head_signatures(
+    config: &PublicConfig,
+    tree_head: &TreeHead,
+    root: &TreeRoot,
+) -> Result<()> {
+    for (key, head) in &tree_head
+        .to_single_signature_tree_heads(config)
+        .ok_or(Error::BadData(
+            "server signatures are either missing or not available for all auditors".to_string(),
+        ))?
+    {
+        verify_tree_head_signature(config, head, root, &config.signature_key, Some(key))?;
+    }
+    Ok(())
+}
+
 /// Checks that a FullTreeHead structure is valid. It stores the tree head for
 /// later requests if it succeeds.
 fn verify_full_tree_head(
@@ -155,18 +173,7 @@ fn verify_full_tree_head(
     }
 
     // 2. Verify the signatures in TreeHead.signature.
-    {
-        for (key, head) in
-            &tree_head
-                .to_single_signature_tree_heads(config)
-                .ok_or(Error::BadData(
-                    "server signatures are either missing or not available for all auditors"
-                        .to_string(),
-                ))?
-        {
-            verify_tree_head_signature(config, head, &root, &config.signature_key, Some(key))?;
-        }
-    }
+    verify_tree_head_signatures(config, tree_head, &root)?;
 
     // 3. Verify that the timestamp in TreeHead is sufficiently recent.
     verify_timestamp(
@@ -197,69 +204,83 @@ fn verify_full_tree_head(
                 now,
             )?;
 
-            // 3. Verify that TreeHead.tree_size is sufficiently close to the most
-            //    recent tree head from the service operator.
-            if auditor_head.tree_size > tree_head.tree_size {
-                return Err(Error::BadData(
-                    "auditor tree head may not be further along than service tree head".to_string(),
-                ));
-            }
-            if tree_head.tree_size - auditor_head.tree_size > ENTRIES_MAX_BEHIND {
-                return Err(Error::BadData(
-                    "auditor tree head is too far behind service tree head".to_string(),
-                ));
-            }
-            // 4. Verify the consistency proof between this tree head and the most
-            //    recent tree head from the service operator.
-            // 1. Verify the signature in TreeHead.signature.
-            if tree_head.tree_size > auditor_head.tree_size {
-                let auditor_root: &[u8; 32] =
-                    get_proto_field(&auditor_tree_head.root_value, "root_value")?
-                        .as_slice()
-                        .try_into()
-                        .map_err(|_| {
-                            Error::BadData("auditor tree head is malformed".to_string())
-                        })?;
-                let proof = get_hash_proof(&auditor_tree_head.consistency)?;
-                verify_consistency_proof(
-                    auditor_head.tree_size,
-                    tree_head.tree_size,
-                    &proof,
-                    auditor_root,
-                    &root,
-                )?;
-                verify_tree_head_signature(
-                    config,
-                    auditor_head,
-                    auditor_root,
-                    verifying_key,
-                    Some(verifying_key),
-                )?;
-            } else {
-                if !auditor_tree_head.consistency.is_empty() {
-                    return Err(Error::BadData(
-                        "consistency proof provided when not expected".to_string(),
-                    ));
-                }
-                if auditor_tree_head.root_value.is_some() {
-                    return Err(Error::BadData(
-                        "explicit root value provided when not expected".to_string(),
-                    ));
-                }
-                verify_tree_head_signature(
-                    config,
-                    auditor_head,
-                    &root,
-                    verifying_key,
-                    Some(verifying_key),
-                )?;
-            }
+            verify_auditor_tree_head(config, verifying_key, auditor_tree_head, tree_head, &root)?;
         }
     }
 
     Ok((tree_head.clone(), root))
 }
 
+/// Checks an auditor's tree head against the service operator's tree head, following steps 3, 4,
+/// and 1 of Section 11.2.
+///
+/// The auditor's timestamp is not checked, since what counts as recent enough depends on where
+/// the tree heads came from.
+pub(crate) fn verify_auditor_tree_head(
+    config: &PublicConfig,
+    verifying_key: &VerifyingKey,
+    auditor_tree_head: &FullAuditorTreeHead,
+    tree_head: &TreeHead,
+    root: &TreeRoot,
+) -> Result<()> {
+    let auditor_head = get_proto_field(&auditor_tree_head.tree_head, "tree_head")?;
+
+    // 3. Verify that TreeHead.tree_size is sufficiently close to the most
+    //    recent tree head from the service operator.
+    if auditor_head.tree_size > tree_head.tree_size {
+        return Err(Error::BadData(
+            "auditor tree head may not be further along than service tree head".to_string(),
+        ));
+    }
+    if tree_head.tree_size - auditor_head.tree_size > ENTRIES_MAX_BEHIND {
+        return Err(Error::BadData(
+            "auditor tree head is too far behind service tree head".to_string(),
+        ));
+    }
+    // 4. Verify the consistency proof between this tree head and the most
+    //    recent tree head from the service operator.
+    // 1. Verify the signature in TreeHead.signature.
+    if tree_head.tree_size > auditor_head.tree_size {
+        let auditor_root: &[u8; 32] = get_proto_field(&auditor_tree_head.root_value, "root_value")?
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::BadData("auditor tree head is malformed".to_string()))?;
+        let proof = get_hash_proof(&auditor_tree_head.consistency)?;
+        verify_consistency_proof(
+            auditor_head.tree_size,
+            tree_head.tree_size,
+            &proof,
+            auditor_root,
+            root,
+        )?;
+        verify_tree_head_signature(
+            config,
+            auditor_head,
+            auditor_root,
+            verifying_key,
+            Some(verifying_key),
+        )
+    } else {
+        if !auditor_tree_head.consistency.is_empty() {
+            return Err(Error::BadData(
+                "consistency proof provided when not expected".to_string(),
+            ));
+        }
+        if auditor_tree_head.root_value.is_some() {
+            return Err(Error::BadData(
+                "explicit root value provided when not expected".to_string(),
+            ));
+        }
+        verify_tree_head_signature(
+            config,
+            auditor_head,
+            root,
+            verifying_key,
+            Some(verifying_key),
+        )
+    }
+}
+
 /// Checks if the consistency proof against the baseline tree head needs to be
 /// verified and if it does, returns a function that performs the verification.
 ///
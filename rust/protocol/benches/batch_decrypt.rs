@@ -0,0 +1,189 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Compares draining a queue of messages with `message_decrypt` one at a time against
+//! `message_decrypt_batch`.
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::TryRngCore as _;
+use rand::rngs::OsRng;
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+const SENDERS: u32 = 4;
+const MESSAGES_PER_SENDER: usize = 25;
+
+/// Bob's sessions with each sender, and a queue of messages from them, interleaved.
+struct Queue {
+    sessions: Vec<(ProtocolAddress, SessionRecord)>,
+    messages: Vec<(ProtocolAddress, CiphertextMessage)>,
+}
+
+fn make_queue() -> Result<Queue, SignalProtocolError> {
+    let bob_address = ProtocolAddress::new("+14158888888".to_owned(), DeviceId::new(1).unwrap());
+
+    let mut sessions = vec![];
+    let mut messages_by_sender = vec![];
+    for i in 0..SENDERS {
+        let sender_address =
+            ProtocolAddress::new(format!("+1415999999{i}"), DeviceId::new(1).unwrap());
+        let (sender_session, bob_session) = support::initialize_sessions_v4()?;
+        let mut sender_store = support::test_in_memory_protocol_store()?;
+        sender_store
+            .store_session(&bob_address, &sender_session)
+            .now_or_never()
+            .expect("sync")?;
+
+        let messages = (0..MESSAGES_PER_SENDER)
+            .map(|_| {
+                support::encrypt(&mut sender_store, &bob_address, "a short message")
+                    .now_or_never()
+                    .expect("sync")
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        sessions.push((sender_address.clone(), bob_session));
+        messages_by_sender.push((sender_address, messages));
+    }
+
+    let mut messages = vec![];
+    let mut iters = messages_by_sender
+        .into_iter()
+        .map(|(address, messages)| (address, messages.into_iter()))
+        .collect::<Vec<_>>();
+    for _ in 0..MESSAGES_PER_SENDER {
+        for (address, sender_messages) in &mut iters {
+            messages.push((
+                address.clone(),
+                sender_messages.next().expect("enough messages"),
+            ));
+        }
+    }
+
+    Ok(Queue { sessions, messages })
+}
+
+pub fn in_memory(c: &mut Criterion) {
+    let queue = make_queue().expect("success");
+    let mut bob_store = support::test_in_memory_protocol_store().expect("success");
+    for (address, session) in &queue.sessions {
+        bob_store
+            .store_session(address, session)
+            .now_or_never()
+            .expect("sync")
+            .expect("success");
+    }
+
+    let mut group = c.benchmark_group("decrypting a queue in memory");
+    group.bench_function("one at a time", |b| {
+        b.iter_batched(
+            || bob_store.clone(),
+            |mut bob_store| {
+                for (address, message) in &queue.messages {
+                    support::decrypt(&mut bob_store, address, message)
+                        .now_or_never()
+                        .expect("sync")
+                        .expect("success");
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("as a batch", |b| {
+        b.iter_batched(
+            || bob_store.clone(),
+            |mut bob_store| {
+                let results = message_decrypt_batch(
+                    &queue.messages,
+                    &mut bob_store.session_store,
+                    &mut bob_store.identity_store,
+                    &mut bob_store.pre_key_store,
+                    &bob_store.signed_pre_key_store,
+                    &mut bob_store.kyber_pre_key_store,
+                    &mut OsRng.unwrap_err(),
+                )
+                .now_or_never()
+                .expect("sync");
+                assert!(results.iter().all(Result::is_ok));
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+#[cfg(feature = "sqlite")]
+pub fn sqlite(c: &mut Criterion) {
+    let queue = make_queue().expect("success");
+    let identity_key_pair = IdentityKeyPair::generate(&mut OsRng.unwrap_err());
+    // Every iteration needs its own copy of the database, since the stores share a connection.
+    let open_bob_store = || {
+        let mut bob_store = SqliteSignalProtocolStore::open_in_memory(identity_key_pair, 1)
+            .expect("can open store");
+        for (address, session) in &queue.sessions {
+            bob_store
+                .store_session(address, session)
+                .now_or_never()
+                .expect("sync")
+                .expect("success");
+        }
+        bob_store
+    };
+
+    let mut group = c.benchmark_group("decrypting a queue in SQLite");
+    group.bench_function("one at a time", |b| {
+        b.iter_batched(
+            open_bob_store,
+            |mut bob_store| {
+                for (address, message) in &queue.messages {
+                    message_decrypt(
+                        message,
+                        address,
+                        &mut bob_store.session_store,
+                        &mut bob_store.identity_store,
+                        &mut bob_store.pre_key_store,
+                        &bob_store.signed_pre_key_store,
+                        &mut bob_store.kyber_pre_key_store,
+                        &mut OsRng.unwrap_err(),
+                    )
+                    .now_or_never()
+                    .expect("sync")
+                    .expect("success");
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("as a batch", |b| {
+        b.iter_batched(
+            open_bob_store,
+            |mut bob_store| {
+                let results = message_decrypt_batch(
+                    &queue.messages,
+                    &mut bob_store.session_store,
+                    &mut bob_store.identity_store,
+                    &mut bob_store.pre_key_store,
+                    &bob_store.signed_pre_key_store,
+                    &mut bob_store.kyber_pre_key_store,
+                    &mut OsRng.unwrap_err(),
+                )
+                .now_or_never()
+                .expect("sync");
+                assert!(results.iter().all(Result::is_ok));
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+#[cfg(not(feature = "sqlite"))]
+criterion_group!(benches, in_memory);
+#[cfg(feature = "sqlite")]
+criterion_group!(benches, in_memory, sqlite);
+
+criterion_main!(benches);
@@ -222,7 +222,7 @@ pub fn v2(c: &mut Criterion) {
     let recipient_counts: &[usize] = if cfg!(debug_assertions) {
         &[50]
     } else {
-        &[2, 5, 10, 100, 1000]
+        &[2, 5, 10, 100, 1000, 5000]
     };
 
     // Fill out additional recipients.
@@ -283,6 +283,52 @@ pub fn v2(c: &mut Criterion) {
     }
     group.finish();
 
+    // The streaming API writes to a sink instead of building the message in memory, and lets the
+    // caller choose how the work is spread out.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .expect("can create thread pool");
+    let mut group = c.benchmark_group("v2/encrypt/multi-recipient-to-writer");
+    for &recipient_count in recipient_counts {
+        for (name, parallelism) in [
+            ("single-threaded", SealedSenderV2Parallelism::SingleThreaded),
+            ("global-pool", SealedSenderV2Parallelism::GlobalThreadPool),
+            (
+                "4-thread-pool",
+                SealedSenderV2Parallelism::ThreadPool(&pool),
+            ),
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, recipient_count),
+                &recipient_count,
+                |b, &recipient_count| {
+                    let recipients: Vec<_> = recipients.iter().take(recipient_count).collect();
+                    let sessions = alice_store
+                        .session_store
+                        .load_existing_sessions(&recipients)
+                        .expect("present");
+                    b.iter(|| {
+                        sealed_sender_multi_recipient_encrypt_to_writer(
+                            &recipients,
+                            &sessions,
+                            [],
+                            &usmc,
+                            &alice_store.identity_store,
+                            parallelism,
+                            &mut std::io::sink(),
+                            &mut rng,
+                        )
+                        .now_or_never()
+                        .expect("sync")
+                        .expect("valid")
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+
     let mut group = c.benchmark_group("v2/encrypt/multi-device");
     for device_count in [2, 5, 10] {
         group.bench_with_input(
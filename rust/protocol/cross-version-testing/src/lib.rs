@@ -3,11 +3,18 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+//! Runs the same conversations between the current code and pinned previous releases.
+//!
+//! To pin another release, add it as a dependency in Cargo.toml, wrap it in a module like
+//! `src/v70.rs`, and add it to [`PREVIOUS_RELEASES`]. Then generate its golden records (see
+//! `tests/golden_records.rs`) and check them in.
+
 #![allow(clippy::new_without_default)]
 
 pub use libsignal_protocol_current::{
     CiphertextMessageType, PreKeyBundle, UnidentifiedSenderMessageContent,
 };
+use uuid::Uuid;
 
 pub trait LibSignalProtocolStore {
     fn version(&self) -> &'static str;
@@ -27,8 +34,30 @@ pub trait LibSignalProtocolStore {
         msg: &UnidentifiedSenderMessageContent,
     ) -> Vec<u8>;
     fn decrypt_sealed_sender(&self, msg: &[u8]) -> UnidentifiedSenderMessageContent;
+
+    /// `sender` is the name this store sends as.
+    fn create_sender_key_distribution_message(
+        &mut self,
+        sender: &str,
+        distribution_id: Uuid,
+    ) -> Vec<u8>;
+    fn process_sender_key_distribution_message(&mut self, sender: &str, msg: &[u8]);
+    /// `sender` is the name this store sends as.
+    fn group_encrypt(&mut self, sender: &str, distribution_id: Uuid, msg: &[u8]) -> Vec<u8>;
+    fn group_decrypt(&mut self, sender: &str, msg: &[u8]) -> Vec<u8>;
+
+    /// The serialized [`SessionRecord`](libsignal_protocol_current::SessionRecord) for `remote`.
+    fn session_record(&self, remote: &str) -> Vec<u8>;
+    /// The serialized [`SenderKeyRecord`](libsignal_protocol_current::SenderKeyRecord) for
+    /// `sender`.
+    fn sender_key_record(&mut self, sender: &str, distribution_id: Uuid) -> Vec<u8>;
 }
 
+pub type StoreMaker = fn() -> Box<dyn LibSignalProtocolStore>;
+
+/// Every pinned previous release.
+pub const PREVIOUS_RELEASES: &[StoreMaker] = &[|| Box::new(LibSignalProtocolV70::new())];
+
 mod current;
 pub use current::LibSignalProtocolCurrent;
 
@@ -43,37 +72,27 @@ pub fn init_test_logger() {
         .try_init();
 }
 
+/// Runs `f` with Alice and Bob on every ordered pair of the current code and `make_previous`.
+///
+/// That covers each pair of versions in both directions, as well as each version talking to
+/// itself, which checks that the test is correct.
 pub fn try_all_combinations(
     f: fn(&mut dyn LibSignalProtocolStore, &mut dyn LibSignalProtocolStore),
-    make_previous: &[fn() -> Box<dyn LibSignalProtocolStore>],
+    make_previous: &[StoreMaker],
 ) {
-    let run = |alice_store: &mut dyn LibSignalProtocolStore,
-               bob_store: &mut dyn LibSignalProtocolStore| {
-        log::info!(
-            "alice: {}, bob: {}",
-            alice_store.version(),
-            bob_store.version()
-        );
-        f(alice_store, bob_store)
-    };
-
-    // Current<->Current, to test that the test is correct.
-    run(
-        &mut LibSignalProtocolCurrent::new(),
-        &mut LibSignalProtocolCurrent::new(),
-    );
-
-    // Current<->Previous
-    for bob_store_maker in make_previous {
-        let mut alice_store = LibSignalProtocolCurrent::new();
-        let mut bob_store = bob_store_maker();
-        run(&mut alice_store, &mut *bob_store);
-    }
+    let make_current: StoreMaker = || Box::new(LibSignalProtocolCurrent::new());
+    let all_makers = std::iter::once(&make_current).chain(make_previous);
 
-    // Previous<->Current
-    for alice_store_maker in make_previous {
-        let mut alice_store = alice_store_maker();
-        let mut bob_store = LibSignalProtocolCurrent::new();
-        run(&mut *alice_store, &mut bob_store);
+    for alice_store_maker in all_makers.clone() {
+        for bob_store_maker in all_makers.clone() {
+            let mut alice_store = alice_store_maker();
+            let mut bob_store = bob_store_maker();
+            log::info!(
+                "alice: {}, bob: {}",
+                alice_store.version(),
+                bob_store.version()
+            );
+            f(&mut *alice_store, &mut *bob_store)
+        }
     }
 }
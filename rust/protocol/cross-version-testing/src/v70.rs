@@ -8,6 +8,7 @@ use std::time::SystemTime;
 use futures_util::FutureExt;
 use libsignal_protocol_v70::*;
 use rand_v8::{Rng, thread_rng};
+use uuid::Uuid;
 
 fn address(id: &str) -> ProtocolAddress {
     ProtocolAddress::new(id.into(), 1.into())
@@ -280,6 +281,79 @@ impl super::LibSignalProtocolStore for LibSignalProtocolV70 {
         )
         .expect("compatible serialization")
     }
+
+    fn create_sender_key_distribution_message(
+        &mut self,
+        sender: &str,
+        distribution_id: Uuid,
+    ) -> Vec<u8> {
+        create_sender_key_distribution_message(
+            &address(sender),
+            distribution_id,
+            &mut self.0,
+            &mut thread_rng(),
+        )
+        .now_or_never()
+        .expect("synchronous")
+        .expect("can create distribution messages")
+        .serialized()
+        .to_vec()
+    }
+
+    fn process_sender_key_distribution_message(&mut self, sender: &str, msg: &[u8]) {
+        process_sender_key_distribution_message(
+            &address(sender),
+            &SenderKeyDistributionMessage::try_from(msg).expect("valid"),
+            &mut self.0,
+        )
+        .now_or_never()
+        .expect("synchronous")
+        .expect("can process distribution messages")
+    }
+
+    fn group_encrypt(&mut self, sender: &str, distribution_id: Uuid, msg: &[u8]) -> Vec<u8> {
+        group_encrypt(
+            &mut self.0,
+            &address(sender),
+            distribution_id,
+            msg,
+            &mut thread_rng(),
+        )
+        .now_or_never()
+        .expect("synchronous")
+        .expect("can encrypt messages")
+        .serialized()
+        .to_vec()
+    }
+
+    fn group_decrypt(&mut self, sender: &str, msg: &[u8]) -> Vec<u8> {
+        group_decrypt(msg, &mut self.0, &address(sender))
+            .now_or_never()
+            .expect("synchronous")
+            .expect("can decrypt messages")
+    }
+
+    fn session_record(&self, remote: &str) -> Vec<u8> {
+        self.0
+            .load_session(&address(remote))
+            .now_or_never()
+            .expect("synchronous")
+            .expect("can fetch sessions")
+            .expect("session established")
+            .serialize()
+            .expect("can serialize")
+    }
+
+    fn sender_key_record(&mut self, sender: &str, distribution_id: Uuid) -> Vec<u8> {
+        self.0
+            .load_sender_key(&address(sender), distribution_id)
+            .now_or_never()
+            .expect("synchronous")
+            .expect("can fetch sender keys")
+            .expect("sender key established")
+            .serialize()
+            .expect("can serialize")
+    }
 }
 
 trait ConvertVersion {
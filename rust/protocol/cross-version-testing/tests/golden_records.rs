@@ -0,0 +1,120 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Checks that records and messages written by each previous release can still be read.
+//!
+//! The records are checked in under `fixtures/<version>/`. After pinning a new release, generate
+//! them with
+//!
+//! ```sh
+//! UPDATE_GOLDEN_RECORDS=1 cargo test --test golden_records
+//! ```
+//!
+//! A release in [`PREVIOUS_RELEASES`] without fixtures fails this test, so pinning a release and
+//! checking in its fixtures go in the same change.
+//!
+//! Fixtures for releases that already have them should never be regenerated; they stand for what
+//! users of that release have on disk.
+
+use std::path::{Path, PathBuf};
+
+use libsignal_protocol_cross_version_testing::*;
+use libsignal_protocol_current::{PreKeySignalMessage, SenderKeyRecord, SessionRecord};
+
+const DISTRIBUTION_ID: uuid::Uuid = uuid::uuid!("d1d1d1d1-7000-11eb-b32a-33b8a8a487a6");
+
+const SESSION_RECORD: &str = "session_record.bin";
+const SENDER_KEY_RECORD: &str = "sender_key_record.bin";
+const PRE_KEY_SIGNAL_MESSAGE: &str = "pre_key_signal_message.bin";
+
+fn fixtures_dir(version: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(version)
+}
+
+/// Has `make_store`'s release set up a session and a sender key, and writes out what it stored.
+fn generate(make_store: StoreMaker, dir: &Path) {
+    let alice_name = "alice";
+    let bob_name = "bob";
+    let mut alice_store = make_store();
+    let mut bob_store = make_store();
+
+    let bob_pre_key_bundle = bob_store.create_pre_key_bundle();
+    alice_store.process_pre_key_bundle(bob_name, bob_pre_key_bundle);
+    let (pre_key_message, message_type) = alice_store.encrypt(bob_name, b"golden");
+    assert_eq!(message_type, CiphertextMessageType::PreKey);
+    bob_store.decrypt(alice_name, &pre_key_message, message_type);
+    let (reply, reply_type) = bob_store.encrypt(alice_name, b"records");
+    alice_store.decrypt(bob_name, &reply, reply_type);
+
+    alice_store.create_sender_key_distribution_message(alice_name, DISTRIBUTION_ID);
+    alice_store.group_encrypt(alice_name, DISTRIBUTION_ID, b"golden records");
+
+    std::fs::create_dir_all(dir).expect("can create fixtures directory");
+    for (name, contents) in [
+        (SESSION_RECORD, alice_store.session_record(bob_name)),
+        (
+            SENDER_KEY_RECORD,
+            alice_store.sender_key_record(alice_name, DISTRIBUTION_ID),
+        ),
+        (PRE_KEY_SIGNAL_MESSAGE, pre_key_message),
+    ] {
+        std::fs::write(dir.join(name), contents).expect("can write fixture");
+    }
+}
+
+fn read_fixture(dir: &Path, name: &str) -> Vec<u8> {
+    std::fs::read(dir.join(name)).unwrap_or_else(|e| {
+        panic!(
+            "missing golden record {}: {e}; see tests/golden_records.rs for how to generate it",
+            dir.join(name).display()
+        )
+    })
+}
+
+#[test]
+fn golden_records_are_readable() {
+    let update = std::env::var_os("UPDATE_GOLDEN_RECORDS").is_some();
+
+    for make_store in PREVIOUS_RELEASES {
+        let version = make_store().version();
+        let dir = fixtures_dir(version);
+        if update && !dir.exists() {
+            generate(*make_store, &dir);
+        }
+        log::info!("checking golden records for {version}");
+
+        let session = SessionRecord::deserialize(&read_fixture(&dir, SESSION_RECORD))
+            .unwrap_or_else(|e| panic!("{version} session record: {e}"));
+        let session = session
+            .describe()
+            .unwrap_or_else(|e| panic!("{version} session record: {e}"));
+        let current = session
+            .current_session
+            .unwrap_or_else(|| panic!("{version} session record has no current session"));
+        assert!(current.remote_identity_key.is_some(), "{version}");
+        assert!(current.sender_chain.is_some(), "{version}");
+        assert!(!current.receiver_chains.is_empty(), "{version}");
+
+        let sender_key = SenderKeyRecord::deserialize(&read_fixture(&dir, SENDER_KEY_RECORD))
+            .unwrap_or_else(|e| panic!("{version} sender key record: {e}"))
+            .describe();
+        assert_eq!(sender_key.states.len(), 1, "{version}");
+        assert_eq!(sender_key.states[0].iteration, Some(1), "{version}");
+        assert!(sender_key.states[0].has_signing_private_key, "{version}");
+
+        let message =
+            PreKeySignalMessage::try_from(read_fixture(&dir, PRE_KEY_SIGNAL_MESSAGE).as_slice())
+                .unwrap_or_else(|e| panic!("{version} pre-key message: {e}"))
+                .describe();
+        assert!(message.kyber_pre_key_id.is_some(), "{version}");
+        assert_eq!(
+            message.identity_key,
+            current.local_identity_key.expect("has local identity"),
+            "{version}"
+        );
+    }
+}
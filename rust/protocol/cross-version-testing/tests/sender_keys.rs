@@ -0,0 +1,60 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use libsignal_protocol_cross_version_testing::*;
+
+const DISTRIBUTION_ID: uuid::Uuid = uuid::uuid!("d1d1d1d1-7000-11eb-b32a-33b8a8a487a6");
+
+#[test]
+fn test_group_messages() {
+    try_all_combinations(run, PREVIOUS_RELEASES);
+
+    fn run(
+        alice_store: &mut dyn LibSignalProtocolStore,
+        bob_store: &mut dyn LibSignalProtocolStore,
+    ) {
+        let alice_name = "alice";
+        let bob_name = "bob";
+
+        let alice_distribution =
+            alice_store.create_sender_key_distribution_message(alice_name, DISTRIBUTION_ID);
+        bob_store.process_sender_key_distribution_message(alice_name, &alice_distribution);
+        let bob_distribution =
+            bob_store.create_sender_key_distribution_message(bob_name, DISTRIBUTION_ID);
+        alice_store.process_sender_key_distribution_message(bob_name, &bob_distribution);
+
+        for i in 0..10 {
+            let alice_ptext = format!("A->group message {i}");
+            let alice_message =
+                alice_store.group_encrypt(alice_name, DISTRIBUTION_ID, alice_ptext.as_bytes());
+            assert_eq!(
+                bob_store.group_decrypt(alice_name, &alice_message),
+                alice_ptext.as_bytes()
+            );
+
+            let bob_ptext = format!("B->group message {i}");
+            let bob_message =
+                bob_store.group_encrypt(bob_name, DISTRIBUTION_ID, bob_ptext.as_bytes());
+            assert_eq!(
+                alice_store.group_decrypt(bob_name, &bob_message),
+                bob_ptext.as_bytes()
+            );
+        }
+
+        let mut alice_ooo_messages = vec![];
+        for i in 0..10 {
+            let alice_ptext = format!("A->group OOO message {i}");
+            let alice_message =
+                alice_store.group_encrypt(alice_name, DISTRIBUTION_ID, alice_ptext.as_bytes());
+            alice_ooo_messages.push((alice_ptext, alice_message));
+        }
+        for (ptext, ctext) in alice_ooo_messages.into_iter().rev() {
+            assert_eq!(
+                bob_store.group_decrypt(alice_name, &ctext),
+                ptext.as_bytes()
+            );
+        }
+    }
+}
@@ -7,7 +7,7 @@ use libsignal_protocol_cross_version_testing::*;
 
 #[test]
 fn test_basic_prekey() {
-    try_all_combinations(run, &[|| Box::new(LibSignalProtocolV70::new())]);
+    try_all_combinations(run, PREVIOUS_RELEASES);
 
     fn run(
         alice_store: &mut dyn LibSignalProtocolStore,
@@ -0,0 +1,89 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Runs the gossip simulation from the protocol tests over a range of gossip frequencies, to see
+//! how quickly a key transparency log that equivocates gets caught.
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+use clap::Parser;
+use futures_util::FutureExt;
+use support::gossip_simulation::*;
+
+#[derive(clap::Parser)]
+struct Cli {
+    /// Number of simulated clients.
+    #[arg(long, default_value_t = 50)]
+    clients: usize,
+    /// Number of random contacts each client picks.
+    #[arg(long, default_value_t = 3)]
+    contacts: usize,
+    /// Messages each client sends per round.
+    #[arg(long, default_value_t = 2)]
+    messages: usize,
+    /// Rounds between each client's fetches from the log.
+    #[arg(long, default_value_t = 4)]
+    fetch_interval: usize,
+    /// Number of clients shown the forked log.
+    #[arg(long, default_value_t = 5)]
+    victims: usize,
+    /// Number of seeds to run for each gossip probability.
+    #[arg(long, default_value_t = 10)]
+    runs: u64,
+    /// Rounds after which a fork is counted as undetected.
+    #[arg(long, default_value_t = 50)]
+    max_rounds: usize,
+    /// Chances of a message carrying gossip to try.
+    #[arg(default_values_t = [0.05, 0.1, 0.25, 0.5, 1.0])]
+    gossip_probabilities: Vec<f64>,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    println!("gossip probability\tdetected\tmean rounds\tworst rounds");
+    for gossip_probability in cli.gossip_probabilities {
+        let rounds_to_detection = (0..cli.runs)
+            .map(|seed| {
+                let config = SimulationConfig {
+                    seed,
+                    clients: cli.clients,
+                    contacts_per_client: cli.contacts,
+                    messages_per_round: cli.messages,
+                    gossip_probability,
+                    fetch_interval: cli.fetch_interval,
+                    fork: Some(Fork {
+                        round: cli.fetch_interval,
+                        victims: cli.victims,
+                    }),
+                    max_rounds: cli.max_rounds,
+                    ..Default::default()
+                };
+                simulate(&config)
+                    .now_or_never()
+                    .expect("sync")
+                    .expect("simulation runs")
+                    .rounds_to_detection()
+            })
+            .collect::<Vec<_>>();
+
+        let detected = rounds_to_detection.iter().flatten().collect::<Vec<_>>();
+        let mean = if detected.is_empty() {
+            f64::NAN
+        } else {
+            detected.iter().copied().sum::<usize>() as f64 / detected.len() as f64
+        };
+        let worst = detected
+            .iter()
+            .max()
+            .map_or_else(|| "-".to_owned(), |worst| worst.to_string());
+        println!(
+            "{gossip_probability}\t{}/{}\t{mean:.1}\t{worst}",
+            detected.len(),
+            cli.runs
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::io::Read as _;
+use std::path::PathBuf;
+
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use clap::{Parser, ValueEnum};
+use libsignal_protocol::{
+    PreKeySignalMessage, SenderKeyMessage, SenderKeyRecord, SessionRecord, SignalMessage,
+    SignalProtocolError,
+};
+
+/// Prints a summary of a serialized record or message as JSON.
+///
+/// The summary never includes keys or message contents, so it is safe to attach to bug reports.
+/// Public keys are shown as short fingerprints, which can be compared between a record and a
+/// message.
+#[derive(Debug, Parser)]
+struct Cli {
+    /// what kind of record or message the input is
+    #[arg(value_enum)]
+    kind: Kind,
+
+    /// filename to read from; reads stdin if not given
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    file: Option<PathBuf>,
+
+    /// how the input is encoded
+    #[arg(long, value_enum, default_value_t = Encoding::Raw)]
+    encoding: Encoding,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Kind {
+    SessionRecord,
+    SenderKeyRecord,
+    PreKeySignalMessage,
+    SignalMessage,
+    SenderKeyMessage,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Encoding {
+    Raw,
+    Hex,
+    Base64,
+}
+
+fn main() {
+    let Cli {
+        kind,
+        file,
+        encoding,
+    } = Cli::parse();
+
+    let input = match file {
+        Some(path) => std::fs::read(&path)
+            .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display())),
+        None => {
+            let mut input = vec![];
+            std::io::stdin()
+                .read_to_end(&mut input)
+                .expect("failed to read stdin");
+            input
+        }
+    };
+
+    let bytes = match encoding {
+        Encoding::Raw => input,
+        Encoding::Hex => {
+            hex::decode(input.trim_ascii()).unwrap_or_else(|e| panic!("invalid hex: {e}"))
+        }
+        Encoding::Base64 => BASE64_STANDARD
+            .decode(input.trim_ascii())
+            .unwrap_or_else(|e| panic!("invalid base64: {e}")),
+    };
+
+    let description = describe(kind, &bytes).unwrap_or_else(|e| panic!("invalid {kind:?}: {e}"));
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&description).expect("can serialize")
+    );
+}
+
+fn describe(kind: Kind, bytes: &[u8]) -> Result<serde_json::Value, SignalProtocolError> {
+    let description = match kind {
+        Kind::SessionRecord => serde_json::to_value(SessionRecord::deserialize(bytes)?.describe()?),
+        Kind::SenderKeyRecord => {
+            serde_json::to_value(SenderKeyRecord::deserialize(bytes)?.describe())
+        }
+        Kind::PreKeySignalMessage => {
+            serde_json::to_value(PreKeySignalMessage::try_from(bytes)?.describe())
+        }
+        Kind::SignalMessage => serde_json::to_value(SignalMessage::try_from(bytes)?.describe()),
+        Kind::SenderKeyMessage => {
+            serde_json::to_value(SenderKeyMessage::try_from(bytes)?.describe())
+        }
+    };
+    Ok(description.expect("descriptions only contain numbers and strings"))
+}
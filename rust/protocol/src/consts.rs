@@ -3,6 +3,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+//! Defaults for [`SessionPolicy`](crate::SessionPolicy).
+
 use std::time::Duration;
 
 pub const MAX_FORWARD_JUMPS: usize = 25_000;
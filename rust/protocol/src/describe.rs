@@ -0,0 +1,168 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Structured summaries of records and messages, for debugging.
+//!
+//! These are safe to log: they never include chain keys, message keys, private keys, or
+//! ciphertext. Public keys are reduced to a [`KeyFingerprint`], so the same key can be matched up
+//! across a record and a message without printing it in full.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// A short identifier for a public key: the first 8 bytes of the SHA-256 of its serialized form.
+///
+/// Serialized and displayed as hex.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KeyFingerprint([u8; 8]);
+
+impl KeyFingerprint {
+    pub(crate) fn of(serialized_key: &[u8]) -> Self {
+        let digest = Sha256::digest(serialized_key);
+        Self(
+            digest[..8]
+                .try_into()
+                .expect("SHA-256 output is longer than 8 bytes"),
+        )
+    }
+
+    /// Like [`KeyFingerprint::of`], but treats an empty field as a missing key.
+    pub(crate) fn of_optional(serialized_key: &[u8]) -> Option<Self> {
+        (!serialized_key.is_empty()).then(|| Self::of(serialized_key))
+    }
+}
+
+impl fmt::Display for KeyFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for KeyFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeyFingerprint({self})")
+    }
+}
+
+impl Serialize for KeyFingerprint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Returned by [`SessionRecord::describe`](crate::SessionRecord::describe).
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionRecordDescription {
+    pub current_session: Option<SessionStateDescription>,
+    pub archived_state_count: usize,
+    /// Most recent first.
+    pub archived_sessions: Vec<SessionStateDescription>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionStateDescription {
+    pub session_version: u32,
+    pub local_identity_key: Option<KeyFingerprint>,
+    pub remote_identity_key: Option<KeyFingerprint>,
+    pub alice_base_key: Option<KeyFingerprint>,
+    pub local_registration_id: u32,
+    pub remote_registration_id: u32,
+    pub previous_counter: u32,
+    pub sender_chain: Option<ChainDescription>,
+    /// Most recent first.
+    pub receiver_chains: Vec<ChainDescription>,
+    /// Set until the other side has responded to the session.
+    pub pending_pre_key: Option<PendingPreKeyDescription>,
+    /// Zero if the session has no post-quantum ratchet.
+    pub pq_ratchet_state_length: usize,
+    /// The size of the key transparency tree the other side last gossiped, if any.
+    pub acknowledged_gossip_tree_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChainDescription {
+    pub ratchet_key: Option<KeyFingerprint>,
+    /// The index of the next message key in the chain.
+    pub chain_index: Option<u32>,
+    /// Keys kept for messages that have not arrived yet; always zero for a sender chain.
+    pub skipped_message_keys: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PendingPreKeyDescription {
+    pub pre_key_id: Option<u32>,
+    pub signed_pre_key_id: u32,
+    pub kyber_pre_key_id: Option<u32>,
+    pub base_key: Option<KeyFingerprint>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// Returned by [`SenderKeyRecord::describe`](crate::SenderKeyRecord::describe).
+#[derive(Clone, Debug, Serialize)]
+pub struct SenderKeyRecordDescription {
+    /// Most recent first.
+    pub states: Vec<SenderKeyStateDescription>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SenderKeyStateDescription {
+    pub message_version: u32,
+    pub chain_id: u32,
+    /// The iteration of the next message key in the chain.
+    pub iteration: Option<u32>,
+    pub skipped_message_keys: usize,
+    pub signing_key: Option<KeyFingerprint>,
+    /// Whether this is our own sender key, which we can send with.
+    pub has_signing_private_key: bool,
+}
+
+/// Returned by [`SignalMessage::describe`](crate::SignalMessage::describe).
+#[derive(Clone, Debug, Serialize)]
+pub struct SignalMessageDescription {
+    pub message_version: u8,
+    pub sender_ratchet_key: KeyFingerprint,
+    pub counter: u32,
+    pub previous_counter: u32,
+    pub ciphertext_length: usize,
+    /// Zero if the message carries no post-quantum ratchet message.
+    pub pq_ratchet_length: usize,
+    pub gossip_length: usize,
+}
+
+/// Returned by [`PreKeySignalMessage::describe`](crate::PreKeySignalMessage::describe).
+#[derive(Clone, Debug, Serialize)]
+pub struct PreKeySignalMessageDescription {
+    pub message_version: u8,
+    pub registration_id: u32,
+    pub pre_key_id: Option<u32>,
+    pub signed_pre_key_id: u32,
+    pub kyber_pre_key_id: Option<u32>,
+    pub base_key: KeyFingerprint,
+    pub identity_key: KeyFingerprint,
+    pub message: SignalMessageDescription,
+}
+
+/// Returned by [`SenderKeyMessage::describe`](crate::SenderKeyMessage::describe).
+#[derive(Clone, Debug, Serialize)]
+pub struct SenderKeyMessageDescription {
+    pub message_version: u8,
+    #[serde(serialize_with = "serialize_display")]
+    pub distribution_id: Uuid,
+    pub chain_id: u32,
+    pub iteration: u32,
+    pub ciphertext_length: usize,
+    pub gossip_length: usize,
+}
+
+fn serialize_display<S: Serializer>(
+    value: &impl fmt::Display,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
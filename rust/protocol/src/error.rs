@@ -67,6 +67,10 @@ pub enum SignalProtocolError {
     InvalidSessionStructure(&'static str),
     /// invalid sender key session with distribution ID {distribution_id}
     InvalidSenderKeySession { distribution_id: Uuid },
+    /// invalid session archive: {0}
+    InvalidSessionArchive(&'static str),
+    /// session archive is older than the stored state for {0}
+    StaleSessionArchive(crate::ProtocolAddress),
     /// session for {0} has invalid registration ID {1:X}
     InvalidRegistrationId(crate::ProtocolAddress, u32),
 
@@ -74,6 +78,8 @@ pub enum SignalProtocolError {
     DuplicatedMessage(u32, u32),
     /// invalid {0:?} message: {1}
     InvalidMessage(crate::CiphertextMessageType, &'static str),
+    /// base key was already used with Kyber pre-key {0} and signed pre-key {1}
+    ReusedBaseKey(crate::KyberPreKeyId, crate::SignedPreKeyId),
 
     /// error while invoking an ffi callback: {0}
     FfiBindingError(String),
@@ -91,6 +97,8 @@ pub enum SignalProtocolError {
     SealedSenderSelfSend,
     /// unknown server certificate ID: {0}
     UnknownSealedSenderServerCertificateId(u32),
+    /// failed to write output: {0}
+    Io(#[source] std::io::Error),
 
     /// bad KEM key type <{0:#04x}>
     BadKEMKeyType(u8),
@@ -11,7 +11,7 @@ use sha2::Sha512;
 use sha2::digest::Digest;
 use subtle::ConstantTimeEq;
 
-use crate::{IdentityKey, proto};
+use crate::{IdentityKey, ServiceId, proto};
 
 #[derive(Debug, displaydoc::Display)]
 pub enum Error {
@@ -21,6 +21,8 @@ pub enum Error {
     ParsingError(&'static str),
     /// Invalid fingerprint iterations {0}
     InvalidIterationCount(u32),
+    /// no identity keys to compute a fingerprint for
+    NoIdentityKeys,
 }
 
 #[derive(Debug, Clone)]
@@ -77,6 +79,7 @@ pub struct ScannableFingerprint {
     version: u32,
     local_fingerprint: Vec<u8>,
     remote_fingerprint: Vec<u8>,
+    key_transparency_root: Option<Vec<u8>>,
 }
 
 impl ScannableFingerprint {
@@ -85,9 +88,18 @@ impl ScannableFingerprint {
             version,
             local_fingerprint: local_fprint[..32].to_vec(),
             remote_fingerprint: remote_fprint[..32].to_vec(),
+            key_transparency_root: None,
         }
     }
 
+    /// The key transparency tree root included by whoever displayed this fingerprint, if any.
+    ///
+    /// After a successful [`compare`](Self::compare), the scanning device can check that the
+    /// identity keys it has are the ones in the key transparency log as of this root.
+    pub fn key_transparency_root(&self) -> Option<&[u8]> {
+        self.key_transparency_root.as_deref()
+    }
+
     pub fn deserialize(protobuf: &[u8]) -> Result<Self, Error> {
         let fingerprint = proto::fingerprint::CombinedFingerprints::decode(protobuf)
             .map_err(|_| Error::ParsingError("failed to decode protobuf"))?;
@@ -104,6 +116,7 @@ impl ScannableFingerprint {
                 .remote_fingerprint
                 .and_then(|m| m.content)
                 .ok_or(Error::ParsingError("missing remote fingerprint"))?,
+            key_transparency_root: fingerprint.key_transparency_root,
         })
     }
 
@@ -116,6 +129,7 @@ impl ScannableFingerprint {
             remote_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
                 content: Some(self.remote_fingerprint.to_owned()),
             }),
+            key_transparency_root: self.key_transparency_root.clone(),
         };
 
         Ok(combined_fingerprints.encode_to_vec())
@@ -158,17 +172,26 @@ pub struct Fingerprint {
 }
 
 impl Fingerprint {
+    /// The version used by [`Fingerprint::for_service_ids`].
+    pub const SERVICE_ID_VERSION: u32 = 2;
+    /// The number of iterations used by [`Fingerprint::for_service_ids`].
+    pub const SERVICE_ID_ITERATIONS: u32 = 5200;
+
     fn get_fingerprint(
         iterations: u32,
         local_id: &[u8],
-        local_key: &IdentityKey,
+        local_keys: &[IdentityKey],
     ) -> Result<Vec<u8>, Error> {
         if iterations <= 1 || iterations > 1000000 {
             return Err(Error::InvalidIterationCount(iterations));
         }
+        if local_keys.is_empty() {
+            return Err(Error::NoIdentityKeys);
+        }
 
         let fingerprint_version = [0u8, 0u8]; // 0x0000
-        let key_bytes = local_key.serialize();
+        // A single key is hashed on its own, so combining keys doesn't change existing fingerprints.
+        let key_bytes: Vec<u8> = local_keys.iter().flat_map(|key| key.serialize()).collect();
 
         let mut sha512 = Sha512::new();
 
@@ -199,8 +222,10 @@ impl Fingerprint {
         remote_id: &[u8],
         remote_key: &IdentityKey,
     ) -> Result<Fingerprint, Error> {
-        let local_fingerprint = Fingerprint::get_fingerprint(iterations, local_id, local_key)?;
-        let remote_fingerprint = Fingerprint::get_fingerprint(iterations, remote_id, remote_key)?;
+        let local_fingerprint =
+            Fingerprint::get_fingerprint(iterations, local_id, std::slice::from_ref(local_key))?;
+        let remote_fingerprint =
+            Fingerprint::get_fingerprint(iterations, remote_id, std::slice::from_ref(remote_key))?;
 
         Ok(Fingerprint {
             display: DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)?,
@@ -208,6 +233,48 @@ impl Fingerprint {
         })
     }
 
+    /// The safety number between two accounts, identified by their service IDs (normally ACIs).
+    ///
+    /// Each side may cover more than one identity key, such as every key an account has had that
+    /// was verified through key transparency, listed oldest first. Both devices must use the same
+    /// keys in the same order to get the same safety number. With a single key per side, this is
+    /// the same as [`Fingerprint::new`] with [`SERVICE_ID_VERSION`](Self::SERVICE_ID_VERSION),
+    /// [`SERVICE_ID_ITERATIONS`](Self::SERVICE_ID_ITERATIONS), and the service IDs' binary form.
+    ///
+    /// If `key_transparency_root` is given, it is included in the scannable fingerprint so the
+    /// scanning device can check the keys against the same state of the key transparency log.
+    /// Clients that don't know about it can still scan and compare the fingerprint.
+    pub fn for_service_ids(
+        local_id: ServiceId,
+        local_keys: &[IdentityKey],
+        remote_id: ServiceId,
+        remote_keys: &[IdentityKey],
+        key_transparency_root: Option<&[u8]>,
+    ) -> Result<Fingerprint, Error> {
+        let local_fingerprint = Fingerprint::get_fingerprint(
+            Self::SERVICE_ID_ITERATIONS,
+            &local_id.service_id_binary(),
+            local_keys,
+        )?;
+        let remote_fingerprint = Fingerprint::get_fingerprint(
+            Self::SERVICE_ID_ITERATIONS,
+            &remote_id.service_id_binary(),
+            remote_keys,
+        )?;
+
+        let mut scannable = ScannableFingerprint::new(
+            Self::SERVICE_ID_VERSION,
+            &local_fingerprint,
+            &remote_fingerprint,
+        );
+        scannable.key_transparency_root = key_transparency_root.map(<[u8]>::to_vec);
+
+        Ok(Fingerprint {
+            display: DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)?,
+            scannable,
+        })
+    }
+
     pub fn display_string(&self) -> Result<String, Error> {
         Ok(self.display.to_string())
     }
@@ -570,4 +637,132 @@ mod test {
 
         Ok(())
     }
+
+    const ALICE_ACI: uuid::Uuid = uuid::Uuid::from_u128(0x9d0652a3_dcc3_4d11_975f_74d61598733f);
+    const BOB_ACI: uuid::Uuid = uuid::Uuid::from_u128(0x796abedb_ca4e_4f18_8803_1fde5b921f9f);
+
+    fn aci(uuid: uuid::Uuid) -> ServiceId {
+        crate::Aci::from(uuid).into()
+    }
+
+    #[test]
+    fn fingerprint_for_service_ids_matches_v2() -> Result<(), Error> {
+        let a_key = IdentityKey::decode(ALICE_IDENTITY).expect("valid");
+        let b_key = IdentityKey::decode(BOB_IDENTITY).expect("valid");
+
+        let a_fprint =
+            Fingerprint::for_service_ids(aci(ALICE_ACI), &[a_key], aci(BOB_ACI), &[b_key], None)?;
+        let a_fprint_v2 = Fingerprint::new(
+            2,
+            5200,
+            ALICE_ACI.as_bytes(),
+            &a_key,
+            BOB_ACI.as_bytes(),
+            &b_key,
+        )?;
+
+        assert_eq!(a_fprint.display_string()?, a_fprint_v2.display_string()?);
+        assert_eq!(
+            a_fprint.scannable.serialize()?,
+            a_fprint_v2.scannable.serialize()?
+        );
+        assert_eq!(a_fprint.scannable.key_transparency_root(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_with_key_transparency_root() -> Result<(), Error> {
+        let a_key = IdentityKey::decode(ALICE_IDENTITY).expect("valid");
+        let b_key = IdentityKey::decode(BOB_IDENTITY).expect("valid");
+        let root = [0x42; 32];
+
+        let a_fprint = Fingerprint::for_service_ids(
+            aci(ALICE_ACI),
+            &[a_key],
+            aci(BOB_ACI),
+            &[b_key],
+            Some(&root),
+        )?;
+        let b_fprint =
+            Fingerprint::for_service_ids(aci(BOB_ACI), &[b_key], aci(ALICE_ACI), &[a_key], None)?;
+
+        // The root doesn't affect whether the fingerprints match, in either direction.
+        let scanned = a_fprint.scannable.serialize()?;
+        assert!(b_fprint.scannable.compare(&scanned)?);
+        assert!(
+            a_fprint
+                .scannable
+                .compare(&b_fprint.scannable.serialize()?)?
+        );
+        assert_eq!(
+            ScannableFingerprint::deserialize(&scanned)?.key_transparency_root(),
+            Some(&root[..])
+        );
+
+        // Fingerprints from before the root was added can still be read.
+        let old = ScannableFingerprint::deserialize(
+            &hex::decode(BOB_SCANNABLE_FINGERPRINT_V2).expect("valid hex"),
+        )?;
+        assert_eq!(old.key_transparency_root(), None);
+        assert_eq!(hex::encode(old.serialize()?), BOB_SCANNABLE_FINGERPRINT_V2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_over_several_keys() -> Result<(), Error> {
+        use rand::rngs::OsRng;
+
+        use crate::IdentityKeyPair;
+
+        let a_old_key = *IdentityKeyPair::generate(&mut OsRng.unwrap_err()).identity_key();
+        let a_key = *IdentityKeyPair::generate(&mut OsRng.unwrap_err()).identity_key();
+        let b_key = *IdentityKeyPair::generate(&mut OsRng.unwrap_err()).identity_key();
+
+        let a_fprint = Fingerprint::for_service_ids(
+            aci(ALICE_ACI),
+            &[a_old_key, a_key],
+            aci(BOB_ACI),
+            &[b_key],
+            None,
+        )?;
+        let b_fprint = Fingerprint::for_service_ids(
+            aci(BOB_ACI),
+            &[b_key],
+            aci(ALICE_ACI),
+            &[a_old_key, a_key],
+            None,
+        )?;
+        assert_eq!(a_fprint.display_string()?, b_fprint.display_string()?);
+        assert!(
+            b_fprint
+                .scannable
+                .compare(&a_fprint.scannable.serialize()?)?
+        );
+
+        // Covering only the latest key, or the keys in a different order, is a different number.
+        for b_view_of_a in [&[a_key][..], &[a_key, a_old_key]] {
+            let b_fprint = Fingerprint::for_service_ids(
+                aci(BOB_ACI),
+                &[b_key],
+                aci(ALICE_ACI),
+                b_view_of_a,
+                None,
+            )?;
+            assert_ne!(a_fprint.display_string()?, b_fprint.display_string()?);
+            assert!(
+                !b_fprint
+                    .scannable
+                    .compare(&a_fprint.scannable.serialize()?)?
+            );
+        }
+
+        assert!(matches!(
+            Fingerprint::for_service_ids(aci(ALICE_ACI), &[], aci(BOB_ACI), &[b_key], None),
+            Err(Error::NoIdentityKeys)
+        ));
+
+        Ok(())
+    }
 }
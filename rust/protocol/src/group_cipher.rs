@@ -3,14 +3,19 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::time::SystemTime;
+
+use libsignal_gossip::GossipService;
 use rand::{CryptoRng, Rng};
 use uuid::Uuid;
 
 use crate::protocol::SENDERKEY_MESSAGE_CURRENT_VERSION;
 use crate::sender_keys::{SenderKeyState, SenderMessageKey};
+use crate::session_cipher::{check_gossip, for_checking, save_checked_gossip};
 use crate::{
-    CiphertextMessageType, KeyPair, ProtocolAddress, Result, SenderKeyDistributionMessage,
-    SenderKeyMessage, SenderKeyRecord, SenderKeyStore, SignalProtocolError, consts,
+    CiphertextMessageType, GossipStatus, KeyPair, ProtocolAddress, Result,
+    SenderKeyDistributionMessage, SenderKeyMessage, SenderKeyRecord, SenderKeyStore, SessionPolicy,
+    SignalProtocolError,
 };
 
 pub async fn group_encrypt<R: Rng + CryptoRng>(
@@ -19,6 +24,52 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
     distribution_id: Uuid,
     plaintext: &[u8],
     csprng: &mut R,
+) -> Result<SenderKeyMessage> {
+    group_encrypt_impl(
+        sender_key_store,
+        sender,
+        distribution_id,
+        plaintext,
+        &[],
+        csprng,
+    )
+    .await
+}
+
+/// Like [`group_encrypt`], but attaches `gossip_service`'s current trusted tree head to the
+/// message, if it has one.
+///
+/// The gossip is covered by the sender key signature, so every member of the group can check it.
+pub async fn group_encrypt_with_gossip<R: Rng + CryptoRng>(
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    plaintext: &[u8],
+    gossip_service: &GossipService,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<SenderKeyMessage> {
+    let gossip = gossip_service.outgoing_gossip(now).map_err(
+        SignalProtocolError::for_application_callback("outgoing_gossip"),
+    )?;
+    group_encrypt_impl(
+        sender_key_store,
+        sender,
+        distribution_id,
+        plaintext,
+        gossip.as_deref().unwrap_or_default(),
+        csprng,
+    )
+    .await
+}
+
+async fn group_encrypt_impl<R: Rng + CryptoRng>(
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    plaintext: &[u8],
+    gossip: &[u8],
+    csprng: &mut R,
 ) -> Result<SenderKeyMessage> {
     let mut record = sender_key_store
         .load_sender_key(sender, distribution_id)
@@ -53,12 +104,13 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
         .signing_key_private()
         .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
 
-    let skm = SenderKeyMessage::new(
+    let skm = SenderKeyMessage::new_with_gossip(
         message_version,
         distribution_id,
         sender_key_state.chain_id(),
         message_keys.iteration(),
         ciphertext.into_boxed_slice(),
+        gossip,
         csprng,
         &signing_key,
     )?;
@@ -76,6 +128,7 @@ fn get_sender_key(
     state: &mut SenderKeyState,
     iteration: u32,
     distribution_id: Uuid,
+    policy: &SessionPolicy,
 ) -> Result<SenderMessageKey> {
     let sender_chain_key = state
         .sender_chain_key()
@@ -97,11 +150,11 @@ fn get_sender_key(
     }
 
     let jump = (iteration - current_iteration) as usize;
-    if jump > consts::MAX_FORWARD_JUMPS {
+    if jump > policy.max_forward_jumps() {
         log::error!(
             "SenderKey distribution {} Exceeded future message limit: {}, current iteration: {})",
             distribution_id,
-            consts::MAX_FORWARD_JUMPS,
+            policy.max_forward_jumps(),
             current_iteration
         );
         return Err(SignalProtocolError::InvalidMessage(
@@ -113,7 +166,7 @@ fn get_sender_key(
     let mut sender_chain_key = sender_chain_key;
 
     while sender_chain_key.iteration() < iteration {
-        state.add_sender_message_key(&sender_chain_key.sender_message_key());
+        state.add_sender_message_key(&sender_chain_key.sender_message_key(), policy);
         sender_chain_key = sender_chain_key.next()?;
     }
 
@@ -126,7 +179,36 @@ pub async fn group_decrypt(
     sender_key_store: &mut dyn SenderKeyStore,
     sender: &ProtocolAddress,
 ) -> Result<Vec<u8>> {
+    let (plaintext, _) = group_decrypt_impl(skm_bytes, sender_key_store, sender, None).await?;
+    Ok(plaintext)
+}
+
+/// Like [`group_decrypt`], but also checks any gossip attached to the message using
+/// `gossip_service`.
+pub async fn group_decrypt_with_gossip(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    gossip_service: &mut GossipService,
+    now: SystemTime,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    group_decrypt_impl(
+        skm_bytes,
+        sender_key_store,
+        sender,
+        Some((gossip_service, now)),
+    )
+    .await
+}
+
+async fn group_decrypt_impl(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    gossip: Option<(&mut GossipService, SystemTime)>,
+) -> Result<(Vec<u8>, GossipStatus)> {
     let skm = SenderKeyMessage::try_from(skm_bytes)?;
+    let policy = sender_key_store.sender_key_policy();
 
     let distribution_id = skm.distribution_id();
     let chain_id = skm.chain_id();
@@ -163,7 +245,7 @@ pub async fn group_decrypt(
         return Err(SignalProtocolError::SignatureValidationFailed);
     }
 
-    let sender_key = get_sender_key(sender_key_state, skm.iteration(), distribution_id)?;
+    let sender_key = get_sender_key(sender_key_state, skm.iteration(), distribution_id, &policy)?;
 
     let plaintext = match signal_crypto::aes_256_cbc_decrypt(
         skm.ciphertext(),
@@ -186,11 +268,14 @@ pub async fn group_decrypt(
         }
     };
 
+    let (gossip_status, checked_gossip) = check_gossip(for_checking(&gossip), sender, skm.gossip());
+
     sender_key_store
         .store_sender_key(sender, distribution_id, &record)
         .await?;
 
-    Ok(plaintext)
+    let gossip_status = save_checked_gossip(gossip, checked_gossip, sender, gossip_status).await;
+    Ok((plaintext, gossip_status))
 }
 
 pub async fn process_sender_key_distribution_message(
@@ -218,6 +303,7 @@ pub async fn process_sender_key_distribution_message(
         skdm.chain_key()?,
         *skdm.signing_key()?,
         None,
+        &sender_key_store.sender_key_policy(),
     );
     sender_key_store
         .store_sender_key(sender, distribution_id, &sender_key_record)
@@ -238,23 +324,10 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
     let sender_key_record = match sender_key_record {
         Some(record) => record,
         None => {
-            // libsignal-protocol-java uses 31-bit integers for sender key chain IDs
-            let chain_id = (csprng.random::<u32>()) >> 1;
-            log::info!(
-                "Creating SenderKey for distribution {distribution_id} with chain ID {chain_id}"
-            );
-
-            let iteration = 0;
-            let sender_key: [u8; 32] = csprng.random();
-            let signing_key = KeyPair::generate(csprng);
-            let mut record = SenderKeyRecord::new_empty();
-            record.add_sender_key_state(
-                SENDERKEY_MESSAGE_CURRENT_VERSION,
-                chain_id,
-                iteration,
-                &sender_key,
-                signing_key.public_key,
-                Some(signing_key.private_key),
+            let record = new_sender_key_record(
+                distribution_id,
+                &sender_key_store.sender_key_policy(),
+                csprng,
             );
             sender_key_store
                 .store_sender_key(sender, distribution_id, &record)
@@ -263,6 +336,61 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
         }
     };
 
+    distribution_message_for(&sender_key_record, distribution_id)
+}
+
+/// Replaces `sender`'s own sender key for `distribution_id` with a new chain, returning the
+/// distribution message for it.
+///
+/// Recipients of the previous chain can still decrypt what was already sent with it, but nothing
+/// sent afterwards, so this is what to do when a member leaves or unlinks a device. Every
+/// remaining recipient needs the new distribution message before they can decrypt again.
+pub async fn rotate_sender_key<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    csprng: &mut R,
+) -> Result<SenderKeyDistributionMessage> {
+    let record = new_sender_key_record(
+        distribution_id,
+        &sender_key_store.sender_key_policy(),
+        csprng,
+    );
+    sender_key_store
+        .store_sender_key(sender, distribution_id, &record)
+        .await?;
+    distribution_message_for(&record, distribution_id)
+}
+
+fn new_sender_key_record<R: Rng + CryptoRng>(
+    distribution_id: Uuid,
+    policy: &SessionPolicy,
+    csprng: &mut R,
+) -> SenderKeyRecord {
+    // libsignal-protocol-java uses 31-bit integers for sender key chain IDs
+    let chain_id = (csprng.random::<u32>()) >> 1;
+    log::info!("Creating SenderKey for distribution {distribution_id} with chain ID {chain_id}");
+
+    let iteration = 0;
+    let sender_key: [u8; 32] = csprng.random();
+    let signing_key = KeyPair::generate(csprng);
+    let mut record = SenderKeyRecord::new_empty();
+    record.add_sender_key_state(
+        SENDERKEY_MESSAGE_CURRENT_VERSION,
+        chain_id,
+        iteration,
+        &sender_key,
+        signing_key.public_key,
+        Some(signing_key.private_key),
+        policy,
+    );
+    record
+}
+
+fn distribution_message_for(
+    sender_key_record: &SenderKeyRecord,
+    distribution_id: Uuid,
+) -> Result<SenderKeyDistributionMessage> {
     let state = sender_key_record
         .sender_key_state()
         .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
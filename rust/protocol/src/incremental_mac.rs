@@ -8,6 +8,9 @@ use hmac::Mac;
 use hmac::digest::generic_array::{ArrayLength, GenericArray};
 use sha2::digest::{FixedOutput, MacError, Output};
 
+mod stream;
+pub use stream::{IncrementalWriter, ValidatingReader};
+
 #[derive(Clone)]
 pub struct Incremental<M: Mac + Clone> {
     mac: M,
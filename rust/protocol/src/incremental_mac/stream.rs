@@ -0,0 +1,715 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! [`Read`]/[`Write`] adapters (and their async equivalents) around [`Incremental`] and
+//! [`Validating`].
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use aes::cipher::Unsigned;
+use futures_util::{AsyncRead, AsyncSeek, AsyncWrite};
+use hmac::Mac;
+use sha2::digest::{FixedOutput, MacError, Output};
+
+use super::{Incremental, Validating, calculate_chunk_size};
+
+/// Passes everything written through to an inner writer, collecting the incremental MACs of the
+/// written bytes along the way.
+///
+/// The MACs cover the bytes exactly as they are written, so when wrapping the output of an
+/// encryption step they are computed over the ciphertext.
+pub struct IncrementalWriter<W, M: Mac + Clone> {
+    inner: W,
+    incremental: Incremental<M>,
+    digests: Vec<u8>,
+}
+
+impl<W, M: Mac + Clone> IncrementalWriter<W, M> {
+    pub fn new(inner: W, mac: M, chunk_size: usize) -> Self {
+        Self {
+            inner,
+            incremental: Incremental::new(mac, chunk_size),
+            digests: Vec::new(),
+        }
+    }
+
+    /// Uses the chunk size from [`calculate_chunk_size`] for `data_size` bytes of output.
+    pub fn for_data_size(inner: W, mac: M, data_size: usize) -> Self
+    where
+        M: FixedOutput,
+    {
+        Self::new(inner, mac, calculate_chunk_size::<M>(data_size))
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.incremental.chunk_size
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writing directly to the inner writer will leave those bytes out of the digests.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the inner writer and the concatenated digests of everything written, in the format
+    /// [`ValidatingReader`] expects.
+    ///
+    /// This does not flush or close the inner writer.
+    pub fn finalize(self) -> (W, Vec<u8>) {
+        let Self {
+            inner,
+            incremental,
+            mut digests,
+        } = self;
+        digests.extend_from_slice(&incremental.finalize());
+        (inner, digests)
+    }
+
+    fn record(&mut self, written: &[u8]) {
+        for digest in self.incremental.update(written) {
+            self.digests.extend_from_slice(&digest);
+        }
+    }
+}
+
+impl<W: Write, M: Mac + Clone> Write for IncrementalWriter<W, M> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.record(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite + Unpin, M: Mac + Clone + Unpin> AsyncWrite for IncrementalWriter<W, M> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.record(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+/// Reads from an inner reader, only producing bytes once the chunk they belong to has been
+/// validated against the expected digests.
+///
+/// A whole chunk is buffered before any of it is returned, and the final (possibly partial) chunk
+/// is only returned once the end of the inner reader has been reached and the complete MAC
+/// checks out. Any mismatch results in an [`io::ErrorKind::InvalidData`] error wrapping
+/// [`MacError`].
+///
+/// The inner reader must be positioned at the start of the data.
+///
+/// # Seeking
+///
+/// If the inner reader can seek, so can this. Because each digest covers everything up to the end
+/// of its chunk, seeking always resumes validation from a chunk boundary:
+///
+/// - seeking backwards restarts from the start of the target chunk, using the MAC state saved when
+///   that chunk was first reached;
+/// - seeking forwards past the chunks validated so far restarts from the last of them, and the
+///   chunks in between are read and validated (but not returned) on the next read.
+///
+/// Either way, no bytes are produced that haven't been validated as part of their chunk.
+pub struct ValidatingReader<R, M: Mac + Clone> {
+    inner: R,
+    // Boxed so that the reader is Unpin whenever the inner reader is.
+    state: Box<ReaderState<M>>,
+    /// The target of an in-progress [`SeekFrom::End`] seek, once the inner reader has found it.
+    pending_seek: Option<u64>,
+}
+
+struct ReaderState<M: Mac + Clone> {
+    expected: Vec<Output<M>>,
+    /// The MAC state at the start of each chunk reached so far.
+    checkpoints: Vec<Incremental<M>>,
+    /// `None` once the final MAC has been checked.
+    validating: Option<Validating<M>>,
+    chunk: Box<[u8]>,
+    chunk_index: usize,
+    /// How much of `chunk` has been read from the inner reader.
+    filled: usize,
+    /// How much of `chunk` has been validated.
+    validated: usize,
+    /// How much of `chunk` has been returned or skipped.
+    consumed: usize,
+    /// Validated bytes to discard before returning anything, to finish a seek.
+    skip: u64,
+    position: u64,
+    failed: bool,
+}
+
+enum SeekPlan {
+    WithinChunk { offset: usize },
+    FromCheckpoint { index: usize },
+}
+
+impl<R, M: Mac + Clone> ValidatingReader<R, M> {
+    /// Returns `None` if `chunk_size` is zero or `digests` is not a non-empty sequence of MACs.
+    pub fn new(inner: R, mac: M, chunk_size: usize, digests: &[u8]) -> Option<Self> {
+        let mac_size = M::OutputSize::USIZE;
+        if chunk_size == 0 || digests.is_empty() || digests.len() % mac_size != 0 {
+            return None;
+        }
+        let expected: Vec<Output<M>> = digests
+            .chunks_exact(mac_size)
+            .map(Output::<M>::clone_from_slice)
+            .collect();
+        let incremental = Incremental::new(mac, chunk_size);
+        Some(Self {
+            inner,
+            state: Box::new(ReaderState {
+                validating: Some(incremental.clone().validating(&expected)),
+                checkpoints: vec![incremental],
+                expected,
+                chunk: vec![0; chunk_size].into_boxed_slice(),
+                chunk_index: 0,
+                filled: 0,
+                validated: 0,
+                consumed: 0,
+                skip: 0,
+                position: 0,
+                failed: false,
+            }),
+            pending_seek: None,
+        })
+    }
+
+    /// Uses the chunk size from [`calculate_chunk_size`] for `data_size` bytes of input.
+    pub fn for_data_size(inner: R, mac: M, data_size: usize, digests: &[u8]) -> Option<Self>
+    where
+        M: FixedOutput,
+    {
+        Self::new(inner, mac, calculate_chunk_size::<M>(data_size), digests)
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.state.chunk.len()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<M: Mac + Clone> ReaderState<M> {
+    fn chunk_start(&self, index: usize) -> u64 {
+        index as u64 * self.chunk.len() as u64
+    }
+
+    /// Copies out validated bytes, returning `None` if more need to be read first.
+    fn read_validated(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        if self.failed {
+            return Err(mac_mismatch());
+        }
+        let available = self.validated - self.consumed;
+        let skipped = usize::try_from(self.skip)
+            .unwrap_or(usize::MAX)
+            .min(available);
+        self.consumed += skipped;
+        self.skip -= skipped as u64;
+
+        if self.consumed < self.validated {
+            let len = buf.len().min(self.validated - self.consumed);
+            buf[..len].copy_from_slice(&self.chunk[self.consumed..][..len]);
+            self.consumed += len;
+            self.position += len as u64;
+            return Ok(Some(len));
+        }
+        if self.validating.is_none() {
+            return Ok(Some(0));
+        }
+        if self.validated == self.chunk.len() {
+            self.chunk_index += 1;
+            self.filled = 0;
+            self.validated = 0;
+            self.consumed = 0;
+        }
+        Ok(None)
+    }
+
+    fn unfilled(&mut self) -> &mut [u8] {
+        &mut self.chunk[self.filled..]
+    }
+
+    fn did_fill(&mut self, len: usize) -> io::Result<()> {
+        let Some(validating) = self.validating.as_mut() else {
+            unreachable!("only reads while validating");
+        };
+        let result = if len == 0 {
+            self.validating
+                .take()
+                .expect("checked above")
+                .finalize()
+                .map(|_| self.validated = self.filled)
+        } else {
+            let new_bytes = &self.chunk[self.filled..][..len];
+            self.filled += len;
+            validating.update(new_bytes).map(|validated| {
+                if validated != 0 {
+                    self.validated = self.filled;
+                    if self.checkpoints.len() == self.chunk_index + 1 {
+                        self.checkpoints.push(validating.incremental.clone());
+                    }
+                }
+            })
+        };
+        result.map_err(|MacError| {
+            self.failed = true;
+            mac_mismatch()
+        })
+    }
+
+    fn target(&self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Start(target) => Some(target),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => unreachable!("must be resolved by the inner reader"),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })
+    }
+
+    /// Decides how to get to `target`, without changing any state.
+    ///
+    /// If `inner_moved` is set, the inner reader is no longer where this state left it, so the
+    /// current chunk can't be reused.
+    fn plan_seek(&self, target: u64, inner_moved: bool) -> SeekPlan {
+        let chunk_size = self.chunk.len() as u64;
+        let chunk_index = usize::try_from(target / chunk_size).unwrap_or(usize::MAX);
+        let offset = target % chunk_size;
+        if !inner_moved
+            && !self.failed
+            && chunk_index == self.chunk_index
+            && self.validated != 0
+            && offset <= self.validated as u64
+        {
+            return SeekPlan::WithinChunk {
+                offset: usize::try_from(offset).expect("no larger than a chunk"),
+            };
+        }
+        SeekPlan::FromCheckpoint {
+            index: chunk_index.min(self.checkpoints.len() - 1),
+        }
+    }
+
+    fn complete_seek(&mut self, target: u64, plan: SeekPlan) {
+        match plan {
+            SeekPlan::WithinChunk { offset } => {
+                self.consumed = offset;
+                self.skip = 0;
+            }
+            SeekPlan::FromCheckpoint { index } => {
+                self.validating = Some(
+                    self.checkpoints[index]
+                        .clone()
+                        .validating(&self.expected[index..]),
+                );
+                self.chunk_index = index;
+                self.filled = 0;
+                self.validated = 0;
+                self.consumed = 0;
+                self.skip = target - self.chunk_start(index);
+                self.failed = false;
+            }
+        }
+        self.position = target;
+    }
+}
+
+fn mac_mismatch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, MacError)
+}
+
+impl<R: Read, M: Mac + Clone> Read for ValidatingReader<R, M> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(len) = self.state.read_validated(buf)? {
+                return Ok(len);
+            }
+            let len = self.inner.read(self.state.unfilled())?;
+            self.state.did_fill(len)?;
+        }
+    }
+}
+
+impl<R: Read + Seek, M: Mac + Clone> Seek for ValidatingReader<R, M> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (target, inner_moved) = match pos {
+            SeekFrom::End(offset) => (self.inner.seek(SeekFrom::End(offset))?, true),
+            pos => (self.state.target(pos)?, false),
+        };
+        let plan = self.state.plan_seek(target, inner_moved);
+        if let SeekPlan::FromCheckpoint { index } = plan {
+            self.inner
+                .seek(SeekFrom::Start(self.state.chunk_start(index)))?;
+        }
+        self.state.complete_seek(target, plan);
+        Ok(target)
+    }
+}
+
+impl<R: AsyncRead + Unpin, M: Mac + Clone> AsyncRead for ValidatingReader<R, M> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let Self { inner, state, .. } = self.get_mut();
+        loop {
+            if let Some(len) = state.read_validated(buf)? {
+                return Poll::Ready(Ok(len));
+            }
+            let len = ready!(Pin::new(&mut *inner).poll_read(cx, state.unfilled()))?;
+            state.did_fill(len)?;
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin, M: Mac + Clone> AsyncSeek for ValidatingReader<R, M> {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let Self {
+            inner,
+            state,
+            pending_seek,
+        } = self.get_mut();
+        let (target, inner_moved) = match (pos, pending_seek.take()) {
+            (SeekFrom::End(_), Some(target)) => (target, true),
+            (SeekFrom::End(offset), None) => (
+                ready!(Pin::new(&mut *inner).poll_seek(cx, SeekFrom::End(offset)))?,
+                true,
+            ),
+            (pos, _) => (state.target(pos)?, false),
+        };
+        let plan = state.plan_seek(target, inner_moved);
+        if let SeekPlan::FromCheckpoint { index } = plan {
+            let start = SeekFrom::Start(state.chunk_start(index));
+            let Poll::Ready(result) = Pin::new(&mut *inner).poll_seek(cx, start) else {
+                // Don't ask the inner reader to find the end again.
+                if inner_moved {
+                    *pending_seek = Some(target);
+                }
+                return Poll::Pending;
+            };
+            result?;
+        }
+        state.complete_seek(target, plan);
+        Poll::Ready(Ok(target))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hmac::Hmac;
+    use rand::Rng as _;
+    use sha2::Sha256;
+
+    use super::*;
+
+    const TEST_HMAC_KEY: &[u8] = &[0x5a; 32];
+    const TEST_CHUNK_SIZE: usize = 32;
+
+    fn new_mac() -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(TEST_HMAC_KEY)
+            .expect("Should be able to create a new HMAC instance")
+    }
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        rand::rng().fill(&mut bytes[..]);
+        bytes
+    }
+
+    fn digests_for(bytes: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut writer = IncrementalWriter::new(Vec::new(), new_mac(), chunk_size);
+        writer.write_all(bytes).expect("can write to a Vec");
+        let (written, digests) = writer.finalize();
+        assert_eq!(written, bytes);
+        digests
+    }
+
+    fn validating_reader(
+        bytes: &[u8],
+        digests: &[u8],
+    ) -> ValidatingReader<io::Cursor<Vec<u8>>, Hmac<Sha256>> {
+        ValidatingReader::new(
+            io::Cursor::new(bytes.to_vec()),
+            new_mac(),
+            TEST_CHUNK_SIZE,
+            digests,
+        )
+        .expect("valid digests")
+    }
+
+    #[test]
+    fn writer_matches_incremental() {
+        for len in [
+            0,
+            1,
+            TEST_CHUNK_SIZE - 1,
+            TEST_CHUNK_SIZE,
+            5 * TEST_CHUNK_SIZE + 3,
+        ] {
+            let bytes = random_bytes(len);
+
+            let mut incremental = Incremental::new(new_mac(), TEST_CHUNK_SIZE);
+            let mut expected: Vec<u8> = incremental.update(&bytes).flatten().collect();
+            expected.extend(incremental.finalize());
+
+            let mut writer = IncrementalWriter::new(Vec::new(), new_mac(), TEST_CHUNK_SIZE);
+            for piece in bytes.chunks(7) {
+                writer.write_all(piece).expect("can write to a Vec");
+            }
+            assert_eq!(writer.finalize().1, expected, "length {len}");
+        }
+    }
+
+    #[test]
+    fn writer_uses_calculated_chunk_size() {
+        let data_size = 20 * 1024 * 1024;
+        let writer = IncrementalWriter::for_data_size(io::sink(), new_mac(), data_size);
+        assert_eq!(
+            writer.chunk_size(),
+            calculate_chunk_size::<Sha256>(data_size)
+        );
+    }
+
+    #[test]
+    fn reader_round_trip() {
+        for len in [0, 1, TEST_CHUNK_SIZE, 5 * TEST_CHUNK_SIZE + 3] {
+            let bytes = random_bytes(len);
+            let digests = digests_for(&bytes, TEST_CHUNK_SIZE);
+
+            let mut reader = validating_reader(&bytes, &digests);
+            let mut read = Vec::new();
+            let mut buf = [0; 7];
+            loop {
+                let n = reader.read(&mut buf).expect("valid");
+                if n == 0 {
+                    break;
+                }
+                read.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(read, bytes, "length {len}");
+        }
+    }
+
+    #[test]
+    fn reader_rejects_bad_digests() {
+        assert!(ValidatingReader::new(io::empty(), new_mac(), TEST_CHUNK_SIZE, &[]).is_none());
+        assert!(ValidatingReader::new(io::empty(), new_mac(), TEST_CHUNK_SIZE, &[0; 33]).is_none());
+        assert!(ValidatingReader::new(io::empty(), new_mac(), 0, &[0; 32]).is_none());
+    }
+
+    #[test]
+    fn reader_stops_at_the_first_bad_chunk() {
+        let bytes = random_bytes(4 * TEST_CHUNK_SIZE + 3);
+        let digests = digests_for(&bytes, TEST_CHUNK_SIZE);
+
+        for corrupted_chunk in 0..5 {
+            let mut corrupted = bytes.clone();
+            corrupted[corrupted_chunk * TEST_CHUNK_SIZE] ^= 1;
+            let mut reader = validating_reader(&corrupted, &digests);
+
+            let mut read = Vec::new();
+            let err = reader.read_to_end(&mut read).expect_err("invalid");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.get_ref().is_some_and(|e| e.is::<MacError>()));
+            // Nothing from the corrupted chunk was returned.
+            assert_eq!(read, bytes[..corrupted_chunk * TEST_CHUNK_SIZE]);
+            // And the reader stays failed.
+            assert_eq!(
+                reader.read(&mut [0; 8]).expect_err("still invalid").kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+
+        let mut truncated = validating_reader(&bytes[..bytes.len() - 1], &digests);
+        assert_eq!(
+            truncated
+                .read_to_end(&mut Vec::new())
+                .expect_err("invalid")
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut extended = bytes.clone();
+        extended.extend_from_slice(&random_bytes(TEST_CHUNK_SIZE));
+        let mut extended = validating_reader(&extended, &digests);
+        assert_eq!(
+            extended
+                .read_to_end(&mut Vec::new())
+                .expect_err("invalid")
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn reader_seeks() {
+        let bytes = random_bytes(6 * TEST_CHUNK_SIZE + 5);
+        let digests = digests_for(&bytes, TEST_CHUNK_SIZE);
+        let mut reader = validating_reader(&bytes, &digests);
+
+        let check_read_at = |reader: &mut ValidatingReader<_, _>, pos: SeekFrom| {
+            let target = reader.seek(pos).expect("can seek");
+            assert_eq!(reader.stream_position().expect("can seek"), target);
+            let start = usize::try_from(target).expect("small").min(bytes.len());
+            let mut buf = vec![0; rand::rng().random_range(1..2 * TEST_CHUNK_SIZE)];
+            let n = reader.read(&mut buf).expect("valid");
+            assert!(n > 0 || start == bytes.len(), "at {pos:?}");
+            assert_eq!(buf[..n], bytes[start..][..n], "at {pos:?}");
+        };
+
+        // Forwards into chunks that haven't been validated yet.
+        check_read_at(
+            &mut reader,
+            SeekFrom::Start(3 * TEST_CHUNK_SIZE as u64 + 10),
+        );
+        // Backwards, within the current chunk, and past a checkpoint.
+        check_read_at(&mut reader, SeekFrom::Current(-5));
+        check_read_at(&mut reader, SeekFrom::Start(TEST_CHUNK_SIZE as u64 - 1));
+        check_read_at(&mut reader, SeekFrom::Start(0));
+        // Relative to the end, including the partial final chunk.
+        check_read_at(&mut reader, SeekFrom::End(-3));
+        check_read_at(&mut reader, SeekFrom::End(-(2 * TEST_CHUNK_SIZE as i64)));
+        check_read_at(&mut reader, SeekFrom::End(0));
+        check_read_at(&mut reader, SeekFrom::End(10));
+
+        assert_eq!(
+            reader
+                .seek(SeekFrom::Current(-1000))
+                .expect_err("negative")
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        for _ in 0..100 {
+            let target = rand::rng().random_range(0..=bytes.len() as u64);
+            check_read_at(&mut reader, SeekFrom::Start(target));
+        }
+    }
+
+    #[test]
+    fn reader_does_not_skip_validation_when_seeking() {
+        let bytes = random_bytes(6 * TEST_CHUNK_SIZE);
+        let digests = digests_for(&bytes, TEST_CHUNK_SIZE);
+        let mut corrupted = bytes.clone();
+        corrupted[TEST_CHUNK_SIZE] ^= 1;
+
+        // Seeking past the corrupted chunk still has to validate it.
+        let mut reader = validating_reader(&corrupted, &digests);
+        reader
+            .seek(SeekFrom::Start(4 * TEST_CHUNK_SIZE as u64))
+            .expect("can seek");
+        assert_eq!(
+            reader.read(&mut [0; 8]).expect_err("invalid").kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // But the chunk before it can be read again.
+        reader.seek(SeekFrom::Start(3)).expect("can seek");
+        let mut buf = [0; TEST_CHUNK_SIZE - 3];
+        reader.read_exact(&mut buf).expect("valid");
+        assert_eq!(buf, bytes[3..TEST_CHUNK_SIZE]);
+    }
+
+    mod futures_io {
+        use std::io::{self, SeekFrom};
+
+        use futures_util::FutureExt as _;
+        use futures_util::io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _, Cursor};
+
+        use super::super::{IncrementalWriter, ValidatingReader};
+        use super::{TEST_CHUNK_SIZE, digests_for, new_mac, random_bytes};
+
+        #[test]
+        fn async_round_trip() {
+            async {
+                let bytes = random_bytes(5 * TEST_CHUNK_SIZE + 3);
+
+                let mut writer =
+                    IncrementalWriter::new(Cursor::new(Vec::new()), new_mac(), TEST_CHUNK_SIZE);
+                writer.write_all(&bytes).await.expect("can write to a Vec");
+                writer.close().await.expect("can close");
+                let (mut written, digests) = writer.finalize();
+                assert_eq!(digests, digests_for(&bytes, TEST_CHUNK_SIZE));
+                written.set_position(0);
+
+                let mut reader =
+                    ValidatingReader::new(written, new_mac(), TEST_CHUNK_SIZE, &digests)
+                        .expect("valid digests");
+                let mut read = Vec::new();
+                reader.read_to_end(&mut read).await.expect("valid");
+                assert_eq!(read, bytes);
+
+                reader
+                    .seek(SeekFrom::Start(2 * TEST_CHUNK_SIZE as u64 + 1))
+                    .await
+                    .expect("can seek");
+                let mut buf = [0; 4];
+                reader.read_exact(&mut buf).await.expect("valid");
+                assert_eq!(buf, bytes[2 * TEST_CHUNK_SIZE + 1..][..4]);
+
+                let end = reader.seek(SeekFrom::End(-2)).await.expect("can seek");
+                assert_eq!(end, bytes.len() as u64 - 2);
+                let mut rest = Vec::new();
+                reader.read_to_end(&mut rest).await.expect("valid");
+                assert_eq!(rest, bytes[bytes.len() - 2..]);
+
+                let mut corrupted = reader.into_inner();
+                corrupted.get_mut()[TEST_CHUNK_SIZE] ^= 1;
+                corrupted.set_position(0);
+                let mut reader =
+                    ValidatingReader::new(corrupted, new_mac(), TEST_CHUNK_SIZE, &digests)
+                        .expect("valid digests");
+                let mut read = Vec::new();
+                let err = reader.read_to_end(&mut read).await.expect_err("invalid");
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                assert_eq!(read, bytes[..TEST_CHUNK_SIZE]);
+            }
+            .now_or_never()
+            .expect("sync")
+        }
+    }
+}
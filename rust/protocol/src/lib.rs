@@ -24,37 +24,57 @@
 
 mod consts;
 mod crypto;
+mod describe;
 pub mod error;
 mod fingerprint;
 mod group_cipher;
 mod identity_key;
 pub mod incremental_mac;
 pub mod kem;
+mod prekey_manager;
 mod proto;
 mod protocol;
 mod ratchet;
 mod sealed_sender;
+mod sealed_sender_fanout;
+mod sender_key_manager;
 mod sender_keys;
 mod session;
+mod session_archive;
 mod session_cipher;
+mod session_policy;
 mod state;
 mod storage;
 mod timestamp;
 
+pub use describe::{
+    ChainDescription, KeyFingerprint, PendingPreKeyDescription, PreKeySignalMessageDescription,
+    SenderKeyMessageDescription, SenderKeyRecordDescription, SenderKeyStateDescription,
+    SessionRecordDescription, SessionStateDescription, SignalMessageDescription,
+};
 use error::Result;
 pub use error::SignalProtocolError;
 pub use fingerprint::{
     DisplayableFingerprint, Error as FingerprintError, Fingerprint, ScannableFingerprint,
 };
 pub use group_cipher::{
-    create_sender_key_distribution_message, group_decrypt, group_encrypt,
-    process_sender_key_distribution_message,
+    create_sender_key_distribution_message, group_decrypt, group_decrypt_with_gossip,
+    group_encrypt, group_encrypt_with_gossip, process_sender_key_distribution_message,
+    rotate_sender_key,
 };
 pub use identity_key::{IdentityKey, IdentityKeyPair};
 pub use libsignal_core::curve::{KeyPair, PrivateKey, PublicKey};
 pub use libsignal_core::{
     Aci, DeviceId, Pni, ProtocolAddress, ServiceId, ServiceIdFixedWidthBinaryBytes, ServiceIdKind,
 };
+pub use libsignal_gossip::{
+    EquivocationProof, GossipError, GossipOutcome, GossipService, GossipStore, InMemGossipStore,
+    KtState,
+};
+pub use prekey_manager::{
+    MAX_PRE_KEY_ID, PreKeyCounts, PreKeyMaintenance, PreKeyManager, PreKeyManagerConfig,
+    PreKeyManagerState, PreKeyUpload, PreKeyUploadSet, SignedPreKeyUpload,
+};
 pub use protocol::{
     CiphertextMessage, CiphertextMessageType, DecryptionErrorMessage, KyberPayload,
     PlaintextContent, PreKeySignalMessage, SenderKeyDistributionMessage, SenderKeyMessage,
@@ -65,26 +85,47 @@ pub use ratchet::{
     initialize_bob_session_record,
 };
 pub use sealed_sender::{
-    ContentHint, SealedSenderDecryptionResult, SealedSenderV2SentMessage,
-    SealedSenderV2SentMessageRecipient, SenderCertificate, ServerCertificate,
-    UnidentifiedSenderMessageContent, sealed_sender_decrypt, sealed_sender_decrypt_to_usmc,
-    sealed_sender_encrypt, sealed_sender_encrypt_from_usmc, sealed_sender_multi_recipient_encrypt,
+    ContentHint, SealedSenderDecryptionResult, SealedSenderV2Parallelism,
+    SealedSenderV2SentMessage, SealedSenderV2SentMessageRecipient, SenderCertificate,
+    ServerCertificate, UnidentifiedSenderMessageContent, sealed_sender_decrypt,
+    sealed_sender_decrypt_to_usmc, sealed_sender_decrypt_with_gossip, sealed_sender_encrypt,
+    sealed_sender_encrypt_from_usmc, sealed_sender_encrypt_with_gossip,
+    sealed_sender_multi_recipient_encrypt, sealed_sender_multi_recipient_encrypt_to_writer,
+};
+pub use sealed_sender_fanout::{
+    MismatchedDevices, SealedSenderV2Delivery, SealedSenderV2DeviceRegistry, SealedSenderV2Fanout,
+    SealedSenderV2FanoutRejection, StaleDevices,
+};
+pub use sender_key_manager::{
+    SenderKeyManager, SenderKeyManagerConfig, SenderKeyManagerState, SenderKeySendPreparation,
 };
 pub use sender_keys::SenderKeyRecord;
 pub use session::{process_prekey, process_prekey_bundle};
+pub use session_archive::SessionArchive;
 pub use session_cipher::{
-    message_decrypt, message_decrypt_prekey, message_decrypt_signal, message_decrypt_signal_with_gossip,
-    message_encrypt, message_encrypt_with_gossip,
+    GossipStatus, message_decrypt, message_decrypt_batch, message_decrypt_prekey,
+    message_decrypt_prekey_with_gossip, message_decrypt_signal, message_decrypt_signal_with_gossip,
+    message_decrypt_transactional, message_decrypt_transactional_with_gossip,
+    message_decrypt_with_gossip, message_encrypt, message_encrypt_transactional,
+    message_encrypt_transactional_with_gossip, message_encrypt_with_gossip,
+    process_incoming_gossip,
 };
+pub use session_policy::{SessionPolicy, SessionPolicyBuilder};
 pub use state::{
     GenericSignedPreKey, KyberPreKeyId, KyberPreKeyRecord, PreKeyBundle, PreKeyBundleContent,
     PreKeyId, PreKeyRecord, SessionRecord, SessionUsabilityRequirements, SignedPreKeyId,
     SignedPreKeyRecord,
 };
 pub use storage::{
-    Direction, IdentityChange, IdentityKeyStore, InMemIdentityKeyStore, InMemKyberPreKeyStore,
-    InMemPreKeyStore, InMemSenderKeyStore, InMemSessionStore, InMemSignalProtocolStore,
-    InMemSignedPreKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore, SenderKeyStore,
-    SessionStore, SignedPreKeyStore,
+    Direction, IdentityChange, IdentityHistoryEntry, IdentityHistoryStore, IdentityKeyStore,
+    IdentitySource, InMemIdentityKeyStore, InMemKyberPreKeyStore, InMemPreKeyStore,
+    InMemSenderKeyStore, InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
+    KyberPreKeyStore, PreKeyStore, ProtocolStore, ProtocolStoreTransaction, SenderKeyStore,
+    SessionStore, SignedPreKeyStore, TransactionalProtocolStore,
+};
+#[cfg(feature = "sqlite")]
+pub use storage::{
+    SqliteIdentityKeyStore, SqliteKyberPreKeyStore, SqlitePreKeyStore, SqliteSenderKeyStore,
+    SqliteSessionStore, SqliteSignalProtocolStore, SqliteSignedPreKeyStore,
 };
 pub use timestamp::Timestamp;
@@ -0,0 +1,634 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Generating, rotating, and replenishing this client's pre-keys.
+
+use std::time::{Duration, SystemTime};
+
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use prost::Message;
+use rand::{CryptoRng, Rng};
+
+use crate::proto::storage::PreKeyManagerStateStructure;
+use crate::proto::storage::pre_key_manager_state_structure::Key as KeyStructure;
+use crate::state::GenericSignedPreKey;
+use crate::{
+    IdentityKeyPair, KeyPair, KyberPreKeyId, KyberPreKeyRecord, KyberPreKeyStore, PreKeyRecord,
+    PreKeyStore, Result, SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord,
+    SignedPreKeyStore, Timestamp, kem,
+};
+
+/// The largest pre-key ID the manager hands out, after which IDs wrap around to 1.
+///
+/// The Signal service only accepts 24-bit pre-key IDs.
+pub const MAX_PRE_KEY_ID: u32 = 0xFF_FFFF;
+
+/// How a [`PreKeyManager`] decides when to generate and retire keys.
+#[derive(Clone, Debug)]
+pub struct PreKeyManagerConfig {
+    /// How many one-time EC pre-keys, and separately one-time Kyber pre-keys, to keep on the
+    /// server.
+    pub one_time_pre_key_target: u32,
+    /// Top the one-time pre-keys back up to the target once the server has fewer than this many
+    /// left.
+    pub one_time_pre_key_minimum: u32,
+    /// How long a signed pre-key, and the last-resort Kyber pre-key alongside it, is used before
+    /// being replaced.
+    pub rotation_interval: Duration,
+    /// How long a replaced signed or last-resort Kyber pre-key is kept, for senders that fetched
+    /// it before it was replaced.
+    pub grace_period: Duration,
+    /// The kind of Kyber pre-keys to generate.
+    pub kyber_key_type: kem::KeyType,
+}
+
+impl Default for PreKeyManagerConfig {
+    fn default() -> Self {
+        Self {
+            one_time_pre_key_target: 100,
+            one_time_pre_key_minimum: 10,
+            rotation_interval: Duration::from_secs(60 * 60 * 24 * 2),
+            grace_period: Duration::from_secs(60 * 60 * 24 * 30),
+            kyber_key_type: kem::KeyType::Kyber1024,
+        }
+    }
+}
+
+/// What a [`PreKeyManager`] needs to remember between runs: the next IDs to hand out, and which
+/// signed and last-resort pre-keys are current or retired.
+#[derive(Clone, Debug)]
+pub struct PreKeyManagerState {
+    state: PreKeyManagerStateStructure,
+}
+
+impl PreKeyManagerState {
+    /// The state for a client that has no pre-keys yet.
+    ///
+    /// IDs start at random points, so that keys from a previous installation are unlikely to be
+    /// confused with new ones.
+    pub fn new<R: Rng + CryptoRng>(csprng: &mut R) -> Self {
+        let mut random_id = || csprng.random_range(1..=MAX_PRE_KEY_ID);
+        Self {
+            state: PreKeyManagerStateStructure {
+                next_pre_key_id: random_id(),
+                next_signed_pre_key_id: random_id(),
+                next_kyber_pre_key_id: random_id(),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let state = PreKeyManagerStateStructure::decode(data)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        let valid_id = |id| (1..=MAX_PRE_KEY_ID).contains(&id);
+        if ![
+            state.next_pre_key_id,
+            state.next_signed_pre_key_id,
+            state.next_kyber_pre_key_id,
+        ]
+        .into_iter()
+        .all(valid_id)
+        {
+            return Err(SignalProtocolError::InvalidProtobufEncoding);
+        }
+        Ok(Self { state })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.state.encode_to_vec()
+    }
+
+    /// The signed pre-key currently being handed out, if one has been generated.
+    pub fn signed_pre_key_id(&self) -> Option<SignedPreKeyId> {
+        self.state.signed_pre_key.as_ref().map(|key| key.id.into())
+    }
+
+    /// The last-resort Kyber pre-key currently being handed out, if one has been generated.
+    pub fn last_resort_kyber_pre_key_id(&self) -> Option<KyberPreKeyId> {
+        self.state
+            .last_resort_kyber_pre_key
+            .as_ref()
+            .map(|key| key.id.into())
+    }
+
+    /// Signed pre-keys that have been replaced but are still within their grace period.
+    pub fn retired_signed_pre_key_ids(&self) -> impl Iterator<Item = SignedPreKeyId> + '_ {
+        self.state
+            .retired_signed_pre_keys
+            .iter()
+            .map(|key| key.id.into())
+    }
+
+    /// Last-resort Kyber pre-keys that have been replaced but are still within their grace
+    /// period.
+    pub fn retired_last_resort_kyber_pre_key_ids(
+        &self,
+    ) -> impl Iterator<Item = KyberPreKeyId> + '_ {
+        self.state
+            .retired_last_resort_kyber_pre_keys
+            .iter()
+            .map(|key| key.id.into())
+    }
+}
+
+/// How many one-time pre-keys the server reports it has left for this device.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PreKeyCounts {
+    pub one_time_pre_keys: u32,
+    pub one_time_kyber_pre_keys: u32,
+}
+
+/// The public halves of newly generated pre-keys, to be uploaded to the server.
+///
+/// Serializes to JSON in the shape the Signal service expects, with keys and signatures in
+/// base64 and anything that wasn't generated left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreKeyUploadSet {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_pre_key: Option<SignedPreKeyUpload>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pq_last_resort_pre_key: Option<SignedPreKeyUpload>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pre_keys: Vec<PreKeyUpload>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pq_pre_keys: Vec<SignedPreKeyUpload>,
+}
+
+impl PreKeyUploadSet {
+    /// Whether there is nothing to upload.
+    pub fn is_empty(&self) -> bool {
+        self.signed_pre_key.is_none()
+            && self.pq_last_resort_pre_key.is_none()
+            && self.pre_keys.is_empty()
+            && self.pq_pre_keys.is_empty()
+    }
+}
+
+/// A one-time EC pre-key in a [`PreKeyUploadSet`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreKeyUpload {
+    pub key_id: u32,
+    #[serde(serialize_with = "serialize_base64")]
+    pub public_key: Box<[u8]>,
+}
+
+/// A signed EC or Kyber pre-key in a [`PreKeyUploadSet`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedPreKeyUpload {
+    pub key_id: u32,
+    #[serde(serialize_with = "serialize_base64")]
+    pub public_key: Box<[u8]>,
+    #[serde(serialize_with = "serialize_base64")]
+    pub signature: Box<[u8]>,
+}
+
+impl<T: GenericSignedPreKey> From<&T> for SignedPreKeyUpload {
+    fn from(record: &T) -> Self {
+        let storage = record.get_storage();
+        Self {
+            key_id: storage.id,
+            public_key: storage.public_key.clone().into(),
+            signature: storage.signature.clone().into(),
+        }
+    }
+}
+
+fn serialize_base64<B: AsRef<[u8]>, S: serde::Serializer>(
+    bytes: &B,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64_STANDARD.encode(bytes))
+}
+
+/// The outcome of [`PreKeyManager::maintain`].
+#[derive(Clone, Debug, Default)]
+pub struct PreKeyMaintenance {
+    /// New keys to upload. These have already been saved to the stores.
+    pub upload: PreKeyUploadSet,
+    /// Signed pre-keys past their grace period, which the caller should now delete.
+    ///
+    /// [`SignedPreKeyStore`] has no way to remove keys, so this is left to the caller. The base
+    /// keys recorded as used with them have already been expired from the
+    /// [`KyberPreKeyStore`].
+    pub expired_signed_pre_key_ids: Vec<SignedPreKeyId>,
+    /// Last-resort Kyber pre-keys past their grace period, which the caller should now delete.
+    pub expired_kyber_pre_key_ids: Vec<KyberPreKeyId>,
+}
+
+/// Keeps this client's pre-keys generated, rotated, and replenished according to a
+/// [`PreKeyManagerConfig`].
+///
+/// Each time the client learns how many one-time pre-keys the server has left (for example, on
+/// connecting), it should call [`maintain`](Self::maintain), upload the returned keys, and then
+/// persist [`state`](Self::state). If the upload fails, the previous state should be kept; the
+/// next run will generate the same IDs again and overwrite the keys that were saved. (Once the
+/// one-time pre-key IDs have wrapped around, those keys can't be told apart from ones still
+/// outstanding, so they are skipped instead.)
+pub struct PreKeyManager {
+    identity_key_pair: IdentityKeyPair,
+    config: PreKeyManagerConfig,
+    state: PreKeyManagerState,
+}
+
+impl PreKeyManager {
+    /// Manage the pre-keys signed by `identity_key_pair`, starting from `state`.
+    pub fn new(
+        identity_key_pair: IdentityKeyPair,
+        config: PreKeyManagerConfig,
+        state: PreKeyManagerState,
+    ) -> Result<Self> {
+        if config.one_time_pre_key_minimum > config.one_time_pre_key_target {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "one-time pre-key minimum ({}) is larger than the target ({})",
+                config.one_time_pre_key_minimum, config.one_time_pre_key_target
+            )));
+        }
+        if config.one_time_pre_key_target >= MAX_PRE_KEY_ID {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "one-time pre-key target ({}) doesn't fit in the pre-key ID space",
+                config.one_time_pre_key_target
+            )));
+        }
+        Ok(Self {
+            identity_key_pair,
+            config,
+            state,
+        })
+    }
+
+    pub fn config(&self) -> &PreKeyManagerConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> &PreKeyManagerState {
+        &self.state
+    }
+
+    /// Rotates the signed and last-resort Kyber pre-keys if they are due, retires old ones whose
+    /// grace period is over, and tops up the one-time pre-keys if the server is running low.
+    ///
+    /// Retiring a signed pre-key also expires the base keys that
+    /// [`KyberPreKeyStore::mark_kyber_pre_key_used`] recorded for it.
+    ///
+    /// New keys are saved to the stores before being returned for upload. Once the IDs have
+    /// wrapped around, any ID still held by a one-time pre-key in the stores is skipped.
+    pub async fn maintain<R: Rng + CryptoRng>(
+        &mut self,
+        server_counts: PreKeyCounts,
+        pre_key_store: &mut dyn PreKeyStore,
+        signed_pre_key_store: &mut dyn SignedPreKeyStore,
+        kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<PreKeyMaintenance> {
+        let mut maintenance = PreKeyMaintenance::default();
+        let timestamp = Timestamp::from_epoch_millis(
+            now.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+        );
+
+        let state = &mut self.state.state;
+        let is_due =
+            |key: &KeyStructure, interval| timestamp_to_time(key.timestamp) + interval <= now;
+
+        if state
+            .signed_pre_key
+            .as_ref()
+            .is_none_or(|key| is_due(key, self.config.rotation_interval))
+        {
+            let held = held_signed_pre_key_ids(state);
+            let id = take_next_id(&mut state.next_signed_pre_key_id, &held);
+            let key_pair = KeyPair::generate(csprng);
+            let signature = self
+                .identity_key_pair
+                .private_key()
+                .calculate_signature(&key_pair.public_key.serialize(), csprng)?;
+            let record = SignedPreKeyRecord::new(id.into(), timestamp, &key_pair, &signature);
+            signed_pre_key_store
+                .save_signed_pre_key(id.into(), &record)
+                .await?;
+            maintenance.upload.signed_pre_key = Some((&record).into());
+            retire(
+                &mut state.signed_pre_key,
+                &mut state.retired_signed_pre_keys,
+                id,
+                timestamp,
+            );
+        }
+
+        if state
+            .last_resort_kyber_pre_key
+            .as_ref()
+            .is_none_or(|key| is_due(key, self.config.rotation_interval))
+        {
+            let held = held_kyber_pre_key_ids(state);
+            let id = take_next_kyber_pre_key_id(
+                &mut state.next_kyber_pre_key_id,
+                &mut state.kyber_pre_key_ids_wrapped,
+                &held,
+                kyber_pre_key_store,
+            )
+            .await?;
+            let record = generate_kyber_pre_key(
+                &self.identity_key_pair,
+                self.config.kyber_key_type,
+                id,
+                timestamp,
+                csprng,
+            )?;
+            kyber_pre_key_store
+                .save_kyber_pre_key(id.into(), &record)
+                .await?;
+            maintenance.upload.pq_last_resort_pre_key = Some((&record).into());
+            retire(
+                &mut state.last_resort_kyber_pre_key,
+                &mut state.retired_last_resort_kyber_pre_keys,
+                id,
+                timestamp,
+            );
+        }
+
+        let grace_period = self.config.grace_period;
+        maintenance.expired_signed_pre_key_ids =
+            drain_expired(&mut state.retired_signed_pre_keys, |key| {
+                is_due(key, grace_period)
+            })
+            .map(SignedPreKeyId::from)
+            .collect();
+        maintenance.expired_kyber_pre_key_ids =
+            drain_expired(&mut state.retired_last_resort_kyber_pre_keys, |key| {
+                is_due(key, grace_period)
+            })
+            .map(KyberPreKeyId::from)
+            .collect();
+        // Messages can no longer use the expired signed pre-keys, so there are no replays left to
+        // catch with them.
+        for &id in &maintenance.expired_signed_pre_key_ids {
+            kyber_pre_key_store
+                .expire_base_keys_for_signed_pre_key(id)
+                .await?;
+        }
+
+        if server_counts.one_time_pre_keys < self.config.one_time_pre_key_minimum {
+            let missing = self.config.one_time_pre_key_target - server_counts.one_time_pre_keys;
+            for _ in 0..missing {
+                let id = take_next_pre_key_id(
+                    &mut state.next_pre_key_id,
+                    &mut state.pre_key_ids_wrapped,
+                    pre_key_store,
+                )
+                .await?;
+                let key_pair = KeyPair::generate(csprng);
+                pre_key_store
+                    .save_pre_key(id.into(), &PreKeyRecord::new(id.into(), &key_pair))
+                    .await?;
+                maintenance.upload.pre_keys.push(PreKeyUpload {
+                    key_id: id,
+                    public_key: key_pair.public_key.serialize(),
+                });
+            }
+        }
+
+        if server_counts.one_time_kyber_pre_keys < self.config.one_time_pre_key_minimum {
+            let missing =
+                self.config.one_time_pre_key_target - server_counts.one_time_kyber_pre_keys;
+            for _ in 0..missing {
+                let held = held_kyber_pre_key_ids(state);
+                let id = take_next_kyber_pre_key_id(
+                    &mut state.next_kyber_pre_key_id,
+                    &mut state.kyber_pre_key_ids_wrapped,
+                    &held,
+                    kyber_pre_key_store,
+                )
+                .await?;
+                let record = generate_kyber_pre_key(
+                    &self.identity_key_pair,
+                    self.config.kyber_key_type,
+                    id,
+                    timestamp,
+                    csprng,
+                )?;
+                kyber_pre_key_store
+                    .save_kyber_pre_key(id.into(), &record)
+                    .await?;
+                maintenance.upload.pq_pre_keys.push((&record).into());
+            }
+        }
+
+        Ok(maintenance)
+    }
+}
+
+fn generate_kyber_pre_key<R: Rng + CryptoRng>(
+    identity_key_pair: &IdentityKeyPair,
+    key_type: kem::KeyType,
+    id: u32,
+    timestamp: Timestamp,
+    csprng: &mut R,
+) -> Result<KyberPreKeyRecord> {
+    let key_pair = kem::KeyPair::generate(key_type, csprng);
+    let signature = identity_key_pair
+        .private_key()
+        .calculate_signature(&key_pair.public_key.serialize(), csprng)?;
+    Ok(KyberPreKeyRecord::new(
+        id.into(),
+        timestamp,
+        &key_pair,
+        &signature,
+    ))
+}
+
+fn timestamp_to_time(epoch_millis: u64) -> SystemTime {
+    Timestamp::from_epoch_millis(epoch_millis).into()
+}
+
+/// Hands out the ID in `next`, skipping any that are `in_use`, and advances `next` past it,
+/// wrapping around from [`MAX_PRE_KEY_ID`] to 1.
+fn take_next_id(next: &mut u32, in_use: &[u32]) -> u32 {
+    let wrapping_increment = |id: u32| if id >= MAX_PRE_KEY_ID { 1 } else { id + 1 };
+    let mut id = *next;
+    while in_use.contains(&id) {
+        id = wrapping_increment(id);
+    }
+    *next = wrapping_increment(id);
+    id
+}
+
+/// Like [`take_next_id`], but once the IDs have `wrapped` around, also skips one-time pre-keys
+/// still in `pre_key_store`, since the keys handed out with them last time round may not all have
+/// been used yet.
+async fn take_next_pre_key_id(
+    next: &mut u32,
+    wrapped: &mut bool,
+    pre_key_store: &dyn PreKeyStore,
+) -> Result<u32> {
+    loop {
+        let id = take_next_id(next, &[]);
+        let outstanding = *wrapped
+            && match pre_key_store.get_pre_key(id.into()).await {
+                Ok(_) => true,
+                Err(SignalProtocolError::InvalidPreKeyId) => false,
+                Err(e) => return Err(e),
+            };
+        *wrapped |= *next == 1;
+        if !outstanding {
+            return Ok(id);
+        }
+    }
+}
+
+/// Like [`take_next_id`], but once the IDs have `wrapped` around, also skips one-time Kyber
+/// pre-keys still in `kyber_pre_key_store`, as [`take_next_pre_key_id`] does.
+async fn take_next_kyber_pre_key_id(
+    next: &mut u32,
+    wrapped: &mut bool,
+    held: &[u32],
+    kyber_pre_key_store: &dyn KyberPreKeyStore,
+) -> Result<u32> {
+    loop {
+        let id = take_next_id(next, held);
+        let outstanding = *wrapped
+            && match kyber_pre_key_store.get_kyber_pre_key(id.into()).await {
+                Ok(_) => true,
+                Err(SignalProtocolError::InvalidKyberPreKeyId) => false,
+                Err(e) => return Err(e),
+            };
+        *wrapped |= *next == 1;
+        if !outstanding {
+            return Ok(id);
+        }
+    }
+}
+
+fn held_signed_pre_key_ids(state: &PreKeyManagerStateStructure) -> Vec<u32> {
+    state
+        .signed_pre_key
+        .iter()
+        .chain(&state.retired_signed_pre_keys)
+        .map(|key| key.id)
+        .collect()
+}
+
+fn held_kyber_pre_key_ids(state: &PreKeyManagerStateStructure) -> Vec<u32> {
+    state
+        .last_resort_kyber_pre_key
+        .iter()
+        .chain(&state.retired_last_resort_kyber_pre_keys)
+        .map(|key| key.id)
+        .collect()
+}
+
+/// Makes the key `id` created at `now` current, moving the previous one (if any) to `retired`.
+fn retire(
+    current: &mut Option<KeyStructure>,
+    retired: &mut Vec<KeyStructure>,
+    id: u32,
+    now: Timestamp,
+) {
+    let previous = current.replace(KeyStructure {
+        id,
+        timestamp: now.epoch_millis(),
+    });
+    if let Some(previous) = previous {
+        retired.push(KeyStructure {
+            id: previous.id,
+            timestamp: now.epoch_millis(),
+        });
+    }
+}
+
+/// Removes the retired keys that are `expired`, returning their IDs.
+fn drain_expired(
+    retired: &mut Vec<KeyStructure>,
+    mut expired: impl FnMut(&KeyStructure) -> bool,
+) -> impl Iterator<Item = u32> {
+    let (gone, kept) = std::mem::take(retired)
+        .into_iter()
+        .partition::<Vec<_>, _>(&mut expired);
+    *retired = kept;
+    gone.into_iter().map(|key| key.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_wrap_around() {
+        let mut next = MAX_PRE_KEY_ID - 1;
+        assert_eq!(take_next_id(&mut next, &[]), MAX_PRE_KEY_ID - 1);
+        assert_eq!(take_next_id(&mut next, &[]), MAX_PRE_KEY_ID);
+        assert_eq!(take_next_id(&mut next, &[]), 1);
+        assert_eq!(next, 2);
+    }
+
+    #[test]
+    fn ids_in_use_are_skipped_across_the_wrap() {
+        let mut next = MAX_PRE_KEY_ID;
+        assert_eq!(take_next_id(&mut next, &[MAX_PRE_KEY_ID, 1]), 2);
+        assert_eq!(next, 3);
+    }
+
+    #[test]
+    fn outstanding_one_time_ids_are_skipped_after_the_wrap() -> Result<()> {
+        use futures_util::FutureExt as _;
+        use rand::TryRngCore as _;
+        use rand::rngs::OsRng;
+
+        use crate::InMemPreKeyStore;
+
+        let mut csprng = OsRng.unwrap_err();
+        let mut pre_key_store = InMemPreKeyStore::new();
+        for id in [MAX_PRE_KEY_ID, 1] {
+            let record = PreKeyRecord::new(id.into(), &KeyPair::generate(&mut csprng));
+            pre_key_store
+                .save_pre_key(id.into(), &record)
+                .now_or_never()
+                .expect("sync")?;
+        }
+
+        let take_next_pre_key_id = |next: &mut u32, wrapped: &mut bool| {
+            take_next_pre_key_id(next, wrapped, &pre_key_store)
+                .now_or_never()
+                .expect("sync")
+        };
+
+        // Before the wrap, a key in the store is one whose upload failed, and its ID is reused.
+        let mut next = MAX_PRE_KEY_ID;
+        let mut wrapped = false;
+        assert_eq!(
+            take_next_pre_key_id(&mut next, &mut wrapped)?,
+            MAX_PRE_KEY_ID
+        );
+        assert!(wrapped);
+
+        // After it, it may still be outstanding.
+        assert_eq!(take_next_pre_key_id(&mut next, &mut wrapped)?, 2);
+        assert_eq!(next, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_ids_are_rejected() {
+        let state = PreKeyManagerStateStructure {
+            next_pre_key_id: 0,
+            next_signed_pre_key_id: 1,
+            next_kyber_pre_key_id: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            PreKeyManagerState::deserialize(&state.encode_to_vec()),
+            Err(SignalProtocolError::InvalidProtobufEncoding)
+        ));
+    }
+}
@@ -10,13 +10,15 @@ use sha2::Sha256;
 use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
+use crate::describe::{
+    KeyFingerprint, PreKeySignalMessageDescription, SenderKeyMessageDescription,
+    SignalMessageDescription,
+};
 use crate::state::{KyberPreKeyId, PreKeyId, SignedPreKeyId};
 use crate::{
     IdentityKey, PrivateKey, PublicKey, Result, SignalProtocolError, Timestamp, kem, proto,
 };
 
-// pub use libsignal_gossip::{Gossip, GossipError, GossipStorage, Gossiper};
-
 pub(crate) const CIPHERTEXT_MESSAGE_CURRENT_VERSION: u8 = 4;
 // Backward compatible, lacking Kyber keys, version
 pub(crate) const CIPHERTEXT_MESSAGE_PRE_KYBER_VERSION: u8 = 3;
@@ -65,7 +67,6 @@ pub struct SignalMessage {
     message_version: u8,
     sender_ratchet_key: PublicKey,
     counter: u32,
-    #[cfg_attr(not(test), expect(dead_code))]
     previous_counter: u32,
     ciphertext: Box<[u8]>,
     pq_ratchet: spqr::SerializedState,
@@ -88,44 +89,24 @@ impl SignalMessage {
         receiver_identity_key: &IdentityKey,
         pq_ratchet: &[u8],
     ) -> Result<Self> {
-        let message = proto::wire::SignalMessage {
-            ratchet_key: Some(sender_ratchet_key.serialize().into_vec()),
-            counter: Some(counter),
-            previous_counter: Some(previous_counter),
-            ciphertext: Some(Vec::<u8>::from(ciphertext)),
-            pq_ratchet: if pq_ratchet.is_empty() {
-                None
-            } else {
-                Some(pq_ratchet.to_vec())
-            },
-            gossip: None,
-        };
-        let mut serialized = Vec::with_capacity(1 + message.encoded_len() + Self::MAC_LENGTH);
-        serialized.push(((message_version & 0xF) << 4) | CIPHERTEXT_MESSAGE_CURRENT_VERSION);
-        message
-            .encode(&mut serialized)
-            .expect("can always append to a buffer");
-        let mac = Self::compute_mac(
-            sender_identity_key,
-            receiver_identity_key,
-            mac_key,
-            &serialized,
-        )?;
-        serialized.extend_from_slice(&mac);
-        let serialized = serialized.into_boxed_slice();
-        Ok(Self {
+        Self::new_with_gossip(
             message_version,
+            mac_key,
             sender_ratchet_key,
             counter,
             previous_counter,
-            ciphertext: ciphertext.into(),
-            pq_ratchet: pq_ratchet.to_vec(),
-            gossip: Box::from(&[][..]), // Empty gossip for backward compatibility
-            serialized,
-        })
+            ciphertext,
+            sender_identity_key,
+            receiver_identity_key,
+            pq_ratchet,
+            &[],
+        )
     }
 
-        #[allow(clippy::too_many_arguments)]
+    /// Like [`SignalMessage::new`], but also carries an encoded key transparency gossip payload.
+    ///
+    /// An empty `gossip` is omitted from the message entirely.
+    #[allow(clippy::too_many_arguments)]
     pub fn new_with_gossip(
         message_version: u8,
         mac_key: &[u8],
@@ -148,7 +129,11 @@ impl SignalMessage {
             } else {
                 Some(pq_ratchet.to_vec())
             },
-            gossip: Some(gossip.to_vec()),
+            gossip: if gossip.is_empty() {
+                None
+            } else {
+                Some(gossip.to_vec())
+            },
         };
         let mut serialized = Vec::with_capacity(1 + message.encoded_len() + Self::MAC_LENGTH);
         serialized.push(((message_version & 0xF) << 4) | CIPHERTEXT_MESSAGE_CURRENT_VERSION);
@@ -210,6 +195,19 @@ impl SignalMessage {
         &self.ciphertext
     }
 
+    /// Summarizes the message for debugging, without its contents.
+    pub fn describe(&self) -> SignalMessageDescription {
+        SignalMessageDescription {
+            message_version: self.message_version,
+            sender_ratchet_key: KeyFingerprint::of(&self.sender_ratchet_key.serialize()),
+            counter: self.counter,
+            previous_counter: self.previous_counter,
+            ciphertext_length: self.ciphertext.len(),
+            pq_ratchet_length: self.pq_ratchet.len(),
+            gossip_length: self.gossip.len(),
+        }
+    }
+
     pub fn verify_mac(
         &self,
         sender_identity_key: &IdentityKey,
@@ -305,7 +303,10 @@ impl TryFrom<&[u8]> for SignalMessage {
             previous_counter,
             ciphertext,
             pq_ratchet: proto_structure.pq_ratchet.unwrap_or(vec![]),
-            gossip: proto_structure.gossip.unwrap_or_default().into_boxed_slice(),
+            gossip: proto_structure
+                .gossip
+                .unwrap_or_default()
+                .into_boxed_slice(),
             serialized: Box::from(value),
         })
     }
@@ -431,6 +432,20 @@ impl PreKeySignalMessage {
     pub fn serialized(&self) -> &[u8] {
         &self.serialized
     }
+
+    /// Summarizes the message for debugging, without its contents.
+    pub fn describe(&self) -> PreKeySignalMessageDescription {
+        PreKeySignalMessageDescription {
+            message_version: self.message_version,
+            registration_id: self.registration_id,
+            pre_key_id: self.pre_key_id.map(u32::from),
+            signed_pre_key_id: self.signed_pre_key_id.into(),
+            kyber_pre_key_id: self.kyber_pre_key_id().map(u32::from),
+            base_key: KeyFingerprint::of(&self.base_key.serialize()),
+            identity_key: KeyFingerprint::of(&self.identity_key.serialize()),
+            message: self.message.describe(),
+        }
+    }
 }
 
 impl AsRef<[u8]> for PreKeySignalMessage {
@@ -518,6 +533,7 @@ pub struct SenderKeyMessage {
     chain_id: u32,
     iteration: u32,
     ciphertext: Box<[u8]>,
+    gossip: Box<[u8]>,
     serialized: Box<[u8]>,
 }
 
@@ -532,12 +548,44 @@ impl SenderKeyMessage {
         ciphertext: Box<[u8]>,
         csprng: &mut R,
         signature_key: &PrivateKey,
+    ) -> Result<Self> {
+        Self::new_with_gossip(
+            message_version,
+            distribution_id,
+            chain_id,
+            iteration,
+            ciphertext,
+            &[],
+            csprng,
+            signature_key,
+        )
+    }
+
+    /// Like [`SenderKeyMessage::new`], but also carries an encoded key transparency gossip
+    /// payload, which is covered by the signature.
+    ///
+    /// An empty `gossip` is omitted from the message entirely.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_gossip<R: CryptoRng + Rng>(
+        message_version: u8,
+        distribution_id: Uuid,
+        chain_id: u32,
+        iteration: u32,
+        ciphertext: Box<[u8]>,
+        gossip: &[u8],
+        csprng: &mut R,
+        signature_key: &PrivateKey,
     ) -> Result<Self> {
         let proto_message = proto::wire::SenderKeyMessage {
             distribution_uuid: Some(distribution_id.as_bytes().to_vec()),
             chain_id: Some(chain_id),
             iteration: Some(iteration),
             ciphertext: Some(ciphertext.to_vec()),
+            gossip: if gossip.is_empty() {
+                None
+            } else {
+                Some(gossip.to_vec())
+            },
         };
         let proto_message_len = proto_message.encoded_len();
         let mut serialized = Vec::with_capacity(1 + proto_message_len + Self::SIGNATURE_LEN);
@@ -553,6 +601,7 @@ impl SenderKeyMessage {
             chain_id,
             iteration,
             ciphertext,
+            gossip: gossip.into(),
             serialized: serialized.into_boxed_slice(),
         })
     }
@@ -591,10 +640,27 @@ impl SenderKeyMessage {
         &self.ciphertext
     }
 
+    #[inline]
+    pub fn gossip(&self) -> &[u8] {
+        &self.gossip
+    }
+
     #[inline]
     pub fn serialized(&self) -> &[u8] {
         &self.serialized
     }
+
+    /// Summarizes the message for debugging, without its contents.
+    pub fn describe(&self) -> SenderKeyMessageDescription {
+        SenderKeyMessageDescription {
+            message_version: self.message_version,
+            distribution_id: self.distribution_id,
+            chain_id: self.chain_id,
+            iteration: self.iteration,
+            ciphertext_length: self.ciphertext.len(),
+            gossip_length: self.gossip.len(),
+        }
+    }
 }
 
 impl AsRef<[u8]> for SenderKeyMessage {
@@ -639,6 +705,10 @@ impl TryFrom<&[u8]> for SenderKeyMessage {
             .ciphertext
             .ok_or(SignalProtocolError::InvalidProtobufEncoding)?
             .into_boxed_slice();
+        let gossip = proto_structure
+            .gossip
+            .unwrap_or_default()
+            .into_boxed_slice();
 
         Ok(SenderKeyMessage {
             message_version,
@@ -646,6 +716,7 @@ impl TryFrom<&[u8]> for SenderKeyMessage {
             chain_id,
             iteration,
             ciphertext,
+            gossip,
             serialized: Box::from(value),
         })
     }
@@ -1121,6 +1192,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sender_key_message_with_gossip() -> Result<()> {
+        let mut csprng = OsRng.unwrap_err();
+        let signature_key_pair = KeyPair::generate(&mut csprng);
+        let sender_key_message = SenderKeyMessage::new_with_gossip(
+            SENDERKEY_MESSAGE_CURRENT_VERSION,
+            Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6),
+            42,
+            7,
+            [1u8, 2, 3].into(),
+            &[4, 5, 6],
+            &mut csprng,
+            &signature_key_pair.private_key,
+        )?;
+        let deser_sender_key_message = SenderKeyMessage::try_from(sender_key_message.as_ref())
+            .expect("should deserialize without error");
+        assert_eq!(deser_sender_key_message.gossip(), &[4, 5, 6]);
+        assert!(deser_sender_key_message.verify_signature(&signature_key_pair.public_key)?);
+
+        // The gossip is covered by the signature.
+        let mut tampered = sender_key_message.serialized().to_vec();
+        let gossip_start = tampered
+            .windows(3)
+            .position(|w| w == [4, 5, 6])
+            .expect("gossip is present");
+        tampered[gossip_start] ^= 1;
+        let tampered = SenderKeyMessage::try_from(tampered.as_slice())?;
+        assert_eq!(tampered.gossip(), &[5, 5, 6]);
+        assert!(!tampered.verify_signature(&signature_key_pair.public_key)?);
+        Ok(())
+    }
+
     #[test]
     fn test_decryption_error_message() -> Result<()> {
         let mut csprng = OsRng.unwrap_err();
@@ -12,7 +12,7 @@ pub(crate) use self::keys::{ChainKey, MessageKeyGenerator, RootKey};
 pub use self::params::{AliceSignalProtocolParameters, BobSignalProtocolParameters};
 use crate::protocol::CIPHERTEXT_MESSAGE_CURRENT_VERSION;
 use crate::state::SessionState;
-use crate::{KeyPair, Result, SessionRecord, SignalProtocolError, consts};
+use crate::{KeyPair, Result, SessionPolicy, SessionRecord, SignalProtocolError};
 
 type InitialPQRKey = [u8; 32];
 
@@ -38,21 +38,28 @@ fn derive_keys_with_label(label: &[u8], secret_input: &[u8]) -> (RootKey, ChainK
     (root_key, chain_key, pqr_key)
 }
 
-fn spqr_chain_params(self_connection: bool) -> spqr::ChainParams {
+fn spqr_chain_params(self_connection: bool, policy: &SessionPolicy) -> spqr::ChainParams {
     #[allow(clippy::needless_update)]
     spqr::ChainParams {
         max_jump: if self_connection {
             u32::MAX
         } else {
-            consts::MAX_FORWARD_JUMPS.try_into().expect("should be <4B")
+            policy
+                .max_forward_jumps()
+                .try_into()
+                .expect("checked by SessionPolicyBuilder")
         },
-        max_ooo_keys: consts::MAX_MESSAGE_KEYS.try_into().expect("should be <4B"),
+        max_ooo_keys: policy
+            .max_message_keys()
+            .try_into()
+            .expect("checked by SessionPolicyBuilder"),
         ..Default::default()
     }
 }
 
 pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
     parameters: &AliceSignalProtocolParameters,
+    policy: &SessionPolicy,
     mut csprng: &mut R,
 ) -> Result<SessionState> {
     let local_identity = parameters.our_identity_key_pair().identity_key();
@@ -107,7 +114,7 @@ pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
         // PQR, we can up this to V1 to require that all subsequent sessions
         // use at least V1.
         min_version: spqr::Version::V0,
-        chain_params: spqr_chain_params(self_session),
+        chain_params: spqr_chain_params(self_session, policy),
     })
     .map_err(|e| {
         // Since this is an error associated with the initial creation of the state,
@@ -125,7 +132,7 @@ pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
         &parameters.our_base_key_pair().public_key,
         pqr_state,
     )
-    .with_receiver_chain(parameters.their_ratchet_key(), &chain_key)
+    .with_receiver_chain(parameters.their_ratchet_key(), &chain_key, policy)
     .with_sender_chain(&sending_ratchet_key, &sending_chain_chain_key);
 
     session.set_kyber_ciphertext(kyber_ciphertext);
@@ -135,6 +142,7 @@ pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
 
 pub(crate) fn initialize_bob_session(
     parameters: &BobSignalProtocolParameters,
+    policy: &SessionPolicy,
 ) -> Result<SessionState> {
     // validate their base key
     if !parameters.their_base_key().is_canonical() {
@@ -198,7 +206,7 @@ pub(crate) fn initialize_bob_session(
         // PQR, we can up this to V1 to require that all subsequent sessions
         // use at least V1.
         min_version: spqr::Version::V0,
-        chain_params: spqr_chain_params(self_session),
+        chain_params: spqr_chain_params(self_session, policy),
     })
     .map_err(|e| {
         // Since this is an error associated with the initial creation of the state,
@@ -225,12 +233,17 @@ pub fn initialize_alice_session_record<R: Rng + CryptoRng>(
     csprng: &mut R,
 ) -> Result<SessionRecord> {
     Ok(SessionRecord::new(initialize_alice_session(
-        parameters, csprng,
+        parameters,
+        &SessionPolicy::default(),
+        csprng,
     )?))
 }
 
 pub fn initialize_bob_session_record(
     parameters: &BobSignalProtocolParameters,
 ) -> Result<SessionRecord> {
-    Ok(SessionRecord::new(initialize_bob_session(parameters)?))
+    Ok(SessionRecord::new(initialize_bob_session(
+        parameters,
+        &SessionPolicy::default(),
+    )?))
 }
@@ -12,6 +12,7 @@ use aes_gcm_siv::aead::generic_array::typenum::Unsigned;
 use aes_gcm_siv::{AeadInPlace, Aes256GcmSiv, KeyInit};
 use indexmap::IndexMap;
 use itertools::Itertools;
+use libsignal_gossip::GossipService;
 use prost::Message;
 use proto::sealed_sender::unidentified_sender_message::message::Type as ProtoMessageType;
 use rand::{CryptoRng, Rng, TryRngCore as _};
@@ -19,11 +20,11 @@ use subtle::{Choice, ConstantTimeEq};
 use zerocopy::{FromBytes, Immutable, KnownLayout};
 
 use crate::{
-    Aci, CiphertextMessageType, DeviceId, Direction, IdentityKey, IdentityKeyPair,
+    Aci, CiphertextMessageType, DeviceId, Direction, GossipStatus, IdentityKey, IdentityKeyPair,
     IdentityKeyStore, KeyPair, KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, PrivateKey,
     ProtocolAddress, PublicKey, Result, ServiceId, ServiceIdFixedWidthBinaryBytes, SessionRecord,
     SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore, Timestamp, crypto,
-    message_encrypt, proto, session_cipher,
+    message_encrypt, message_encrypt_with_gossip, proto, session_cipher,
 };
 
 #[derive(Debug, Clone)]
@@ -914,6 +915,39 @@ pub async fn sealed_sender_encrypt<R: Rng + CryptoRng>(
     sealed_sender_encrypt_from_usmc(destination, &usmc, identity_store, rng).await
 }
 
+/// Like [`sealed_sender_encrypt`], but attaches `gossip_service`'s current trusted tree head to the
+/// inner message (see [`message_encrypt_with_gossip`]).
+#[expect(clippy::too_many_arguments)]
+pub async fn sealed_sender_encrypt_with_gossip<R: Rng + CryptoRng>(
+    destination: &ProtocolAddress,
+    sender_cert: &SenderCertificate,
+    ptext: &[u8],
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    gossip_service: &GossipService,
+    now: SystemTime,
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    let message = message_encrypt_with_gossip(
+        ptext,
+        destination,
+        session_store,
+        identity_store,
+        gossip_service,
+        now,
+        rng,
+    )
+    .await?;
+    let usmc = UnidentifiedSenderMessageContent::new(
+        message.message_type(),
+        sender_cert.clone(),
+        message.serialize().to_vec(),
+        ContentHint::Default,
+        None,
+    )?;
+    sealed_sender_encrypt_from_usmc(destination, &usmc, identity_store, rng).await
+}
+
 /// This method implements the single-key single-recipient [KEM] described in [this Signal blog
 /// post], a.k.a. Sealed Sender v1.
 ///
@@ -1363,28 +1397,96 @@ pub async fn sealed_sender_multi_recipient_encrypt<
 where
     X::IntoIter: ExactSizeIterator,
 {
+    let mut serialized = vec![];
     sealed_sender_multi_recipient_encrypt_impl(
         destinations,
         destination_sessions,
         excluded_recipients,
         usmc,
         identity_store,
+        SealedSenderV2Parallelism::GlobalThreadPool,
+        &mut serialized,
+        rng,
+    )
+    .await?;
+    Ok(serialized)
+}
+
+/// How [`sealed_sender_multi_recipient_encrypt_to_writer`] spreads the per-recipient key agreements
+/// across threads.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SealedSenderV2Parallelism<'a> {
+    /// Do all the work on the calling thread.
+    SingleThreaded,
+    /// Use rayon's global thread pool, like [`sealed_sender_multi_recipient_encrypt`].
+    #[default]
+    GlobalThreadPool,
+    /// Use the given thread pool, to keep a very large send from competing with other work.
+    ThreadPool(&'a rayon::ThreadPool),
+}
+
+/// Like [`sealed_sender_multi_recipient_encrypt`], but writes the [sent message] to `output` as it
+/// is produced rather than building it in memory.
+///
+/// Recipients are processed in batches, each of which is written out before the next is started,
+/// so the memory held for the encrypted output is bounded by the batch size rather than growing
+/// with the size of the whole message. The recipients and their sessions are still all passed in
+/// up front. The output is identical to what [`sealed_sender_multi_recipient_encrypt`] would
+/// produce for the same inputs and random number generator.
+///
+/// If the function fails, `output` may already have been partially written to.
+///
+/// [sent message]: SealedSenderV2SentMessage
+#[expect(clippy::too_many_arguments)]
+pub async fn sealed_sender_multi_recipient_encrypt_to_writer<
+    R: Rng + CryptoRng,
+    X: IntoIterator<Item = ServiceId>,
+    W: std::io::Write + ?Sized,
+>(
+    destinations: &[&ProtocolAddress],
+    destination_sessions: &[&SessionRecord],
+    excluded_recipients: X,
+    usmc: &UnidentifiedSenderMessageContent,
+    identity_store: &dyn IdentityKeyStore,
+    parallelism: SealedSenderV2Parallelism<'_>,
+    output: &mut W,
+    rng: &mut R,
+) -> Result<()>
+where
+    X::IntoIter: ExactSizeIterator,
+{
+    sealed_sender_multi_recipient_encrypt_impl(
+        destinations,
+        destination_sessions,
+        excluded_recipients,
+        usmc,
+        identity_store,
+        parallelism,
+        output,
         rng,
     )
     .await
 }
 
+/// How many recipients [`sealed_sender_multi_recipient_encrypt_to_writer`] serializes before
+/// writing them out.
+const SEALED_SENDER_V2_RECIPIENTS_PER_BATCH: usize = 1024;
+
+#[expect(clippy::too_many_arguments)]
 async fn sealed_sender_multi_recipient_encrypt_impl<
     R: Rng + CryptoRng,
     X: IntoIterator<Item = ServiceId>,
+    W: std::io::Write + ?Sized,
 >(
     destinations: &[&ProtocolAddress],
     destination_sessions: &[&SessionRecord],
     excluded_recipients: X,
     usmc: &UnidentifiedSenderMessageContent,
     identity_store: &dyn IdentityKeyStore,
+    parallelism: SealedSenderV2Parallelism<'_>,
+    output: &mut W,
     rng: &mut R,
-) -> Result<Vec<u8>>
+) -> Result<()>
 where
     X::IntoIter: ExactSizeIterator,
 {
@@ -1546,47 +1648,69 @@ where
             Ok(())
         };
 
+    // Fan out to N threads, like Rayon would. But don't bother for less than 6 items.
+    let parallelism_count = match parallelism {
+        SealedSenderV2Parallelism::SingleThreaded => 1,
+        SealedSenderV2Parallelism::GlobalThreadPool => std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1),
+        SealedSenderV2Parallelism::ThreadPool(pool) => pool.current_num_threads(),
+    };
+    let process_batch =
+        |serialized: &mut Vec<u8>, batch: &[(IdentityKey, Range<usize>)]| -> Result<()> {
+            let chunk_size = std::cmp::max(6, batch.len().div_ceil(parallelism_count));
+
+            if parallelism_count == 1 || chunk_size >= batch.len() {
+                return process_chunk(serialized, batch);
+            }
+
+            let mut chunks = batch.chunks(chunk_size);
+            // We'll process the first chunk on the current thread once we've spawned all the others.
+            let first_chunk = chunks.next().expect("at least one chunk, tested above");
+
+            let mut all_outputs = Vec::new();
+            all_outputs.resize_with(chunks.len(), || Ok(vec![]));
+
+            rayon::scope(|scope| -> Result<()> {
+                let mut outputs = &mut all_outputs[..];
+                for chunk in chunks {
+                    let (next_output, remaining_outputs) = outputs
+                        .split_first_mut()
+                        .expect("as many outputs as remaining chunks");
+                    scope.spawn(|_| {
+                        let mut serialized = vec![];
+                        *next_output = process_chunk(&mut serialized, chunk).map(|_| serialized);
+                    });
+                    outputs = remaining_outputs;
+                }
+
+                process_chunk(serialized, first_chunk)
+            })?;
+
+            for chunk_output in all_outputs {
+                serialized.extend(chunk_output?);
+            }
+            Ok(())
+        };
+
     let mut serialized: Vec<u8> = vec![SEALED_SENDER_V2_SERVICE_ID_FULL_VERSION];
 
     let count_of_recipients = identity_keys_and_ranges.len() + excluded_recipients.len();
     prost::encode_length_delimiter(count_of_recipients, &mut serialized)
         .expect("can always resize a Vec");
 
-    // Fan out to N threads, like Rayon would. But don't bother for less than 6 items.
-    let parallelism = std::thread::available_parallelism()
-        .map(usize::from)
-        .unwrap_or(1);
-    let chunk_size = std::cmp::max(6, identity_keys_and_ranges.len().div_ceil(parallelism));
-
-    if parallelism == 1 || chunk_size >= identity_keys_and_ranges.len() {
-        process_chunk(&mut serialized, &identity_keys_and_ranges)?;
-    } else {
-        let mut chunks = identity_keys_and_ranges.chunks(chunk_size);
-        // We'll process the first chunk on the current thread once we've spawned all the others.
-        let first_chunk = chunks.next().expect("at least one chunk, tested above");
-
-        let mut all_outputs = Vec::new();
-        all_outputs.resize_with(chunks.len(), || Ok(vec![]));
-
-        rayon::scope(|scope| -> Result<()> {
-            let mut outputs = &mut all_outputs[..];
-            for chunk in chunks {
-                let (next_output, remaining_outputs) = outputs
-                    .split_first_mut()
-                    .expect("as many outputs as remaining chunks");
-                scope.spawn(|_| {
-                    let mut serialized = vec![];
-                    *next_output = process_chunk(&mut serialized, chunk).map(|_| serialized);
-                });
-                outputs = remaining_outputs;
+    for batch in identity_keys_and_ranges.chunks(SEALED_SENDER_V2_RECIPIENTS_PER_BATCH) {
+        match parallelism {
+            SealedSenderV2Parallelism::ThreadPool(pool) => {
+                pool.install(|| process_batch(&mut serialized, batch))?
             }
-
-            process_chunk(&mut serialized, first_chunk)
-        })?;
-
-        for output in all_outputs {
-            serialized.extend(output?);
+            SealedSenderV2Parallelism::SingleThreaded
+            | SealedSenderV2Parallelism::GlobalThreadPool => process_batch(&mut serialized, batch)?,
         }
+        output
+            .write_all(&serialized)
+            .map_err(SignalProtocolError::Io)?;
+        serialized.clear();
     }
 
     for excluded in excluded_recipients {
@@ -1595,9 +1719,10 @@ where
     }
 
     serialized.extend_from_slice(e_pub.public_key_bytes());
-    serialized.extend_from_slice(&ciphertext);
-
-    Ok(serialized)
+    output
+        .write_all(&serialized)
+        .and_then(|()| output.write_all(&ciphertext))
+        .map_err(SignalProtocolError::Io)
 }
 
 /// Represents a single recipient in an SSv2 SentMessage.
@@ -2010,6 +2135,74 @@ pub async fn sealed_sender_decrypt(
     signed_pre_key_store: &dyn SignedPreKeyStore,
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
 ) -> Result<SealedSenderDecryptionResult> {
+    let (result, _) = sealed_sender_decrypt_impl(
+        ciphertext,
+        trust_root,
+        timestamp,
+        local_e164,
+        local_uuid,
+        local_device_id,
+        identity_store,
+        session_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        None,
+    )
+    .await?;
+    Ok(result)
+}
+
+/// Like [`sealed_sender_decrypt`], but also checks any gossip attached to the inner message using
+/// `gossip_service`.
+#[expect(clippy::too_many_arguments)]
+pub async fn sealed_sender_decrypt_with_gossip(
+    ciphertext: &[u8],
+    trust_root: &PublicKey,
+    timestamp: Timestamp,
+    local_e164: Option<String>,
+    local_uuid: String,
+    local_device_id: DeviceId,
+    identity_store: &mut dyn IdentityKeyStore,
+    session_store: &mut dyn SessionStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    gossip_service: &mut GossipService,
+    now: SystemTime,
+) -> Result<(SealedSenderDecryptionResult, GossipStatus)> {
+    sealed_sender_decrypt_impl(
+        ciphertext,
+        trust_root,
+        timestamp,
+        local_e164,
+        local_uuid,
+        local_device_id,
+        identity_store,
+        session_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        Some((gossip_service, now)),
+    )
+    .await
+}
+
+#[expect(clippy::too_many_arguments)]
+async fn sealed_sender_decrypt_impl(
+    ciphertext: &[u8],
+    trust_root: &PublicKey,
+    timestamp: Timestamp,
+    local_e164: Option<String>,
+    local_uuid: String,
+    local_device_id: DeviceId,
+    identity_store: &mut dyn IdentityKeyStore,
+    session_store: &mut dyn SessionStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    gossip: Option<(&mut GossipService, SystemTime)>,
+) -> Result<(SealedSenderDecryptionResult, GossipStatus)> {
     let usmc = sealed_sender_decrypt_to_usmc(ciphertext, identity_store).await?;
 
     if !usmc.sender()?.validate(trust_root, timestamp)? {
@@ -2036,21 +2229,22 @@ pub async fn sealed_sender_decrypt(
         usmc.sender()?.sender_device_id()?,
     );
 
-    let message = match usmc.msg_type()? {
+    let (message, gossip_status) = match usmc.msg_type()? {
         CiphertextMessageType::Whisper => {
             let ctext = SignalMessage::try_from(usmc.contents()?)?;
-            session_cipher::message_decrypt_signal(
+            session_cipher::message_decrypt_signal_impl(
                 &ctext,
                 &remote_address,
                 session_store,
                 identity_store,
+                gossip,
                 &mut rng,
             )
             .await?
         }
         CiphertextMessageType::PreKey => {
             let ctext = PreKeySignalMessage::try_from(usmc.contents()?)?;
-            session_cipher::message_decrypt_prekey(
+            session_cipher::message_decrypt_prekey_impl(
                 &ctext,
                 &remote_address,
                 session_store,
@@ -2058,6 +2252,7 @@ pub async fn sealed_sender_decrypt(
                 pre_key_store,
                 signed_pre_key_store,
                 kyber_pre_key_store,
+                gossip,
                 &mut rng,
             )
             .await?
@@ -2070,12 +2265,13 @@ pub async fn sealed_sender_decrypt(
         }
     };
 
-    Ok(SealedSenderDecryptionResult {
+    let result = SealedSenderDecryptionResult {
         sender_uuid: usmc.sender()?.sender_uuid()?.to_string(),
         sender_e164: usmc.sender()?.sender_e164()?.map(|s| s.to_string()),
         device_id: usmc.sender()?.sender_device_id()?,
         message,
-    })
+    };
+    Ok((result, gossip_status))
 }
 
 #[test]
@@ -0,0 +1,252 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Splitting a [Sealed Sender v2 sent message](SealedSenderV2SentMessage) into the messages for
+//! each recipient, the way the server does.
+
+use std::collections::{BTreeSet, HashMap};
+use std::hash::BuildHasher;
+
+use displaydoc::Display;
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::{DeviceId, Result, SealedSenderV2SentMessage, ServiceId};
+
+/// The devices registered for each account, as the server knows them.
+pub trait SealedSenderV2DeviceRegistry {
+    /// The devices registered for `service_id`, each with its registration ID, or `None` if there
+    /// is no such account.
+    fn registered_devices(&self, service_id: &ServiceId) -> Option<Vec<(DeviceId, u32)>>;
+}
+
+impl<S: BuildHasher> SealedSenderV2DeviceRegistry for HashMap<ServiceId, Vec<(DeviceId, u32)>, S> {
+    fn registered_devices(&self, service_id: &ServiceId) -> Option<Vec<(DeviceId, u32)>> {
+        self.get(service_id).cloned()
+    }
+}
+
+/// A recipient whose devices in the message don't match their registered devices.
+///
+/// Serializes like an entry in the server's 409 response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MismatchedDevices {
+    pub service_id: ServiceId,
+    /// Registered devices the message wasn't encrypted for, in ascending order.
+    pub missing_devices: Vec<DeviceId>,
+    /// Devices the message was encrypted for that aren't registered, in ascending order.
+    pub extra_devices: Vec<DeviceId>,
+}
+
+/// A recipient with devices whose registration ID has changed since the sender's session was set
+/// up.
+///
+/// Serializes like an entry in the server's 410 response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaleDevices {
+    pub service_id: ServiceId,
+    /// The devices with a different registration ID, in ascending order.
+    pub stale_devices: Vec<DeviceId>,
+}
+
+/// Why [`SealedSenderV2Fanout::validate`] refused to deliver a message.
+///
+/// Each case corresponds to one of the server's error responses; see
+/// [`status_code`](Self::status_code).
+#[derive(Clone, Debug, Display, Error, PartialEq, Eq)]
+pub enum SealedSenderV2FanoutRejection {
+    /// some recipients are not registered
+    UnknownRecipients(Vec<ServiceId>),
+    /// some recipients' devices do not match their registered devices
+    MismatchedDevices(Vec<MismatchedDevices>),
+    /// some recipients' devices have changed registration IDs
+    StaleDevices(Vec<StaleDevices>),
+}
+
+impl SealedSenderV2FanoutRejection {
+    /// The HTTP status the server responds with in this case.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Self::UnknownRecipients(_) => 404,
+            Self::MismatchedDevices(_) => 409,
+            Self::StaleDevices(_) => 410,
+        }
+    }
+}
+
+/// The message to deliver to one recipient of a [`SealedSenderV2Fanout`].
+#[derive(Clone, Debug)]
+pub struct SealedSenderV2Delivery {
+    pub service_id: ServiceId,
+    /// Every device to deliver [`message`](Self::message) to, in ascending order.
+    pub devices: Vec<DeviceId>,
+    /// A received message, as passed to [`sealed_sender_decrypt`](crate::sealed_sender_decrypt).
+    ///
+    /// It is the same for all of the recipient's devices.
+    pub message: Vec<u8>,
+}
+
+/// A parsed multi-recipient message, ready to be checked and split up like the server would.
+///
+/// This is meant for servers, and for tests that need to stand in for one.
+pub struct SealedSenderV2Fanout<'a> {
+    message: SealedSenderV2SentMessage<'a>,
+}
+
+impl<'a> SealedSenderV2Fanout<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        Ok(Self {
+            message: SealedSenderV2SentMessage::parse(data)?,
+        })
+    }
+
+    pub fn message(&self) -> &SealedSenderV2SentMessage<'a> {
+        &self.message
+    }
+
+    /// Recipients the sender deliberately left out of the message.
+    ///
+    /// They were encoded without any devices, and are neither validated nor delivered to.
+    pub fn excluded_recipients(&self) -> impl Iterator<Item = ServiceId> + '_ {
+        self.message
+            .recipients
+            .iter()
+            .filter(|(_, recipient)| recipient.devices.is_empty())
+            .map(|(service_id, _)| *service_id)
+    }
+
+    /// Checks the message's recipients against `registry`, and if they match, produces the
+    /// message for each recipient.
+    ///
+    /// The checks happen in the same order as on the server: first for unknown recipients, then
+    /// for recipients with missing or extra devices, then for recipients with stale registration
+    /// IDs. Only the first kind of problem found is reported, but it is reported for every
+    /// recipient it applies to.
+    pub fn validate(
+        &self,
+        registry: &dyn SealedSenderV2DeviceRegistry,
+    ) -> std::result::Result<Vec<SealedSenderV2Delivery>, SealedSenderV2FanoutRejection> {
+        let mut unknown = vec![];
+        let mut mismatched = vec![];
+        let mut stale = vec![];
+
+        for (service_id, recipient) in &self.message.recipients {
+            if recipient.devices.is_empty() {
+                continue;
+            }
+            let Some(registered) = registry.registered_devices(service_id) else {
+                unknown.push(*service_id);
+                continue;
+            };
+
+            let sent_ids: BTreeSet<DeviceId> =
+                recipient.devices.iter().map(|(id, _)| *id).collect();
+            let registered_ids: BTreeSet<DeviceId> = registered.iter().map(|(id, _)| *id).collect();
+            if sent_ids != registered_ids {
+                mismatched.push(MismatchedDevices {
+                    service_id: *service_id,
+                    missing_devices: registered_ids.difference(&sent_ids).copied().collect(),
+                    extra_devices: sent_ids.difference(&registered_ids).copied().collect(),
+                });
+                continue;
+            }
+
+            let stale_ids: BTreeSet<DeviceId> = recipient
+                .devices
+                .iter()
+                .filter(|(device_id, registration_id)| {
+                    !registered.contains(&(*device_id, u32::from(*registration_id)))
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            if !stale_ids.is_empty() {
+                stale.push(StaleDevices {
+                    service_id: *service_id,
+                    stale_devices: stale_ids.into_iter().collect(),
+                });
+            }
+        }
+
+        if !unknown.is_empty() {
+            return Err(SealedSenderV2FanoutRejection::UnknownRecipients(unknown));
+        }
+        if !mismatched.is_empty() {
+            return Err(SealedSenderV2FanoutRejection::MismatchedDevices(mismatched));
+        }
+        if !stale.is_empty() {
+            return Err(SealedSenderV2FanoutRejection::StaleDevices(stale));
+        }
+
+        Ok(self
+            .message
+            .recipients
+            .iter()
+            .filter(|(_, recipient)| !recipient.devices.is_empty())
+            .map(|(service_id, recipient)| {
+                let devices: BTreeSet<DeviceId> =
+                    recipient.devices.iter().map(|(id, _)| *id).collect();
+                SealedSenderV2Delivery {
+                    service_id: *service_id,
+                    devices: devices.into_iter().collect(),
+                    message: self
+                        .message
+                        .received_message_parts_for_recipient(recipient)
+                        .as_ref()
+                        .concat(),
+                }
+            })
+            .collect())
+    }
+}
+
+/// The shape of each entry in the server's 409 and 410 responses.
+#[derive(Serialize)]
+struct RecipientDevicesResponse<D> {
+    uuid: String,
+    devices: D,
+}
+
+fn device_numbers(devices: &[DeviceId]) -> Vec<u8> {
+    devices
+        .iter()
+        .map(|device_id| u8::from(*device_id))
+        .collect()
+}
+
+impl Serialize for MismatchedDevices {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Devices {
+            missing_devices: Vec<u8>,
+            extra_devices: Vec<u8>,
+        }
+        RecipientDevicesResponse {
+            uuid: self.service_id.service_id_string(),
+            devices: Devices {
+                missing_devices: device_numbers(&self.missing_devices),
+                extra_devices: device_numbers(&self.extra_devices),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Serialize for StaleDevices {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Devices {
+            stale_devices: Vec<u8>,
+        }
+        RecipientDevicesResponse {
+            uuid: self.service_id.service_id_string(),
+            devices: Devices {
+                stale_devices: device_numbers(&self.stale_devices),
+            },
+        }
+        .serialize(serializer)
+    }
+}
@@ -0,0 +1,439 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Deciding when to rotate this client's sender keys, and who still needs them.
+
+use std::time::{Duration, SystemTime};
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+use uuid::Uuid;
+
+use crate::proto::storage::SenderKeyManagerStateStructure;
+use crate::proto::storage::sender_key_manager_state_structure::{
+    Distribution as DistributionStructure, Recipient as RecipientStructure,
+};
+use crate::{
+    DeviceId, ProtocolAddress, Result, SenderKeyDistributionMessage, SenderKeyMessage,
+    SenderKeyStore, SignalProtocolError, Timestamp, create_sender_key_distribution_message,
+    group_encrypt, rotate_sender_key,
+};
+
+/// How a [`SenderKeyManager`] decides when a sender key chain has been used enough.
+#[derive(Clone, Debug)]
+pub struct SenderKeyManagerConfig {
+    /// How many messages are sent with a chain before it is replaced.
+    pub max_messages_per_chain: u32,
+    /// How long a chain is used before it is replaced.
+    pub max_chain_age: Duration,
+}
+
+impl Default for SenderKeyManagerConfig {
+    fn default() -> Self {
+        Self {
+            max_messages_per_chain: 1000,
+            max_chain_age: Duration::from_secs(60 * 60 * 24 * 7),
+        }
+    }
+}
+
+/// What a [`SenderKeyManager`] needs to remember between runs: for each distribution ID, the
+/// current chain, how much it has been used, and who has received it.
+#[derive(Clone, Debug, Default)]
+pub struct SenderKeyManagerState {
+    state: SenderKeyManagerStateStructure,
+}
+
+impl SenderKeyManagerState {
+    /// The state for a client that hasn't sent with any sender keys yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let state = SenderKeyManagerStateStructure::decode(data)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        for distribution in &state.distributions {
+            Uuid::from_slice(&distribution.distribution_id)
+                .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+            for recipient in distribution
+                .recipients
+                .iter()
+                .chain(&distribution.distributed_to)
+            {
+                recipient_address(recipient)?;
+            }
+        }
+        Ok(Self { state })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.state.encode_to_vec()
+    }
+
+    /// Every distribution ID being tracked.
+    pub fn distribution_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.state.distributions.iter().map(|distribution| {
+            Uuid::from_slice(&distribution.distribution_id).expect("checked on creation")
+        })
+    }
+
+    /// The chain currently used for `distribution_id`, if it is being tracked.
+    pub fn chain_id(&self, distribution_id: Uuid) -> Option<u32> {
+        self.find(distribution_id)
+            .map(|distribution| distribution.chain_id)
+    }
+
+    /// How many messages have been sent with the current chain for `distribution_id`.
+    pub fn messages_sent(&self, distribution_id: Uuid) -> Option<u32> {
+        self.find(distribution_id)
+            .map(|distribution| distribution.messages_sent)
+    }
+
+    fn find(&self, distribution_id: Uuid) -> Option<&DistributionStructure> {
+        self.state
+            .distributions
+            .iter()
+            .find(|distribution| distribution.distribution_id == distribution_id.as_bytes())
+    }
+
+    fn find_mut(&mut self, distribution_id: Uuid) -> Option<&mut DistributionStructure> {
+        self.state
+            .distributions
+            .iter_mut()
+            .find(|distribution| distribution.distribution_id == distribution_id.as_bytes())
+    }
+}
+
+/// The outcome of [`SenderKeyManager::prepare_send`].
+#[derive(Clone, Debug)]
+pub struct SenderKeySendPreparation {
+    /// The distribution message for the chain the next message will be sent with.
+    pub distribution_message: SenderKeyDistributionMessage,
+    /// The recipients that need `distribution_message` before they can decrypt the next message.
+    ///
+    /// Once it has been delivered to them, call [`SenderKeyManager::mark_distributed`].
+    pub needs_distribution: Vec<ProtocolAddress>,
+    /// Whether a new chain was started, in which case every recipient needs the distribution
+    /// message.
+    pub rotated: bool,
+}
+
+/// Keeps track of this client's sender keys according to a [`SenderKeyManagerConfig`].
+///
+/// Before each group send, call [`prepare_send`](Self::prepare_send) with every device the message
+/// is for. It replaces the chain if a device was removed since the last send or the chain has been
+/// used too much, and says which devices still need the distribution message. Once those have
+/// been delivered, call [`mark_distributed`](Self::mark_distributed), then encrypt with
+/// [`group_encrypt`](Self::group_encrypt). Persist [`state`](Self::state) after each step.
+#[derive(Debug)]
+pub struct SenderKeyManager {
+    sender: ProtocolAddress,
+    config: SenderKeyManagerConfig,
+    state: SenderKeyManagerState,
+}
+
+impl SenderKeyManager {
+    /// Manage the sender keys `sender` sends with, starting from `state`.
+    pub fn new(
+        sender: ProtocolAddress,
+        config: SenderKeyManagerConfig,
+        state: SenderKeyManagerState,
+    ) -> Result<Self> {
+        if config.max_messages_per_chain == 0 {
+            return Err(SignalProtocolError::InvalidArgument(
+                "max_messages_per_chain must be at least 1".to_owned(),
+            ));
+        }
+        if config.max_chain_age.is_zero() {
+            return Err(SignalProtocolError::InvalidArgument(
+                "max_chain_age must not be zero".to_owned(),
+            ));
+        }
+        Ok(Self {
+            sender,
+            config,
+            state,
+        })
+    }
+
+    pub fn sender(&self) -> &ProtocolAddress {
+        &self.sender
+    }
+
+    pub fn config(&self) -> &SenderKeyManagerConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> &SenderKeyManagerState {
+        &self.state
+    }
+
+    /// Gets the chain for `distribution_id` ready to send to `recipients`.
+    ///
+    /// A new chain is started if:
+    /// - the distribution ID isn't being tracked yet, since whoever received an existing chain is
+    ///   unknown,
+    /// - a recipient of an earlier send is missing from `recipients`, or was
+    ///   [removed](Self::remove_recipient),
+    /// - the chain has been used for [`max_messages_per_chain`] messages or is older than
+    ///   [`max_chain_age`], or
+    /// - `sender_key_store` no longer has the tracked chain.
+    ///
+    /// [`max_messages_per_chain`]: SenderKeyManagerConfig::max_messages_per_chain
+    /// [`max_chain_age`]: SenderKeyManagerConfig::max_chain_age
+    pub async fn prepare_send<R: Rng + CryptoRng>(
+        &mut self,
+        distribution_id: Uuid,
+        recipients: &[ProtocolAddress],
+        sender_key_store: &mut dyn SenderKeyStore,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<SenderKeySendPreparation> {
+        let mut recipients = recipients.to_vec();
+        recipients.sort();
+        recipients.dedup();
+
+        let config = &self.config;
+        let is_worn_out = |distribution: &DistributionStructure| {
+            distribution.needs_rotation
+                || distribution.messages_sent >= config.max_messages_per_chain
+                // An expiry too far off for `SystemTime` to represent counts as never.
+                || timestamp_to_time(distribution.created_at)
+                    .and_then(|created_at| created_at.checked_add(config.max_chain_age))
+                    .is_some_and(|expires_at| expires_at <= now)
+                || distribution.recipients.iter().any(|previous| {
+                    recipient_address(previous).is_ok_and(|address| !recipients.contains(&address))
+                })
+        };
+
+        let current = match self.state.find(distribution_id) {
+            Some(distribution) if !is_worn_out(distribution) => {
+                let message = create_sender_key_distribution_message(
+                    &self.sender,
+                    distribution_id,
+                    sender_key_store,
+                    csprng,
+                )
+                .await?;
+                (message.chain_id()? == distribution.chain_id).then_some(message)
+            }
+            _ => None,
+        };
+        let rotated = current.is_none();
+        let distribution_message = match current {
+            Some(message) => message,
+            None => {
+                rotate_sender_key(&self.sender, distribution_id, sender_key_store, csprng).await?
+            }
+        };
+
+        let distribution = match self.state.find_mut(distribution_id) {
+            Some(distribution) => distribution,
+            None => {
+                self.state.state.distributions.push(DistributionStructure {
+                    distribution_id: distribution_id.as_bytes().to_vec(),
+                    ..Default::default()
+                });
+                self.state
+                    .state
+                    .distributions
+                    .last_mut()
+                    .expect("just added")
+            }
+        };
+        if rotated {
+            *distribution = DistributionStructure {
+                distribution_id: distribution_id.as_bytes().to_vec(),
+                chain_id: distribution_message.chain_id()?,
+                created_at: time_to_timestamp(now).epoch_millis(),
+                ..Default::default()
+            };
+        }
+        distribution.recipients = recipients.iter().map(recipient_structure).collect();
+
+        Ok(SenderKeySendPreparation {
+            distribution_message,
+            needs_distribution: needing_distribution(distribution)?,
+            rotated,
+        })
+    }
+
+    /// Records that the current chain for `distribution_id` has been delivered to `recipients`.
+    pub fn mark_distributed<'a>(
+        &mut self,
+        distribution_id: Uuid,
+        recipients: impl IntoIterator<Item = &'a ProtocolAddress>,
+    ) -> Result<()> {
+        let distribution = self
+            .state
+            .find_mut(distribution_id)
+            .ok_or_else(|| not_prepared("mark_distributed", distribution_id))?;
+        for recipient in recipients {
+            let recipient = recipient_structure(recipient);
+            if !distribution.distributed_to.contains(&recipient) {
+                distribution.distributed_to.push(recipient);
+            }
+        }
+        Ok(())
+    }
+
+    /// The recipients of the last [`prepare_send`](Self::prepare_send) for `distribution_id` that
+    /// haven't been [marked](Self::mark_distributed) as having the current chain.
+    pub fn recipients_needing_distribution(
+        &self,
+        distribution_id: Uuid,
+    ) -> Result<Vec<ProtocolAddress>> {
+        match self.state.find(distribution_id) {
+            Some(distribution) => needing_distribution(distribution),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Stops sending to `recipient`, such as when they leave every group or unlink a device.
+    ///
+    /// Every chain `recipient` may have received will be replaced before it is used again. Returns
+    /// the affected distribution IDs.
+    pub fn remove_recipient(&mut self, recipient: &ProtocolAddress) -> Vec<Uuid> {
+        let recipient = recipient_structure(recipient);
+        let mut affected = vec![];
+        for distribution in &mut self.state.state.distributions {
+            let was_recipient = distribution.recipients.contains(&recipient)
+                || distribution.distributed_to.contains(&recipient);
+            if was_recipient {
+                distribution.recipients.retain(|r| *r != recipient);
+                distribution.distributed_to.retain(|r| *r != recipient);
+                distribution.needs_rotation = true;
+                affected.push(
+                    Uuid::from_slice(&distribution.distribution_id).expect("checked on creation"),
+                );
+            }
+        }
+        affected
+    }
+
+    /// Stops tracking `distribution_id`, such as when leaving the group.
+    pub fn forget(&mut self, distribution_id: Uuid) {
+        self.state
+            .state
+            .distributions
+            .retain(|distribution| distribution.distribution_id != distribution_id.as_bytes());
+    }
+
+    /// Encrypts `plaintext` with the chain [prepared](Self::prepare_send) for `distribution_id`,
+    /// counting it towards [`max_messages_per_chain`].
+    ///
+    /// Fails with [`SignalProtocolError::InvalidState`] if the distribution ID hasn't been
+    /// prepared, or if `sender_key_store` has a different chain than the one prepared.
+    ///
+    /// [`max_messages_per_chain`]: SenderKeyManagerConfig::max_messages_per_chain
+    pub async fn group_encrypt<R: Rng + CryptoRng>(
+        &mut self,
+        distribution_id: Uuid,
+        plaintext: &[u8],
+        sender_key_store: &mut dyn SenderKeyStore,
+        csprng: &mut R,
+    ) -> Result<SenderKeyMessage> {
+        let distribution = self
+            .state
+            .find_mut(distribution_id)
+            .ok_or_else(|| not_prepared("group_encrypt", distribution_id))?;
+        let stored_chain_id = sender_key_store
+            .load_sender_key(&self.sender, distribution_id)
+            .await?
+            .and_then(|record| record.sender_key_state().ok().map(|state| state.chain_id()));
+        if distribution.needs_rotation || stored_chain_id != Some(distribution.chain_id) {
+            return Err(not_prepared("group_encrypt", distribution_id));
+        }
+
+        let message = group_encrypt(
+            sender_key_store,
+            &self.sender,
+            distribution_id,
+            plaintext,
+            csprng,
+        )
+        .await?;
+        distribution.messages_sent = distribution.messages_sent.saturating_add(1);
+        Ok(message)
+    }
+}
+
+fn not_prepared(method: &'static str, distribution_id: Uuid) -> SignalProtocolError {
+    SignalProtocolError::InvalidState(
+        method,
+        format!("sender key for distribution {distribution_id} has not been prepared"),
+    )
+}
+
+fn needing_distribution(distribution: &DistributionStructure) -> Result<Vec<ProtocolAddress>> {
+    distribution
+        .recipients
+        .iter()
+        .filter(|recipient| !distribution.distributed_to.contains(recipient))
+        .map(recipient_address)
+        .collect()
+}
+
+fn recipient_structure(address: &ProtocolAddress) -> RecipientStructure {
+    RecipientStructure {
+        name: address.name().to_owned(),
+        device_id: address.device_id().into(),
+    }
+}
+
+fn recipient_address(recipient: &RecipientStructure) -> Result<ProtocolAddress> {
+    let device_id = DeviceId::try_from(recipient.device_id)
+        .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+    Ok(ProtocolAddress::new(recipient.name.clone(), device_id))
+}
+
+fn timestamp_to_time(epoch_millis: u64) -> Option<SystemTime> {
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_millis(epoch_millis))
+}
+
+fn time_to_timestamp(time: SystemTime) -> Timestamp {
+    Timestamp::from_epoch_millis(
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_state_is_rejected() {
+        let valid_distribution = DistributionStructure {
+            distribution_id: Uuid::nil().as_bytes().to_vec(),
+            ..Default::default()
+        };
+        for distribution in [
+            DistributionStructure {
+                distribution_id: vec![1, 2, 3],
+                ..Default::default()
+            },
+            DistributionStructure {
+                recipients: vec![RecipientStructure {
+                    name: "+14151111112".to_owned(),
+                    device_id: 0,
+                }],
+                ..valid_distribution.clone()
+            },
+        ] {
+            let state = SenderKeyManagerStateStructure {
+                distributions: vec![valid_distribution.clone(), distribution],
+            };
+            assert!(matches!(
+                SenderKeyManagerState::deserialize(&state.encode_to_vec()),
+                Err(SignalProtocolError::InvalidProtobufEncoding)
+            ));
+        }
+    }
+}
@@ -9,8 +9,9 @@ use itertools::Itertools;
 use prost::Message;
 
 use crate::crypto::hmac_sha256;
+use crate::describe::{KeyFingerprint, SenderKeyRecordDescription, SenderKeyStateDescription};
 use crate::proto::storage as storage_proto;
-use crate::{PrivateKey, PublicKey, SignalProtocolError, consts};
+use crate::{PrivateKey, PublicKey, SessionPolicy, SignalProtocolError, consts};
 
 /// A distinct error type to keep from accidentally propagating deserialization errors.
 #[derive(Debug)]
@@ -208,15 +209,35 @@ impl SenderKeyState {
         }
     }
 
+    pub(crate) fn describe(&self) -> SenderKeyStateDescription {
+        let signing_key = self.state.sender_signing_key.as_ref();
+        SenderKeyStateDescription {
+            message_version: self.message_version(),
+            chain_id: self.chain_id(),
+            iteration: self
+                .state
+                .sender_chain_key
+                .as_ref()
+                .map(|chain_key| chain_key.iteration),
+            skipped_message_keys: self.state.sender_message_keys.len(),
+            signing_key: signing_key.and_then(|key| KeyFingerprint::of_optional(&key.public)),
+            has_signing_private_key: signing_key.is_some_and(|key| !key.private.is_empty()),
+        }
+    }
+
     pub(crate) fn as_protobuf(&self) -> storage_proto::SenderKeyStateStructure {
         self.state.clone()
     }
 
-    pub(crate) fn add_sender_message_key(&mut self, sender_message_key: &SenderMessageKey) {
+    pub(crate) fn add_sender_message_key(
+        &mut self,
+        sender_message_key: &SenderMessageKey,
+        policy: &SessionPolicy,
+    ) {
         self.state
             .sender_message_keys
             .push(sender_message_key.as_protobuf());
-        while self.state.sender_message_keys.len() > consts::MAX_MESSAGE_KEYS {
+        while self.state.sender_message_keys.len() > policy.max_message_keys() {
             self.state.sender_message_keys.remove(0);
         }
     }
@@ -291,6 +312,7 @@ impl SenderKeyRecord {
         self.states.iter().map(|state| state.chain_id())
     }
 
+    #[expect(clippy::too_many_arguments)]
     pub(crate) fn add_sender_key_state(
         &mut self,
         message_version: u8,
@@ -299,6 +321,7 @@ impl SenderKeyRecord {
         chain_key: &[u8],
         signature_key: PublicKey,
         signature_private_key: Option<PrivateKey>,
+        policy: &SessionPolicy,
     ) {
         let existing_state = self.remove_state(chain_id, signature_key);
 
@@ -320,7 +343,7 @@ impl SenderKeyRecord {
             Some(state) => state,
         };
 
-        while self.states.len() >= consts::MAX_SENDER_KEY_STATES {
+        while self.states.len() >= policy.max_sender_key_states() {
             self.states.pop_back();
         }
 
@@ -347,6 +370,38 @@ impl SenderKeyRecord {
         initial_length - self.states.len()
     }
 
+    /// Whether storing `self` in place of `existing` could lose state.
+    ///
+    /// That's the case if `self` is missing the newest chain in `existing`, or is behind on any
+    /// chain they share.
+    pub(crate) fn is_older_than(&self, existing: &SenderKeyRecord) -> bool {
+        fn iteration(state: &SenderKeyState) -> Option<u32> {
+            state
+                .state
+                .sender_chain_key
+                .as_ref()
+                .map(|chain_key| chain_key.iteration)
+        }
+
+        existing.states.iter().enumerate().any(|(i, theirs)| {
+            match self
+                .states
+                .iter()
+                .find(|ours| ours.chain_id() == theirs.chain_id())
+            {
+                Some(ours) => iteration(ours) < iteration(theirs),
+                None => i == 0,
+            }
+        })
+    }
+
+    /// Summarizes the record for debugging, without any secrets.
+    pub fn describe(&self) -> SenderKeyRecordDescription {
+        SenderKeyRecordDescription {
+            states: self.states.iter().map(SenderKeyState::describe).collect(),
+        }
+    }
+
     pub(crate) fn as_protobuf(&self) -> storage_proto::SenderKeyRecordStructure {
         let mut states = Vec::with_capacity(self.states.len());
         for state in &self.states {
@@ -395,8 +450,15 @@ mod sender_key_record_add_sender_key_state_tests {
         /// method under test in this module.
         fn add_sender_key_state_record(&mut self, record_key: (PublicKey, u32), chain_key: &[u8]) {
             let (public_key, chain_id) = record_key;
-            self.sender_key_record
-                .add_sender_key_state(1, chain_id, 1, chain_key, public_key, None);
+            self.sender_key_record.add_sender_key_state(
+                1,
+                chain_id,
+                1,
+                chain_key,
+                public_key,
+                None,
+                &SessionPolicy::default(),
+            );
         }
 
         fn assert_number_of_states(&self, expected: usize) {
@@ -496,7 +558,7 @@ mod sender_key_record_add_sender_key_state_tests {
     fn when_exceed_maximum_states_then_oldest_is_ejected() {
         assert_eq!(
             5,
-            consts::MAX_SENDER_KEY_STATES,
+            SessionPolicy::default().max_sender_key_states(),
             "Test written to expect this limit"
         );
 
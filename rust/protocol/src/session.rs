@@ -11,10 +11,10 @@ use crate::protocol::CIPHERTEXT_MESSAGE_PRE_KYBER_VERSION;
 use crate::ratchet::{AliceSignalProtocolParameters, BobSignalProtocolParameters};
 use crate::state::GenericSignedPreKey;
 use crate::{
-    CiphertextMessageType, Direction, IdentityKey, IdentityKeyStore, KeyPair, KyberPreKeyId,
-    KyberPreKeyStore, PreKeyBundle, PreKeyId, PreKeySignalMessage, PreKeyStore, ProtocolAddress,
-    Result, SessionRecord, SessionStore, SignalProtocolError, SignedPreKeyId, SignedPreKeyStore,
-    ratchet,
+    CiphertextMessageType, Direction, IdentityKey, IdentityKeyStore, IdentitySource, KeyPair,
+    KyberPreKeyId, KyberPreKeyStore, PreKeyBundle, PreKeyId, PreKeySignalMessage, PreKeyStore,
+    ProtocolAddress, Result, SessionPolicy, SessionRecord, SessionStore, SignalProtocolError,
+    SignedPreKeyId, SignedPreKeyStore, ratchet,
 };
 
 pub struct PreKeysUsed {
@@ -43,6 +43,8 @@ its reference to the various data stores, instead the functions are
 free standing.
  */
 
+/// Sets up `session_record` for an incoming pre-key message, using the default
+/// [`SessionPolicy`].
 pub async fn process_prekey<'a>(
     message: &'a PreKeySignalMessage,
     remote_address: &'a ProtocolAddress,
@@ -51,6 +53,30 @@ pub async fn process_prekey<'a>(
     pre_key_store: &dyn PreKeyStore,
     signed_prekey_store: &dyn SignedPreKeyStore,
     kyber_prekey_store: &dyn KyberPreKeyStore,
+) -> Result<(Option<PreKeysUsed>, IdentityToSave<'a>)> {
+    process_prekey_with_policy(
+        message,
+        remote_address,
+        session_record,
+        identity_store,
+        pre_key_store,
+        signed_prekey_store,
+        kyber_prekey_store,
+        &SessionPolicy::default(),
+    )
+    .await
+}
+
+#[expect(clippy::too_many_arguments)]
+pub(crate) async fn process_prekey_with_policy<'a>(
+    message: &'a PreKeySignalMessage,
+    remote_address: &'a ProtocolAddress,
+    session_record: &mut SessionRecord,
+    identity_store: &dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_prekey_store: &dyn SignedPreKeyStore,
+    kyber_prekey_store: &dyn KyberPreKeyStore,
+    policy: &SessionPolicy,
 ) -> Result<(Option<PreKeysUsed>, IdentityToSave<'a>)> {
     let their_identity_key = message.identity_key();
 
@@ -71,6 +97,7 @@ pub async fn process_prekey<'a>(
         kyber_prekey_store,
         pre_key_store,
         identity_store,
+        policy,
     )
     .await?;
 
@@ -90,10 +117,12 @@ async fn process_prekey_impl(
     kyber_prekey_store: &dyn KyberPreKeyStore,
     pre_key_store: &dyn PreKeyStore,
     identity_store: &dyn IdentityKeyStore,
+    policy: &SessionPolicy,
 ) -> Result<Option<PreKeysUsed>> {
     if session_record.promote_matching_session(
         message.message_version() as u32,
         &message.base_key().serialize(),
+        policy,
     )? {
         // We've already set up a session for this message, we can exit early.
         return Ok(None);
@@ -111,22 +140,39 @@ async fn process_prekey_impl(
         ));
     }
 
+    let kyber_pre_key_id =
+        message
+            .kyber_pre_key_id()
+            .ok_or(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::PreKey,
+                "missing pq pre-key ID",
+            ))?;
+
+    // A last-resort Kyber pre-key isn't deleted after use, so without this the same message could
+    // set up a fresh session again, e.g. after the first one has been archived or deleted.
+    if kyber_prekey_store
+        .is_base_key_used(
+            kyber_pre_key_id,
+            message.signed_pre_key_id(),
+            message.base_key(),
+        )
+        .await?
+    {
+        return Err(SignalProtocolError::ReusedBaseKey(
+            kyber_pre_key_id,
+            message.signed_pre_key_id(),
+        ));
+    }
+
     let our_signed_pre_key_pair = signed_prekey_store
         .get_signed_pre_key(message.signed_pre_key_id())
         .await?
         .key_pair()?;
 
-    let our_kyber_pre_key_pair = if let Some(kyber_pre_key_id) = message.kyber_pre_key_id() {
-        kyber_prekey_store
-            .get_kyber_pre_key(kyber_pre_key_id)
-            .await?
-            .key_pair()?
-    } else {
-        return Err(SignalProtocolError::InvalidMessage(
-            CiphertextMessageType::PreKey,
-            "missing pq pre-key ID",
-        ));
-    };
+    let our_kyber_pre_key_pair = kyber_prekey_store
+        .get_kyber_pre_key(kyber_pre_key_id)
+        .await?
+        .key_pair()?;
     let kyber_ciphertext =
         message
             .kyber_ciphertext()
@@ -154,12 +200,12 @@ async fn process_prekey_impl(
         kyber_ciphertext,
     );
 
-    let mut new_session = ratchet::initialize_bob_session(&parameters)?;
+    let mut new_session = ratchet::initialize_bob_session(&parameters, policy)?;
 
     new_session.set_local_registration_id(identity_store.get_local_registration_id().await?);
     new_session.set_remote_registration_id(message.registration_id());
 
-    session_record.promote_state(new_session);
+    session_record.promote_state(new_session, policy);
 
     let pre_keys_used = PreKeysUsed {
         one_time_ec_pre_key_id: message.pre_key_id(),
@@ -177,6 +223,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
     now: SystemTime,
     mut csprng: &mut R,
 ) -> Result<()> {
+    let policy = &session_store.session_policy();
     let their_identity_key = bundle.identity_key()?;
 
     if !identity_store
@@ -227,7 +274,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         parameters.set_their_one_time_pre_key(key);
     }
 
-    let mut session = ratchet::initialize_alice_session(&parameters, csprng)?;
+    let mut session = ratchet::initialize_alice_session(&parameters, policy, csprng)?;
 
     log::info!(
         "set_unacknowledged_pre_key_message for: {} with preKeyId: {}",
@@ -249,8 +296,21 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
     identity_store
         .save_identity(remote_address, their_identity_key)
         .await?;
+    // The identity has been saved by now, so a failure to record it in its history shouldn't
+    // stop the session from being stored as well.
+    if let Err(e) = identity_store
+        .observe_identity(
+            remote_address,
+            their_identity_key,
+            IdentitySource::PreKeyBundle,
+            now,
+        )
+        .await
+    {
+        log::warn!("failed to record the identity of {remote_address} in its history: {e}");
+    }
 
-    session_record.promote_state(session);
+    session_record.promote_state(session, policy);
 
     session_store
         .store_session(remote_address, &session_record)
@@ -0,0 +1,377 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Moving sessions, sender keys, and identity state to a new device.
+
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+use signal_crypto::{Aes256GcmDecryption, Aes256GcmEncryption};
+use uuid::Uuid;
+
+use crate::proto::storage::{SessionArchiveStructure, session_archive_structure};
+use crate::{
+    DeviceId, IdentityKey, IdentityKeyPair, IdentityKeyStore, ProtocolAddress, Result,
+    SenderKeyRecord, SenderKeyStore, SessionRecord, SessionStore, SignalProtocolError, Timestamp,
+};
+
+/// The only archive format so far; written as the first byte of every archive.
+const ARCHIVE_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const ENCRYPTION_KEY_INFO: &[u8] = b"Signal Session Archive Encryption Key";
+
+/// A snapshot of a client's sessions, sender keys, and identity state, for moving them to a new
+/// device without rebuilding every session from pre-keys.
+///
+/// [`export`](Self::export) produces an encrypted archive; [`decrypt`](Self::decrypt) checks it
+/// and [`import`](Self::import) writes it to another set of stores. The archive key must be 32
+/// random bytes, shared between the two devices some other way.
+pub struct SessionArchive {
+    identity_key_pair: IdentityKeyPair,
+    registration_id: u32,
+    exported_at: Timestamp,
+    sessions: Vec<ArchivedSession>,
+    sender_keys: Vec<ArchivedSenderKey>,
+}
+
+struct ArchivedSession {
+    address: ProtocolAddress,
+    record: Option<SessionRecord>,
+    identity_key: Option<IdentityKey>,
+}
+
+struct ArchivedSenderKey {
+    sender: ProtocolAddress,
+    distribution_id: Uuid,
+    record: SenderKeyRecord,
+}
+
+impl SessionArchive {
+    /// Exports the sessions and saved identity keys for `addresses`, and the sender keys for
+    /// `sender_keys`, along with the local identity.
+    ///
+    /// Stores can't be enumerated, so the caller says what to export. Addresses with neither a
+    /// session nor an identity key, and sender keys that aren't in the store, are skipped.
+    ///
+    /// Once the archive is made, this device must stop using the exported sessions and sender
+    /// keys, for example by deleting them. Otherwise both devices would encrypt with the same
+    /// message keys, and each would miss the ratchet steps the other takes.
+    #[expect(clippy::too_many_arguments)]
+    pub async fn export<R: Rng + CryptoRng>(
+        archive_key: &[u8; 32],
+        addresses: &[ProtocolAddress],
+        sender_keys: &[(ProtocolAddress, Uuid)],
+        session_store: &dyn SessionStore,
+        identity_store: &dyn IdentityKeyStore,
+        sender_key_store: &mut dyn SenderKeyStore,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<Vec<u8>> {
+        let mut structure = SessionArchiveStructure {
+            identity_key_pair: identity_store
+                .get_identity_key_pair()
+                .await?
+                .serialize()
+                .into(),
+            registration_id: identity_store.get_local_registration_id().await?,
+            exported_at: now
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+            sessions: vec![],
+            sender_keys: vec![],
+        };
+
+        for address in addresses {
+            let record = session_store.load_session(address).await?;
+            let identity_key = identity_store.get_identity(address).await?;
+            if record.is_none() && identity_key.is_none() {
+                continue;
+            }
+            structure.sessions.push(session_archive_structure::Session {
+                name: address.name().to_owned(),
+                device_id: address.device_id().into(),
+                record: record
+                    .map(|record| record.serialize())
+                    .transpose()?
+                    .unwrap_or_default(),
+                identity_key: identity_key
+                    .map(|key| key.serialize().into())
+                    .unwrap_or_default(),
+            });
+        }
+
+        for (sender, distribution_id) in sender_keys {
+            let Some(record) = sender_key_store
+                .load_sender_key(sender, *distribution_id)
+                .await?
+            else {
+                continue;
+            };
+            structure
+                .sender_keys
+                .push(session_archive_structure::SenderKey {
+                    name: sender.name().to_owned(),
+                    device_id: sender.device_id().into(),
+                    distribution_id: distribution_id.as_bytes().to_vec(),
+                    record: record.serialize()?,
+                });
+        }
+
+        let nonce: [u8; NONCE_LEN] = csprng.random();
+        let mut archive = vec![ARCHIVE_VERSION];
+        archive.extend_from_slice(&nonce);
+        let body_start = archive.len();
+        archive.extend(structure.encode_to_vec());
+
+        let mut encryption = Aes256GcmEncryption::new(
+            &derive_encryption_key(archive_key),
+            &nonce,
+            &[ARCHIVE_VERSION],
+        )
+        .expect("valid key and nonce lengths");
+        encryption.encrypt(&mut archive[body_start..]);
+        archive.extend_from_slice(&encryption.compute_tag());
+        Ok(archive)
+    }
+
+    /// Decrypts an archive made by [`export`](Self::export) and checks that its contents are
+    /// consistent.
+    pub fn decrypt(archive_key: &[u8; 32], archive: &[u8]) -> Result<Self> {
+        let (&version, rest) =
+            archive
+                .split_first()
+                .ok_or(SignalProtocolError::InvalidSessionArchive(
+                    "archive is empty",
+                ))?;
+        if version != ARCHIVE_VERSION {
+            return Err(SignalProtocolError::InvalidSessionArchive(
+                "unsupported archive version",
+            ));
+        }
+        if rest.len() < NONCE_LEN + TAG_LEN {
+            return Err(SignalProtocolError::InvalidSessionArchive(
+                "archive is truncated",
+            ));
+        }
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+        let mut plaintext = ciphertext.to_vec();
+        let mut decryption =
+            Aes256GcmDecryption::new(&derive_encryption_key(archive_key), nonce, &[version])
+                .expect("valid key and nonce lengths");
+        decryption.decrypt(&mut plaintext);
+        decryption.verify_tag(tag).map_err(|_| {
+            SignalProtocolError::InvalidSessionArchive("wrong key, or archive was modified")
+        })?;
+
+        let structure = SessionArchiveStructure::decode(plaintext.as_slice())
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        Self::from_structure(structure)
+    }
+
+    fn from_structure(structure: SessionArchiveStructure) -> Result<Self> {
+        let invalid = SignalProtocolError::InvalidSessionArchive;
+        let identity_key_pair = IdentityKeyPair::try_from(&structure.identity_key_pair[..])
+            .map_err(|_| invalid("invalid local identity key pair"))?;
+        let local_identity_key = identity_key_pair.identity_key().serialize();
+
+        let mut seen_addresses = HashSet::new();
+        let sessions = structure
+            .sessions
+            .into_iter()
+            .map(|session| {
+                let address = to_address(session.name, session.device_id)?;
+                if !seen_addresses.insert(address.clone()) {
+                    return Err(invalid("duplicate session"));
+                }
+                let identity_key = (!session.identity_key.is_empty())
+                    .then(|| IdentityKey::decode(&session.identity_key))
+                    .transpose()
+                    .map_err(|_| invalid("invalid identity key"))?;
+                let record = (!session.record.is_empty())
+                    .then(|| SessionRecord::deserialize(&session.record))
+                    .transpose()
+                    .map_err(|_| invalid("invalid session record"))?;
+                if let Some(state) = record.as_ref().and_then(SessionRecord::session_state) {
+                    if state.local_identity_key_bytes()? != *local_identity_key {
+                        return Err(invalid("session belongs to a different local identity"));
+                    }
+                    if state.remote_identity_key_bytes()?
+                        != identity_key.map(|key| key.serialize().into_vec())
+                    {
+                        return Err(invalid("session does not match the saved identity key"));
+                    }
+                }
+                Ok(ArchivedSession {
+                    address,
+                    record,
+                    identity_key,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut seen_sender_keys = HashSet::new();
+        let sender_keys = structure
+            .sender_keys
+            .into_iter()
+            .map(|sender_key| {
+                let sender = to_address(sender_key.name, sender_key.device_id)?;
+                let distribution_id = Uuid::from_slice(&sender_key.distribution_id)
+                    .map_err(|_| invalid("invalid distribution ID"))?;
+                if !seen_sender_keys.insert((sender.clone(), distribution_id)) {
+                    return Err(invalid("duplicate sender key"));
+                }
+                let record = SenderKeyRecord::deserialize(&sender_key.record)
+                    .map_err(|_| invalid("invalid sender key record"))?;
+                Ok(ArchivedSenderKey {
+                    sender,
+                    distribution_id,
+                    record,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            identity_key_pair,
+            registration_id: structure.registration_id,
+            exported_at: Timestamp::from_epoch_millis(structure.exported_at),
+            sessions,
+            sender_keys,
+        })
+    }
+
+    /// The identity of the exporting client, which the importing stores must already have.
+    pub fn identity_key_pair(&self) -> &IdentityKeyPair {
+        &self.identity_key_pair
+    }
+
+    pub fn registration_id(&self) -> u32 {
+        self.registration_id
+    }
+
+    pub fn exported_at(&self) -> Timestamp {
+        self.exported_at
+    }
+
+    /// The addresses that have a session or identity key in the archive.
+    pub fn addresses(&self) -> impl ExactSizeIterator<Item = &ProtocolAddress> {
+        self.sessions.iter().map(|session| &session.address)
+    }
+
+    /// The senders and distribution IDs that have a sender key in the archive.
+    pub fn sender_keys(&self) -> impl ExactSizeIterator<Item = (&ProtocolAddress, Uuid)> {
+        self.sender_keys
+            .iter()
+            .map(|sender_key| (&sender_key.sender, sender_key.distribution_id))
+    }
+
+    /// Writes the archive's contents to the given stores.
+    ///
+    /// The stores must have been set up with the archive's
+    /// [identity](Self::identity_key_pair) and [registration ID](Self::registration_id). Nothing is
+    /// written if any record in the stores is newer than the one in the archive, or if a saved
+    /// identity key differs from the archived one; that fails with
+    /// [`SignalProtocolError::StaleSessionArchive`].
+    ///
+    /// The records are written one at a time, so if a write fails, part of the archive may
+    /// already be in the stores. Call `import` with the same archive again until it succeeds;
+    /// records that were already imported don't count as newer than the archive.
+    pub async fn import(
+        &self,
+        session_store: &mut dyn SessionStore,
+        identity_store: &mut dyn IdentityKeyStore,
+        sender_key_store: &mut dyn SenderKeyStore,
+    ) -> Result<()> {
+        let local_identity = identity_store.get_identity_key_pair().await?;
+        if local_identity.identity_key() != self.identity_key_pair.identity_key()
+            || identity_store.get_local_registration_id().await? != self.registration_id
+        {
+            return Err(SignalProtocolError::InvalidSessionArchive(
+                "archive belongs to a different local identity",
+            ));
+        }
+
+        for session in &self.sessions {
+            let stale_record = match (
+                &session.record,
+                session_store.load_session(&session.address).await?,
+            ) {
+                (Some(record), Some(existing)) => record.is_older_than(&existing)?,
+                (None, Some(existing)) => existing.session_state().is_some(),
+                (_, None) => false,
+            };
+            let stale_identity = match (
+                session.identity_key,
+                identity_store.get_identity(&session.address).await?,
+            ) {
+                (Some(identity_key), Some(existing)) => identity_key != existing,
+                (None, Some(_)) => true,
+                (_, None) => false,
+            };
+            if stale_record || stale_identity {
+                return Err(SignalProtocolError::StaleSessionArchive(
+                    session.address.clone(),
+                ));
+            }
+        }
+        for sender_key in &self.sender_keys {
+            let existing = sender_key_store
+                .load_sender_key(&sender_key.sender, sender_key.distribution_id)
+                .await?;
+            if existing.is_some_and(|existing| sender_key.record.is_older_than(&existing)) {
+                return Err(SignalProtocolError::StaleSessionArchive(
+                    sender_key.sender.clone(),
+                ));
+            }
+        }
+
+        for session in &self.sessions {
+            if let Some(identity_key) = &session.identity_key {
+                identity_store
+                    .save_identity(&session.address, identity_key)
+                    .await?;
+            }
+            if let Some(record) = &session.record {
+                session_store
+                    .store_session(&session.address, record)
+                    .await?;
+            }
+        }
+        for sender_key in &self.sender_keys {
+            sender_key_store
+                .store_sender_key(
+                    &sender_key.sender,
+                    sender_key.distribution_id,
+                    &sender_key.record,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn derive_encryption_key(archive_key: &[u8; 32]) -> [u8; 32] {
+    let mut encryption_key = [0; 32];
+    hkdf::Hkdf::<sha2::Sha256>::new(None, archive_key)
+        .expand(ENCRYPTION_KEY_INFO, &mut encryption_key)
+        .expect("valid output length");
+    encryption_key
+}
+
+fn to_address(name: String, device_id: u32) -> Result<ProtocolAddress> {
+    let device_id =
+        DeviceId::try_from(device_id).map_err(|_| SignalProtocolError::InvalidProtocolAddress {
+            name: name.clone(),
+            device_id,
+        })?;
+    Ok(ProtocolAddress::new(name, device_id))
+}
@@ -5,17 +5,55 @@
 
 use std::time::SystemTime;
 
+use async_trait::async_trait;
+use libsignal_gossip::{
+    CheckedGossip, EquivocationProof, GossipError, GossipMessage, GossipOutcome, GossipService,
+};
 use rand::{CryptoRng, Rng};
 
-use crate::consts::{MAX_FORWARD_JUMPS, MAX_UNACKNOWLEDGED_SESSION_AGE};
 use crate::ratchet::{ChainKey, MessageKeyGenerator};
 use crate::state::{InvalidSessionError, SessionState};
 use crate::{
-    CiphertextMessage, CiphertextMessageType, Direction, IdentityKeyStore, KeyPair, KyberPayload,
-    KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, ProtocolAddress, PublicKey, Result,
-    SessionRecord, SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore, session,
+    CiphertextMessage, CiphertextMessageType, Direction, IdentityChange, IdentityKey,
+    IdentityKeyPair, IdentityKeyStore, IdentitySource, KeyPair, KyberPayload, KyberPreKeyId,
+    KyberPreKeyRecord, KyberPreKeyStore, PreKeyId, PreKeyRecord, PreKeySignalMessage, PreKeyStore,
+    ProtocolAddress, ProtocolStoreTransaction, PublicKey, Result, SessionPolicy, SessionRecord,
+    SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyId, SignedPreKeyStore,
+    TransactionalProtocolStore, session,
 };
 
+/// Outcome of checking the key transparency gossip attached to a received message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GossipStatus {
+    /// The sender's tree head is consistent with ours.
+    ///
+    /// If the sender was behind, the outcome carries gossip to send back to them.
+    Verified(GossipOutcome),
+    /// The sender's tree head could not be reconciled with ours.
+    ///
+    /// The attached proof can be reported to an auditor or shown to the user.
+    Inconsistent(Box<EquivocationProof>),
+    /// The message carried gossip that could not be parsed, or whose tree head wasn't signed by
+    /// the key transparency service.
+    Invalid,
+    /// The sender's gossip was too old, too far in the future, or carried a newer tree head
+    /// timestamped earlier than ours, and was not adopted.
+    Stale,
+    /// The message carried gossip, but there is no distinguished tree head yet to check it
+    /// against, its proof starts from a different distinguished head than ours, it was compact
+    /// gossip naming a tree head we don't have, or too few auditors vouched for it to satisfy the
+    /// [`GossipService`]'s auditor policy.
+    ///
+    /// If the [`GossipService`] allows bootstrapping from peers, the sender's head may still have
+    /// been adopted.
+    Unverified,
+    /// The message did not carry any gossip.
+    Missing,
+    /// The sender's gossip was checked, with the given outcome, but the update to our state it
+    /// called for could not be saved, so our tree head hasn't changed.
+    NotSaved(GossipOutcome),
+}
+
 pub async fn message_encrypt<R: Rng + CryptoRng>(
     ptext: &[u8],
     remote_address: &ProtocolAddress,
@@ -24,149 +62,125 @@ pub async fn message_encrypt<R: Rng + CryptoRng>(
     now: SystemTime,
     csprng: &mut R,
 ) -> Result<CiphertextMessage> {
-    let mut session_record = session_store
-        .load_session(remote_address)
-        .await?
-        .ok_or_else(|| SignalProtocolError::SessionNotFound(remote_address.clone()))?;
-    let session_state = session_record
-        .session_state_mut()
-        .ok_or_else(|| SignalProtocolError::SessionNotFound(remote_address.clone()))?;
-
-    let chain_key = session_state.get_sender_chain_key()?;
-
-    let (pqr_msg, pqr_key) = session_state.pq_ratchet_send(csprng).map_err(|e| {
-        // Since we're sending, this must be an error with the state.
-        SignalProtocolError::InvalidState(
-            "message_encrypt",
-            format!("post-quantum ratchet send error: {e}"),
-        )
-    })?;
-    let message_keys = chain_key.message_keys().generate_keys(pqr_key);
-
-    let sender_ephemeral = session_state.sender_ratchet_key()?;
-    let previous_counter = session_state.previous_counter();
-    let session_version = session_state
-        .session_version()?
-        .try_into()
-        .map_err(|_| SignalProtocolError::InvalidSessionStructure("version does not fit in u8"))?;
-
-    let local_identity_key = session_state.local_identity_key()?;
-    let their_identity_key = session_state.remote_identity_key()?.ok_or_else(|| {
-        SignalProtocolError::InvalidState(
-            "message_encrypt",
-            format!("no remote identity key for {remote_address}"),
-        )
-    })?;
-
-    let ctext =
-        signal_crypto::aes_256_cbc_encrypt(ptext, message_keys.cipher_key(), message_keys.iv())
-            .map_err(|_| {
-                log::error!("session state corrupt for {remote_address}");
-                SignalProtocolError::InvalidSessionStructure("invalid sender chain message keys")
-            })?;
-
-    let message = if let Some(items) = session_state.unacknowledged_pre_key_message_items()? {
-        let timestamp_as_unix_time = items
-            .timestamp()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        if items.timestamp() + MAX_UNACKNOWLEDGED_SESSION_AGE < now {
-            log::warn!(
-                "stale unacknowledged session for {remote_address} (created at {timestamp_as_unix_time})"
-            );
-            return Err(SignalProtocolError::SessionNotFound(remote_address.clone()));
-        }
-
-        let local_registration_id = session_state.local_registration_id();
-
-        log::info!(
-            "Building PreKeyWhisperMessage for: {} with preKeyId: {} (session created at {})",
-            remote_address,
-            items
-                .pre_key_id()
-                .map_or_else(|| "<none>".to_string(), |id| id.to_string()),
-            timestamp_as_unix_time,
-        );
-
-        let message = SignalMessage::new(
-            session_version,
-            message_keys.mac_key(),
-            sender_ephemeral,
-            chain_key.index(),
-            previous_counter,
-            &ctext,
-            &local_identity_key,
-            &their_identity_key,
-            &pqr_msg,
-        )?;
+    message_encrypt_impl(
+        ptext,
+        remote_address,
+        session_store,
+        identity_store,
+        None,
+        now,
+        csprng,
+    )
+    .await
+}
 
-        let kyber_payload = items
-            .kyber_pre_key_id()
-            .zip(items.kyber_ciphertext())
-            .map(|(id, ciphertext)| KyberPayload::new(id, ciphertext.into()));
+/// Like [`message_encrypt`], but attaches `gossip_service`'s current trusted tree head to the
+/// message, if it has one.
+///
+/// Once the remote party has gossiped a head at least as new as ours in this session, only the
+/// head's size, root, and signatures are sent.
+pub async fn message_encrypt_with_gossip<R: Rng + CryptoRng>(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    gossip_service: &GossipService,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<CiphertextMessage> {
+    message_encrypt_impl(
+        ptext,
+        remote_address,
+        session_store,
+        identity_store,
+        Some(gossip_service),
+        now,
+        csprng,
+    )
+    .await
+}
 
-        CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::new(
-            session_version,
-            local_registration_id,
-            items.pre_key_id(),
-            items.signed_pre_key_id(),
-            kyber_payload,
-            *items.base_key(),
-            local_identity_key,
-            message,
-        )?)
-    } else {
-        CiphertextMessage::SignalMessage(SignalMessage::new(
-            session_version,
-            message_keys.mac_key(),
-            sender_ephemeral,
-            chain_key.index(),
-            previous_counter,
-            &ctext,
-            &local_identity_key,
-            &their_identity_key,
-            &pqr_msg,
-        )?)
+async fn message_encrypt_impl<R: Rng + CryptoRng>(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    gossip_service: Option<&GossipService>,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<CiphertextMessage> {
+    let (message, transaction) = stage_message_encrypt(
+        ptext,
+        remote_address,
+        session_store,
+        identity_store,
+        gossip_service,
+        now,
+        csprng,
+    )
+    .await?;
+    let mut stores = SeparateStores {
+        session_store,
+        identity_store,
+        pre_key_stores: None,
     };
+    commit_staged(&mut stores, transaction, now).await?;
+    Ok(message)
+}
 
-    session_state.set_sender_chain_key(&chain_key.next_chain_key());
-
-    // XXX why is this check after everything else?!!
-    if !identity_store
-        .is_trusted_identity(remote_address, &their_identity_key, Direction::Sending)
-        .await?
-    {
-        log::warn!(
-            "Identity key {} is not trusted for remote address {}",
-            hex::encode(their_identity_key.public_key().public_key_bytes()),
-            remote_address,
-        );
-        return Err(SignalProtocolError::UntrustedIdentity(
-            remote_address.clone(),
-        ));
-    }
-
-    // XXX this could be combined with the above call to the identity store (in a new API)
-    identity_store
-        .save_identity(remote_address, &their_identity_key)
-        .await?;
-
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
+/// Like [`message_encrypt`], but commits the updated session and identity to `store` in a single
+/// [`TransactionalProtocolStore::commit_transaction`] call.
+pub async fn message_encrypt_transactional<S: TransactionalProtocolStore, R: Rng + CryptoRng>(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<CiphertextMessage> {
+    let (message, transaction) =
+        stage_message_encrypt(ptext, remote_address, store, store, None, now, csprng).await?;
+    commit_staged(store, transaction, now).await?;
     Ok(message)
 }
 
-pub async fn message_encrypt_with_gossip<R: Rng + CryptoRng>(
+/// Like [`message_encrypt_transactional`], but attaches `gossip_service`'s current trusted tree
+/// head to the message, as [`message_encrypt_with_gossip`] does.
+pub async fn message_encrypt_transactional_with_gossip<
+    S: TransactionalProtocolStore,
+    R: Rng + CryptoRng,
+>(
     ptext: &[u8],
     remote_address: &ProtocolAddress,
-    session_store: &mut dyn SessionStore,
-    identity_store: &mut dyn IdentityKeyStore,
+    store: &mut S,
+    gossip_service: &GossipService,
     now: SystemTime,
     csprng: &mut R,
-    gossip: &[u8],
 ) -> Result<CiphertextMessage> {
+    let (message, transaction) = stage_message_encrypt(
+        ptext,
+        remote_address,
+        store,
+        store,
+        Some(gossip_service),
+        now,
+        csprng,
+    )
+    .await?;
+    commit_staged(store, transaction, now).await?;
+    Ok(message)
+}
+
+/// Encrypts `ptext`, returning the store updates that go with it rather than applying them.
+async fn stage_message_encrypt<R: Rng + CryptoRng>(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    gossip_service: Option<&GossipService>,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<(CiphertextMessage, ProtocolStoreTransaction)> {
+    let policy = session_store.session_policy();
     let mut session_record = session_store
         .load_session(remote_address)
         .await?
@@ -175,6 +189,18 @@ pub async fn message_encrypt_with_gossip<R: Rng + CryptoRng>(
         .session_state_mut()
         .ok_or_else(|| SignalProtocolError::SessionNotFound(remote_address.clone()))?;
 
+    let gossip = gossip_service
+        .map(|gossip_service| {
+            gossip_service
+                .outgoing_gossip_for_peer(session_state.acknowledged_gossip_head().as_ref(), now)
+        })
+        .transpose()
+        .map_err(SignalProtocolError::for_application_callback(
+            "outgoing_gossip",
+        ))?
+        .flatten()
+        .unwrap_or_default();
+
     let chain_key = session_state.get_sender_chain_key()?;
 
     let (pqr_msg, pqr_key) = session_state.pq_ratchet_send(csprng).map_err(|e| {
@@ -214,7 +240,7 @@ pub async fn message_encrypt_with_gossip<R: Rng + CryptoRng>(
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
-        if items.timestamp() + MAX_UNACKNOWLEDGED_SESSION_AGE < now {
+        if items.timestamp() + policy.max_unacknowledged_session_age() < now {
             log::warn!(
                 "stale unacknowledged session for {remote_address} (created at {timestamp_as_unix_time})"
             );
@@ -292,17 +318,19 @@ pub async fn message_encrypt_with_gossip<R: Rng + CryptoRng>(
         ));
     }
 
-    // XXX this could be combined with the above call to the identity store (in a new API)
-    identity_store
-        .save_identity(remote_address, &their_identity_key)
-        .await?;
-
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
-    Ok(message)
+    let mut transaction = ProtocolStoreTransaction::new(remote_address.clone());
+    transaction.stage_identity(their_identity_key);
+    transaction.stage_session(session_record);
+    Ok((message, transaction))
 }
 
+/// Decrypts `ciphertext` from `remote_address`, updating the session, identity, and pre-keys that
+/// go with it.
+///
+/// The separate stores can't be updated atomically. If one of the writes fails, the session and
+/// identity written before it are restored where possible, but a Kyber pre-key that was marked as
+/// used stays that way. Use [`message_decrypt_transactional`] where all the updates need to be kept
+/// or dropped together.
 #[allow(clippy::too_many_arguments)]
 pub async fn message_decrypt<R: Rng + CryptoRng>(
     ciphertext: &CiphertextMessage,
@@ -314,12 +342,79 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
     csprng: &mut R,
 ) -> Result<Vec<u8>> {
+    let (ptext, _) = message_decrypt_impl(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        None,
+        csprng,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+/// Like [`message_decrypt`], but also checks any gossip attached to the message using
+/// `gossip_service`.
+///
+/// A message whose gossip is inconsistent is still decrypted; it is up to the caller to decide
+/// what to do with the returned [`GossipStatus`].
+#[allow(clippy::too_many_arguments)]
+pub async fn message_decrypt_with_gossip<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    gossip_service: &mut GossipService,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    message_decrypt_impl(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        Some((gossip_service, now)),
+        csprng,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn message_decrypt_impl<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    gossip: Option<(&mut GossipService, SystemTime)>,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
     match ciphertext {
         CiphertextMessage::SignalMessage(m) => {
-            message_decrypt_signal(m, remote_address, session_store, identity_store, csprng).await
+            message_decrypt_signal_impl(
+                m,
+                remote_address,
+                session_store,
+                identity_store,
+                gossip,
+                csprng,
+            )
+            .await
         }
         CiphertextMessage::PreKeySignalMessage(m) => {
-            message_decrypt_prekey(
+            message_decrypt_prekey_impl(
                 m,
                 remote_address,
                 session_store,
@@ -327,6 +422,7 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
                 pre_key_store,
                 signed_pre_key_store,
                 kyber_pre_key_store,
+                gossip,
                 csprng,
             )
             .await
@@ -338,6 +434,91 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
     }
 }
 
+/// Like [`message_decrypt`], but commits every store update to `store` in a single
+/// [`TransactionalProtocolStore::commit_transaction`] call.
+///
+/// If the commit fails, for example because the message reused a last-resort Kyber pre-key with
+/// the same base key, none of the updates are kept.
+pub async fn message_decrypt_transactional<S: TransactionalProtocolStore, R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    csprng: &mut R,
+) -> Result<Vec<u8>> {
+    let (ptext, _) =
+        message_decrypt_transactional_impl(ciphertext, remote_address, store, None, csprng).await?;
+    Ok(ptext)
+}
+
+/// Like [`message_decrypt_transactional`], but also checks any gossip attached to the message
+/// using `gossip_service`, as [`message_decrypt_with_gossip`] does.
+///
+/// The gossip state is kept in its own store and is not part of the transaction, but it is only
+/// updated once the transaction has been committed.
+pub async fn message_decrypt_transactional_with_gossip<
+    S: TransactionalProtocolStore,
+    R: Rng + CryptoRng,
+>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    gossip_service: &mut GossipService,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    message_decrypt_transactional_impl(
+        ciphertext,
+        remote_address,
+        store,
+        Some((gossip_service, now)),
+        csprng,
+    )
+    .await
+}
+
+async fn message_decrypt_transactional_impl<S: TransactionalProtocolStore, R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    gossip: Option<(&mut GossipService, SystemTime)>,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    let staged = match ciphertext {
+        CiphertextMessage::SignalMessage(m) => {
+            stage_message_decrypt_signal(
+                m,
+                remote_address,
+                store,
+                store,
+                for_checking(&gossip),
+                csprng,
+            )
+            .await?
+        }
+        CiphertextMessage::PreKeySignalMessage(m) => {
+            stage_message_decrypt_prekey(
+                m,
+                remote_address,
+                store,
+                store,
+                store,
+                store,
+                store,
+                for_checking(&gossip),
+                csprng,
+            )
+            .await?
+        }
+        _ => {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "message_decrypt cannot be used to decrypt {:?} messages",
+                ciphertext.message_type()
+            )));
+        }
+    };
+    staged.commit(store, gossip).await
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
     ciphertext: &PreKeySignalMessage,
@@ -349,13 +530,104 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
     csprng: &mut R,
 ) -> Result<Vec<u8>> {
+    let (ptext, _) = message_decrypt_prekey_impl(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        None,
+        csprng,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+/// Like [`message_decrypt_prekey`], but also checks any gossip attached to the message using
+/// `gossip_service`.
+#[allow(clippy::too_many_arguments)]
+pub async fn message_decrypt_prekey_with_gossip<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    gossip_service: &mut GossipService,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    message_decrypt_prekey_impl(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        Some((gossip_service, now)),
+        csprng,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn message_decrypt_prekey_impl<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    gossip: Option<(&mut GossipService, SystemTime)>,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    let staged = stage_message_decrypt_prekey(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        for_checking(&gossip),
+        csprng,
+    )
+    .await?;
+    let mut stores = SeparateStores {
+        session_store,
+        identity_store,
+        pre_key_stores: Some((pre_key_store, kyber_pre_key_store)),
+    };
+    staged.commit(&mut stores, gossip).await
+}
+
+/// Decrypts a [`PreKeySignalMessage`], returning the store updates that go with it rather than
+/// applying them.
+#[allow(clippy::too_many_arguments)]
+async fn stage_message_decrypt_prekey<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &dyn KyberPreKeyStore,
+    gossip: Option<(&GossipService, SystemTime)>,
+    csprng: &mut R,
+) -> Result<StagedDecryption> {
+    let policy = session_store.session_policy();
     let mut session_record = session_store
         .load_session(remote_address)
         .await?
         .unwrap_or_else(SessionRecord::new_fresh);
 
     // Make sure we log the session state if we fail to process the pre-key.
-    let process_prekey_result = session::process_prekey(
+    let process_prekey_result = session::process_prekey_with_policy(
         ciphertext,
         remote_address,
         &mut session_record,
@@ -363,6 +635,7 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         pre_key_store,
         signed_pre_key_store,
         kyber_pre_key_store,
+        &policy,
     )
     .await;
 
@@ -389,37 +662,42 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         &mut session_record,
         ciphertext.message(),
         CiphertextMessageType::PreKey,
+        &policy,
         csprng,
     )?;
 
-    identity_store
-        .save_identity(
-            identity_to_save.remote_address,
-            identity_to_save.their_identity_key,
-        )
-        .await?;
+    let mut transaction = ProtocolStoreTransaction::new(identity_to_save.remote_address.clone());
+    transaction.stage_identity(*identity_to_save.their_identity_key);
 
     if let Some(pre_key_used) = pre_key_used {
         if let Some(kyber_pre_key_id) = pre_key_used.kyber_pre_key_id {
-            kyber_pre_key_store
-                .mark_kyber_pre_key_used(
-                    kyber_pre_key_id,
-                    pre_key_used.signed_ec_pre_key_id,
-                    ciphertext.base_key(),
-                )
-                .await?;
+            transaction.stage_kyber_pre_key_used(
+                kyber_pre_key_id,
+                pre_key_used.signed_ec_pre_key_id,
+                *ciphertext.base_key(),
+            );
         }
 
         if let Some(pre_key_id) = pre_key_used.one_time_ec_pre_key_id {
-            pre_key_store.remove_pre_key(pre_key_id).await?;
+            transaction.stage_pre_key_removal(pre_key_id);
         }
     }
 
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
-
-    Ok(ptext)
+    let (gossip_status, checked_gossip) =
+        check_gossip(gossip, remote_address, ciphertext.message().gossip());
+    record_acknowledged_gossip_head(
+        &mut session_record,
+        ciphertext.message().gossip(),
+        &gossip_status,
+    );
+
+    transaction.stage_session(session_record);
+    Ok(StagedDecryption {
+        ptext,
+        gossip_status,
+        checked_gossip,
+        transaction,
+    })
 }
 
 pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
@@ -429,6 +707,76 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
     identity_store: &mut dyn IdentityKeyStore,
     csprng: &mut R,
 ) -> Result<Vec<u8>> {
+    let (ptext, _) = message_decrypt_signal_impl(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        None,
+        csprng,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+/// Like [`message_decrypt_signal`], but also checks any gossip attached to the message using
+/// `gossip_service`.
+pub async fn message_decrypt_signal_with_gossip<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    gossip_service: &mut GossipService,
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    message_decrypt_signal_impl(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        Some((gossip_service, now)),
+        csprng,
+    )
+    .await
+}
+
+pub(crate) async fn message_decrypt_signal_impl<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    gossip: Option<(&mut GossipService, SystemTime)>,
+    csprng: &mut R,
+) -> Result<(Vec<u8>, GossipStatus)> {
+    let staged = stage_message_decrypt_signal(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        for_checking(&gossip),
+        csprng,
+    )
+    .await?;
+    let mut stores = SeparateStores {
+        session_store,
+        identity_store,
+        pre_key_stores: None,
+    };
+    staged.commit(&mut stores, gossip).await
+}
+
+/// Decrypts a [`SignalMessage`], returning the store updates that go with it rather than applying
+/// them.
+async fn stage_message_decrypt_signal<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    gossip: Option<(&GossipService, SystemTime)>,
+    csprng: &mut R,
+) -> Result<StagedDecryption> {
+    let policy = session_store.session_policy();
     let mut session_record = session_store
         .load_session(remote_address)
         .await?
@@ -439,6 +787,7 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
         &mut session_record,
         ciphertext,
         CiphertextMessageType::Whisper,
+        &policy,
         csprng,
     )?;
 
@@ -464,68 +813,728 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
         ));
     }
 
-    identity_store
-        .save_identity(remote_address, &their_identity_key)
-        .await?;
+    let (gossip_status, checked_gossip) = check_gossip(gossip, remote_address, ciphertext.gossip());
+    record_acknowledged_gossip_head(&mut session_record, ciphertext.gossip(), &gossip_status);
+
+    let mut transaction = ProtocolStoreTransaction::new(remote_address.clone());
+    transaction.stage_identity(their_identity_key);
+    transaction.stage_session(session_record);
+    Ok(StagedDecryption {
+        ptext,
+        gossip_status,
+        checked_gossip,
+        transaction,
+    })
+}
 
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
+/// A message decrypted by one of the `stage_*` functions, along with everything that still has to
+/// be saved for it.
+struct StagedDecryption {
+    ptext: Vec<u8>,
+    gossip_status: GossipStatus,
+    checked_gossip: Option<CheckedGossip>,
+    transaction: ProtocolStoreTransaction,
+}
 
-    Ok(ptext)
+impl StagedDecryption {
+    /// Commits the staged updates to `stores`, and only then saves what the message's gossip
+    /// taught `gossip`'s service, if anything.
+    ///
+    /// If that can't be saved, the returned status is [`GossipStatus::NotSaved`].
+    async fn commit(
+        self,
+        stores: &mut (impl CommitTarget + ?Sized),
+        gossip: Option<(&mut GossipService, SystemTime)>,
+    ) -> Result<(Vec<u8>, GossipStatus)> {
+        let Self {
+            ptext,
+            gossip_status,
+            checked_gossip,
+            transaction,
+        } = self;
+        let address = transaction.address().clone();
+        // Only the gossip functions are given the time by the caller.
+        let now = gossip
+            .as_ref()
+            .map_or_else(SystemTime::now, |(_, now)| *now);
+        commit_staged(stores, transaction, now).await?;
+        let gossip_status =
+            save_checked_gossip(gossip, checked_gossip, &address, gossip_status).await;
+        Ok((ptext, gossip_status))
+    }
 }
 
-pub async fn message_decrypt_signal_with_gossip<R: Rng + CryptoRng>(
-    ciphertext: &SignalMessage,
+/// Lends out the gossip service passed to a decrypt function for checking the message's gossip,
+/// keeping it for saving the result once the message has been committed.
+pub(crate) fn for_checking<'a>(
+    gossip: &'a Option<(&mut GossipService, SystemTime)>,
+) -> Option<(&'a GossipService, SystemTime)> {
+    gossip
+        .as_ref()
+        .map(|(gossip_service, now)| (&**gossip_service, *now))
+}
+
+/// Where the cipher functions commit the updates they staged.
+#[async_trait(?Send)]
+trait CommitTarget {
+    async fn commit(&mut self, transaction: ProtocolStoreTransaction) -> Result<()>;
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        now: SystemTime,
+    ) -> Result<()>;
+}
+
+#[async_trait(?Send)]
+impl<S: TransactionalProtocolStore> CommitTarget for S {
+    async fn commit(&mut self, transaction: ProtocolStoreTransaction) -> Result<()> {
+        self.commit_transaction(transaction).await
+    }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        now: SystemTime,
+    ) -> Result<()> {
+        IdentityKeyStore::observe_identity(self, address, identity, IdentitySource::Message, now)
+            .await
+    }
+}
+
+/// The separate stores passed to the non-transactional cipher functions.
+///
+/// These can't be written to atomically, so [`CommitTarget::commit`] does the next best thing:
+/// the Kyber pre-key is marked as used first, so that a replayed message is rejected before
+/// anything else is written, and if a later write fails, the ones before it are undone as far as
+/// the store traits allow. That doesn't extend to the Kyber pre-key, or to an identity or session
+/// that is new for the address, which can't be removed again.
+struct SeparateStores<'a> {
+    session_store: &'a mut dyn SessionStore,
+    identity_store: &'a mut dyn IdentityKeyStore,
+    pre_key_stores: Option<(&'a mut dyn PreKeyStore, &'a mut dyn KyberPreKeyStore)>,
+}
+
+impl SeparateStores<'_> {
+    async fn apply(&mut self, transaction: &ProtocolStoreTransaction) -> Result<()> {
+        let address = transaction.address();
+        if let Some(identity) = transaction.identity() {
+            self.identity_store.save_identity(address, identity).await?;
+        }
+        if let Some(record) = transaction.session() {
+            self.session_store.store_session(address, record).await?;
+        }
+        // Last, so that the message can still be decrypted again if anything before this fails.
+        if let (Some(pre_key_id), Some((pre_key_store, _))) =
+            (transaction.removed_pre_key(), &mut self.pre_key_stores)
+        {
+            pre_key_store.remove_pre_key(pre_key_id).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl CommitTarget for SeparateStores<'_> {
+    async fn commit(&mut self, transaction: ProtocolStoreTransaction) -> Result<()> {
+        debug_assert!(
+            self.pre_key_stores.is_some()
+                || (transaction.used_kyber_pre_key().is_none()
+                    && transaction.removed_pre_key().is_none()),
+            "pre-key updates are only staged for PreKeySignalMessages"
+        );
+        if let (Some((kyber_pre_key_id, signed_pre_key_id, base_key)), Some((_, kyber_store))) =
+            (transaction.used_kyber_pre_key(), &mut self.pre_key_stores)
+        {
+            kyber_store
+                .mark_kyber_pre_key_used(kyber_pre_key_id, signed_pre_key_id, base_key)
+                .await?;
+        }
+
+        let address = transaction.address();
+        let previous_identity = match transaction.identity() {
+            Some(_) => self.identity_store.get_identity(address).await?,
+            None => None,
+        };
+        let previous_session = match transaction.session() {
+            Some(_) => self.session_store.load_session(address).await?,
+            None => None,
+        };
+
+        let result = self.apply(&transaction).await;
+        if result.is_err() {
+            log::warn!("failed to commit updates for {address}; undoing the ones already made");
+            let restored = async {
+                if let Some(record) = &previous_session {
+                    self.session_store.store_session(address, record).await?;
+                }
+                if let Some(identity) = &previous_identity {
+                    self.identity_store.save_identity(address, identity).await?;
+                }
+                Ok::<_, SignalProtocolError>(())
+            };
+            if let Err(e) = restored.await {
+                log::error!("failed to undo updates for {address}: {e}");
+            }
+        }
+        result
+    }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        now: SystemTime,
+    ) -> Result<()> {
+        self.identity_store
+            .observe_identity(address, identity, IdentitySource::Message, now)
+            .await
+    }
+}
+
+/// Commits the updates staged by one of the cipher functions to `stores`.
+///
+/// Only once they have been committed is the identity they saved recorded in its history. The
+/// message can't be failed any more at that point, so a failure to do so is only logged.
+async fn commit_staged(
+    stores: &mut (impl CommitTarget + ?Sized),
+    transaction: ProtocolStoreTransaction,
+    now: SystemTime,
+) -> Result<()> {
+    let address = transaction.address().clone();
+    let observed = transaction.identity().copied();
+    stores.commit(transaction).await?;
+    if let Some(identity) = observed {
+        if let Err(e) = stores.observe_identity(&address, &identity, now).await {
+            log::warn!("failed to record the identity of {address} in its history: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Saves what gossip checked by [`check_gossip`] taught `gossip`'s service, once the message it
+/// came with has been saved, returning the status to report for it.
+///
+/// The message can't be failed any more at that point, so a failure is reported as
+/// [`GossipStatus::NotSaved`] in place of `gossip_status`.
+pub(crate) async fn save_checked_gossip(
+    gossip: Option<(&mut GossipService, SystemTime)>,
+    checked_gossip: Option<CheckedGossip>,
     remote_address: &ProtocolAddress,
+    gossip_status: GossipStatus,
+) -> GossipStatus {
+    if let (Some((gossip_service, _)), Some(checked)) = (gossip, checked_gossip) {
+        let outcome = checked.outcome().clone();
+        if let Err(e) = gossip_service.save_checked_gossip(checked).await {
+            log::warn!("failed to save gossip from {remote_address}: {e}");
+            return GossipStatus::NotSaved(outcome);
+        }
+    }
+    gossip_status
+}
+
+/// Decrypts a queue of messages, possibly from several senders, in order.
+///
+/// This behaves like calling [`message_decrypt`] for each message in turn, but each session and
+/// identity is loaded at most once and written back once at the end, rather than once per message.
+/// The one-time and Kyber pre-keys the messages used are consumed along with the session they set
+/// up, so that a sender's pre-key is never gone without their session having been saved.
+///
+/// Each message gets its own result in the returned list. A message that can't be decrypted (for
+/// example, because it is a [duplicate][] or is [invalid][]) doesn't affect the messages around
+/// it. If writing back the updates for a sender fails, every message from that sender that was
+/// decrypted gets an error instead, and those messages can be decrypted again later; the other
+/// senders' messages are unaffected.
+///
+/// [duplicate]: SignalProtocolError::DuplicatedMessage
+/// [invalid]: SignalProtocolError::InvalidMessage
+pub async fn message_decrypt_batch<R: Rng + CryptoRng>(
+    messages: &[(ProtocolAddress, CiphertextMessage)],
     session_store: &mut dyn SessionStore,
     identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
     csprng: &mut R,
-) -> Result<Vec<u8>> {
-    let mut session_record = session_store
-        .load_session(remote_address)
-        .await?
-        .ok_or_else(|| SignalProtocolError::SessionNotFound(remote_address.clone()))?;
+) -> Vec<Result<Vec<u8>>> {
+    let mut batch = BatchStores {
+        session_store,
+        identity_store,
+        pre_key_store,
+        kyber_pre_key_store,
+        updates: vec![],
+    };
 
-    let ptext = decrypt_message_with_record(
-        remote_address,
-        &mut session_record,
-        ciphertext,
-        CiphertextMessageType::Whisper,
-        csprng,
-    )?;
+    let mut results = Vec::with_capacity(messages.len());
+    for (remote_address, ciphertext) in messages {
+        let staged = match ciphertext {
+            CiphertextMessage::SignalMessage(m) => {
+                stage_message_decrypt_signal(m, remote_address, &batch, &batch, None, csprng).await
+            }
+            CiphertextMessage::PreKeySignalMessage(m) => {
+                stage_message_decrypt_prekey(
+                    m,
+                    remote_address,
+                    &batch,
+                    &batch,
+                    &batch,
+                    signed_pre_key_store,
+                    &batch,
+                    None,
+                    csprng,
+                )
+                .await
+            }
+            _ => Err(SignalProtocolError::InvalidArgument(format!(
+                "message_decrypt_batch cannot be used to decrypt {:?} messages",
+                ciphertext.message_type()
+            ))),
+        };
+        results.push(staged.map(
+            |StagedDecryption {
+                 ptext, transaction, ..
+             }| {
+                batch.absorb(transaction);
+                ptext
+            },
+        ));
+    }
 
-    // Why are we performing this check after decryption instead of before?
-    let their_identity_key = session_record
-        .session_state()
-        .expect("successfully decrypted; must have a current state")
-        .remote_identity_key()
-        .expect("successfully decrypted; must have a remote identity key")
-        .expect("successfully decrypted; must have a remote identity key");
+    let updates = batch.updates;
+    let now = SystemTime::now();
+    for update in updates {
+        let address = update.address.clone();
+        let Err(e) = update
+            .write_back(
+                session_store,
+                identity_store,
+                pre_key_store,
+                kyber_pre_key_store,
+                now,
+            )
+            .await
+        else {
+            continue;
+        };
+        log::warn!("failed to save the updates from a batch for {address}: {e}");
+        let mut error = Some(e);
+        for (result, _) in results
+            .iter_mut()
+            .zip(messages)
+            .filter(|(result, (remote_address, _))| result.is_ok() && *remote_address == address)
+        {
+            *result = Err(error.take().unwrap_or_else(|| {
+                SignalProtocolError::InvalidState(
+                    "message_decrypt_batch",
+                    format!("failed to save the session with {address}"),
+                )
+            }));
+        }
+    }
+    results
+}
 
-    if !identity_store
-        .is_trusted_identity(remote_address, &their_identity_key, Direction::Receiving)
-        .await?
-    {
-        log::warn!(
-            "Identity key {} is not trusted for remote address {}",
-            hex::encode(their_identity_key.public_key().public_key_bytes()),
-            remote_address,
-        );
-        return Err(SignalProtocolError::UntrustedIdentity(
-            remote_address.clone(),
-        ));
+/// The updates for one address collected over a [`message_decrypt_batch`] call.
+struct BatchUpdate {
+    address: ProtocolAddress,
+    identity: Option<IdentityKey>,
+    session: Option<SessionRecord>,
+    used_kyber_pre_keys: Vec<(KyberPreKeyId, SignedPreKeyId, PublicKey)>,
+    removed_pre_keys: Vec<PreKeyId>,
+}
+
+impl BatchUpdate {
+    /// Saves the address's session and identity, and only then consumes the pre-keys they were set
+    /// up with.
+    ///
+    /// If a pre-key can't be consumed, for example because the store only detects a reused Kyber
+    /// pre-key when it is marked as used, the previous session and identity are put back as far
+    /// as the store traits allow.
+    async fn write_back(
+        self,
+        session_store: &mut dyn SessionStore,
+        identity_store: &mut dyn IdentityKeyStore,
+        pre_key_store: &mut dyn PreKeyStore,
+        kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+        now: SystemTime,
+    ) -> Result<()> {
+        let address = &self.address;
+        let previous_identity = match self.identity {
+            Some(_) => identity_store.get_identity(address).await?,
+            None => None,
+        };
+        let previous_session = match self.session {
+            Some(_) => session_store.load_session(address).await?,
+            None => None,
+        };
+
+        if let Some(identity) = &self.identity {
+            identity_store.save_identity(address, identity).await?;
+        }
+        if let Some(record) = &self.session {
+            session_store.store_session(address, record).await?;
+        }
+
+        let consumed = async {
+            for (kyber_pre_key_id, signed_pre_key_id, base_key) in &self.used_kyber_pre_keys {
+                kyber_pre_key_store
+                    .mark_kyber_pre_key_used(*kyber_pre_key_id, *signed_pre_key_id, base_key)
+                    .await?;
+            }
+            for pre_key_id in &self.removed_pre_keys {
+                pre_key_store.remove_pre_key(*pre_key_id).await?;
+            }
+            Ok::<_, SignalProtocolError>(())
+        };
+        if let Err(e) = consumed.await {
+            let restored = async {
+                if let Some(record) = &previous_session {
+                    session_store.store_session(address, record).await?;
+                }
+                if let Some(identity) = &previous_identity {
+                    identity_store.save_identity(address, identity).await?;
+                }
+                Ok::<_, SignalProtocolError>(())
+            };
+            if let Err(e) = restored.await {
+                log::error!("failed to undo updates for {address}: {e}");
+            }
+            return Err(e);
+        }
+
+        if let Some(identity) = &self.identity {
+            // As in `commit_staged`, the messages have been saved, so this is only logged.
+            if let Err(e) = identity_store
+                .observe_identity(address, identity, IdentitySource::Message, now)
+                .await
+            {
+                log::warn!("failed to record the identity of {address} in its history: {e}");
+            }
+        }
+        Ok(())
     }
+}
 
-    identity_store
-        .save_identity(remote_address, &their_identity_key)
-        .await?;
+/// Reads through to the real stores, but sees the sessions and identities updated and the
+/// pre-keys used earlier in a [`message_decrypt_batch`] call before they are written back.
+///
+/// The stores are only written to once the whole batch has been decrypted, so the methods that
+/// would write to them outside of [`BatchStores::absorb`] fail.
+struct BatchStores<'a> {
+    session_store: &'a dyn SessionStore,
+    identity_store: &'a dyn IdentityKeyStore,
+    pre_key_store: &'a dyn PreKeyStore,
+    kyber_pre_key_store: &'a dyn KyberPreKeyStore,
+    updates: Vec<BatchUpdate>,
+}
 
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
+impl BatchStores<'_> {
+    /// Takes on the updates staged for a successfully decrypted message.
+    fn absorb(&mut self, mut transaction: ProtocolStoreTransaction) {
+        let update = self.update_for_mut(transaction.address());
+        if let Some((kyber_pre_key_id, signed_pre_key_id, base_key)) =
+            transaction.used_kyber_pre_key()
+        {
+            update
+                .used_kyber_pre_keys
+                .push((kyber_pre_key_id, signed_pre_key_id, *base_key));
+        }
+        if let Some(pre_key_id) = transaction.removed_pre_key() {
+            update.removed_pre_keys.push(pre_key_id);
+        }
+        if let Some(identity) = transaction.identity() {
+            update.identity = Some(*identity);
+        }
+        if let Some(record) = transaction.take_session() {
+            update.session = Some(record);
+        }
+    }
 
-    Ok(ptext)
+    fn update_for(&self, address: &ProtocolAddress) -> Option<&BatchUpdate> {
+        self.updates
+            .iter()
+            .find(|update| update.address == *address)
+    }
+
+    fn update_for_mut(&mut self, address: &ProtocolAddress) -> &mut BatchUpdate {
+        match self
+            .updates
+            .iter()
+            .position(|update| update.address == *address)
+        {
+            Some(index) => &mut self.updates[index],
+            None => {
+                self.updates.push(BatchUpdate {
+                    address: address.clone(),
+                    identity: None,
+                    session: None,
+                    used_kyber_pre_keys: vec![],
+                    removed_pre_keys: vec![],
+                });
+                self.updates.last_mut().expect("just pushed")
+            }
+        }
+    }
+}
+
+fn written_during_batch() -> SignalProtocolError {
+    SignalProtocolError::InvalidState(
+        "message_decrypt_batch",
+        "pre-keys are only written back once the batch has been decrypted".to_owned(),
+    )
+}
+
+#[async_trait(?Send)]
+impl SessionStore for BatchStores<'_> {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        match self
+            .update_for(address)
+            .and_then(|update| update.session.as_ref())
+        {
+            Some(record) => Ok(Some(record.clone())),
+            None => self.session_store.load_session(address).await,
+        }
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.update_for_mut(address).session = Some(record.clone());
+        Ok(())
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.session_store.session_policy()
+    }
+}
+
+#[async_trait(?Send)]
+impl IdentityKeyStore for BatchStores<'_> {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        self.identity_store.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        self.identity_store.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        let previous = self.get_identity(address).await?;
+        self.update_for_mut(address).identity = Some(*identity);
+        Ok(IdentityChange::from_changed(
+            previous.is_some_and(|previous| previous != *identity),
+        ))
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: Direction,
+    ) -> Result<bool> {
+        // An identity saved earlier in the batch was already trusted.
+        if self
+            .update_for(address)
+            .is_some_and(|update| update.identity.as_ref() == Some(identity))
+        {
+            return Ok(true);
+        }
+        self.identity_store
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        match self.update_for(address).and_then(|update| update.identity) {
+            Some(identity) => Ok(Some(identity)),
+            None => self.identity_store.get_identity(address).await,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl PreKeyStore for BatchStores<'_> {
+    async fn get_pre_key(&self, prekey_id: PreKeyId) -> Result<PreKeyRecord> {
+        // A one-time pre-key used earlier in the batch is as good as gone.
+        if self
+            .updates
+            .iter()
+            .any(|update| update.removed_pre_keys.contains(&prekey_id))
+        {
+            return Err(SignalProtocolError::InvalidPreKeyId);
+        }
+        self.pre_key_store.get_pre_key(prekey_id).await
+    }
+
+    async fn save_pre_key(&mut self, _prekey_id: PreKeyId, _record: &PreKeyRecord) -> Result<()> {
+        Err(written_during_batch())
+    }
+
+    async fn remove_pre_key(&mut self, _prekey_id: PreKeyId) -> Result<()> {
+        Err(written_during_batch())
+    }
+}
+
+#[async_trait(?Send)]
+impl KyberPreKeyStore for BatchStores<'_> {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        self.kyber_pre_key_store
+            .get_kyber_pre_key(kyber_prekey_id)
+            .await
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        _kyber_prekey_id: KyberPreKeyId,
+        _record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        Err(written_during_batch())
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        _kyber_prekey_id: KyberPreKeyId,
+        _ec_prekey_id: SignedPreKeyId,
+        _base_key: &PublicKey,
+    ) -> Result<()> {
+        Err(written_during_batch())
+    }
+
+    async fn is_base_key_used(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<bool> {
+        let used = (kyber_prekey_id, ec_prekey_id, *base_key);
+        if self
+            .updates
+            .iter()
+            .any(|update| update.used_kyber_pre_keys.contains(&used))
+        {
+            return Ok(true);
+        }
+        self.kyber_pre_key_store
+            .is_base_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+}
+
+/// Remembers the tree head the remote party gossiped in the current session, so that later
+/// messages to them can carry our head in compact form once they have seen it.
+///
+/// Only heads that were verified against our own are remembered; otherwise the remote party
+/// could stop us sending them our head in full just by claiming to have seen it.
+fn record_acknowledged_gossip_head(
+    session_record: &mut SessionRecord,
+    gossip_bytes: &[u8],
+    gossip_status: &GossipStatus,
+) {
+    if !matches!(gossip_status, GossipStatus::Verified(_)) {
+        return;
+    }
+    let Ok(head) = GossipMessage::decode(gossip_bytes).and_then(|m| m.acknowledged_head()) else {
+        return;
+    };
+    if let Some(session_state) = session_record.session_state_mut() {
+        session_state.set_acknowledged_gossip_head(head);
+    }
+}
+
+/// Checks gossip received from `remote_address` outside of a [`SignalMessage`], such as gossip a
+/// client extracted from a decrypted sealed sender message or relayed from another device.
+///
+/// Bad gossip is reported through the returned [`GossipStatus`] rather than as an error, exactly
+/// as for [`message_decrypt_with_gossip`]; only failures to persist the updated gossip state are
+/// errors. Empty `gossip_bytes` are reported as [`GossipStatus::Missing`].
+pub async fn process_incoming_gossip(
+    gossip_service: &mut GossipService,
+    remote_address: &ProtocolAddress,
+    gossip_bytes: &[u8],
+    now: SystemTime,
+) -> Result<GossipStatus> {
+    process_gossip(Some((gossip_service, now)), remote_address, gossip_bytes).await
+}
+
+/// Hands `gossip_bytes` received from `remote_address` to the gossip service, if there is one,
+/// and saves whatever it learned right away.
+///
+/// Only failures to persist the updated gossip state are treated as errors; a peer sending bad
+/// gossip shouldn't prevent us from reading their message.
+async fn process_gossip(
+    gossip: Option<(&mut GossipService, SystemTime)>,
+    remote_address: &ProtocolAddress,
+    gossip_bytes: &[u8],
+) -> Result<GossipStatus> {
+    let (gossip_status, checked) =
+        check_gossip(for_checking(&gossip), remote_address, gossip_bytes);
+    if let (Some((gossip_service, _)), Some(checked)) = (gossip, checked) {
+        gossip_service
+            .save_checked_gossip(checked)
+            .await
+            .map_err(|e| {
+                SignalProtocolError::ApplicationCallbackError(
+                    "process_incoming_gossip",
+                    Box::new(e),
+                )
+            })?;
+    }
+    Ok(gossip_status)
+}
+
+/// Checks `gossip_bytes` received from `remote_address` with the gossip service, if there is one.
+///
+/// Nothing the gossip taught us is saved yet; that is up to the caller, with the returned
+/// [`CheckedGossip`], once whatever the gossip came with has been saved.
+pub(crate) fn check_gossip(
+    gossip: Option<(&GossipService, SystemTime)>,
+    remote_address: &ProtocolAddress,
+    gossip_bytes: &[u8],
+) -> (GossipStatus, Option<CheckedGossip>) {
+    let Some((gossip_service, now)) = gossip else {
+        return (GossipStatus::Missing, None);
+    };
+    if gossip_bytes.is_empty() {
+        return (GossipStatus::Missing, None);
+    }
+    match gossip_service.check_incoming_gossip(&remote_address.to_string(), gossip_bytes, now) {
+        Ok(checked) => {
+            let gossip_status = match checked.outcome() {
+                GossipOutcome::Bootstrapped | GossipOutcome::Unverified => GossipStatus::Unverified,
+                outcome => GossipStatus::Verified(outcome.clone()),
+            };
+            (gossip_status, Some(checked))
+        }
+        Err(
+            GossipError::Uninitialized
+            | GossipError::UnknownTreeHead
+            | GossipError::InsufficientAuditors(..),
+        ) => (GossipStatus::Unverified, None),
+        Err(GossipError::Inconsistent(proof)) => {
+            log::warn!("gossip from {remote_address} is inconsistent with our tree head");
+            (GossipStatus::Inconsistent(proof), None)
+        }
+        Err(
+            e @ (GossipError::Stale
+            | GossipError::FutureTimestamp
+            | GossipError::TimestampRegression),
+        ) => {
+            log::info!("gossip from {remote_address} rejected: {e}");
+            (GossipStatus::Stale, None)
+        }
+        Err(e) => {
+            log::warn!("gossip from {remote_address} could not be verified: {e}");
+            (GossipStatus::Invalid, None)
+        }
+    }
 }
 
 fn create_decryption_failure_log(
@@ -624,6 +1633,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
     record: &mut SessionRecord,
     ciphertext: &SignalMessage,
     original_message_type: CiphertextMessageType,
+    policy: &SessionPolicy,
     csprng: &mut R,
 ) -> Result<Vec<u8>> {
     debug_assert!(matches!(
@@ -657,6 +1667,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             ciphertext,
             original_message_type,
             remote_address,
+            policy,
             csprng,
         );
 
@@ -720,6 +1731,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             ciphertext,
             original_message_type,
             remote_address,
+            policy,
             csprng,
         );
 
@@ -747,7 +1759,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
     }
 
     if let Some((ptext, idx, updated_session)) = updated_session {
-        record.promote_old_session(idx, updated_session);
+        record.promote_old_session(idx, updated_session, policy);
         Ok(ptext)
     } else {
         let previous_state_count = || record.previous_session_states().len();
@@ -800,6 +1812,7 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
     ciphertext: &SignalMessage,
     original_message_type: CiphertextMessageType,
     remote_address: &ProtocolAddress,
+    policy: &SessionPolicy,
     csprng: &mut R,
 ) -> Result<Vec<u8>> {
     // Check for a completely empty or invalid session state before we do anything else.
@@ -819,7 +1832,8 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
 
     let their_ephemeral = ciphertext.sender_ratchet_key();
     let counter = ciphertext.counter();
-    let chain_key = get_or_create_chain_key(state, their_ephemeral, remote_address, csprng)?;
+    let chain_key =
+        get_or_create_chain_key(state, their_ephemeral, remote_address, policy, csprng)?;
     let message_key_gen = get_or_create_message_key(
         state,
         their_ephemeral,
@@ -827,6 +1841,7 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
         original_message_type,
         &chain_key,
         counter,
+        policy,
     )?;
     let pqr_key = state
         .pq_ratchet_recv(ciphertext.pq_ratchet())
@@ -895,6 +1910,7 @@ fn get_or_create_chain_key<R: Rng + CryptoRng>(
     state: &mut SessionState,
     their_ephemeral: &PublicKey,
     remote_address: &ProtocolAddress,
+    policy: &SessionPolicy,
     csprng: &mut R,
 ) -> Result<ChainKey> {
     if let Some(chain) = state.get_receiver_chain_key(their_ephemeral)? {
@@ -913,7 +1929,7 @@ fn get_or_create_chain_key<R: Rng + CryptoRng>(
         .create_chain(their_ephemeral, &our_new_ephemeral.private_key)?;
 
     state.set_root_key(&sender_chain.0);
-    state.add_receiver_chain(their_ephemeral, &receiver_chain.1);
+    state.add_receiver_chain(their_ephemeral, &receiver_chain.1, policy);
 
     let current_index = state.get_sender_chain_key()?.index();
     let previous_index = if current_index > 0 {
@@ -934,6 +1950,7 @@ fn get_or_create_message_key(
     original_message_type: CiphertextMessageType,
     chain_key: &ChainKey,
     counter: u32,
+    policy: &SessionPolicy,
 ) -> Result<MessageKeyGenerator> {
     let chain_index = chain_key.index();
 
@@ -951,14 +1968,15 @@ fn get_or_create_message_key(
 
     let jump = (counter - chain_index) as usize;
 
-    if jump > MAX_FORWARD_JUMPS {
+    let max_forward_jumps = policy.max_forward_jumps();
+    if jump > max_forward_jumps {
         if state.session_with_self()? {
             log::info!(
                 "{remote_address} Jumping ahead {jump} messages (index: {chain_index}, counter: {counter})"
             );
         } else {
             log::error!(
-                "{remote_address} Exceeded future message limit: {MAX_FORWARD_JUMPS}, index: {chain_index}, counter: {counter})"
+                "{remote_address} Exceeded future message limit: {max_forward_jumps}, index: {chain_index}, counter: {counter})"
             );
             return Err(SignalProtocolError::InvalidMessage(
                 original_message_type,
@@ -971,7 +1989,7 @@ fn get_or_create_message_key(
 
     while chain_key.index() < counter {
         let message_keys = chain_key.message_keys();
-        state.set_message_keys(their_ephemeral, message_keys)?;
+        state.set_message_keys(their_ephemeral, message_keys, policy)?;
         chain_key = chain_key.next_chain_key();
     }
 
@@ -0,0 +1,215 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::time::Duration;
+
+use crate::{Result, SignalProtocolError, consts};
+
+/// Limits on how much state a session or sender key record keeps, trading tolerance of
+/// out-of-order and delayed delivery against storage size.
+///
+/// The [default](Self::default) matches what every Signal client uses. Build a different policy
+/// with [`SessionPolicy::builder`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionPolicy {
+    max_forward_jumps: usize,
+    max_message_keys: usize,
+    max_receiver_chains: usize,
+    archived_states_max_length: usize,
+    max_sender_key_states: usize,
+    max_unacknowledged_session_age: Duration,
+}
+
+impl SessionPolicy {
+    pub fn builder() -> SessionPolicyBuilder {
+        SessionPolicyBuilder(Self::default())
+    }
+
+    /// How far ahead of the current chain index an incoming message may be.
+    pub fn max_forward_jumps(&self) -> usize {
+        self.max_forward_jumps
+    }
+
+    /// How many skipped message keys are kept per chain for messages that arrive out of order.
+    pub fn max_message_keys(&self) -> usize {
+        self.max_message_keys
+    }
+
+    /// How many of the peer's ratchet chains are kept for receiving.
+    pub fn max_receiver_chains(&self) -> usize {
+        self.max_receiver_chains
+    }
+
+    /// How many previous sessions a [`SessionRecord`](crate::SessionRecord) keeps.
+    pub fn archived_states_max_length(&self) -> usize {
+        self.archived_states_max_length
+    }
+
+    /// How many sender key states a [`SenderKeyRecord`](crate::SenderKeyRecord) keeps.
+    pub fn max_sender_key_states(&self) -> usize {
+        self.max_sender_key_states
+    }
+
+    /// How long a session can go without a response before it is no longer used for sending.
+    pub fn max_unacknowledged_session_age(&self) -> Duration {
+        self.max_unacknowledged_session_age
+    }
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            max_forward_jumps: consts::MAX_FORWARD_JUMPS,
+            max_message_keys: consts::MAX_MESSAGE_KEYS,
+            max_receiver_chains: consts::MAX_RECEIVER_CHAINS,
+            archived_states_max_length: consts::ARCHIVED_STATES_MAX_LENGTH,
+            max_sender_key_states: consts::MAX_SENDER_KEY_STATES,
+            max_unacknowledged_session_age: consts::MAX_UNACKNOWLEDGED_SESSION_AGE,
+        }
+    }
+}
+
+/// Builds a [`SessionPolicy`], starting from the defaults.
+#[derive(Clone, Debug)]
+pub struct SessionPolicyBuilder(SessionPolicy);
+
+impl SessionPolicyBuilder {
+    pub fn max_forward_jumps(mut self, value: usize) -> Self {
+        self.0.max_forward_jumps = value;
+        self
+    }
+
+    pub fn max_message_keys(mut self, value: usize) -> Self {
+        self.0.max_message_keys = value;
+        self
+    }
+
+    pub fn max_receiver_chains(mut self, value: usize) -> Self {
+        self.0.max_receiver_chains = value;
+        self
+    }
+
+    pub fn archived_states_max_length(mut self, value: usize) -> Self {
+        self.0.archived_states_max_length = value;
+        self
+    }
+
+    pub fn max_sender_key_states(mut self, value: usize) -> Self {
+        self.0.max_sender_key_states = value;
+        self
+    }
+
+    pub fn max_unacknowledged_session_age(mut self, value: Duration) -> Self {
+        self.0.max_unacknowledged_session_age = value;
+        self
+    }
+
+    /// Checks that every limit leaves a session usable.
+    ///
+    /// Each count must be at least 1, and the forward jump and message key limits must fit in a
+    /// `u32`, since they are also given to the post-quantum ratchet.
+    pub fn build(self) -> Result<SessionPolicy> {
+        let policy = self.0;
+        for (name, value) in [
+            ("max_forward_jumps", policy.max_forward_jumps),
+            ("max_message_keys", policy.max_message_keys),
+            ("max_receiver_chains", policy.max_receiver_chains),
+            (
+                "archived_states_max_length",
+                policy.archived_states_max_length,
+            ),
+            ("max_sender_key_states", policy.max_sender_key_states),
+        ] {
+            if value == 0 {
+                return Err(SignalProtocolError::InvalidArgument(format!(
+                    "{name} must be at least 1"
+                )));
+            }
+        }
+        for (name, value) in [
+            ("max_forward_jumps", policy.max_forward_jumps),
+            ("max_message_keys", policy.max_message_keys),
+        ] {
+            if u32::try_from(value).is_err() {
+                return Err(SignalProtocolError::InvalidArgument(format!(
+                    "{name} ({value}) must fit in a u32"
+                )));
+            }
+        }
+        if policy.max_unacknowledged_session_age.is_zero() {
+            return Err(SignalProtocolError::InvalidArgument(
+                "max_unacknowledged_session_age must not be zero".to_owned(),
+            ));
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn builder_starts_from_defaults() {
+        assert_eq!(
+            SessionPolicy::builder().build().expect("valid"),
+            SessionPolicy::default()
+        );
+    }
+
+    #[test]
+    fn builder_rejects_zero_limits() {
+        assert_matches!(
+            SessionPolicy::builder().max_forward_jumps(0).build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder().max_message_keys(0).build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder().max_receiver_chains(0).build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder()
+                .archived_states_max_length(0)
+                .build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder().max_sender_key_states(0).build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder()
+                .max_unacknowledged_session_age(Duration::ZERO)
+                .build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn builder_rejects_limits_that_do_not_fit_in_u32() {
+        let too_big = usize::try_from(u64::from(u32::MAX) + 1).expect("64-bit");
+        assert_matches!(
+            SessionPolicy::builder().max_forward_jumps(too_big).build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder().max_message_keys(too_big).build(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        assert_matches!(
+            SessionPolicy::builder()
+                .max_receiver_chains(too_big)
+                .build(),
+            Ok(_)
+        );
+    }
+}
@@ -7,15 +7,20 @@ use std::result::Result;
 use std::time::{Duration, SystemTime};
 
 use bitflags::bitflags;
+use libsignal_gossip::AcknowledgedTreeHead;
 use prost::Message;
 use rand::{CryptoRng, Rng};
 use subtle::ConstantTimeEq;
 
+use crate::describe::{
+    ChainDescription, KeyFingerprint, PendingPreKeyDescription, SessionRecordDescription,
+    SessionStateDescription,
+};
 use crate::proto::storage::{RecordStructure, SessionStructure, session_structure};
 use crate::protocol::CIPHERTEXT_MESSAGE_PRE_KYBER_VERSION;
 use crate::ratchet::{ChainKey, MessageKeyGenerator, RootKey};
 use crate::state::{KyberPreKeyId, PreKeyId, SignedPreKeyId};
-use crate::{IdentityKey, KeyPair, PrivateKey, PublicKey, SignalProtocolError, consts, kem};
+use crate::{IdentityKey, KeyPair, PrivateKey, PublicKey, SessionPolicy, SignalProtocolError, kem};
 
 /// A distinct error type to keep from accidentally propagating deserialization errors.
 #[derive(Debug)]
@@ -161,6 +166,7 @@ impl SessionState {
                 local_registration_id: 0,
                 alice_base_key: alice_base_key.serialize().into_vec(),
                 pq_ratchet_state,
+                acknowledged_gossip_head: None,
             },
         }
     }
@@ -253,6 +259,7 @@ impl SessionState {
         &self,
         now: SystemTime,
         requirements: SessionUsabilityRequirements,
+        policy: &SessionPolicy,
     ) -> Result<bool, InvalidSessionError> {
         if self.session.sender_chain.is_none() {
             return Ok(false);
@@ -261,7 +268,7 @@ impl SessionState {
             if let Some(pending_pre_key) = &self.session.pending_pre_key {
                 let creation_timestamp =
                     SystemTime::UNIX_EPOCH + Duration::from_secs(pending_pre_key.timestamp);
-                if creation_timestamp + consts::MAX_UNACKNOWLEDGED_SESSION_AGE < now {
+                if creation_timestamp + policy.max_unacknowledged_session_age() < now {
                     return Ok(false);
                 }
             }
@@ -281,6 +288,78 @@ impl SessionState {
         Ok(true)
     }
 
+    pub(crate) fn describe(&self) -> SessionStateDescription {
+        fn describe_chain(chain: &session_structure::Chain) -> ChainDescription {
+            ChainDescription {
+                ratchet_key: KeyFingerprint::of_optional(&chain.sender_ratchet_key),
+                chain_index: chain.chain_key.as_ref().map(|chain_key| chain_key.index),
+                skipped_message_keys: chain.message_keys.len(),
+            }
+        }
+
+        let session = &self.session;
+        SessionStateDescription {
+            session_version: self.session_version().expect("session_version never fails"),
+            local_identity_key: KeyFingerprint::of_optional(&session.local_identity_public),
+            remote_identity_key: KeyFingerprint::of_optional(&session.remote_identity_public),
+            alice_base_key: KeyFingerprint::of_optional(&session.alice_base_key),
+            local_registration_id: session.local_registration_id,
+            remote_registration_id: session.remote_registration_id,
+            previous_counter: session.previous_counter,
+            sender_chain: session.sender_chain.as_ref().map(describe_chain),
+            receiver_chains: session.receiver_chains.iter().map(describe_chain).collect(),
+            pending_pre_key: session.pending_pre_key.as_ref().map(|pending_pre_key| {
+                PendingPreKeyDescription {
+                    pre_key_id: pending_pre_key.pre_key_id,
+                    signed_pre_key_id: pending_pre_key.signed_pre_key_id as u32,
+                    kyber_pre_key_id: session
+                        .pending_kyber_pre_key
+                        .as_ref()
+                        .map(|pending_kyber_pre_key| pending_kyber_pre_key.pre_key_id),
+                    base_key: KeyFingerprint::of_optional(&pending_pre_key.base_key),
+                    timestamp: pending_pre_key.timestamp,
+                }
+            }),
+            pq_ratchet_state_length: session.pq_ratchet_state.len(),
+            acknowledged_gossip_tree_size: session
+                .acknowledged_gossip_head
+                .as_ref()
+                .map(|head| head.tree_size),
+        }
+    }
+
+    /// Whether `other` has made progress that `self` lacks, assuming both are copies of the same
+    /// session.
+    ///
+    /// A receiver chain in `other` that `self` doesn't have at all counts as progress, even though
+    /// `self` might instead have moved on far enough to trim it.
+    fn is_behind(&self, other: &SessionState) -> bool {
+        fn chain_index(chain: &session_structure::Chain) -> Option<u32> {
+            chain.chain_key.as_ref().map(|chain_key| chain_key.index)
+        }
+
+        if let (Some(ours), Some(theirs)) =
+            (&self.session.sender_chain, &other.session.sender_chain)
+        {
+            if ours.sender_ratchet_key == theirs.sender_ratchet_key
+                && chain_index(ours) < chain_index(theirs)
+            {
+                return true;
+            }
+        }
+        other.session.receiver_chains.iter().any(|theirs| {
+            match self
+                .session
+                .receiver_chains
+                .iter()
+                .find(|ours| ours.sender_ratchet_key == theirs.sender_ratchet_key)
+            {
+                Some(ours) => chain_index(ours) < chain_index(theirs),
+                None => true,
+            }
+        })
+    }
+
     pub(crate) fn all_receiver_chain_logging_info(&self) -> Vec<(Vec<u8>, Option<u32>)> {
         let mut results = vec![];
         for chain in self.session.receiver_chains.iter() {
@@ -329,7 +408,12 @@ impl SessionState {
         }
     }
 
-    pub(crate) fn add_receiver_chain(&mut self, sender: &PublicKey, chain_key: &ChainKey) {
+    pub(crate) fn add_receiver_chain(
+        &mut self,
+        sender: &PublicKey,
+        chain_key: &ChainKey,
+        policy: &SessionPolicy,
+    ) {
         let chain_key = session_structure::chain::ChainKey {
             index: chain_key.index(),
             key: chain_key.key().to_vec(),
@@ -344,7 +428,7 @@ impl SessionState {
 
         self.session.receiver_chains.push(chain);
 
-        if self.session.receiver_chains.len() > consts::MAX_RECEIVER_CHAINS {
+        if self.session.receiver_chains.len() > policy.max_receiver_chains() {
             log::info!(
                 "Trimming excessive receiver_chain for session with base key {}, chain count: {}",
                 self.sender_ratchet_key_for_logging()
@@ -355,8 +439,13 @@ impl SessionState {
         }
     }
 
-    pub(crate) fn with_receiver_chain(mut self, sender: &PublicKey, chain_key: &ChainKey) -> Self {
-        self.add_receiver_chain(sender, chain_key);
+    pub(crate) fn with_receiver_chain(
+        mut self,
+        sender: &PublicKey,
+        chain_key: &ChainKey,
+        policy: &SessionPolicy,
+    ) -> Self {
+        self.add_receiver_chain(sender, chain_key, policy);
         self
     }
 
@@ -458,6 +547,7 @@ impl SessionState {
         &mut self,
         sender: &PublicKey,
         message_keys: MessageKeyGenerator,
+        policy: &SessionPolicy,
     ) -> Result<(), InvalidSessionError> {
         let chain_and_index = self
             .get_receiver_chain(sender)?
@@ -465,7 +555,7 @@ impl SessionState {
         let mut updated_chain = chain_and_index.0;
         updated_chain.message_keys.insert(0, message_keys.into_pb());
 
-        if updated_chain.message_keys.len() > consts::MAX_MESSAGE_KEYS {
+        if updated_chain.message_keys.len() > policy.max_message_keys() {
             updated_chain.message_keys.pop();
         }
 
@@ -567,6 +657,7 @@ impl SessionState {
             local_registration_id: _local_registration_id,
             alice_base_key: _alice_base_key,
             pq_ratchet_state: _pq_ratchet_state,
+            acknowledged_gossip_head: _acknowledged_gossip_head,
         } = &self.session;
         // ####### IMPORTANT #######
         // Don't forget to clean up new pending fields.
@@ -591,6 +682,22 @@ impl SessionState {
         self.session.local_registration_id
     }
 
+    /// The key transparency tree head the remote party last gossiped to us.
+    pub(crate) fn acknowledged_gossip_head(&self) -> Option<AcknowledgedTreeHead> {
+        let head = self.session.acknowledged_gossip_head.as_ref()?;
+        Some(AcknowledgedTreeHead {
+            tree_size: head.tree_size,
+            tree_root: head.tree_root.as_slice().try_into().ok()?,
+        })
+    }
+
+    pub(crate) fn set_acknowledged_gossip_head(&mut self, head: AcknowledgedTreeHead) {
+        self.session.acknowledged_gossip_head = Some(session_structure::GossipTreeHead {
+            tree_size: head.tree_size,
+            tree_root: head.tree_root.to_vec(),
+        });
+    }
+
     pub(crate) fn get_kyber_ciphertext(&self) -> Option<&Vec<u8>> {
         self.session
             .pending_kyber_pre_key
@@ -680,6 +787,7 @@ impl SessionRecord {
         &mut self,
         version: u32,
         alice_base_key: &[u8],
+        policy: &SessionPolicy,
     ) -> Result<bool, InvalidSessionError> {
         if let Some(current_session) = &self.current_session {
             if current_session.session_version()? == version
@@ -703,7 +811,7 @@ impl SessionRecord {
         }
 
         if let Some((i, state)) = session_to_promote {
-            self.promote_old_session(i, state);
+            self.promote_old_session(i, state, policy);
             return Ok(true);
         }
 
@@ -736,24 +844,25 @@ impl SessionRecord {
         &mut self,
         old_session: usize,
         updated_session: SessionState,
+        policy: &SessionPolicy,
     ) {
         self.previous_sessions.remove(old_session);
-        self.promote_state(updated_session)
+        self.promote_state(updated_session, policy)
     }
 
-    pub(crate) fn promote_state(&mut self, new_state: SessionState) {
-        self.archive_current_state_inner();
+    pub(crate) fn promote_state(&mut self, new_state: SessionState, policy: &SessionPolicy) {
+        self.archive_current_state_inner(policy);
         self.current_session = Some(new_state);
     }
 
     // A non-fallible version of archive_current_state.
     //
     // Returns `true` if there was a session to archive, `false` if not.
-    fn archive_current_state_inner(&mut self) -> bool {
+    fn archive_current_state_inner(&mut self, policy: &SessionPolicy) -> bool {
         if let Some(mut current_session) = self.current_session.take() {
-            if self.previous_sessions.len() >= consts::ARCHIVED_STATES_MAX_LENGTH {
-                self.previous_sessions.pop();
-            }
+            // The policy may have been lowered since this record was last updated.
+            self.previous_sessions
+                .truncate(policy.archived_states_max_length() - 1);
             current_session.clear_unacknowledged_pre_key_message();
             self.previous_sessions
                 .insert(0, current_session.session.encode_to_vec());
@@ -763,8 +872,10 @@ impl SessionRecord {
         }
     }
 
+    /// Moves the current session into the previous sessions, keeping as many of them as the
+    /// default [`SessionPolicy`] allows.
     pub fn archive_current_state(&mut self) -> Result<(), SignalProtocolError> {
-        if !self.archive_current_state_inner() {
+        if !self.archive_current_state_inner(&SessionPolicy::default()) {
             log::info!("Skipping archive, current session state is fresh");
         }
         Ok(())
@@ -778,6 +889,53 @@ impl SessionRecord {
         Ok(record.encode_to_vec())
     }
 
+    /// Whether storing `self` in place of `existing` could lose state.
+    ///
+    /// That's the case if `existing` has a session that `self` doesn't know about, has archived the
+    /// session that is current in `self`, or is further along in the same session.
+    pub(crate) fn is_older_than(
+        &self,
+        existing: &SessionRecord,
+    ) -> Result<bool, InvalidSessionError> {
+        fn archived_base_keys(record: &SessionRecord) -> Result<Vec<Vec<u8>>, InvalidSessionError> {
+            record
+                .previous_session_states()
+                .map(|state| Ok(state?.alice_base_key().to_vec()))
+                .collect()
+        }
+
+        let Some(theirs) = existing.session_state() else {
+            return Ok(match self.session_state() {
+                Some(ours) => archived_base_keys(existing)?
+                    .iter()
+                    .any(|base_key| base_key == ours.alice_base_key()),
+                None => false,
+            });
+        };
+        let Some(ours) = self.session_state() else {
+            return Ok(true);
+        };
+        if ours.alice_base_key() == theirs.alice_base_key() {
+            return Ok(ours.is_behind(theirs));
+        }
+        // Unless `self` has already replaced the stored session, the store has moved past `self`.
+        Ok(!archived_base_keys(self)?
+            .iter()
+            .any(|base_key| base_key == theirs.alice_base_key()))
+    }
+
+    /// Summarizes the record for debugging, without any secrets.
+    pub fn describe(&self) -> Result<SessionRecordDescription, SignalProtocolError> {
+        Ok(SessionRecordDescription {
+            current_session: self.current_session.as_ref().map(SessionState::describe),
+            archived_state_count: self.previous_sessions.len(),
+            archived_sessions: self
+                .previous_session_states()
+                .map(|state| Ok(state?.describe()))
+                .collect::<Result<_, InvalidSessionError>>()?,
+        })
+    }
+
     pub fn current_pq_state(&self) -> Option<&spqr::SerializedState> {
         self.current_session.as_ref().map(|s| s.pq_ratchet_state())
     }
@@ -839,13 +997,19 @@ impl SessionRecord {
             .remote_identity_key_bytes()?)
     }
 
+    /// Whether the current session can be used to send, treating a session as stale after the
+    /// default [`SessionPolicy::max_unacknowledged_session_age`].
     pub fn has_usable_sender_chain(
         &self,
         now: SystemTime,
         requirements: SessionUsabilityRequirements,
     ) -> Result<bool, SignalProtocolError> {
         match &self.current_session {
-            Some(session) => Ok(session.has_usable_sender_chain(now, requirements)?),
+            Some(session) => Ok(session.has_usable_sender_chain(
+                now,
+                requirements,
+                &SessionPolicy::default(),
+            )?),
             None => Ok(false),
         }
     }
@@ -4,17 +4,27 @@
 //
 
 //! Interfaces in [traits] and reference implementations in [inmem] for various mutable stores.
+//!
+//! With the `sqlite` feature, there are also implementations persisted in a SQLite database.
 
 #![warn(missing_docs)]
 
 mod inmem;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 mod traits;
 
 pub use inmem::{
     InMemIdentityKeyStore, InMemKyberPreKeyStore, InMemPreKeyStore, InMemSenderKeyStore,
     InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
 };
+#[cfg(feature = "sqlite")]
+pub use sqlite::{
+    SqliteIdentityKeyStore, SqliteKyberPreKeyStore, SqlitePreKeyStore, SqliteSenderKeyStore,
+    SqliteSessionStore, SqliteSignalProtocolStore, SqliteSignedPreKeyStore,
+};
 pub use traits::{
-    Direction, IdentityChange, IdentityKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore,
-    SenderKeyStore, SessionStore, SignedPreKeyStore,
+    Direction, IdentityChange, IdentityHistoryEntry, IdentityHistoryStore, IdentityKeyStore,
+    IdentitySource, KyberPreKeyStore, PreKeyStore, ProtocolStore, ProtocolStoreTransaction,
+    SenderKeyStore, SessionStore, SignedPreKeyStore, TransactionalProtocolStore,
 };
@@ -9,23 +9,26 @@
 
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use crate::storage::traits::{self, IdentityChange};
+use crate::storage::traits::{self, IdentityChange, IdentityHistoryEntry, IdentitySource};
 use crate::{
-    CiphertextMessageType, IdentityKey, IdentityKeyPair, KyberPreKeyId, KyberPreKeyRecord,
-    PreKeyId, PreKeyRecord, ProtocolAddress, PublicKey, Result, SenderKeyRecord, SessionRecord,
+    IdentityKey, IdentityKeyPair, KyberPreKeyId, KyberPreKeyRecord, PreKeyId, PreKeyRecord,
+    ProtocolAddress, PublicKey, Result, SenderKeyRecord, SessionPolicy, SessionRecord,
     SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord,
 };
 
-/// Reference implementation of [traits::IdentityKeyStore].
+/// Reference implementation of [traits::IdentityKeyStore] and [traits::IdentityHistoryStore].
 #[derive(Clone)]
 pub struct InMemIdentityKeyStore {
     key_pair: IdentityKeyPair,
     registration_id: u32,
     known_keys: HashMap<ProtocolAddress, IdentityKey>,
+    /// Oldest first.
+    history: HashMap<ProtocolAddress, Vec<IdentityHistoryEntry>>,
 }
 
 impl InMemIdentityKeyStore {
@@ -38,10 +41,13 @@ impl InMemIdentityKeyStore {
             key_pair,
             registration_id,
             known_keys: HashMap::new(),
+            history: HashMap::new(),
         }
     }
 
     /// Clear the mapping of known keys.
+    ///
+    /// The identity history is kept.
     pub fn reset(&mut self) {
         self.known_keys.clear();
     }
@@ -95,6 +101,54 @@ impl traits::IdentityKeyStore for InMemIdentityKeyStore {
             Some(k) => Ok(Some(k.to_owned())),
         }
     }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        source: IdentitySource,
+        now: SystemTime,
+    ) -> Result<()> {
+        let entries = self.history.entry(address.clone()).or_default();
+        match entries
+            .iter_mut()
+            .find(|entry| entry.identity() == identity)
+        {
+            Some(entry) => entry.observe(source, now),
+            None => entries.push(IdentityHistoryEntry::new(*identity, source, now)),
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityHistoryStore for InMemIdentityKeyStore {
+    async fn identity_history(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Vec<IdentityHistoryEntry>> {
+        Ok(self.history.get(address).cloned().unwrap_or_default())
+    }
+
+    async fn set_identity_verified(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        verified: bool,
+    ) -> Result<bool> {
+        let entry = self.history.get_mut(address).and_then(|entries| {
+            entries
+                .iter_mut()
+                .find(|entry| entry.identity() == identity)
+        });
+        Ok(match entry {
+            Some(entry) => {
+                entry.set_verified(verified);
+                true
+            }
+            None => false,
+        })
+    }
 }
 
 /// Reference implementation of [traits::PreKeyStore].
@@ -255,20 +309,42 @@ impl traits::KyberPreKeyStore for InMemKyberPreKeyStore {
             .entry((kyber_prekey_id, ec_prekey_id))
             .or_default();
         if base_keys_seen.contains(base_key) {
-            return Err(SignalProtocolError::InvalidMessage(
-                CiphertextMessageType::PreKey,
-                "reused base key",
+            return Err(SignalProtocolError::ReusedBaseKey(
+                kyber_prekey_id,
+                ec_prekey_id,
             ));
         }
         base_keys_seen.push(*base_key);
         Ok(())
     }
+
+    async fn is_base_key_used(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<bool> {
+        Ok(self
+            .base_keys_seen
+            .get(&(kyber_prekey_id, ec_prekey_id))
+            .is_some_and(|base_keys_seen| base_keys_seen.contains(base_key)))
+    }
+
+    async fn expire_base_keys_for_signed_pre_key(
+        &mut self,
+        ec_prekey_id: SignedPreKeyId,
+    ) -> Result<()> {
+        self.base_keys_seen
+            .retain(|&(_, signed_pre_key_id), _| signed_pre_key_id != ec_prekey_id);
+        Ok(())
+    }
 }
 
 /// Reference implementation of [traits::SessionStore].
 #[derive(Clone)]
 pub struct InMemSessionStore {
     sessions: HashMap<ProtocolAddress, SessionRecord>,
+    policy: SessionPolicy,
 }
 
 impl InMemSessionStore {
@@ -276,9 +352,17 @@ impl InMemSessionStore {
     pub fn new() -> Self {
         Self {
             sessions: HashMap::new(),
+            policy: SessionPolicy::default(),
         }
     }
 
+    /// Sets the limits reported by [`SessionStore::session_policy`].
+    ///
+    /// [`SessionStore::session_policy`]: crate::SessionStore::session_policy
+    pub fn set_session_policy(&mut self, policy: SessionPolicy) {
+        self.policy = policy;
+    }
+
     /// Bulk version of [`SessionStore::load_session`].
     ///
     /// Useful for [crate::sealed_sender_multi_recipient_encrypt].
@@ -322,6 +406,10 @@ impl traits::SessionStore for InMemSessionStore {
         self.sessions.insert(address.clone(), record.clone());
         Ok(())
     }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.policy
+    }
 }
 
 /// Reference implementation of [traits::SenderKeyStore].
@@ -330,6 +418,7 @@ pub struct InMemSenderKeyStore {
     // We use Cow keys in order to store owned values but compare to referenced ones.
     // See https://users.rust-lang.org/t/hashmap-with-tuple-keys/12711/6.
     keys: HashMap<(Cow<'static, ProtocolAddress>, Uuid), SenderKeyRecord>,
+    policy: SessionPolicy,
 }
 
 impl InMemSenderKeyStore {
@@ -337,8 +426,16 @@ impl InMemSenderKeyStore {
     pub fn new() -> Self {
         Self {
             keys: HashMap::new(),
+            policy: SessionPolicy::default(),
         }
     }
+
+    /// Sets the limits reported by [`SenderKeyStore::sender_key_policy`].
+    ///
+    /// [`SenderKeyStore::sender_key_policy`]: crate::SenderKeyStore::sender_key_policy
+    pub fn set_sender_key_policy(&mut self, policy: SessionPolicy) {
+        self.policy = policy;
+    }
 }
 
 impl Default for InMemSenderKeyStore {
@@ -372,6 +469,10 @@ impl traits::SenderKeyStore for InMemSenderKeyStore {
             .get(&(Cow::Borrowed(sender), distribution_id))
             .cloned())
     }
+
+    fn sender_key_policy(&self) -> SessionPolicy {
+        self.policy
+    }
 }
 
 /// Reference implementation of [traits::ProtocolStore].
@@ -448,6 +549,39 @@ impl traits::IdentityKeyStore for InMemSignalProtocolStore {
     async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
         self.identity_store.get_identity(address).await
     }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        source: IdentitySource,
+        now: SystemTime,
+    ) -> Result<()> {
+        self.identity_store
+            .observe_identity(address, identity, source, now)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityHistoryStore for InMemSignalProtocolStore {
+    async fn identity_history(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Vec<IdentityHistoryEntry>> {
+        self.identity_store.identity_history(address).await
+    }
+
+    async fn set_identity_verified(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        verified: bool,
+    ) -> Result<bool> {
+        self.identity_store
+            .set_identity_verified(address, identity, verified)
+            .await
+    }
 }
 
 #[async_trait(?Send)]
@@ -510,6 +644,26 @@ impl traits::KyberPreKeyStore for InMemSignalProtocolStore {
             .mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
             .await
     }
+
+    async fn is_base_key_used(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<bool> {
+        self.kyber_pre_key_store
+            .is_base_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+
+    async fn expire_base_keys_for_signed_pre_key(
+        &mut self,
+        ec_prekey_id: SignedPreKeyId,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .expire_base_keys_for_signed_pre_key(ec_prekey_id)
+            .await
+    }
 }
 
 #[async_trait(?Send)]
@@ -525,6 +679,10 @@ impl traits::SessionStore for InMemSignalProtocolStore {
     ) -> Result<()> {
         self.session_store.store_session(address, record).await
     }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.session_store.session_policy()
+    }
 }
 
 #[async_trait(?Send)]
@@ -549,6 +707,93 @@ impl traits::SenderKeyStore for InMemSignalProtocolStore {
             .load_sender_key(sender, distribution_id)
             .await
     }
+
+    fn sender_key_policy(&self) -> SessionPolicy {
+        self.sender_key_store.sender_key_policy()
+    }
 }
 
 impl traits::ProtocolStore for InMemSignalProtocolStore {}
+
+impl InMemSignalProtocolStore {
+    /// Apply the updates in `transaction` one at a time, stopping at the first failure.
+    async fn apply_transaction(
+        &mut self,
+        transaction: &traits::ProtocolStoreTransaction,
+    ) -> Result<()> {
+        use traits::{
+            IdentityKeyStore as _, KyberPreKeyStore as _, PreKeyStore as _, SessionStore as _,
+        };
+
+        let address = transaction.address();
+        if let Some(identity) = transaction.identity() {
+            self.save_identity(address, identity).await?;
+        }
+        if let Some((kyber_prekey_id, ec_prekey_id, base_key)) = transaction.used_kyber_pre_key() {
+            self.mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+                .await?;
+        }
+        if let Some(prekey_id) = transaction.removed_pre_key() {
+            self.remove_pre_key(prekey_id).await?;
+        }
+        if let Some(record) = transaction.session() {
+            self.store_session(address, record).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::TransactionalProtocolStore for InMemSignalProtocolStore {
+    async fn commit_transaction(
+        &mut self,
+        transaction: traits::ProtocolStoreTransaction,
+    ) -> Result<()> {
+        // Remember every entry the transaction could touch, so they can be put back on failure.
+        let address = transaction.address();
+        let previous_identity = self.identity_store.known_keys.get(address).cloned();
+        let previous_session = self.session_store.sessions.get(address).cloned();
+        let previous_pre_key = transaction
+            .removed_pre_key()
+            .map(|id| (id, self.pre_key_store.pre_keys.get(&id).cloned()));
+        let previous_base_keys =
+            transaction
+                .used_kyber_pre_key()
+                .map(|(kyber_prekey_id, ec_prekey_id, _)| {
+                    let key = (kyber_prekey_id, ec_prekey_id);
+                    (
+                        key,
+                        self.kyber_pre_key_store.base_keys_seen.get(&key).cloned(),
+                    )
+                });
+
+        let result = self.apply_transaction(&transaction).await;
+        if result.is_err() {
+            restore(
+                &mut self.identity_store.known_keys,
+                address.clone(),
+                previous_identity,
+            );
+            restore(
+                &mut self.session_store.sessions,
+                address.clone(),
+                previous_session,
+            );
+            if let Some((id, record)) = previous_pre_key {
+                restore(&mut self.pre_key_store.pre_keys, id, record);
+            }
+            if let Some((key, base_keys)) = previous_base_keys {
+                restore(&mut self.kyber_pre_key_store.base_keys_seen, key, base_keys);
+            }
+        }
+        result
+    }
+}
+
+/// Put `map[key]` back to `previous`, removing it if it wasn't present before.
+fn restore<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>, key: K, previous: Option<V>) {
+    match previous {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
@@ -0,0 +1,1203 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Implementations for stores defined in [super::traits], persisted in a SQLite database.
+//!
+//! All the stores opened together share a single connection. Every write is either a single
+//! statement or runs in a transaction, and the database is opened in write-ahead logging mode with
+//! full synchronization, so a crash never leaves a record half-written.
+
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension as _, params};
+use uuid::Uuid;
+
+use crate::storage::traits::{self, IdentityChange, IdentityHistoryEntry, IdentitySource};
+use crate::{
+    DeviceId, GenericSignedPreKey as _, IdentityKey, IdentityKeyPair, KyberPreKeyId,
+    KyberPreKeyRecord, PreKeyId, PreKeyRecord, ProtocolAddress, PublicKey, Result, SenderKeyRecord,
+    SessionPolicy, SessionRecord, SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord,
+};
+
+/// The schema, as a series of migrations applied in order.
+///
+/// A database's `user_version` is the number of migrations that have been applied to it. Released
+/// migrations must never be changed; add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: Initial schema.
+    "
+    CREATE TABLE local_identity (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        key_pair BLOB NOT NULL,
+        registration_id INTEGER NOT NULL
+    );
+    CREATE TABLE identities (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        identity_key BLOB NOT NULL,
+        PRIMARY KEY (name, device_id)
+    ) WITHOUT ROWID;
+    CREATE TABLE sessions (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        record BLOB NOT NULL,
+        PRIMARY KEY (name, device_id)
+    ) WITHOUT ROWID;
+    CREATE TABLE pre_keys (
+        id INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE signed_pre_keys (
+        id INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE kyber_pre_keys (
+        id INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE kyber_base_keys_seen (
+        kyber_pre_key_id INTEGER NOT NULL,
+        signed_pre_key_id INTEGER NOT NULL,
+        base_key BLOB NOT NULL,
+        PRIMARY KEY (kyber_pre_key_id, signed_pre_key_id, base_key)
+    ) WITHOUT ROWID;
+    CREATE TABLE sender_keys (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        distribution_id BLOB NOT NULL,
+        record BLOB NOT NULL,
+        PRIMARY KEY (name, device_id, distribution_id)
+    ) WITHOUT ROWID;
+    ",
+    // 2: Identity key history. Times are milliseconds since the Unix epoch, and sources is a
+    // bitmask as in IdentityHistoryEntry::sources_bitmask.
+    "
+    CREATE TABLE identity_history (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        identity_key BLOB NOT NULL,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        sources INTEGER NOT NULL,
+        verified INTEGER NOT NULL,
+        PRIMARY KEY (name, device_id, identity_key)
+    ) WITHOUT ROWID;
+    ",
+];
+
+// rusqlite's errors aren't UnwindSafe, so they're flattened to a message to be carried by
+// SignalProtocolError::ApplicationCallbackError.
+#[derive(Debug, displaydoc::Display, thiserror::Error)]
+/// SQLite error: {0}
+struct SqliteError(String);
+
+fn sqlite_error(method: &'static str) -> impl FnOnce(rusqlite::Error) -> SignalProtocolError {
+    move |error| {
+        SignalProtocolError::ApplicationCallbackError(
+            method,
+            Box::new(SqliteError(error.to_string())),
+        )
+    }
+}
+
+fn device_id_column(address: &ProtocolAddress) -> u32 {
+    address.device_id().into()
+}
+
+/// Times before the Unix epoch are stored as the epoch itself.
+fn time_column(time: SystemTime) -> i64 {
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    i64::try_from(millis).unwrap_or(i64::MAX)
+}
+
+fn time_from_column(millis: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(u64::try_from(millis).unwrap_or_default())
+}
+
+/// Opens the schema of `connection`, creating or upgrading it as needed.
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let on_error = || sqlite_error("migrate");
+    connection
+        .execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;")
+        .map_err(on_error())?;
+
+    let version: u32 = connection
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(on_error())?;
+    let version = usize::try_from(version).expect("u32 fits in usize");
+    if version > MIGRATIONS.len() {
+        return Err(SignalProtocolError::InvalidState(
+            "migrate",
+            format!(
+                "database schema version {version} is newer than the latest supported version {}",
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+
+    for (applied, migration) in (version + 1..).zip(&MIGRATIONS[version..]) {
+        let transaction = connection.transaction().map_err(on_error())?;
+        transaction.execute_batch(migration).map_err(on_error())?;
+        transaction
+            .pragma_update(
+                None,
+                "user_version",
+                u32::try_from(applied).expect("not that many migrations"),
+            )
+            .map_err(on_error())?;
+        transaction.commit().map_err(on_error())?;
+    }
+    Ok(())
+}
+
+/// SQLite implementation of [traits::IdentityKeyStore] and [traits::IdentityHistoryStore].
+#[derive(Clone)]
+pub struct SqliteIdentityKeyStore {
+    connection: Rc<Connection>,
+    key_pair: IdentityKeyPair,
+    registration_id: u32,
+}
+
+impl SqliteIdentityKeyStore {
+    /// Clear the mapping of known keys.
+    ///
+    /// The identity history is kept.
+    pub fn reset(&mut self) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM identities", [])
+            .map_err(sqlite_error("reset"))?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for SqliteIdentityKeyStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        Ok(self.key_pair)
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        Ok(self.registration_id)
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        let transaction = self
+            .connection
+            .unchecked_transaction()
+            .map_err(sqlite_error("save_identity"))?;
+        let change = save_identity_in(&transaction, address, identity)?;
+        transaction
+            .commit()
+            .map_err(sqlite_error("save_identity"))?;
+        Ok(change)
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        _direction: traits::Direction,
+    ) -> Result<bool> {
+        match self.get_identity(address).await? {
+            None => {
+                Ok(true) // first use
+            }
+            Some(k) => Ok(k == *identity),
+        }
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        let on_error = || sqlite_error("get_identity");
+        self.connection
+            .query_row(
+                "SELECT identity_key FROM identities WHERE name = ?1 AND device_id = ?2",
+                params![address.name(), device_id_column(address)],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(on_error())?
+            .map(|key| IdentityKey::decode(&key))
+            .transpose()
+    }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        source: IdentitySource,
+        now: SystemTime,
+    ) -> Result<()> {
+        let sources = IdentityHistoryEntry::new(*identity, source, now).sources_bitmask();
+        self.connection
+            .execute(
+                "INSERT INTO identity_history \
+                 (name, device_id, identity_key, first_seen, last_seen, sources, verified) \
+                 VALUES (?1, ?2, ?3, ?4, ?4, ?5, FALSE) \
+                 ON CONFLICT (name, device_id, identity_key) DO UPDATE SET \
+                 first_seen = min(first_seen, excluded.first_seen), \
+                 last_seen = max(last_seen, excluded.last_seen), \
+                 sources = sources | excluded.sources",
+                params![
+                    address.name(),
+                    device_id_column(address),
+                    identity.serialize(),
+                    time_column(now),
+                    sources
+                ],
+            )
+            .map_err(sqlite_error("observe_identity"))?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityHistoryStore for SqliteIdentityKeyStore {
+    async fn identity_history(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Vec<IdentityHistoryEntry>> {
+        let on_error = || sqlite_error("identity_history");
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT identity_key, first_seen, last_seen, sources, verified \
+                 FROM identity_history WHERE name = ?1 AND device_id = ?2 \
+                 ORDER BY first_seen, last_seen",
+            )
+            .map_err(on_error())?;
+        let rows = statement
+            .query_map(params![address.name(), device_id_column(address)], |row| {
+                Ok((
+                    row.get::<_, Vec<u8>>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, u8>(3)?,
+                    row.get::<_, bool>(4)?,
+                ))
+            })
+            .map_err(on_error())?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(on_error())?;
+        rows.into_iter()
+            .map(|(key, first_seen, last_seen, sources, verified)| {
+                Ok(IdentityHistoryEntry::from_parts(
+                    IdentityKey::decode(&key)?,
+                    time_from_column(first_seen),
+                    time_from_column(last_seen),
+                    sources,
+                    verified,
+                ))
+            })
+            .collect()
+    }
+
+    async fn set_identity_verified(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        verified: bool,
+    ) -> Result<bool> {
+        let updated = self
+            .connection
+            .execute(
+                "UPDATE identity_history SET verified = ?4 \
+                 WHERE name = ?1 AND device_id = ?2 AND identity_key = ?3",
+                params![
+                    address.name(),
+                    device_id_column(address),
+                    identity.serialize(),
+                    verified
+                ],
+            )
+            .map_err(sqlite_error("set_identity_verified"))?;
+        Ok(updated > 0)
+    }
+}
+
+/// Saves `identity` for `address`, without starting a transaction of its own.
+fn save_identity_in(
+    connection: &Connection,
+    address: &ProtocolAddress,
+    identity: &IdentityKey,
+) -> Result<IdentityChange> {
+    let on_error = || sqlite_error("save_identity");
+    let existing: Option<Vec<u8>> = connection
+        .query_row(
+            "SELECT identity_key FROM identities WHERE name = ?1 AND device_id = ?2",
+            params![address.name(), device_id_column(address)],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(on_error())?;
+
+    let serialized = identity.serialize();
+    let change = match existing {
+        Some(existing) if *existing == *serialized => {
+            return Ok(IdentityChange::NewOrUnchanged);
+        }
+        Some(_) => IdentityChange::ReplacedExisting,
+        None => IdentityChange::NewOrUnchanged,
+    };
+    connection
+        .execute(
+            "INSERT OR REPLACE INTO identities (name, device_id, identity_key) VALUES (?1, ?2, ?3)",
+            params![address.name(), device_id_column(address), serialized],
+        )
+        .map_err(on_error())?;
+    Ok(change)
+}
+
+/// SQLite implementation of [traits::PreKeyStore].
+#[derive(Clone)]
+pub struct SqlitePreKeyStore {
+    connection: Rc<Connection>,
+}
+
+impl SqlitePreKeyStore {
+    /// Returns all registered pre-key ids
+    pub fn all_pre_key_ids(&self) -> Result<Vec<PreKeyId>> {
+        all_ids(&self.connection, "SELECT id FROM pre_keys ORDER BY id")
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for SqlitePreKeyStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        let record = load_record(
+            &self.connection,
+            "get_pre_key",
+            "SELECT record FROM pre_keys WHERE id = ?1",
+            id.into(),
+        )?
+        .ok_or(SignalProtocolError::InvalidPreKeyId)?;
+        PreKeyRecord::deserialize(&record)
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        // This overwrites old values, matching the in-memory store.
+        save_record(
+            &self.connection,
+            "save_pre_key",
+            "INSERT OR REPLACE INTO pre_keys (id, record) VALUES (?1, ?2)",
+            id.into(),
+            &record.serialize()?,
+        )
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        // If id does not exist this silently does nothing
+        self.connection
+            .execute("DELETE FROM pre_keys WHERE id = ?1", [u32::from(id)])
+            .map_err(sqlite_error("remove_pre_key"))?;
+        Ok(())
+    }
+}
+
+/// SQLite implementation of [traits::SignedPreKeyStore].
+#[derive(Clone)]
+pub struct SqliteSignedPreKeyStore {
+    connection: Rc<Connection>,
+}
+
+impl SqliteSignedPreKeyStore {
+    /// Returns all registered signed pre-key ids
+    pub fn all_signed_pre_key_ids(&self) -> Result<Vec<SignedPreKeyId>> {
+        all_ids(
+            &self.connection,
+            "SELECT id FROM signed_pre_keys ORDER BY id",
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for SqliteSignedPreKeyStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        let record = load_record(
+            &self.connection,
+            "get_signed_pre_key",
+            "SELECT record FROM signed_pre_keys WHERE id = ?1",
+            id.into(),
+        )?
+        .ok_or(SignalProtocolError::InvalidSignedPreKeyId)?;
+        SignedPreKeyRecord::deserialize(&record)
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        // This overwrites old values, matching the in-memory store.
+        save_record(
+            &self.connection,
+            "save_signed_pre_key",
+            "INSERT OR REPLACE INTO signed_pre_keys (id, record) VALUES (?1, ?2)",
+            id.into(),
+            &record.serialize()?,
+        )
+    }
+}
+
+/// SQLite implementation of [traits::KyberPreKeyStore].
+///
+/// Like [`InMemKyberPreKeyStore`](crate::InMemKyberPreKeyStore), this does not clear any keys upon
+/// use, but does reject a base key being used twice with the same pair of pre-keys.
+#[derive(Clone)]
+pub struct SqliteKyberPreKeyStore {
+    connection: Rc<Connection>,
+}
+
+impl SqliteKyberPreKeyStore {
+    /// Returns all registered Kyber pre-key ids
+    pub fn all_kyber_pre_key_ids(&self) -> Result<Vec<KyberPreKeyId>> {
+        all_ids(
+            &self.connection,
+            "SELECT id FROM kyber_pre_keys ORDER BY id",
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for SqliteKyberPreKeyStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        let record = load_record(
+            &self.connection,
+            "get_kyber_pre_key",
+            "SELECT record FROM kyber_pre_keys WHERE id = ?1",
+            kyber_prekey_id.into(),
+        )?
+        .ok_or(SignalProtocolError::InvalidKyberPreKeyId)?;
+        KyberPreKeyRecord::deserialize(&record)
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        save_record(
+            &self.connection,
+            "save_kyber_pre_key",
+            "INSERT OR REPLACE INTO kyber_pre_keys (id, record) VALUES (?1, ?2)",
+            kyber_prekey_id.into(),
+            &record.serialize()?,
+        )
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<()> {
+        // Checking and recording the base key in a single statement keeps this atomic.
+        let inserted = self
+            .connection
+            .execute(
+                "INSERT OR IGNORE INTO kyber_base_keys_seen \
+                 (kyber_pre_key_id, signed_pre_key_id, base_key) VALUES (?1, ?2, ?3)",
+                params![
+                    u32::from(kyber_prekey_id),
+                    u32::from(ec_prekey_id),
+                    base_key.serialize()
+                ],
+            )
+            .map_err(sqlite_error("mark_kyber_pre_key_used"))?;
+        if inserted == 0 {
+            return Err(SignalProtocolError::ReusedBaseKey(
+                kyber_prekey_id,
+                ec_prekey_id,
+            ));
+        }
+        Ok(())
+    }
+
+    async fn is_base_key_used(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<bool> {
+        self.connection
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM kyber_base_keys_seen \
+                 WHERE kyber_pre_key_id = ?1 AND signed_pre_key_id = ?2 AND base_key = ?3)",
+                params![
+                    u32::from(kyber_prekey_id),
+                    u32::from(ec_prekey_id),
+                    base_key.serialize()
+                ],
+                |row| row.get(0),
+            )
+            .map_err(sqlite_error("is_base_key_used"))
+    }
+
+    async fn expire_base_keys_for_signed_pre_key(
+        &mut self,
+        ec_prekey_id: SignedPreKeyId,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                "DELETE FROM kyber_base_keys_seen WHERE signed_pre_key_id = ?1",
+                [u32::from(ec_prekey_id)],
+            )
+            .map_err(sqlite_error("expire_base_keys_for_signed_pre_key"))?;
+        Ok(())
+    }
+}
+
+/// SQLite implementation of [traits::SessionStore].
+#[derive(Clone)]
+pub struct SqliteSessionStore {
+    connection: Rc<Connection>,
+    policy: SessionPolicy,
+}
+
+impl SqliteSessionStore {
+    /// Sets the limits reported by [`SessionStore::session_policy`].
+    ///
+    /// The policy is not saved in the database.
+    ///
+    /// [`SessionStore::session_policy`]: crate::SessionStore::session_policy
+    pub fn set_session_policy(&mut self, policy: SessionPolicy) {
+        self.policy = policy;
+    }
+
+    /// Returns the ids of all devices of `name` that there is a session with.
+    pub fn device_ids_with_sessions(&self, name: &str) -> Result<Vec<DeviceId>> {
+        let on_error = || sqlite_error("device_ids_with_sessions");
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT device_id FROM sessions WHERE name = ?1 ORDER BY device_id")
+            .map_err(on_error())?;
+        let device_ids = statement
+            .query_map([name], |row| row.get::<_, u32>(0))
+            .map_err(on_error())?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(on_error())?;
+        device_ids
+            .into_iter()
+            .map(|device_id| {
+                DeviceId::try_from(device_id).map_err(|_| {
+                    SignalProtocolError::InvalidProtocolAddress {
+                        name: name.to_owned(),
+                        device_id,
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for SqliteSessionStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.connection
+            .query_row(
+                "SELECT record FROM sessions WHERE name = ?1 AND device_id = ?2",
+                params![address.name(), device_id_column(address)],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(sqlite_error("load_session"))?
+            .map(|record| SessionRecord::deserialize(&record))
+            .transpose()
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO sessions (name, device_id, record) VALUES (?1, ?2, ?3)",
+                params![
+                    address.name(),
+                    device_id_column(address),
+                    record.serialize()?
+                ],
+            )
+            .map_err(sqlite_error("store_session"))?;
+        Ok(())
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.policy
+    }
+}
+
+/// SQLite implementation of [traits::SenderKeyStore].
+#[derive(Clone)]
+pub struct SqliteSenderKeyStore {
+    connection: Rc<Connection>,
+    policy: SessionPolicy,
+}
+
+impl SqliteSenderKeyStore {
+    /// Sets the limits reported by [`SenderKeyStore::sender_key_policy`].
+    ///
+    /// The policy is not saved in the database.
+    ///
+    /// [`SenderKeyStore::sender_key_policy`]: crate::SenderKeyStore::sender_key_policy
+    pub fn set_sender_key_policy(&mut self, policy: SessionPolicy) {
+        self.policy = policy;
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for SqliteSenderKeyStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO sender_keys (name, device_id, distribution_id, record) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    sender.name(),
+                    device_id_column(sender),
+                    distribution_id.as_bytes(),
+                    record.serialize()?
+                ],
+            )
+            .map_err(sqlite_error("store_sender_key"))?;
+        Ok(())
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>> {
+        self.connection
+            .query_row(
+                "SELECT record FROM sender_keys \
+                 WHERE name = ?1 AND device_id = ?2 AND distribution_id = ?3",
+                params![
+                    sender.name(),
+                    device_id_column(sender),
+                    distribution_id.as_bytes()
+                ],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(sqlite_error("load_sender_key"))?
+            .map(|record| SenderKeyRecord::deserialize(&record))
+            .transpose()
+    }
+
+    fn sender_key_policy(&self) -> SessionPolicy {
+        self.policy
+    }
+}
+
+fn load_record(
+    connection: &Connection,
+    method: &'static str,
+    query: &str,
+    id: u32,
+) -> Result<Option<Vec<u8>>> {
+    connection
+        .query_row(query, [id], |row| row.get(0))
+        .optional()
+        .map_err(sqlite_error(method))
+}
+
+fn save_record(
+    connection: &Connection,
+    method: &'static str,
+    query: &str,
+    id: u32,
+    record: &[u8],
+) -> Result<()> {
+    connection
+        .execute(query, params![id, record])
+        .map_err(sqlite_error(method))?;
+    Ok(())
+}
+
+fn all_ids<Id: From<u32>>(connection: &Connection, query: &str) -> Result<Vec<Id>> {
+    let on_error = || sqlite_error("all_ids");
+    let mut statement = connection.prepare_cached(query).map_err(on_error())?;
+    statement
+        .query_map([], |row| row.get::<_, u32>(0).map(Id::from))
+        .map_err(on_error())?
+        .collect::<rusqlite::Result<_>>()
+        .map_err(on_error())
+}
+
+/// SQLite implementation of [traits::ProtocolStore], along with [traits::SenderKeyStore].
+///
+/// The individual stores share a single connection, and can be used separately, just like those
+/// of [`InMemSignalProtocolStore`](crate::InMemSignalProtocolStore).
+#[allow(missing_docs)]
+#[derive(Clone)]
+pub struct SqliteSignalProtocolStore {
+    pub session_store: SqliteSessionStore,
+    pub pre_key_store: SqlitePreKeyStore,
+    pub signed_pre_key_store: SqliteSignedPreKeyStore,
+    pub kyber_pre_key_store: SqliteKyberPreKeyStore,
+    pub identity_store: SqliteIdentityKeyStore,
+    pub sender_key_store: SqliteSenderKeyStore,
+}
+
+impl SqliteSignalProtocolStore {
+    /// Opens (or creates) the database at `path` as the store for the given identity `key_pair`
+    /// and `registration_id`.
+    ///
+    /// Fails if the database already belongs to a different identity or registration, or was
+    /// written by a newer version of this library.
+    pub fn open(
+        path: impl AsRef<Path>,
+        key_pair: IdentityKeyPair,
+        registration_id: u32,
+    ) -> Result<Self> {
+        let connection = Connection::open(path).map_err(sqlite_error("open"))?;
+        Self::with_identity(connection, key_pair, registration_id)
+    }
+
+    /// Like [`Self::open`], but the database is only kept in memory.
+    pub fn open_in_memory(key_pair: IdentityKeyPair, registration_id: u32) -> Result<Self> {
+        let connection = Connection::open_in_memory().map_err(sqlite_error("open"))?;
+        Self::with_identity(connection, key_pair, registration_id)
+    }
+
+    /// Reopens a database at `path` previously created with [`Self::open`], using the identity
+    /// it was created for.
+    pub fn open_existing(path: impl AsRef<Path>) -> Result<Self> {
+        let mut connection = Connection::open(path).map_err(sqlite_error("open_existing"))?;
+        migrate(&mut connection)?;
+        let (key_pair, registration_id) = load_local_identity(&connection)?.ok_or_else(|| {
+            SignalProtocolError::InvalidState(
+                "open_existing",
+                "database has no local identity".to_owned(),
+            )
+        })?;
+        Ok(Self::from_connection(
+            Rc::new(connection),
+            key_pair,
+            registration_id,
+        ))
+    }
+
+    fn with_identity(
+        mut connection: Connection,
+        key_pair: IdentityKeyPair,
+        registration_id: u32,
+    ) -> Result<Self> {
+        migrate(&mut connection)?;
+        match load_local_identity(&connection)? {
+            None => {
+                connection
+                    .execute(
+                        "INSERT INTO local_identity (id, key_pair, registration_id) \
+                         VALUES (0, ?1, ?2)",
+                        params![key_pair.serialize(), registration_id],
+                    )
+                    .map_err(sqlite_error("open"))?;
+            }
+            Some((existing_key_pair, existing_registration_id))
+                if existing_key_pair.identity_key() == key_pair.identity_key()
+                    && existing_registration_id == registration_id => {}
+            Some(_) => {
+                return Err(SignalProtocolError::InvalidState(
+                    "open",
+                    "database belongs to a different identity".to_owned(),
+                ));
+            }
+        }
+        Ok(Self::from_connection(
+            Rc::new(connection),
+            key_pair,
+            registration_id,
+        ))
+    }
+
+    fn from_connection(
+        connection: Rc<Connection>,
+        key_pair: IdentityKeyPair,
+        registration_id: u32,
+    ) -> Self {
+        Self {
+            session_store: SqliteSessionStore {
+                connection: connection.clone(),
+                policy: SessionPolicy::default(),
+            },
+            pre_key_store: SqlitePreKeyStore {
+                connection: connection.clone(),
+            },
+            signed_pre_key_store: SqliteSignedPreKeyStore {
+                connection: connection.clone(),
+            },
+            kyber_pre_key_store: SqliteKyberPreKeyStore {
+                connection: connection.clone(),
+            },
+            sender_key_store: SqliteSenderKeyStore {
+                connection: connection.clone(),
+                policy: SessionPolicy::default(),
+            },
+            identity_store: SqliteIdentityKeyStore {
+                connection,
+                key_pair,
+                registration_id,
+            },
+        }
+    }
+
+    /// Returns the schema version of the database, which is the number of migrations applied.
+    pub fn schema_version(&self) -> Result<u32> {
+        self.session_store
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .map_err(sqlite_error("schema_version"))
+    }
+
+    /// Returns all registered pre-key ids
+    pub fn all_pre_key_ids(&self) -> Result<Vec<PreKeyId>> {
+        self.pre_key_store.all_pre_key_ids()
+    }
+
+    /// Returns all registered signed pre-key ids
+    pub fn all_signed_pre_key_ids(&self) -> Result<Vec<SignedPreKeyId>> {
+        self.signed_pre_key_store.all_signed_pre_key_ids()
+    }
+
+    /// Returns all registered Kyber pre-key ids
+    pub fn all_kyber_pre_key_ids(&self) -> Result<Vec<KyberPreKeyId>> {
+        self.kyber_pre_key_store.all_kyber_pre_key_ids()
+    }
+}
+
+fn load_local_identity(connection: &Connection) -> Result<Option<(IdentityKeyPair, u32)>> {
+    connection
+        .query_row(
+            "SELECT key_pair, registration_id FROM local_identity WHERE id = 0",
+            [],
+            |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, u32>(1)?)),
+        )
+        .optional()
+        .map_err(sqlite_error("load_local_identity"))?
+        .map(|(key_pair, registration_id)| {
+            Ok((
+                IdentityKeyPair::try_from(key_pair.as_slice())?,
+                registration_id,
+            ))
+        })
+        .transpose()
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for SqliteSignalProtocolStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        self.identity_store.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        self.identity_store.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        self.identity_store.save_identity(address, identity).await
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: traits::Direction,
+    ) -> Result<bool> {
+        self.identity_store
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        self.identity_store.get_identity(address).await
+    }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        source: IdentitySource,
+        now: SystemTime,
+    ) -> Result<()> {
+        self.identity_store
+            .observe_identity(address, identity, source, now)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityHistoryStore for SqliteSignalProtocolStore {
+    async fn identity_history(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Vec<IdentityHistoryEntry>> {
+        self.identity_store.identity_history(address).await
+    }
+
+    async fn set_identity_verified(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        verified: bool,
+    ) -> Result<bool> {
+        self.identity_store
+            .set_identity_verified(address, identity, verified)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for SqliteSignalProtocolStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        self.pre_key_store.get_pre_key(id).await
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        self.pre_key_store.save_pre_key(id, record).await
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        self.pre_key_store.remove_pre_key(id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for SqliteSignalProtocolStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        self.signed_pre_key_store.get_signed_pre_key(id).await
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        self.signed_pre_key_store
+            .save_signed_pre_key(id, record)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for SqliteSignalProtocolStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        self.kyber_pre_key_store
+            .get_kyber_pre_key(kyber_prekey_id)
+            .await
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .save_kyber_pre_key(kyber_prekey_id, record)
+            .await
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+
+    async fn is_base_key_used(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<bool> {
+        self.kyber_pre_key_store
+            .is_base_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+
+    async fn expire_base_keys_for_signed_pre_key(
+        &mut self,
+        ec_prekey_id: SignedPreKeyId,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .expire_base_keys_for_signed_pre_key(ec_prekey_id)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for SqliteSignalProtocolStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.session_store.load_session(address).await
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.session_store.store_session(address, record).await
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.session_store.session_policy()
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for SqliteSignalProtocolStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<()> {
+        self.sender_key_store
+            .store_sender_key(sender, distribution_id, record)
+            .await
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>> {
+        self.sender_key_store
+            .load_sender_key(sender, distribution_id)
+            .await
+    }
+
+    fn sender_key_policy(&self) -> SessionPolicy {
+        self.sender_key_store.sender_key_policy()
+    }
+}
+
+impl traits::ProtocolStore for SqliteSignalProtocolStore {}
+
+#[async_trait(?Send)]
+impl traits::TransactionalProtocolStore for SqliteSignalProtocolStore {
+    async fn commit_transaction(
+        &mut self,
+        transaction: traits::ProtocolStoreTransaction,
+    ) -> Result<()> {
+        use traits::{KyberPreKeyStore as _, PreKeyStore as _, SessionStore as _};
+
+        let on_error = || sqlite_error("commit_transaction");
+        // All the stores share this connection, so their writes below happen inside this
+        // transaction, which rolls back if it is dropped without being committed.
+        let connection = Rc::clone(&self.session_store.connection);
+        let sql_transaction = connection.unchecked_transaction().map_err(on_error())?;
+
+        let address = transaction.address();
+        if let Some(identity) = transaction.identity() {
+            save_identity_in(&sql_transaction, address, identity)?;
+        }
+        if let Some((kyber_prekey_id, ec_prekey_id, base_key)) = transaction.used_kyber_pre_key() {
+            self.mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+                .await?;
+        }
+        if let Some(prekey_id) = transaction.removed_pre_key() {
+            self.remove_pre_key(prekey_id).await?;
+        }
+        if let Some(record) = transaction.session() {
+            self.store_session(address, record).await?;
+        }
+
+        sql_transaction.commit().map_err(on_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use rand::TryRngCore as _;
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn migrations_are_applied_once() {
+        let mut connection = Connection::open_in_memory().expect("can open");
+        migrate(&mut connection).expect("can migrate");
+        migrate(&mut connection).expect("can migrate again");
+        let version: u32 = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .expect("has version");
+        assert_eq!(usize::try_from(version).expect("fits"), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut connection = Connection::open_in_memory().expect("can open");
+        let newer = u32::try_from(MIGRATIONS.len() + 1).expect("fits");
+        connection
+            .pragma_update(None, "user_version", newer)
+            .expect("can set version");
+        assert!(matches!(
+            migrate(&mut connection),
+            Err(SignalProtocolError::InvalidState("migrate", _))
+        ));
+    }
+
+    #[test]
+    fn reused_base_key_is_rejected() {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store =
+            SqliteSignalProtocolStore::open_in_memory(IdentityKeyPair::generate(&mut csprng), 1)
+                .expect("can open");
+        let base_key = crate::KeyPair::generate(&mut csprng).public_key;
+
+        let mark = |store: &mut SqliteSignalProtocolStore| {
+            traits::KyberPreKeyStore::mark_kyber_pre_key_used(store, 1.into(), 2.into(), &base_key)
+                .now_or_never()
+                .expect("sync")
+        };
+        let is_used = |store: &SqliteSignalProtocolStore| {
+            traits::KyberPreKeyStore::is_base_key_used(store, 1.into(), 2.into(), &base_key)
+                .now_or_never()
+                .expect("sync")
+                .expect("can query")
+        };
+        assert!(!is_used(&store));
+        mark(&mut store).expect("first use is fine");
+        assert!(is_used(&store));
+        assert!(matches!(
+            mark(&mut store),
+            Err(SignalProtocolError::ReusedBaseKey(kyber_id, signed_id))
+                if kyber_id == 1.into() && signed_id == 2.into()
+        ));
+
+        traits::KyberPreKeyStore::expire_base_keys_for_signed_pre_key(&mut store, 2.into())
+            .now_or_never()
+            .expect("sync")
+            .expect("can expire");
+        assert!(!is_used(&store));
+        mark(&mut store).expect("can be used again with a new signed pre-key of the same ID");
+    }
+}
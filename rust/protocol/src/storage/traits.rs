@@ -5,6 +5,8 @@
 
 //! Traits defining several stores used throughout the Signal Protocol.
 
+use std::time::SystemTime;
+
 use async_trait::async_trait;
 use uuid::Uuid;
 
@@ -14,7 +16,7 @@ use crate::state::{
     KyberPreKeyId, KyberPreKeyRecord, PreKeyId, PreKeyRecord, SessionRecord, SignedPreKeyId,
     SignedPreKeyRecord,
 };
-use crate::{IdentityKey, IdentityKeyPair, ProtocolAddress, PublicKey};
+use crate::{IdentityKey, IdentityKeyPair, ProtocolAddress, PublicKey, SessionPolicy};
 
 // TODO: consider moving this enum into utils.rs?
 /// Each Signal message can be considered to have exactly two participants, a sender and receiver.
@@ -40,6 +42,121 @@ pub enum IdentityChange {
     ReplacedExisting,
 }
 
+/// Where an identity key for a protocol address was observed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, derive_more::TryFrom)]
+#[repr(u8)]
+#[try_from(repr)]
+pub enum IdentitySource {
+    /// A message sent to or received from the address.
+    Message = 0,
+    /// A pre-key bundle fetched for the address.
+    PreKeyBundle = 1,
+    /// A key transparency lookup or monitoring result.
+    KeyTransparency = 2,
+}
+
+impl IdentitySource {
+    /// Every source, in order.
+    pub const ALL: [Self; 3] = [Self::Message, Self::PreKeyBundle, Self::KeyTransparency];
+
+    fn bit(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+/// Everything an [IdentityHistoryStore] knows about one identity key seen for an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityHistoryEntry {
+    identity: IdentityKey,
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+    sources: u8,
+    verified: bool,
+}
+
+impl IdentityHistoryEntry {
+    /// Start an entry for `identity`, first observed from `source` at `now`.
+    pub fn new(identity: IdentityKey, source: IdentitySource, now: SystemTime) -> Self {
+        Self {
+            identity,
+            first_seen: now,
+            last_seen: now,
+            sources: source.bit(),
+            verified: false,
+        }
+    }
+
+    /// Rebuild an entry from its parts, as loaded from storage.
+    ///
+    /// `sources` is a bitmask as returned by [Self::sources_bitmask]; unknown bits are ignored.
+    pub fn from_parts(
+        identity: IdentityKey,
+        first_seen: SystemTime,
+        last_seen: SystemTime,
+        sources: u8,
+        verified: bool,
+    ) -> Self {
+        Self {
+            identity,
+            first_seen,
+            last_seen,
+            sources: sources & IdentitySource::ALL.iter().fold(0, |all, s| all | s.bit()),
+            verified,
+        }
+    }
+
+    /// Note that the key was seen again from `source` at `now`.
+    ///
+    /// `last_seen` never moves backwards, so observations may be recorded out of order.
+    pub fn observe(&mut self, source: IdentitySource, now: SystemTime) {
+        self.first_seen = self.first_seen.min(now);
+        self.last_seen = self.last_seen.max(now);
+        self.sources |= source.bit();
+    }
+
+    /// Mark whether the user has verified the key, such as by comparing safety numbers.
+    pub fn set_verified(&mut self, verified: bool) {
+        self.verified = verified;
+    }
+
+    /// The identity key this entry is about.
+    pub fn identity(&self) -> &IdentityKey {
+        &self.identity
+    }
+
+    /// When the key was first observed.
+    pub fn first_seen(&self) -> SystemTime {
+        self.first_seen
+    }
+
+    /// When the key was most recently observed.
+    pub fn last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    /// Every source the key has been observed from, in the order of [IdentitySource::ALL].
+    pub fn sources(&self) -> impl Iterator<Item = IdentitySource> + '_ {
+        IdentitySource::ALL
+            .into_iter()
+            .filter(|source| self.was_seen_from(*source))
+    }
+
+    /// Whether the key has been observed from `source`.
+    pub fn was_seen_from(&self, source: IdentitySource) -> bool {
+        self.sources & source.bit() != 0
+    }
+
+    /// The sources as a bitmask, with bit `n` set for the source whose value is `n`.
+    pub fn sources_bitmask(&self) -> u8 {
+        self.sources
+    }
+
+    /// Whether the user has verified the key.
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+}
+
 /// Interface defining the identity store, which may be in-memory, on-disk, etc.
 ///
 /// Signal clients usually use the identity store in a [TOFU] manner, but this is not required.
@@ -79,6 +196,72 @@ pub trait IdentityKeyStore {
 
     /// Return the public identity for the given `address`, if known.
     async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>>;
+
+    /// Note that `identity` was seen for `address`, for stores that keep an
+    /// [IdentityHistoryStore].
+    ///
+    /// The library calls this after every [save_identity](Self::save_identity), whether or not
+    /// the key changed. Key transparency results are not seen by the library, so clients should
+    /// record those themselves with [IdentitySource::KeyTransparency]. The default implementation
+    /// does nothing.
+    async fn observe_identity(
+        &mut self,
+        _address: &ProtocolAddress,
+        _identity: &IdentityKey,
+        _source: IdentitySource,
+        _now: SystemTime,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Interface for looking up every identity key ever observed for an address, not just the current
+/// one.
+///
+/// Entries are added through [IdentityKeyStore::observe_identity] and are kept even after the
+/// address's identity changes, so that a client can tell a returning key from a new one.
+#[async_trait(?Send)]
+pub trait IdentityHistoryStore {
+    /// Return every identity key observed for `address`, oldest first.
+    async fn identity_history(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Vec<IdentityHistoryEntry>>;
+
+    /// Mark whether the user has verified `identity` for `address`.
+    ///
+    /// Returns `false`, and changes nothing, if `identity` has never been observed for `address`.
+    async fn set_identity_verified(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        verified: bool,
+    ) -> Result<bool>;
+
+    /// Return the history entry for `identity`, if it has ever been observed for `address`.
+    async fn identity_history_entry(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<Option<IdentityHistoryEntry>> {
+        Ok(self
+            .identity_history(address)
+            .await?
+            .into_iter()
+            .find(|entry| entry.identity() == identity))
+    }
+
+    /// Return whether `identity` has ever been observed for `address`.
+    async fn was_identity_seen(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<bool> {
+        Ok(self
+            .identity_history_entry(address, identity)
+            .await?
+            .is_some())
+    }
 }
 
 /// Interface for storing pre-keys downloaded from a server.
@@ -130,13 +313,40 @@ pub trait KyberPreKeyStore {
     ///
     /// A one-time Kyber pre-key should be deleted after this point. A last-resort pre-key should
     /// not immediately be deleted, but should check whether the same combination of pre-keys was
-    /// used with the given base key before, and produce an error if so.
+    /// used with the given base key before, and produce
+    /// [`SignalProtocolError::ReusedBaseKey`](crate::SignalProtocolError::ReusedBaseKey) if so.
     async fn mark_kyber_pre_key_used(
         &mut self,
         kyber_prekey_id: KyberPreKeyId,
         ec_prekey_id: SignedPreKeyId,
         base_key: &PublicKey,
     ) -> Result<()>;
+
+    /// Whether `base_key` has already been marked as used with this pair of pre-keys.
+    ///
+    /// [`process_prekey`](crate::process_prekey) checks this to reject a replayed message before
+    /// doing any work. The default implementation always returns `false`, leaving the check to
+    /// [`mark_kyber_pre_key_used`](Self::mark_kyber_pre_key_used).
+    async fn is_base_key_used(
+        &self,
+        _kyber_prekey_id: KyberPreKeyId,
+        _ec_prekey_id: SignedPreKeyId,
+        _base_key: &PublicKey,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Forget the base keys recorded as used with the signed pre-key `ec_prekey_id`.
+    ///
+    /// Once a signed pre-key has been deleted, no message can use it again, so there is nothing
+    /// left to protect against. [`PreKeyManager`](crate::PreKeyManager) calls this when a retired
+    /// signed pre-key's grace period is over. The default implementation does nothing.
+    async fn expire_base_keys_for_signed_pre_key(
+        &mut self,
+        _ec_prekey_id: SignedPreKeyId,
+    ) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Interface for a Signal client instance to store a session associated with another particular
@@ -157,6 +367,13 @@ pub trait SessionStore {
         address: &ProtocolAddress,
         record: &SessionRecord,
     ) -> Result<()>;
+
+    /// The limits that sessions in this store are kept to.
+    ///
+    /// The default implementation returns [`SessionPolicy::default`].
+    fn session_policy(&self) -> SessionPolicy {
+        SessionPolicy::default()
+    }
 }
 
 /// Interface for storing sender key records, allowing multiple keys per user.
@@ -177,6 +394,13 @@ pub trait SenderKeyStore {
         sender: &ProtocolAddress,
         distribution_id: Uuid,
     ) -> Result<Option<SenderKeyRecord>>;
+
+    /// The limits that sender key records in this store are kept to.
+    ///
+    /// The default implementation returns [`SessionPolicy::default`].
+    fn sender_key_policy(&self) -> SessionPolicy {
+        SessionPolicy::default()
+    }
 }
 
 /// Mixes in all the store interfaces defined in this module.
@@ -185,6 +409,106 @@ pub trait ProtocolStore:
 {
 }
 
+/// The store updates that encrypting or decrypting a single message calls for.
+///
+/// Rather than writing to each store as they go, the transactional cipher functions (such as
+/// [`message_decrypt_transactional`](crate::message_decrypt_transactional)) stage their updates
+/// here and hand them to [TransactionalProtocolStore::commit_transaction] all at once, so that a
+/// crash or failure part-way through can't leave the session and identity out of sync.
+#[derive(Clone)]
+pub struct ProtocolStoreTransaction {
+    address: ProtocolAddress,
+    session: Option<SessionRecord>,
+    identity: Option<IdentityKey>,
+    removed_pre_key: Option<PreKeyId>,
+    used_kyber_pre_key: Option<(KyberPreKeyId, SignedPreKeyId, PublicKey)>,
+}
+
+impl ProtocolStoreTransaction {
+    /// Start an empty transaction for updates concerning `address`.
+    pub fn new(address: ProtocolAddress) -> Self {
+        Self {
+            address,
+            session: None,
+            identity: None,
+            removed_pre_key: None,
+            used_kyber_pre_key: None,
+        }
+    }
+
+    /// Stage storing `record` as the session for the transaction's address.
+    pub fn stage_session(&mut self, record: SessionRecord) {
+        self.session = Some(record);
+    }
+
+    /// Stage saving `identity` for the transaction's address.
+    pub fn stage_identity(&mut self, identity: IdentityKey) {
+        self.identity = Some(identity);
+    }
+
+    /// Stage removing the one-time pre-key `prekey_id`.
+    pub fn stage_pre_key_removal(&mut self, prekey_id: PreKeyId) {
+        self.removed_pre_key = Some(prekey_id);
+    }
+
+    /// Stage marking `kyber_prekey_id` as used with `ec_prekey_id` and `base_key`.
+    ///
+    /// See [KyberPreKeyStore::mark_kyber_pre_key_used].
+    pub fn stage_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: PublicKey,
+    ) {
+        self.used_kyber_pre_key = Some((kyber_prekey_id, ec_prekey_id, base_key));
+    }
+
+    /// The address the session and identity updates are for.
+    pub fn address(&self) -> &ProtocolAddress {
+        &self.address
+    }
+
+    /// The session to store, if any.
+    pub fn session(&self) -> Option<&SessionRecord> {
+        self.session.as_ref()
+    }
+
+    /// Take the session to store out of the transaction, if any.
+    pub(crate) fn take_session(&mut self) -> Option<SessionRecord> {
+        self.session.take()
+    }
+
+    /// The identity to save, if any.
+    pub fn identity(&self) -> Option<&IdentityKey> {
+        self.identity.as_ref()
+    }
+
+    /// The one-time pre-key to remove, if any.
+    pub fn removed_pre_key(&self) -> Option<PreKeyId> {
+        self.removed_pre_key
+    }
+
+    /// The Kyber pre-key to mark as used, along with the signed pre-key and base key it was used
+    /// with, if any.
+    pub fn used_kyber_pre_key(&self) -> Option<(KyberPreKeyId, SignedPreKeyId, &PublicKey)> {
+        self.used_kyber_pre_key
+            .as_ref()
+            .map(|(kyber_prekey_id, ec_prekey_id, base_key)| {
+                (*kyber_prekey_id, *ec_prekey_id, base_key)
+            })
+    }
+}
+
+/// A [ProtocolStore] that can apply a [ProtocolStoreTransaction] atomically.
+#[async_trait(?Send)]
+pub trait TransactionalProtocolStore: ProtocolStore {
+    /// Apply every update staged in `transaction`, or none of them.
+    ///
+    /// If any update fails (for example, because the Kyber pre-key was already used with the same
+    /// base key), the store must be left as it was before the call and the error returned.
+    async fn commit_transaction(&mut self, transaction: ProtocolStoreTransaction) -> Result<()>;
+}
+
 impl IdentityChange {
     /// Convenience constructor from a boolean `changed` flag.
     ///
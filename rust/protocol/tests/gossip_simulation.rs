@@ -0,0 +1,85 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use futures_util::FutureExt;
+use support::gossip_simulation::*;
+
+fn run(config: &SimulationConfig) -> SimulationReport {
+    simulate(config)
+        .now_or_never()
+        .expect("sync")
+        .expect("simulation runs")
+}
+
+#[test]
+fn honest_log_raises_no_alarms() {
+    let config = SimulationConfig {
+        max_rounds: 12,
+        ..Default::default()
+    };
+    let report = run(&config);
+    assert_eq!(report.rounds, 12);
+    assert_eq!(report.detections, 0);
+    assert_eq!(report.first_detection, None);
+    assert_eq!(report.messages, report.messages_with_gossip);
+
+    // Clients fetch from the log at different times, so they gossip from different
+    // distinguished heads without that being mistaken for equivocation.
+    let mut sizes = report.distinguished_tree_sizes.clone();
+    sizes.sort_unstable();
+    sizes.dedup();
+    assert_eq!(sizes.len(), config.fetch_interval);
+}
+
+#[test]
+fn equivocation_is_detected_by_gossip() {
+    for seed in 0..4 {
+        let config = SimulationConfig {
+            seed,
+            fork: Some(Fork {
+                round: 2,
+                victims: 5,
+            }),
+            ..Default::default()
+        };
+        let report = run(&config);
+        let rounds = report
+            .rounds_to_detection()
+            .unwrap_or_else(|| panic!("fork went undetected with seed {seed}: {report:?}"));
+        assert!(rounds <= 4, "took {rounds} rounds with seed {seed}");
+    }
+}
+
+#[test]
+fn equivocation_goes_unnoticed_without_gossip() {
+    let config = SimulationConfig {
+        gossip_probability: 0.0,
+        fork: Some(Fork {
+            round: 2,
+            victims: 5,
+        }),
+        ..Default::default()
+    };
+    let report = run(&config);
+    assert_eq!(report.rounds, config.max_rounds);
+    assert_eq!(report.messages_with_gossip, 0);
+    assert_eq!(report.rounds_to_detection(), None);
+}
+
+#[test]
+fn simulation_is_deterministic() {
+    let config = SimulationConfig {
+        seed: 42,
+        gossip_probability: 0.3,
+        fork: Some(Fork {
+            round: 1,
+            victims: 8,
+        }),
+        ..Default::default()
+    };
+    assert_eq!(run(&config), run(&config));
+}
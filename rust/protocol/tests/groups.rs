@@ -7,7 +7,9 @@ mod support;
 
 use std::time::SystemTime;
 
+use assert_matches::assert_matches;
 use futures_util::FutureExt;
+use libsignal_gossip::GossipOutcome;
 use libsignal_protocol::*;
 use rand::rngs::OsRng;
 use rand::seq::SliceRandom;
@@ -144,6 +146,109 @@ fn group_basic_encrypt_decrypt() -> Result<(), SignalProtocolError> {
     .expect("sync")
 }
 
+#[test]
+fn group_encrypt_decrypt_with_gossip() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+        let alice_gossip = test_gossip_service(Some([1; 32]));
+        let mut bob_gossip = test_gossip_service(Some([1; 32]));
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?,
+            &mut bob_store,
+        )
+        .await?;
+
+        let alice_ciphertext = group_encrypt_with_gossip(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "space camp?".as_bytes(),
+            &alice_gossip,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        assert!(!alice_ciphertext.gossip().is_empty());
+
+        let (bob_plaintext, status) = group_decrypt_with_gossip(
+            alice_ciphertext.serialized(),
+            &mut bob_store,
+            &sender_address,
+            &mut bob_gossip,
+            SystemTime::now(),
+        )
+        .await?;
+        assert_eq!(
+            String::from_utf8(bob_plaintext).expect("valid utf8"),
+            "space camp?"
+        );
+        assert_eq!(status, GossipStatus::Verified(GossipOutcome::UpToDate));
+
+        // Messages without gossip are still readable.
+        let alice_ciphertext = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "no gossip".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        let (_, status) = group_decrypt_with_gossip(
+            alice_ciphertext.serialized(),
+            &mut bob_store,
+            &sender_address,
+            &mut bob_gossip,
+            SystemTime::now(),
+        )
+        .await?;
+        assert_eq!(status, GossipStatus::Missing);
+
+        // A sender on a different fork is caught.
+        let forked_gossip = test_gossip_service(Some([2; 32]));
+        let alice_ciphertext = group_encrypt_with_gossip(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "forked".as_bytes(),
+            &forked_gossip,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        let (_, status) = group_decrypt_with_gossip(
+            alice_ciphertext.serialized(),
+            &mut bob_store,
+            &sender_address,
+            &mut bob_gossip,
+            SystemTime::now(),
+        )
+        .await?;
+        assert_matches!(status, GossipStatus::Inconsistent(proof) => {
+            assert_eq!(proof.peer_address(), sender_address.to_string());
+        });
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn group_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
@@ -1291,3 +1396,183 @@ fn group_message_key_limit() -> Result<(), SignalProtocolError> {
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn group_session_policy_limits() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        bob_store.sender_key_store.set_sender_key_policy(
+            SessionPolicy::builder()
+                .max_forward_jumps(10)
+                .max_sender_key_states(1)
+                .build()?,
+        );
+
+        let first_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &first_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        for _ in 0..11 {
+            group_encrypt(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                "skipped".as_bytes(),
+                &mut csprng,
+            )
+            .await?;
+        }
+        let too_far = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "too far for the policy".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        assert_matches!(
+            group_decrypt(too_far.serialized(), &mut bob_store, &sender_address).await,
+            Err(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::SenderKey,
+                "message from too far into the future"
+            ))
+        );
+
+        // The default policy allows a much larger jump.
+        let policy = bob_store.sender_key_store.sender_key_policy();
+        bob_store
+            .sender_key_store
+            .set_sender_key_policy(SessionPolicy::default());
+        assert_eq!(
+            group_decrypt(too_far.serialized(), &mut bob_store, &sender_address).await?,
+            b"too far for the policy"
+        );
+        bob_store.sender_key_store.set_sender_key_policy(policy);
+
+        // A new chain from Alice replaces the old one, since Bob only keeps one.
+        let stale = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "from the first chain".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let second_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &second_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+        assert_matches!(
+            group_decrypt(stale.serialized(), &mut bob_store, &sender_address).await,
+            Err(SignalProtocolError::NoSenderKeyState { .. })
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_describe_record_and_message() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let mut ciphertexts = vec![];
+        for _ in 0..4 {
+            ciphertexts.push(
+                group_encrypt(
+                    &mut alice_store,
+                    &sender_address,
+                    distribution_id,
+                    "describe me".as_bytes(),
+                    &mut csprng,
+                )
+                .await?,
+            );
+        }
+        let message_description = ciphertexts[3].describe();
+        assert_eq!(message_description.distribution_id, distribution_id);
+        assert_eq!(message_description.iteration, 3);
+        group_decrypt(ciphertexts[3].serialized(), &mut bob_store, &sender_address).await?;
+
+        let alice_record = alice_store
+            .load_sender_key(&sender_address, distribution_id)
+            .await?
+            .expect("has record");
+        let bob_record = bob_store
+            .load_sender_key(&sender_address, distribution_id)
+            .await?
+            .expect("has record");
+        let [alice_state] = &alice_record.describe().states[..] else {
+            panic!("expected one state");
+        };
+        let [bob_state] = &bob_record.describe().states[..] else {
+            panic!("expected one state");
+        };
+
+        assert_eq!(alice_state.chain_id, message_description.chain_id);
+        assert!(alice_state.has_signing_private_key);
+        assert_eq!(alice_state.iteration, Some(4));
+        assert_eq!(alice_state.skipped_message_keys, 0);
+
+        assert_eq!(bob_state.chain_id, message_description.chain_id);
+        assert!(!bob_state.has_signing_private_key);
+        assert_eq!(bob_state.signing_key, alice_state.signing_key);
+        assert_eq!(bob_state.iteration, Some(4));
+        assert_eq!(bob_state.skipped_message_keys, 3);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -0,0 +1,428 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use std::time::{Duration, SystemTime};
+
+use assert_matches::assert_matches;
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::TryRngCore as _;
+use rand::rngs::OsRng;
+use support::*;
+
+type TestResult = Result<(), SignalProtocolError>;
+
+const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+fn new_manager(store: &InMemSignalProtocolStore) -> Result<PreKeyManager, SignalProtocolError> {
+    let identity_key_pair = store
+        .get_identity_key_pair()
+        .now_or_never()
+        .expect("sync")?;
+    PreKeyManager::new(
+        identity_key_pair,
+        PreKeyManagerConfig::default(),
+        PreKeyManagerState::new(&mut OsRng.unwrap_err()),
+    )
+}
+
+fn maintain(
+    manager: &mut PreKeyManager,
+    store: &mut InMemSignalProtocolStore,
+    server_counts: PreKeyCounts,
+    now: SystemTime,
+) -> Result<PreKeyMaintenance, SignalProtocolError> {
+    manager
+        .maintain(
+            server_counts,
+            &mut store.pre_key_store,
+            &mut store.signed_pre_key_store,
+            &mut store.kyber_pre_key_store,
+            now,
+            &mut OsRng.unwrap_err(),
+        )
+        .now_or_never()
+        .expect("sync")
+}
+
+/// What the server would report after receiving everything in `upload` on top of `counts`.
+fn counts_after(counts: PreKeyCounts, upload: &PreKeyUploadSet) -> PreKeyCounts {
+    let len = |keys: usize| u32::try_from(keys).expect("fits");
+    PreKeyCounts {
+        one_time_pre_keys: counts.one_time_pre_keys + len(upload.pre_keys.len()),
+        one_time_kyber_pre_keys: counts.one_time_kyber_pre_keys + len(upload.pq_pre_keys.len()),
+    }
+}
+
+#[test]
+fn first_run_generates_everything() -> TestResult {
+    let mut store = test_in_memory_protocol_store()?;
+    let mut manager = new_manager(&store)?;
+    let config = manager.config().clone();
+
+    let maintenance = maintain(
+        &mut manager,
+        &mut store,
+        PreKeyCounts::default(),
+        SystemTime::now(),
+    )?;
+    let upload = &maintenance.upload;
+
+    let signed_pre_key = upload.signed_pre_key.as_ref().expect("generated");
+    assert_eq!(
+        Some(SignedPreKeyId::from(signed_pre_key.key_id)),
+        manager.state().signed_pre_key_id()
+    );
+    let last_resort = upload.pq_last_resort_pre_key.as_ref().expect("generated");
+    assert_eq!(
+        Some(KyberPreKeyId::from(last_resort.key_id)),
+        manager.state().last_resort_kyber_pre_key_id()
+    );
+    assert_eq!(
+        upload.pre_keys.len(),
+        config.one_time_pre_key_target as usize
+    );
+    assert_eq!(
+        upload.pq_pre_keys.len(),
+        config.one_time_pre_key_target as usize
+    );
+    assert!(maintenance.expired_signed_pre_key_ids.is_empty());
+    assert!(maintenance.expired_kyber_pre_key_ids.is_empty());
+
+    // Everything uploaded has been saved, and is validly signed.
+    let identity_key = *store
+        .get_identity_key_pair()
+        .now_or_never()
+        .expect("sync")?
+        .identity_key();
+    let record = store
+        .get_signed_pre_key(signed_pre_key.key_id.into())
+        .now_or_never()
+        .expect("sync")?;
+    assert_eq!(
+        *record.public_key()?.serialize(),
+        *signed_pre_key.public_key
+    );
+    assert!(
+        identity_key
+            .public_key()
+            .verify_signature(&signed_pre_key.public_key, &signed_pre_key.signature)
+    );
+    for kyber_pre_key in upload.pq_pre_keys.iter().chain([last_resort]) {
+        let record = store
+            .get_kyber_pre_key(kyber_pre_key.key_id.into())
+            .now_or_never()
+            .expect("sync")?;
+        assert_eq!(*record.public_key()?.serialize(), *kyber_pre_key.public_key);
+        assert!(
+            identity_key
+                .public_key()
+                .verify_signature(&kyber_pre_key.public_key, &kyber_pre_key.signature)
+        );
+    }
+    for pre_key in &upload.pre_keys {
+        let record = store
+            .get_pre_key(pre_key.key_id.into())
+            .now_or_never()
+            .expect("sync")?;
+        assert_eq!(*record.public_key()?.serialize(), *pre_key.public_key);
+    }
+
+    // One-time Kyber pre-keys and the last-resort key share an ID space.
+    let mut kyber_ids = upload
+        .pq_pre_keys
+        .iter()
+        .chain([last_resort])
+        .map(|key| key.key_id)
+        .collect::<Vec<_>>();
+    kyber_ids.sort_unstable();
+    kyber_ids.dedup();
+    assert_eq!(kyber_ids.len(), upload.pq_pre_keys.len() + 1);
+
+    Ok(())
+}
+
+#[test]
+fn nothing_to_do_when_up_to_date() -> TestResult {
+    let mut store = test_in_memory_protocol_store()?;
+    let mut manager = new_manager(&store)?;
+    let now = SystemTime::now();
+
+    let first = maintain(&mut manager, &mut store, PreKeyCounts::default(), now)?;
+    let counts = counts_after(PreKeyCounts::default(), &first.upload);
+    let second = maintain(&mut manager, &mut store, counts, now + DAY)?;
+    assert!(second.upload.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn replenishes_below_minimum() -> TestResult {
+    let mut store = test_in_memory_protocol_store()?;
+    let mut manager = new_manager(&store)?;
+    let config = manager.config().clone();
+    let now = SystemTime::now();
+
+    let first = maintain(&mut manager, &mut store, PreKeyCounts::default(), now)?;
+
+    // At the minimum, nothing is needed yet.
+    let counts = PreKeyCounts {
+        one_time_pre_keys: config.one_time_pre_key_minimum,
+        one_time_kyber_pre_keys: config.one_time_pre_key_minimum,
+    };
+    assert!(
+        maintain(&mut manager, &mut store, counts, now)?
+            .upload
+            .is_empty()
+    );
+
+    // Below it, top back up to the target.
+    let counts = PreKeyCounts {
+        one_time_pre_keys: 3,
+        one_time_kyber_pre_keys: config.one_time_pre_key_minimum,
+    };
+    let upload = maintain(&mut manager, &mut store, counts, now)?.upload;
+    assert_eq!(
+        upload.pre_keys.len(),
+        (config.one_time_pre_key_target - 3) as usize
+    );
+    assert!(upload.pq_pre_keys.is_empty());
+    assert!(upload.signed_pre_key.is_none());
+    assert!(upload.pq_last_resort_pre_key.is_none());
+
+    // New keys don't reuse IDs from the first batch.
+    let first_ids = first
+        .upload
+        .pre_keys
+        .iter()
+        .map(|key| key.key_id)
+        .collect::<Vec<_>>();
+    assert!(
+        upload
+            .pre_keys
+            .iter()
+            .all(|key| !first_ids.contains(&key.key_id))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rotates_signed_keys_and_expires_them_after_grace_period() -> TestResult {
+    let mut store = test_in_memory_protocol_store()?;
+    let mut manager = new_manager(&store)?;
+    let config = manager.config().clone();
+    let start = SystemTime::now();
+
+    let first = maintain(&mut manager, &mut store, PreKeyCounts::default(), start)?;
+    let counts = counts_after(PreKeyCounts::default(), &first.upload);
+    let old_signed_id = manager.state().signed_pre_key_id().expect("generated");
+    let old_kyber_id = manager
+        .state()
+        .last_resort_kyber_pre_key_id()
+        .expect("generated");
+
+    // Someone sets up a session with the old keys.
+    let base_key = KeyPair::generate(&mut OsRng.unwrap_err()).public_key;
+    store
+        .mark_kyber_pre_key_used(old_kyber_id, old_signed_id, &base_key)
+        .now_or_never()
+        .expect("sync")?;
+    let is_base_key_used = |store: &InMemSignalProtocolStore| {
+        store
+            .is_base_key_used(old_kyber_id, old_signed_id, &base_key)
+            .now_or_never()
+            .expect("sync")
+    };
+
+    let rotated_at = start + config.rotation_interval;
+    let second = maintain(&mut manager, &mut store, counts, rotated_at)?;
+    let new_signed = second.upload.signed_pre_key.expect("rotated");
+    let new_kyber = second.upload.pq_last_resort_pre_key.expect("rotated");
+    assert_ne!(SignedPreKeyId::from(new_signed.key_id), old_signed_id);
+    assert_ne!(KyberPreKeyId::from(new_kyber.key_id), old_kyber_id);
+    assert!(second.upload.pre_keys.is_empty());
+
+    // Someone else sets up a session with the new ones.
+    let new_ids = (
+        KyberPreKeyId::from(new_kyber.key_id),
+        SignedPreKeyId::from(new_signed.key_id),
+    );
+    let new_base_key = KeyPair::generate(&mut OsRng.unwrap_err()).public_key;
+    store
+        .mark_kyber_pre_key_used(new_ids.0, new_ids.1, &new_base_key)
+        .now_or_never()
+        .expect("sync")?;
+    assert!(second.upload.pq_pre_keys.is_empty());
+    assert_eq!(
+        manager
+            .state()
+            .retired_signed_pre_key_ids()
+            .collect::<Vec<_>>(),
+        [old_signed_id]
+    );
+    assert_eq!(
+        manager
+            .state()
+            .retired_last_resort_kyber_pre_key_ids()
+            .collect::<Vec<_>>(),
+        [old_kyber_id]
+    );
+
+    // The old keys stay around for the grace period, counted from when they were replaced...
+    let during_grace = maintain(
+        &mut manager,
+        &mut store,
+        counts,
+        rotated_at + config.grace_period - DAY,
+    )?;
+    assert!(during_grace.expired_signed_pre_key_ids.is_empty());
+    assert!(during_grace.expired_kyber_pre_key_ids.is_empty());
+    assert!(is_base_key_used(&store)?);
+
+    // ...and are then handed back to be deleted.
+    let after_grace = maintain(
+        &mut manager,
+        &mut store,
+        counts,
+        rotated_at + config.grace_period,
+    )?;
+    assert_eq!(after_grace.expired_signed_pre_key_ids, [old_signed_id]);
+    assert_eq!(after_grace.expired_kyber_pre_key_ids, [old_kyber_id]);
+    // Only the base keys used with the expired signed pre-key are forgotten.
+    assert!(!is_base_key_used(&store)?);
+    assert!(
+        store
+            .is_base_key_used(new_ids.0, new_ids.1, &new_base_key)
+            .now_or_never()
+            .expect("sync")?
+    );
+    assert!(
+        manager
+            .state()
+            .retired_signed_pre_key_ids()
+            .all(|id| id != old_signed_id)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn state_round_trips() -> TestResult {
+    let mut store = test_in_memory_protocol_store()?;
+    let mut manager = new_manager(&store)?;
+    let identity_key_pair = store
+        .get_identity_key_pair()
+        .now_or_never()
+        .expect("sync")?;
+    let now = SystemTime::now();
+
+    let first = maintain(&mut manager, &mut store, PreKeyCounts::default(), now)?;
+    let counts = counts_after(PreKeyCounts::default(), &first.upload);
+
+    let state = PreKeyManagerState::deserialize(&manager.state().serialize())?;
+    assert_eq!(
+        state.signed_pre_key_id(),
+        manager.state().signed_pre_key_id()
+    );
+    let mut restored =
+        PreKeyManager::new(identity_key_pair, PreKeyManagerConfig::default(), state)?;
+
+    // The restored manager knows the keys are fresh, and carries on from the same IDs.
+    assert!(
+        maintain(&mut restored, &mut store, counts, now)?
+            .upload
+            .is_empty()
+    );
+    let low = PreKeyCounts {
+        one_time_pre_keys: 0,
+        ..counts
+    };
+    let from_original = maintain(&mut manager, &mut store, low, now)?.upload;
+    let from_restored = maintain(&mut restored, &mut store, low, now)?.upload;
+    assert_eq!(
+        from_original
+            .pre_keys
+            .iter()
+            .map(|key| key.key_id)
+            .collect::<Vec<_>>(),
+        from_restored
+            .pre_keys
+            .iter()
+            .map(|key| key.key_id)
+            .collect::<Vec<_>>(),
+    );
+
+    assert_matches!(
+        PreKeyManagerState::deserialize(&[0xff; 8]),
+        Err(SignalProtocolError::InvalidProtobufEncoding)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn invalid_config_is_rejected() -> TestResult {
+    let store = test_in_memory_protocol_store()?;
+    let identity_key_pair = store
+        .get_identity_key_pair()
+        .now_or_never()
+        .expect("sync")?;
+    let config = PreKeyManagerConfig {
+        one_time_pre_key_minimum: 50,
+        one_time_pre_key_target: 20,
+        ..Default::default()
+    };
+    assert_matches!(
+        PreKeyManager::new(
+            identity_key_pair,
+            config,
+            PreKeyManagerState::new(&mut OsRng.unwrap_err()),
+        )
+        .err(),
+        Some(SignalProtocolError::InvalidArgument(_))
+    );
+    Ok(())
+}
+
+#[test]
+fn upload_set_serializes_in_service_format() -> TestResult {
+    let mut store = test_in_memory_protocol_store()?;
+    let mut manager = new_manager(&store)?;
+    let now = SystemTime::now();
+
+    let first = maintain(&mut manager, &mut store, PreKeyCounts::default(), now)?;
+    let json = serde_json::to_value(&first.upload).expect("can serialize");
+    let signed_pre_key = first.upload.signed_pre_key.as_ref().expect("generated");
+    assert_eq!(
+        json["signedPreKey"],
+        serde_json::json!({
+            "keyId": signed_pre_key.key_id,
+            "publicKey": BASE64_STANDARD.encode(&signed_pre_key.public_key),
+            "signature": BASE64_STANDARD.encode(&signed_pre_key.signature),
+        })
+    );
+    assert_eq!(json["preKeys"][0]["keyId"], first.upload.pre_keys[0].key_id);
+    assert!(json["pqLastResortPreKey"]["signature"].is_string());
+    assert!(json["pqPreKeys"][0]["publicKey"].is_string());
+
+    // Anything not being uploaded is left out entirely.
+    let counts = PreKeyCounts {
+        one_time_pre_keys: 0,
+        ..counts_after(PreKeyCounts::default(), &first.upload)
+    };
+    let second = maintain(&mut manager, &mut store, counts, now)?;
+    let json = serde_json::to_value(&second.upload).expect("can serialize");
+    assert_eq!(
+        json.as_object().expect("object").keys().collect::<Vec<_>>(),
+        ["preKeys"]
+    );
+
+    Ok(())
+}
@@ -7,9 +7,10 @@ mod support;
 use std::time::SystemTime;
 
 use futures_util::FutureExt;
+use libsignal_gossip::GossipOutcome;
 use libsignal_protocol::*;
-use rand::TryRngCore as _;
-use rand::rngs::OsRng;
+use rand::rngs::{OsRng, StdRng};
+use rand::{Rng as _, SeedableRng as _, TryRngCore as _};
 use support::*;
 use uuid::Uuid;
 
@@ -301,6 +302,101 @@ fn test_sealed_sender() -> Result<(), SignalProtocolError> {
     .expect("sync")
 }
 
+#[test]
+fn test_sealed_sender_with_gossip() -> Result<(), SignalProtocolError> {
+    async {
+        let mut rng = OsRng.unwrap_err();
+
+        let alice_device_id = DeviceId::new(23).unwrap();
+        let bob_device_id = DeviceId::new(42).unwrap();
+
+        let alice_uuid = "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string();
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+
+        let bob_uuid_address = ProtocolAddress::new(bob_uuid.clone(), bob_device_id);
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let mut bob_store = support::test_in_memory_protocol_store()?;
+
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut rng).await?;
+
+        process_prekey_bundle(
+            &bob_uuid_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await?;
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+
+        let expires = Timestamp::from_epoch_millis(1605722925);
+
+        let sender_cert = SenderCertificate::new(
+            alice_uuid.clone(),
+            None,
+            alice_pubkey,
+            alice_device_id,
+            expires,
+            server_cert,
+            &server_key.private_key,
+            &mut rng,
+        )?;
+
+        let alice_gossip = test_gossip_service(Some([1; 32]));
+        let mut bob_gossip = test_gossip_service(Some([1; 32]));
+
+        let alice_ptext = vec![1, 2, 3, 23, 99];
+        let alice_ctext = sealed_sender_encrypt_with_gossip(
+            &bob_uuid_address,
+            &sender_cert,
+            &alice_ptext,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &alice_gossip,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await?;
+
+        let (bob_ptext, gossip_status) = sealed_sender_decrypt_with_gossip(
+            &alice_ctext,
+            &trust_root.public_key,
+            expires.sub_millis(1),
+            None,
+            bob_uuid.clone(),
+            bob_device_id,
+            &mut bob_store.identity_store,
+            &mut bob_store.session_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            &mut bob_gossip,
+            SystemTime::now(),
+        )
+        .await?;
+
+        assert_eq!(bob_ptext.message, alice_ptext);
+        assert_eq!(bob_ptext.sender_uuid, alice_uuid);
+        assert_eq!(
+            gossip_status,
+            GossipStatus::Verified(GossipOutcome::UpToDate)
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn test_sender_key_in_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
@@ -1110,3 +1206,140 @@ fn test_sealed_sender_multi_recipient_redundant_empty_devices() -> Result<(), Si
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn test_sealed_sender_multi_recipient_encrypt_to_writer() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let alice_uuid = "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string();
+        let alice_device_id = DeviceId::new(23).unwrap();
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        // Enough recipients that the work is split up between threads.
+        let mut recipient_stores = vec![];
+        for _ in 0..13 {
+            let address = ProtocolAddress::new(
+                Uuid::from_bytes(csprng.random()).to_string(),
+                DeviceId::new(1).unwrap(),
+            );
+            let mut store = support::test_in_memory_protocol_store()?;
+            let pre_key_bundle = create_pre_key_bundle(&mut store, &mut csprng).await?;
+            process_prekey_bundle(
+                &address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+            recipient_stores.push((address, store));
+        }
+        let recipients: Vec<_> = recipient_stores.iter().map(|(address, _)| address).collect();
+        let sessions = alice_store
+            .session_store
+            .load_existing_sessions(&recipients)?;
+        let excluded = ServiceId::from(Aci::from(Uuid::from_bytes(csprng.random())));
+
+        let trust_root = KeyPair::generate(&mut csprng);
+        let server_key = KeyPair::generate(&mut csprng);
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut csprng)?;
+        let sender_cert = SenderCertificate::new(
+            alice_uuid,
+            None,
+            alice_pubkey,
+            alice_device_id,
+            Timestamp::from_epoch_millis(1605722925),
+            server_cert,
+            &server_key.private_key,
+            &mut csprng,
+        )?;
+        let alice_usmc = UnidentifiedSenderMessageContent::new(
+            CiphertextMessageType::Plaintext,
+            sender_cert,
+            b"hello everyone".to_vec(),
+            ContentHint::Default,
+            None,
+        )?;
+
+        let expected = sealed_sender_multi_recipient_encrypt(
+            &recipients,
+            &sessions,
+            [excluded],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut StdRng::seed_from_u64(42),
+        )
+        .await?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .expect("can create thread pool");
+        for parallelism in [
+            SealedSenderV2Parallelism::SingleThreaded,
+            SealedSenderV2Parallelism::GlobalThreadPool,
+            SealedSenderV2Parallelism::ThreadPool(&pool),
+        ] {
+            let mut written = vec![];
+            sealed_sender_multi_recipient_encrypt_to_writer(
+                &recipients,
+                &sessions,
+                [excluded],
+                &alice_usmc,
+                &alice_store.identity_store,
+                parallelism,
+                &mut written,
+                &mut StdRng::seed_from_u64(42),
+            )
+            .await?;
+            assert_eq!(written, expected, "{parallelism:?}");
+        }
+
+        let sent = SealedSenderV2SentMessage::parse(&expected)?;
+        assert_eq!(sent.recipients.len(), recipients.len() + 1);
+        assert!(sent.recipients[&excluded].devices.is_empty());
+        for (address, store) in &recipient_stores {
+            let service_id = ServiceId::parse_from_service_id_string(address.name()).unwrap();
+            let received = sent
+                .received_message_parts_for_recipient(&sent.recipients[&service_id])
+                .as_ref()
+                .concat();
+            let usmc = sealed_sender_decrypt_to_usmc(&received, &store.identity_store).await?;
+            assert_eq!(usmc.contents()?, b"hello everyone");
+        }
+
+        // Errors from the output are reported as such.
+        struct FailingWriter;
+        impl std::io::Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::StorageFull.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let result = sealed_sender_multi_recipient_encrypt_to_writer(
+            &recipients,
+            &sessions,
+            [],
+            &alice_usmc,
+            &alice_store.identity_store,
+            SealedSenderV2Parallelism::default(),
+            &mut FailingWriter,
+            &mut csprng,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(SignalProtocolError::Io(ref e)) if e.kind() == std::io::ErrorKind::StorageFull),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
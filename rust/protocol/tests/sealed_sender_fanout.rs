@@ -0,0 +1,276 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use assert_matches::assert_matches;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+use rand::{Rng as _, TryRngCore as _};
+use support::*;
+use uuid::Uuid;
+
+type Registry = HashMap<ServiceId, Vec<(DeviceId, u32)>>;
+
+fn device(id: u8) -> DeviceId {
+    DeviceId::new(id).expect("valid")
+}
+
+fn random_service_id() -> ServiceId {
+    Aci::from(Uuid::from_bytes(OsRng.unwrap_err().random())).into()
+}
+
+/// A message from Alice to both of Bob's devices and to Carol's one device, leaving out Dave.
+struct GroupSend {
+    bob: ServiceId,
+    bob_store: InMemSignalProtocolStore,
+    carol: ServiceId,
+    carol_store: InMemSignalProtocolStore,
+    dave: ServiceId,
+    message: Vec<u8>,
+}
+
+impl GroupSend {
+    async fn new() -> Result<Self, SignalProtocolError> {
+        let mut csprng = OsRng.unwrap_err();
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        let bob = random_service_id();
+        let carol = random_service_id();
+        let dave = random_service_id();
+        let mut bob_store = test_in_memory_protocol_store()?;
+        let mut carol_store = test_in_memory_protocol_store()?;
+
+        let addresses = [
+            ProtocolAddress::new(bob.service_id_string(), device(1)),
+            ProtocolAddress::new(bob.service_id_string(), device(2)),
+            ProtocolAddress::new(carol.service_id_string(), device(1)),
+        ];
+        for address in &addresses {
+            let store = if address.name() == bob.service_id_string() {
+                &mut bob_store
+            } else {
+                &mut carol_store
+            };
+            let bundle = create_pre_key_bundle(store, &mut csprng).await?;
+            process_prekey_bundle(
+                address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+        }
+
+        let trust_root = KeyPair::generate(&mut csprng);
+        let server_key = KeyPair::generate(&mut csprng);
+        let server_cert = ServerCertificate::new(
+            1,
+            server_key.public_key,
+            &trust_root.private_key,
+            &mut csprng,
+        )?;
+        let sender_cert = SenderCertificate::new(
+            random_service_id().service_id_string(),
+            None,
+            alice_pubkey,
+            device(1),
+            Timestamp::from_epoch_millis(1605722925),
+            server_cert,
+            &server_key.private_key,
+            &mut csprng,
+        )?;
+        let usmc = UnidentifiedSenderMessageContent::new(
+            CiphertextMessageType::Plaintext,
+            sender_cert,
+            b"hello group".to_vec(),
+            ContentHint::Default,
+            None,
+        )?;
+
+        let recipients: Vec<_> = addresses.iter().collect();
+        let message = sealed_sender_multi_recipient_encrypt(
+            &recipients,
+            &alice_store
+                .session_store
+                .load_existing_sessions(&recipients)?,
+            [dave],
+            &usmc,
+            &alice_store.identity_store,
+            &mut csprng,
+        )
+        .await?;
+
+        Ok(Self {
+            bob,
+            bob_store,
+            carol,
+            carol_store,
+            dave,
+            message,
+        })
+    }
+
+    /// What the server would have on record if nothing had changed since Alice's sessions were set
+    /// up.
+    async fn registry(&self) -> Result<Registry, SignalProtocolError> {
+        let bob_registration_id = self.bob_store.get_local_registration_id().await?;
+        let carol_registration_id = self.carol_store.get_local_registration_id().await?;
+        Ok(HashMap::from([
+            (
+                self.bob,
+                vec![
+                    (device(1), bob_registration_id),
+                    (device(2), bob_registration_id),
+                ],
+            ),
+            (self.carol, vec![(device(1), carol_registration_id)]),
+            (self.dave, vec![(device(1), 1234)]),
+        ]))
+    }
+}
+
+#[test]
+fn matching_devices_are_delivered_to() -> Result<(), SignalProtocolError> {
+    async {
+        let send = GroupSend::new().await?;
+        let fanout = SealedSenderV2Fanout::parse(&send.message)?;
+        assert_eq!(
+            fanout.excluded_recipients().collect::<Vec<_>>(),
+            [send.dave]
+        );
+
+        let deliveries = fanout
+            .validate(&send.registry().await?)
+            .expect("devices match");
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].service_id, send.bob);
+        assert_eq!(deliveries[0].devices, [device(1), device(2)]);
+        assert_eq!(deliveries[1].service_id, send.carol);
+        assert_eq!(deliveries[1].devices, [device(1)]);
+
+        for (delivery, store) in deliveries.iter().zip([&send.bob_store, &send.carol_store]) {
+            let usmc =
+                sealed_sender_decrypt_to_usmc(&delivery.message, &store.identity_store).await?;
+            assert_eq!(usmc.contents()?, b"hello group");
+        }
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn unknown_recipients_are_rejected() -> Result<(), SignalProtocolError> {
+    async {
+        let send = GroupSend::new().await?;
+        let fanout = SealedSenderV2Fanout::parse(&send.message)?;
+
+        // Excluded recipients don't need to be registered.
+        let mut registry = send.registry().await?;
+        registry.remove(&send.dave);
+        assert_matches!(fanout.validate(&registry), Ok(_));
+
+        registry.remove(&send.carol);
+        let rejection = fanout.validate(&registry).expect_err("Carol is unknown");
+        assert_eq!(rejection.status_code(), 404);
+        assert_eq!(
+            rejection,
+            SealedSenderV2FanoutRejection::UnknownRecipients(vec![send.carol])
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn mismatched_devices_are_reported_like_a_409() -> Result<(), SignalProtocolError> {
+    async {
+        let send = GroupSend::new().await?;
+        let fanout = SealedSenderV2Fanout::parse(&send.message)?;
+
+        let mut registry = send.registry().await?;
+        let bob_devices = registry.get_mut(&send.bob).expect("present");
+        let (_, bob_registration_id) = bob_devices.pop().expect("has device 2");
+        bob_devices.push((device(3), bob_registration_id));
+        // A stale registration ID for Carol doesn't matter until the devices match.
+        registry.get_mut(&send.carol).expect("present")[0].1 += 1;
+
+        let rejection = fanout.validate(&registry).expect_err("devices don't match");
+        assert_eq!(rejection.status_code(), 409);
+        let SealedSenderV2FanoutRejection::MismatchedDevices(mismatched) = rejection else {
+            panic!("unexpected rejection: {rejection:?}");
+        };
+        assert_eq!(
+            mismatched,
+            [MismatchedDevices {
+                service_id: send.bob,
+                missing_devices: vec![device(3)],
+                extra_devices: vec![device(2)],
+            }]
+        );
+        assert_eq!(
+            serde_json::to_value(&mismatched).expect("can serialize"),
+            serde_json::json!([{
+                "uuid": send.bob.service_id_string(),
+                "devices": {
+                    "missingDevices": [3],
+                    "extraDevices": [2],
+                },
+            }])
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn stale_devices_are_reported_like_a_410() -> Result<(), SignalProtocolError> {
+    async {
+        let send = GroupSend::new().await?;
+        let fanout = SealedSenderV2Fanout::parse(&send.message)?;
+
+        let mut registry = send.registry().await?;
+        registry.get_mut(&send.bob).expect("present")[1].1 += 1;
+
+        let rejection = fanout.validate(&registry).expect_err("device 2 is stale");
+        assert_eq!(rejection.status_code(), 410);
+        let SealedSenderV2FanoutRejection::StaleDevices(stale) = rejection else {
+            panic!("unexpected rejection: {rejection:?}");
+        };
+        assert_eq!(
+            stale,
+            [StaleDevices {
+                service_id: send.bob,
+                stale_devices: vec![device(2)],
+            }]
+        );
+        assert_eq!(
+            serde_json::to_value(&stale).expect("can serialize"),
+            serde_json::json!([{
+                "uuid": send.bob.service_id_string(),
+                "devices": {
+                    "staleDevices": [2],
+                },
+            }])
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -0,0 +1,324 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::time::{Duration, SystemTime};
+
+use assert_matches::assert_matches;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::TryRngCore as _;
+use rand::rngs::OsRng;
+use uuid::Uuid;
+
+type TestResult = Result<(), SignalProtocolError>;
+
+const DISTRIBUTION_ID: Uuid = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+fn address(name: &str, device_id: u8) -> ProtocolAddress {
+    ProtocolAddress::new(name.to_owned(), DeviceId::new(device_id).expect("valid"))
+}
+
+struct Sender {
+    manager: SenderKeyManager,
+    store: InMemSenderKeyStore,
+}
+
+impl Sender {
+    fn new(config: SenderKeyManagerConfig) -> Result<Self, SignalProtocolError> {
+        Ok(Self {
+            manager: SenderKeyManager::new(
+                address("+14151111111", 1),
+                config,
+                SenderKeyManagerState::new(),
+            )?,
+            store: InMemSenderKeyStore::new(),
+        })
+    }
+
+    fn prepare_send(
+        &mut self,
+        recipients: &[ProtocolAddress],
+        now: SystemTime,
+    ) -> Result<SenderKeySendPreparation, SignalProtocolError> {
+        self.manager
+            .prepare_send(
+                DISTRIBUTION_ID,
+                recipients,
+                &mut self.store,
+                now,
+                &mut OsRng.unwrap_err(),
+            )
+            .now_or_never()
+            .expect("sync")
+    }
+
+    fn encrypt(&mut self) -> Result<SenderKeyMessage, SignalProtocolError> {
+        self.manager
+            .group_encrypt(
+                DISTRIBUTION_ID,
+                b"hello",
+                &mut self.store,
+                &mut OsRng.unwrap_err(),
+            )
+            .now_or_never()
+            .expect("sync")
+    }
+
+    /// Prepares a send to `recipients`, delivers the distribution message to whoever needs it, and
+    /// sends one message.
+    fn send(
+        &mut self,
+        recipients: &[ProtocolAddress],
+        now: SystemTime,
+    ) -> Result<SenderKeySendPreparation, SignalProtocolError> {
+        let preparation = self.prepare_send(recipients, now)?;
+        self.manager
+            .mark_distributed(DISTRIBUTION_ID, &preparation.needs_distribution)?;
+        self.encrypt()?;
+        Ok(preparation)
+    }
+}
+
+#[test]
+fn only_new_recipients_need_distribution() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig::default())?;
+    let bob = address("+14151111112", 1);
+    let carol = address("+14151111113", 1);
+    let now = SystemTime::now();
+
+    let first = sender.prepare_send(std::slice::from_ref(&bob), now)?;
+    assert!(first.rotated);
+    assert_eq!(first.needs_distribution, std::slice::from_ref(&bob));
+    assert_eq!(
+        sender
+            .manager
+            .recipients_needing_distribution(DISTRIBUTION_ID)?,
+        std::slice::from_ref(&bob)
+    );
+    sender.manager.mark_distributed(DISTRIBUTION_ID, [&bob])?;
+    assert!(
+        sender
+            .manager
+            .recipients_needing_distribution(DISTRIBUTION_ID)?
+            .is_empty()
+    );
+    sender.encrypt()?;
+
+    let second = sender.prepare_send(&[bob.clone(), carol.clone()], now)?;
+    assert!(!second.rotated);
+    assert_eq!(second.needs_distribution, [carol]);
+    assert_eq!(
+        second.distribution_message.chain_id()?,
+        first.distribution_message.chain_id()?
+    );
+    assert_eq!(
+        sender.manager.state().messages_sent(DISTRIBUTION_ID),
+        Some(1)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn removing_a_recipient_rotates() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig::default())?;
+    let bob = address("+14151111112", 1);
+    let bob_tablet = address("+14151111112", 2);
+    let carol = address("+14151111113", 1);
+    let now = SystemTime::now();
+
+    let all = [bob.clone(), bob_tablet.clone(), carol.clone()];
+    let first = sender.send(&all, now)?;
+
+    assert_eq!(
+        sender.manager.remove_recipient(&bob_tablet),
+        [DISTRIBUTION_ID]
+    );
+    assert!(sender.manager.remove_recipient(&bob_tablet).is_empty());
+
+    // The old chain can't be used until the manager has replaced it.
+    assert_matches!(
+        sender.encrypt(),
+        Err(SignalProtocolError::InvalidState("group_encrypt", _))
+    );
+
+    let second = sender.prepare_send(&[bob.clone(), carol.clone()], now)?;
+    assert!(second.rotated);
+    assert_ne!(
+        second.distribution_message.chain_id()?,
+        first.distribution_message.chain_id()?
+    );
+    assert_eq!(second.needs_distribution, [bob, carol]);
+
+    Ok(())
+}
+
+#[test]
+fn leaving_out_a_previous_recipient_rotates() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig::default())?;
+    let bob = address("+14151111112", 1);
+    let carol = address("+14151111113", 1);
+    let now = SystemTime::now();
+
+    sender.send(&[bob.clone(), carol], now)?;
+    let preparation = sender.prepare_send(std::slice::from_ref(&bob), now)?;
+    assert!(preparation.rotated);
+    assert_eq!(preparation.needs_distribution, [bob]);
+
+    Ok(())
+}
+
+#[test]
+fn chains_are_rotated_after_enough_messages() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig {
+        max_messages_per_chain: 3,
+        ..Default::default()
+    })?;
+    let bob = address("+14151111112", 1);
+    let now = SystemTime::now();
+
+    assert!(sender.send(std::slice::from_ref(&bob), now)?.rotated);
+    assert!(!sender.send(std::slice::from_ref(&bob), now)?.rotated);
+    assert!(!sender.send(std::slice::from_ref(&bob), now)?.rotated);
+    assert_eq!(
+        sender.manager.state().messages_sent(DISTRIBUTION_ID),
+        Some(3)
+    );
+
+    let preparation = sender.send(std::slice::from_ref(&bob), now)?;
+    assert!(preparation.rotated);
+    assert_eq!(preparation.needs_distribution, [bob]);
+    assert_eq!(
+        sender.manager.state().messages_sent(DISTRIBUTION_ID),
+        Some(1)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn chains_are_rotated_after_enough_time() -> TestResult {
+    let config = SenderKeyManagerConfig::default();
+    let max_chain_age = config.max_chain_age;
+    let mut sender = Sender::new(config)?;
+    let bob = address("+14151111112", 1);
+    let start = SystemTime::now();
+
+    assert!(sender.send(std::slice::from_ref(&bob), start)?.rotated);
+    let almost = start + max_chain_age - Duration::from_secs(1);
+    assert!(!sender.send(std::slice::from_ref(&bob), almost)?.rotated);
+    assert!(
+        sender
+            .send(std::slice::from_ref(&bob), start + max_chain_age)?
+            .rotated
+    );
+
+    Ok(())
+}
+
+#[test]
+fn chains_with_an_unrepresentable_expiry_never_expire() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig {
+        max_chain_age: Duration::MAX,
+        ..Default::default()
+    })?;
+    let bob = address("+14151111112", 1);
+    let now = SystemTime::now();
+
+    assert!(sender.send(std::slice::from_ref(&bob), now)?.rotated);
+    assert!(!sender.send(std::slice::from_ref(&bob), now)?.rotated);
+
+    Ok(())
+}
+
+#[test]
+fn existing_sender_keys_are_replaced_when_first_tracked() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig::default())?;
+    let bob = address("+14151111112", 1);
+
+    // A chain made before the manager was in use may have gone to anyone.
+    let untracked = create_sender_key_distribution_message(
+        sender.manager.sender(),
+        DISTRIBUTION_ID,
+        &mut sender.store,
+        &mut OsRng.unwrap_err(),
+    )
+    .now_or_never()
+    .expect("sync")?;
+
+    let preparation = sender.prepare_send(&[bob], SystemTime::now())?;
+    assert!(preparation.rotated);
+    assert_ne!(
+        preparation.distribution_message.chain_id()?,
+        untracked.chain_id()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn state_round_trips() -> TestResult {
+    let mut sender = Sender::new(SenderKeyManagerConfig::default())?;
+    let bob = address("+14151111112", 1);
+    let carol = address("+14151111113", 1);
+    let now = SystemTime::now();
+
+    let first = sender.prepare_send(&[bob.clone(), carol.clone()], now)?;
+    sender.manager.mark_distributed(DISTRIBUTION_ID, [&bob])?;
+
+    let state = SenderKeyManagerState::deserialize(&sender.manager.state().serialize())?;
+    assert_eq!(
+        state.distribution_ids().collect::<Vec<_>>(),
+        [DISTRIBUTION_ID]
+    );
+    assert_eq!(
+        state.chain_id(DISTRIBUTION_ID),
+        Some(first.distribution_message.chain_id()?)
+    );
+
+    sender.manager = SenderKeyManager::new(
+        sender.manager.sender().clone(),
+        SenderKeyManagerConfig::default(),
+        state,
+    )?;
+    assert_eq!(
+        sender
+            .manager
+            .recipients_needing_distribution(DISTRIBUTION_ID)?,
+        std::slice::from_ref(&carol)
+    );
+    let second = sender.prepare_send(&[bob, carol.clone()], now)?;
+    assert!(!second.rotated);
+    assert_eq!(second.needs_distribution, [carol]);
+
+    sender.manager.forget(DISTRIBUTION_ID);
+    assert_eq!(sender.manager.state().distribution_ids().count(), 0);
+    assert_matches!(
+        sender.encrypt(),
+        Err(SignalProtocolError::InvalidState("group_encrypt", _))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let sender = address("+14151111111", 1);
+    for config in [
+        SenderKeyManagerConfig {
+            max_messages_per_chain: 0,
+            ..Default::default()
+        },
+        SenderKeyManagerConfig {
+            max_chain_age: Duration::ZERO,
+            ..Default::default()
+        },
+    ] {
+        assert_matches!(
+            SenderKeyManager::new(sender.clone(), config, SenderKeyManagerState::new()),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+    }
+}
@@ -8,7 +8,9 @@ use std::collections::VecDeque;
 use std::time::{Duration, SystemTime};
 
 use assert_matches::assert_matches;
+use async_trait::async_trait;
 use futures_util::FutureExt;
+use libsignal_gossip::{GossipMessage, GossipOutcome};
 use libsignal_protocol::*;
 use rand::rngs::OsRng;
 use rand::{RngCore, SeedableRng, TryRngCore as _};
@@ -2279,6 +2281,451 @@ fn prekey_message_failed_decryption_does_not_update_stores_even_when_previously_
     .expect("sync")
 }
 
+/// Leaves detecting a reused Kyber pre-key to [`KyberPreKeyStore::mark_kyber_pre_key_used`], as
+/// stores that don't override [`KyberPreKeyStore::is_base_key_used`] do, so that a replayed
+/// message is only rejected when its updates are committed.
+struct ReplayCheckedOnCommit<'a>(&'a mut InMemSignalProtocolStore);
+
+#[async_trait(?Send)]
+impl SessionStore for ReplayCheckedOnCommit<'_> {
+    async fn load_session(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<SessionRecord>, SignalProtocolError> {
+        self.0.load_session(address).await
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.0.store_session(address, record).await
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.0.session_policy()
+    }
+}
+
+#[async_trait(?Send)]
+impl IdentityKeyStore for ReplayCheckedOnCommit<'_> {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair, SignalProtocolError> {
+        self.0.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32, SignalProtocolError> {
+        self.0.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange, SignalProtocolError> {
+        self.0.save_identity(address, identity).await
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: Direction,
+    ) -> Result<bool, SignalProtocolError> {
+        self.0
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<IdentityKey>, SignalProtocolError> {
+        self.0.get_identity(address).await
+    }
+
+    async fn observe_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        source: IdentitySource,
+        now: SystemTime,
+    ) -> Result<(), SignalProtocolError> {
+        self.0
+            .observe_identity(address, identity, source, now)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl PreKeyStore for ReplayCheckedOnCommit<'_> {
+    async fn get_pre_key(&self, prekey_id: PreKeyId) -> Result<PreKeyRecord, SignalProtocolError> {
+        self.0.get_pre_key(prekey_id).await
+    }
+
+    async fn save_pre_key(
+        &mut self,
+        prekey_id: PreKeyId,
+        record: &PreKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.0.save_pre_key(prekey_id, record).await
+    }
+
+    async fn remove_pre_key(&mut self, prekey_id: PreKeyId) -> Result<(), SignalProtocolError> {
+        self.0.remove_pre_key(prekey_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl SignedPreKeyStore for ReplayCheckedOnCommit<'_> {
+    async fn get_signed_pre_key(
+        &self,
+        signed_prekey_id: SignedPreKeyId,
+    ) -> Result<SignedPreKeyRecord, SignalProtocolError> {
+        self.0.get_signed_pre_key(signed_prekey_id).await
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        signed_prekey_id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.0.save_signed_pre_key(signed_prekey_id, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl KyberPreKeyStore for ReplayCheckedOnCommit<'_> {
+    async fn get_kyber_pre_key(
+        &self,
+        kyber_prekey_id: KyberPreKeyId,
+    ) -> Result<KyberPreKeyRecord, SignalProtocolError> {
+        self.0.get_kyber_pre_key(kyber_prekey_id).await
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        self.0.save_kyber_pre_key(kyber_prekey_id, record).await
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<(), SignalProtocolError> {
+        self.0
+            .mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+}
+
+impl ProtocolStore for ReplayCheckedOnCommit<'_> {}
+
+#[async_trait(?Send)]
+impl TransactionalProtocolStore for ReplayCheckedOnCommit<'_> {
+    async fn commit_transaction(
+        &mut self,
+        transaction: ProtocolStoreTransaction,
+    ) -> Result<(), SignalProtocolError> {
+        self.0.commit_transaction(transaction).await
+    }
+}
+
+#[test]
+fn transactional_prekey_decrypt_is_rolled_back_on_replay() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        // No one-time pre-key, so that only the last-resort Kyber pre-key protects against replay.
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_signed_pre_key(22.into())
+            .with_kyber_pre_key(8000.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let original_message = b"tout ou rien";
+        let outgoing_message = message_encrypt_transactional(
+            original_message,
+            &bob_address,
+            &mut alice_store,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        let incoming_message = CiphertextMessage::PreKeySignalMessage(
+            PreKeySignalMessage::try_from(outgoing_message.serialize())?,
+        );
+
+        let bob_store = &mut bob_store_builder.store;
+        let ptext = message_decrypt_transactional(
+            &incoming_message,
+            &alice_address,
+            bob_store,
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(ptext, original_message);
+
+        // Forget Alice entirely, so that the replayed message sets up a new session from scratch,
+        // and is only caught once the identity has already been saved as part of the commit.
+        bob_store.identity_store.reset();
+        bob_store.session_store = InMemSessionStore::new();
+
+        assert_matches!(
+            message_decrypt_transactional(
+                &incoming_message,
+                &alice_address,
+                &mut ReplayCheckedOnCommit(bob_store),
+                &mut csprng
+            )
+            .await,
+            Err(SignalProtocolError::ReusedBaseKey(kyber_pre_key_id, signed_pre_key_id))
+                if kyber_pre_key_id == 8000.into() && signed_pre_key_id == 22.into()
+        );
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+        assert_eq!(bob_store.get_identity(&alice_address).await?, None);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn prekey_decrypt_replay_leaves_stores_and_gossip_untouched() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_signed_pre_key(22.into())
+            .with_kyber_pre_key(8000.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let alice_gossip = test_gossip_service(Some([1; 32]));
+        let outgoing_message =
+            encrypt_with_gossip(&mut alice_store, &bob_address, &alice_gossip, "once").await?;
+
+        let bob_store = &mut bob_store_builder.store;
+        let ptext = support::decrypt(bob_store, &alice_address, &outgoing_message).await?;
+        assert_eq!(ptext, b"once");
+
+        bob_store.identity_store.reset();
+        bob_store.session_store = InMemSessionStore::new();
+
+        // The replayed message's gossip would be enough to bootstrap from, but it mustn't be
+        // adopted when the message itself is rejected.
+        let mut bob_gossip = test_gossip_service(None).allow_bootstrap_from_peers();
+        assert_matches!(
+            decrypt_with_gossip(
+                bob_store,
+                &alice_address,
+                &mut bob_gossip,
+                &outgoing_message
+            )
+            .await,
+            Err(SignalProtocolError::ReusedBaseKey(..))
+        );
+        assert!(bob_gossip.state().last_tree_head().is_none());
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+        assert_eq!(bob_store.get_identity(&alice_address).await?, None);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+/// A [`GossipStore`] that has nothing saved and can't save anything.
+struct FailingGossipStore;
+
+#[async_trait(?Send)]
+impl GossipStore for FailingGossipStore {
+    async fn load_state(&self) -> Result<Option<KtState>, GossipError> {
+        Ok(None)
+    }
+
+    async fn save_state(&mut self, _state: &KtState) -> Result<(), GossipError> {
+        Err(GossipError::Storage("simulated failure".to_owned()))
+    }
+}
+
+#[test]
+fn gossip_that_cannot_be_saved_is_reported() -> TestResult {
+    async {
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let (alice_session, bob_session) = initialize_sessions_v4()?;
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+        alice_store
+            .store_session(&bob_address, &alice_session)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session)
+            .await?;
+
+        let alice_gossip = test_gossip_service(Some([1; 32]));
+        let msg = encrypt_with_gossip(&mut alice_store, &bob_address, &alice_gossip, "hi").await?;
+
+        let mut bob_gossip = GossipService::new(
+            libsignal_gossip::gossip_test::create_test_key_transparency(),
+            Box::new(FailingGossipStore),
+        )
+        .await
+        .expect("nothing to load")
+        .allow_bootstrap_from_peers();
+        let (ptext, status) =
+            decrypt_with_gossip(&mut bob_store, &alice_address, &mut bob_gossip, &msg).await?;
+        assert_eq!(ptext, b"hi");
+        assert_eq!(status, GossipStatus::NotSaved(GossipOutcome::Bootstrapped));
+        assert!(bob_gossip.state().last_tree_head().is_none());
+
+        // The message itself was still saved.
+        let reply = encrypt(&mut bob_store, &alice_address, "reply").await?;
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"reply"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn last_resort_kyber_pre_key_replay_is_rejected() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_signed_pre_key(22.into())
+            .with_kyber_pre_key(8000.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        let outgoing_message = support::encrypt(&mut alice_store, &bob_address, "once").await?;
+        let pre_key_message = PreKeySignalMessage::try_from(outgoing_message.serialize())?;
+
+        let bob_store = &mut bob_store_builder.store;
+        let ptext = support::decrypt(bob_store, &alice_address, &outgoing_message).await?;
+        assert_eq!(ptext, b"once");
+        assert!(
+            bob_store
+                .is_base_key_used(8000.into(), 22.into(), pre_key_message.base_key())
+                .await?
+        );
+
+        // Delivering the same message again to the existing session is fine...
+        let mut existing_session = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session created");
+        let (pre_keys_used, _) = process_prekey(
+            &pre_key_message,
+            &alice_address,
+            &mut existing_session,
+            &bob_store.identity_store,
+            &bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &bob_store.kyber_pre_key_store,
+        )
+        .await?;
+        assert!(pre_keys_used.is_none());
+
+        // ...but it must not set up another one.
+        let mut fresh_session = SessionRecord::new_fresh();
+        assert_matches!(
+            process_prekey(
+                &pre_key_message,
+                &alice_address,
+                &mut fresh_session,
+                &bob_store.identity_store,
+                &bob_store.pre_key_store,
+                &bob_store.signed_pre_key_store,
+                &bob_store.kyber_pre_key_store
+            )
+            .await
+            .err(),
+            Some(SignalProtocolError::ReusedBaseKey(kyber_pre_key_id, signed_pre_key_id))
+                if kyber_pre_key_id == 8000.into() && signed_pre_key_id == 22.into()
+        );
+
+        bob_store.session_store = InMemSessionStore::new();
+        assert_matches!(
+            support::decrypt(bob_store, &alice_address, &outgoing_message).await,
+            Err(SignalProtocolError::ReusedBaseKey(..))
+        );
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+
+        // Once the signed pre-key is gone, so is the record of what was used with it.
+        bob_store
+            .expire_base_keys_for_signed_pre_key(22.into())
+            .await?;
+        assert!(
+            !bob_store
+                .is_base_key_used(8000.into(), 22.into(), pre_key_message.base_key())
+                .await?
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn prekey_message_to_archived_session() -> TestResult {
     async {
@@ -2998,28 +3445,21 @@ fn test_duplicate_message_error_returned() -> TestResult {
 }
 
 #[test]
-fn test_pqr_state_and_message_contents_nonempty() -> TestResult {
+fn test_batch_decrypt() -> TestResult {
     async {
         let mut csprng = OsRng.unwrap_err();
 
-        let alice_device_id = DeviceId::new(1).unwrap();
-        let bob_device_id = DeviceId::new(1).unwrap();
-
-        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), alice_device_id);
-        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), bob_device_id);
-
-        let mut alice_store_builder = TestStoreBuilder::new();
-        alice_store_builder.add_pre_key(IdChoice::Next);
-        alice_store_builder.add_signed_pre_key(IdChoice::Next);
-        alice_store_builder.add_kyber_pre_key(IdChoice::Next);
-        let mut bob_store_builder = TestStoreBuilder::new();
-        bob_store_builder.add_pre_key(IdChoice::Next);
-        bob_store_builder.add_signed_pre_key(IdChoice::Next);
-        bob_store_builder.add_kyber_pre_key(IdChoice::Next);
+        let device_id = DeviceId::new(1).unwrap();
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), device_id);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), device_id);
+        let carol_address = ProtocolAddress::new("+14151111113".to_owned(), device_id);
 
-        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
-
-        let alice_store = &mut alice_store_builder.store;
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(device_id);
         let bob_store = &mut bob_store_builder.store;
 
         process_prekey_bundle(
@@ -3032,9 +3472,415 @@ fn test_pqr_state_and_message_contents_nonempty() -> TestResult {
         )
         .await?;
 
-        let msg = encrypt(alice_store, &bob_address, "msg1").await?;
-        assert_matches!(&msg, CiphertextMessage::PreKeySignalMessage(m) if !m.message().pq_ratchet().is_empty());
-        decrypt(bob_store, &alice_address, &msg).await?;
+        let (carol_session, bob_session_with_carol) = initialize_sessions_v4()?;
+        let mut carol_store = test_in_memory_protocol_store()?;
+        carol_store
+            .store_session(&bob_address, &carol_session)
+            .await?;
+        bob_store
+            .store_session(&carol_address, &bob_session_with_carol)
+            .await?;
+
+        let a1 = encrypt(&mut alice_store, &bob_address, "a1").await?;
+        let a2 = encrypt(&mut alice_store, &bob_address, "a2").await?;
+        let a3 = encrypt(&mut alice_store, &bob_address, "a3").await?;
+        let c1 = encrypt(&mut carol_store, &bob_address, "c1").await?;
+        let c2 = encrypt(&mut carol_store, &bob_address, "c2").await?;
+        assert_eq!(a1.message_type(), CiphertextMessageType::PreKey);
+
+        let mut corrupted_c2 = c2.serialize().to_vec();
+        *corrupted_c2.last_mut().expect("not empty") ^= 1;
+        let corrupted_c2 =
+            CiphertextMessage::SignalMessage(SignalMessage::try_from(corrupted_c2.as_slice())?);
+
+        let a1_again =
+            CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(a1.serialize())?);
+
+        let queue = [
+            (alice_address.clone(), a1),
+            (carol_address.clone(), c1),
+            (alice_address.clone(), a2),
+            (alice_address.clone(), a1_again),
+            (carol_address.clone(), corrupted_c2),
+            (carol_address.clone(), c2),
+            (alice_address.clone(), a3),
+        ];
+        let results = message_decrypt_batch(
+            &queue,
+            &mut bob_store.session_store,
+            &mut bob_store.identity_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            &mut csprng,
+        )
+        .await;
+
+        let [a1, c1, a2, a1_again, corrupted_c2, c2, a3] =
+            <[_; 7]>::try_from(results).expect("one result per message");
+        assert_eq!(a1?, b"a1");
+        assert_eq!(c1?, b"c1");
+        assert_eq!(a2?, b"a2");
+        assert_matches!(a1_again, Err(SignalProtocolError::DuplicatedMessage(_, _)));
+        assert_matches!(
+            corrupted_c2,
+            Err(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::Whisper,
+                _
+            ))
+        );
+        assert_eq!(c2?, b"c2");
+        assert_eq!(a3?, b"a3");
+
+        // The updated sessions and identities were written back, and the one-time pre-key used.
+        assert_eq!(
+            bob_store.get_identity(&alice_address).await?.as_ref(),
+            Some(alice_store.get_identity_key_pair().await?.identity_key())
+        );
+        assert_eq!(bob_store.all_pre_key_ids().count(), 0);
+
+        let reply = encrypt(bob_store, &alice_address, "got them").await?;
+        assert_eq!(reply.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"got them"
+        );
+        let c3 = encrypt(&mut carol_store, &bob_address, "c3").await?;
+        assert_eq!(decrypt(bob_store, &carol_address, &c3).await?, b"c3");
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+/// Fails to save the session with one address, and passes everything else through.
+struct FailingSessionStore<'a> {
+    inner: &'a mut dyn SessionStore,
+    failing_address: ProtocolAddress,
+}
+
+#[async_trait(?Send)]
+impl SessionStore for FailingSessionStore<'_> {
+    async fn load_session(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<SessionRecord>, SignalProtocolError> {
+        self.inner.load_session(address).await
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<(), SignalProtocolError> {
+        if *address == self.failing_address {
+            return Err(SignalProtocolError::InvalidState(
+                "store_session",
+                "simulated failure".to_owned(),
+            ));
+        }
+        self.inner.store_session(address, record).await
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.inner.session_policy()
+    }
+}
+
+#[test]
+fn batch_decrypt_keeps_pre_keys_when_a_session_is_not_saved() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let device_id = DeviceId::new(1).unwrap();
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), device_id);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), device_id);
+        let carol_address = ProtocolAddress::new("+14151111113".to_owned(), device_id);
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(device_id);
+        let bob_store = &mut bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let (carol_session, bob_session_with_carol) = initialize_sessions_v4()?;
+        let mut carol_store = test_in_memory_protocol_store()?;
+        carol_store
+            .store_session(&bob_address, &carol_session)
+            .await?;
+        bob_store
+            .store_session(&carol_address, &bob_session_with_carol)
+            .await?;
+
+        let a1 = encrypt(&mut alice_store, &bob_address, "a1").await?;
+        let a2 = encrypt(&mut alice_store, &bob_address, "a2").await?;
+        let c1 = encrypt(&mut carol_store, &bob_address, "c1").await?;
+        let queue = [
+            (alice_address.clone(), a1),
+            (carol_address.clone(), c1),
+            (alice_address.clone(), a2),
+        ];
+
+        let results = message_decrypt_batch(
+            &queue,
+            &mut FailingSessionStore {
+                inner: &mut bob_store.session_store,
+                failing_address: alice_address.clone(),
+            },
+            &mut bob_store.identity_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            &mut csprng,
+        )
+        .await;
+        let [a1, c1, a2] = <[_; 3]>::try_from(results).expect("one result per message");
+        assert_matches!(
+            a1,
+            Err(SignalProtocolError::InvalidState("store_session", _))
+        );
+        assert_matches!(
+            a2,
+            Err(SignalProtocolError::InvalidState(
+                "message_decrypt_batch",
+                _
+            ))
+        );
+        assert_eq!(c1?, b"c1");
+
+        // Alice's pre-keys weren't consumed, so her messages can still be read.
+        assert_eq!(bob_store.all_pre_key_ids().count(), 1);
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+        let results = message_decrypt_batch(
+            &queue[..1],
+            &mut bob_store.session_store,
+            &mut bob_store.identity_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            &mut csprng,
+        )
+        .await;
+        assert_matches!(results.as_slice(), [Ok(ptext)] if ptext == b"a1");
+        assert_eq!(bob_store.all_pre_key_ids().count(), 0);
+        assert_eq!(
+            decrypt(bob_store, &alice_address, &queue[2].1).await?,
+            b"a2"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+/// Fails to record identities in their history, and passes everything else through.
+struct FailingHistoryIdentityStore<'a>(&'a mut dyn IdentityKeyStore);
+
+#[async_trait(?Send)]
+impl IdentityKeyStore for FailingHistoryIdentityStore<'_> {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair, SignalProtocolError> {
+        self.0.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32, SignalProtocolError> {
+        self.0.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange, SignalProtocolError> {
+        self.0.save_identity(address, identity).await
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: Direction,
+    ) -> Result<bool, SignalProtocolError> {
+        self.0
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(
+        &self,
+        address: &ProtocolAddress,
+    ) -> Result<Option<IdentityKey>, SignalProtocolError> {
+        self.0.get_identity(address).await
+    }
+
+    async fn observe_identity(
+        &mut self,
+        _address: &ProtocolAddress,
+        _identity: &IdentityKey,
+        _source: IdentitySource,
+        _now: SystemTime,
+    ) -> Result<(), SignalProtocolError> {
+        Err(SignalProtocolError::InvalidState(
+            "observe_identity",
+            "simulated failure".to_owned(),
+        ))
+    }
+}
+
+#[test]
+fn identity_history_failure_does_not_fail_processing_a_bundle() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let device_id = DeviceId::new(1).unwrap();
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), device_id);
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_pre_key_bundle = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next)
+            .make_bundle_with_latest_keys(device_id);
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut FailingHistoryIdentityStore(&mut alice_store.identity_store),
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        assert!(alice_store.load_session(&bob_address).await?.is_some());
+        assert!(alice_store.get_identity(&bob_address).await?.is_some());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn identity_history_failure_does_not_fail_decryption() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let device_id = DeviceId::new(1).unwrap();
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), device_id);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), device_id);
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(device_id);
+        let bob_store = &mut bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        let a1 = encrypt(&mut alice_store, &bob_address, "a1").await?;
+        let a2 = encrypt(&mut alice_store, &bob_address, "a2").await?;
+
+        // The session and identity are committed before the history is updated, so the message
+        // still counts as decrypted.
+        let ptext = message_decrypt(
+            &a1,
+            &alice_address,
+            &mut bob_store.session_store,
+            &mut FailingHistoryIdentityStore(&mut bob_store.identity_store),
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(ptext, b"a1");
+        assert!(bob_store.load_session(&alice_address).await?.is_some());
+        assert!(bob_store.get_identity(&alice_address).await?.is_some());
+
+        let results = message_decrypt_batch(
+            &[(alice_address.clone(), a2)],
+            &mut bob_store.session_store,
+            &mut FailingHistoryIdentityStore(&mut bob_store.identity_store),
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            &mut csprng,
+        )
+        .await;
+        assert_matches!(results.as_slice(), [Ok(ptext)] if ptext == b"a2");
+
+        let reply = encrypt(bob_store, &alice_address, "reply").await?;
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"reply"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_pqr_state_and_message_contents_nonempty() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let alice_device_id = DeviceId::new(1).unwrap();
+        let bob_device_id = DeviceId::new(1).unwrap();
+
+        let alice_address = ProtocolAddress::new("+14151111111".to_owned(), alice_device_id);
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), bob_device_id);
+
+        let mut alice_store_builder = TestStoreBuilder::new();
+        alice_store_builder.add_pre_key(IdChoice::Next);
+        alice_store_builder.add_signed_pre_key(IdChoice::Next);
+        alice_store_builder.add_kyber_pre_key(IdChoice::Next);
+        let mut bob_store_builder = TestStoreBuilder::new();
+        bob_store_builder.add_pre_key(IdChoice::Next);
+        bob_store_builder.add_signed_pre_key(IdChoice::Next);
+        bob_store_builder.add_kyber_pre_key(IdChoice::Next);
+
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
+
+        let alice_store = &mut alice_store_builder.store;
+        let bob_store = &mut bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let msg = encrypt(alice_store, &bob_address, "msg1").await?;
+        assert_matches!(&msg, CiphertextMessage::PreKeySignalMessage(m) if !m.message().pq_ratchet().is_empty());
+        decrypt(bob_store, &alice_address, &msg).await?;
 
         let msg = encrypt(bob_store, &alice_address, "msg2").await?;
         assert_matches!(&msg, CiphertextMessage::SignalMessage(m) if !m.pq_ratchet().is_empty());
@@ -3288,10 +4134,7 @@ fn prekey_message_sent_from_different_user_is_rejected() {
         let err = support::decrypt(bob_store, &mallory_address, &pre_key_message)
             .await
             .expect_err("should be rejected");
-        assert_matches!(
-            err,
-            SignalProtocolError::InvalidMessage(CiphertextMessageType::PreKey, "reused base key")
-        );
+        assert_matches!(err, SignalProtocolError::ReusedBaseKey(..));
         assert!(
             bob_store
                 .load_session(&mallory_address)
@@ -3304,3 +4147,494 @@ fn prekey_message_sent_from_different_user_is_rejected() {
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn test_gossip_piggybacking() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_device_id = DeviceId::new(1).unwrap();
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), bob_device_id);
+
+        let mut alice_store_builder = TestStoreBuilder::new();
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
+
+        let alice_store = &mut alice_store_builder.store;
+        let bob_store = &mut bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let alice_gossip = test_gossip_service(Some([1; 32]));
+        let mut bob_gossip = test_gossip_service(Some([1; 32]));
+
+        // The gossip rides along on the initial PreKeySignalMessage...
+        let msg = encrypt_with_gossip(alice_store, &bob_address, &alice_gossip, "hello").await?;
+        assert_eq!(msg.message_type(), CiphertextMessageType::PreKey);
+        assert_eq!(
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?,
+            (
+                b"hello".to_vec(),
+                GossipStatus::Verified(GossipOutcome::UpToDate)
+            )
+        );
+
+        // ...and on plain SignalMessages.
+        let msg = encrypt(bob_store, &alice_address, "no gossip here").await?;
+        decrypt(alice_store, &bob_address, &msg).await?;
+        let msg = encrypt_with_gossip(alice_store, &bob_address, &alice_gossip, "again").await?;
+        assert_eq!(msg.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?,
+            (
+                b"again".to_vec(),
+                GossipStatus::Verified(GossipOutcome::UpToDate)
+            )
+        );
+
+        let msg = encrypt(alice_store, &bob_address, "missing").await?;
+        assert_eq!(
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?,
+            (b"missing".to_vec(), GossipStatus::Missing)
+        );
+
+        // A forked view of the log is reported, but doesn't stop the message from being read.
+        let forked_gossip = test_gossip_service(Some([2; 32]));
+        let msg = encrypt_with_gossip(alice_store, &bob_address, &forked_gossip, "forked").await?;
+        let (ptext, status) =
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?;
+        assert_eq!(ptext, b"forked");
+        let proof = assert_matches!(status, GossipStatus::Inconsistent(proof) => proof);
+        assert_eq!(proof.peer_address(), alice_address.to_string());
+        assert_eq!(proof.local_tree_head().1, [1; 32]);
+        assert_eq!(proof.peer_tree_head().1, [2; 32]);
+
+        let mut untrusting_gossip = test_gossip_service(None);
+        let msg =
+            encrypt_with_gossip(alice_store, &bob_address, &alice_gossip, "unchecked").await?;
+        assert_eq!(
+            decrypt_with_gossip(bob_store, &alice_address, &mut untrusting_gossip, &msg).await?,
+            (b"unchecked".to_vec(), GossipStatus::Unverified)
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_gossip_compact_once_acknowledged() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_device_id = DeviceId::new(1).unwrap();
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), bob_device_id);
+
+        let mut alice_store_builder = TestStoreBuilder::new();
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(IdChoice::Next)
+            .with_signed_pre_key(IdChoice::Next)
+            .with_kyber_pre_key(IdChoice::Next);
+
+        let bob_pre_key_bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
+
+        let alice_store = &mut alice_store_builder.store;
+        let bob_store = &mut bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let mut alice_gossip = test_gossip_service(Some([1; 32]));
+        let mut bob_gossip = test_gossip_service(Some([1; 32]));
+
+        fn gossip_of(msg: &CiphertextMessage) -> GossipMessage {
+            let bytes = match msg {
+                CiphertextMessage::SignalMessage(m) => m.gossip(),
+                CiphertextMessage::PreKeySignalMessage(m) => m.message().gossip(),
+                _ => panic!("unexpected message type"),
+            };
+            GossipMessage::decode(bytes).expect("valid gossip")
+        }
+
+        // Alice doesn't know what Bob has seen yet, so her head goes out in full.
+        let msg = encrypt_with_gossip(alice_store, &bob_address, &alice_gossip, "hello").await?;
+        assert_matches!(gossip_of(&msg), GossipMessage::Full(_));
+        let (_, status) =
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?;
+        assert_eq!(status, GossipStatus::Verified(GossipOutcome::UpToDate));
+
+        // Bob has now seen Alice's head, which is the same as his, so he only names it.
+        let msg = encrypt_with_gossip(bob_store, &alice_address, &bob_gossip, "hi").await?;
+        assert_matches!(gossip_of(&msg), GossipMessage::Compact(_));
+        let (_, status) =
+            decrypt_with_gossip(alice_store, &bob_address, &mut alice_gossip, &msg).await?;
+        assert_eq!(status, GossipStatus::Verified(GossipOutcome::UpToDate));
+
+        // And so does Alice, from then on.
+        let msg = encrypt_with_gossip(alice_store, &bob_address, &alice_gossip, "again").await?;
+        assert_matches!(gossip_of(&msg), GossipMessage::Compact(_));
+        let (_, status) =
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?;
+        assert_eq!(status, GossipStatus::Verified(GossipOutcome::UpToDate));
+
+        // A head that Bob hasn't seen is sent in full again.
+        let forked_gossip = test_gossip_service(Some([2; 32]));
+        let msg = encrypt_with_gossip(alice_store, &bob_address, &forked_gossip, "forked").await?;
+        assert_matches!(gossip_of(&msg), GossipMessage::Full(_));
+        let (_, status) =
+            decrypt_with_gossip(bob_store, &alice_address, &mut bob_gossip, &msg).await?;
+        assert_matches!(status, GossipStatus::Inconsistent(_));
+
+        // Only verified heads count as acknowledged, so Bob still takes Alice to have seen his.
+        let msg = encrypt_with_gossip(bob_store, &alice_address, &bob_gossip, "still").await?;
+        assert_matches!(gossip_of(&msg), GossipMessage::Compact(_));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_session_policy_limits_forward_jumps() -> TestResult {
+    async {
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let (alice_session, bob_session) = initialize_sessions_v4()?;
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+        alice_store
+            .store_session(&bob_address, &alice_session)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session)
+            .await?;
+
+        for _ in 0..11 {
+            encrypt(&mut alice_store, &bob_address, "skipped").await?;
+        }
+        let too_far = encrypt(&mut alice_store, &bob_address, "too far for the policy").await?;
+
+        bob_store
+            .session_store
+            .set_session_policy(SessionPolicy::builder().max_forward_jumps(10).build()?);
+        assert_matches!(
+            decrypt(&mut bob_store, &alice_address, &too_far).await,
+            Err(SignalProtocolError::InvalidMessage(..))
+        );
+
+        // The default policy allows a much larger jump.
+        bob_store
+            .session_store
+            .set_session_policy(SessionPolicy::default());
+        assert_eq!(
+            decrypt(&mut bob_store, &alice_address, &too_far).await?,
+            b"too far for the policy"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_session_policy_limits_archived_sessions() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(1.into())
+            .with_signed_pre_key(1.into())
+            .with_kyber_pre_key(1.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+        let bob_store = &mut bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        let outgoing = encrypt(&mut alice_store, &bob_address, "first session").await?;
+        decrypt(bob_store, &alice_address, &outgoing).await?;
+        let reply = encrypt(bob_store, &alice_address, "reply in the first session").await?;
+
+        // Two fresh sessions push the first one out of an archive that only holds one.
+        let alice_session_store_before = alice_store.session_store.clone();
+        alice_store.session_store.set_session_policy(
+            SessionPolicy::builder()
+                .archived_states_max_length(1)
+                .build()?,
+        );
+        for _ in 0..2 {
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+        }
+        assert_matches!(
+            decrypt(&mut alice_store, &bob_address, &reply).await,
+            Err(SignalProtocolError::InvalidMessage(..))
+        );
+
+        // With the default archive, the first session is still around.
+        alice_store.session_store = alice_session_store_before;
+        assert_eq!(
+            alice_store.session_store.session_policy(),
+            SessionPolicy::default()
+        );
+        for _ in 0..2 {
+            process_prekey_bundle(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+            )
+            .await?;
+        }
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"reply in the first session"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_session_policy_limits_unacknowledged_session_age() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::UNIX_EPOCH,
+            &mut csprng,
+        )
+        .await?;
+        let session = alice_store
+            .session_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session exists");
+
+        // The session is still usable under the default policy...
+        let two_hours_later = SystemTime::UNIX_EPOCH + Duration::from_secs(2 * 60 * 60);
+        assert!(
+            session
+                .has_usable_sender_chain(two_hours_later, SessionUsabilityRequirements::NotStale)?
+        );
+
+        // ...but encrypting follows the policy of the session store.
+        let policy = SessionPolicy::builder()
+            .max_unacknowledged_session_age(Duration::from_secs(60 * 60))
+            .build()?;
+        alice_store.session_store.set_session_policy(policy);
+        assert_matches!(
+            message_encrypt(
+                b"too late",
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                two_hours_later,
+                &mut csprng,
+            )
+            .await,
+            Err(SignalProtocolError::SessionNotFound(_))
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_describe_session_and_messages() -> TestResult {
+    async {
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let (alice_session, bob_session) = initialize_sessions_v4()?;
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+        alice_store
+            .store_session(&bob_address, &alice_session)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session)
+            .await?;
+
+        let mut messages = vec![];
+        for _ in 0..3 {
+            messages.push(encrypt(&mut alice_store, &bob_address, "describe me").await?);
+        }
+        let CiphertextMessage::SignalMessage(last_message) = messages.last().unwrap() else {
+            panic!("expected a SignalMessage");
+        };
+        let message_description = last_message.describe();
+        assert_eq!(message_description.counter, 2);
+        assert_eq!(message_description.gossip_length, 0);
+
+        decrypt(&mut bob_store, &alice_address, &messages[2]).await?;
+
+        let bob_record = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session exists");
+        let description = bob_record.describe()?;
+        assert_eq!(description.archived_state_count, 0);
+        let current = description.current_session.expect("has a current session");
+        assert_eq!(current.session_version, 4);
+        assert_eq!(current.receiver_chains.len(), 1);
+        let receiver_chain = &current.receiver_chains[0];
+        assert_eq!(
+            receiver_chain.ratchet_key,
+            Some(message_description.sender_ratchet_key)
+        );
+        assert_eq!(receiver_chain.chain_index, Some(3));
+        assert_eq!(receiver_chain.skipped_message_keys, 2);
+
+        let json = serde_json::to_value(&current).expect("can serialize");
+        assert_eq!(
+            json["receiver_chains"][0]["ratchet_key"],
+            message_description.sender_ratchet_key.to_string()
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_identity_history_records_sources() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(1.into())
+            .with_signed_pre_key(1.into())
+            .with_kyber_pre_key(1.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+        let mut bob_store = bob_store_builder.store;
+
+        let bundle_time = SystemTime::now() - Duration::from_secs(60);
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            bundle_time,
+            &mut csprng,
+        )
+        .await?;
+
+        let bob_identity = *bob_store.get_identity_key_pair().await?.identity_key();
+        let history = alice_store.identity_history(&bob_address).await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].identity(), &bob_identity);
+        assert_eq!(history[0].first_seen(), bundle_time);
+        assert_eq!(
+            history[0].sources().collect::<Vec<_>>(),
+            [IdentitySource::PreKeyBundle]
+        );
+
+        let message = encrypt(&mut alice_store, &bob_address, "hi Bob").await?;
+        let history = alice_store.identity_history(&bob_address).await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(
+            history[0].sources().collect::<Vec<_>>(),
+            [IdentitySource::Message, IdentitySource::PreKeyBundle]
+        );
+        assert!(history[0].last_seen() > bundle_time);
+
+        decrypt(&mut bob_store, &alice_address, &message).await?;
+        let alice_identity = *alice_store.get_identity_key_pair().await?.identity_key();
+        assert!(
+            bob_store
+                .was_identity_seen(&alice_address, &alice_identity)
+                .await?
+        );
+        assert_eq!(
+            bob_store
+                .identity_history_entry(&alice_address, &alice_identity)
+                .await?
+                .expect("seen")
+                .sources()
+                .collect::<Vec<_>>(),
+            [IdentitySource::Message]
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
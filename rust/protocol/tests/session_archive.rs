@@ -0,0 +1,434 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use std::time::SystemTime;
+
+use assert_matches::assert_matches;
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+use rand::{Rng, TryRngCore as _};
+use support::*;
+use uuid::Uuid;
+
+const DISTRIBUTION_ID: Uuid = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+/// Alice and Bob with a session that both have used, and a sender key Alice has sent with.
+struct Conversation {
+    alice_address: ProtocolAddress,
+    bob_address: ProtocolAddress,
+    alice_store: InMemSignalProtocolStore,
+    bob_store: InMemSignalProtocolStore,
+}
+
+impl Conversation {
+    async fn new() -> Result<Self, SignalProtocolError> {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(1.into())
+            .with_signed_pre_key(1.into())
+            .with_kyber_pre_key(1.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+        let mut bob_store = bob_store_builder.store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        let message = encrypt(&mut alice_store, &bob_address, "hi Bob").await?;
+        decrypt(&mut bob_store, &alice_address, &message).await?;
+        let reply = encrypt(&mut bob_store, &alice_address, "hi Alice").await?;
+        decrypt(&mut alice_store, &bob_address, &reply).await?;
+
+        create_sender_key_distribution_message(
+            &alice_address,
+            DISTRIBUTION_ID,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        group_encrypt(
+            &mut alice_store,
+            &alice_address,
+            DISTRIBUTION_ID,
+            b"hi everyone",
+            &mut csprng,
+        )
+        .await?;
+
+        Ok(Self {
+            alice_address,
+            bob_address,
+            alice_store,
+            bob_store,
+        })
+    }
+
+    async fn export_alice(
+        &mut self,
+        archive_key: &[u8; 32],
+    ) -> Result<Vec<u8>, SignalProtocolError> {
+        SessionArchive::export(
+            archive_key,
+            std::slice::from_ref(&self.bob_address),
+            &[(self.alice_address.clone(), DISTRIBUTION_ID)],
+            &self.alice_store.session_store,
+            &self.alice_store.identity_store,
+            &mut self.alice_store.sender_key_store,
+            SystemTime::now(),
+            &mut OsRng.unwrap_err(),
+        )
+        .await
+    }
+}
+
+fn import(
+    archive: &SessionArchive,
+    store: &mut InMemSignalProtocolStore,
+) -> Result<(), SignalProtocolError> {
+    archive
+        .import(
+            &mut store.session_store,
+            &mut store.identity_store,
+            &mut store.sender_key_store,
+        )
+        .now_or_never()
+        .expect("sync")
+}
+
+#[test]
+fn export_and_import_keeps_sessions_working() -> Result<(), SignalProtocolError> {
+    async {
+        let mut conversation = Conversation::new().await?;
+        let archive_key = OsRng.unwrap_err().random();
+        let archive = conversation.export_alice(&archive_key).await?;
+
+        let archive = SessionArchive::decrypt(&archive_key, &archive)?;
+        assert_eq!(
+            archive.addresses().collect::<Vec<_>>(),
+            [&conversation.bob_address]
+        );
+        assert_eq!(
+            archive.sender_keys().collect::<Vec<_>>(),
+            [(&conversation.alice_address, DISTRIBUTION_ID)]
+        );
+
+        let mut new_alice_store =
+            InMemSignalProtocolStore::new(*archive.identity_key_pair(), archive.registration_id())?;
+        import(&archive, &mut new_alice_store)?;
+
+        // Bob's identity carried over, so there's no safety number change.
+        let bob_identity = conversation
+            .bob_store
+            .identity_store
+            .get_identity_key_pair()
+            .await?;
+        assert_eq!(
+            new_alice_store
+                .identity_store
+                .get_identity(&conversation.bob_address)
+                .await?,
+            Some(*bob_identity.identity_key())
+        );
+
+        // The session continues in both directions without new pre-keys.
+        let message = encrypt(
+            &mut conversation.bob_store,
+            &conversation.alice_address,
+            "welcome to your new device",
+        )
+        .await?;
+        assert_eq!(
+            decrypt(&mut new_alice_store, &conversation.bob_address, &message).await?,
+            b"welcome to your new device"
+        );
+        let reply = encrypt(&mut new_alice_store, &conversation.bob_address, "thanks").await?;
+        assert_eq!(reply.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            decrypt(
+                &mut conversation.bob_store,
+                &conversation.alice_address,
+                &reply
+            )
+            .await?,
+            b"thanks"
+        );
+
+        // And so does the sender key.
+        let group_message = group_encrypt(
+            &mut new_alice_store,
+            &conversation.alice_address,
+            DISTRIBUTION_ID,
+            b"still me",
+            &mut OsRng.unwrap_err(),
+        )
+        .await?;
+        assert_eq!(group_message.iteration(), 1);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn import_refuses_older_state() -> Result<(), SignalProtocolError> {
+    async {
+        let mut conversation = Conversation::new().await?;
+        let archive_key = OsRng.unwrap_err().random();
+        let archive = SessionArchive::decrypt(
+            &archive_key,
+            &conversation.export_alice(&archive_key).await?,
+        )?;
+
+        let mut new_alice_store =
+            InMemSignalProtocolStore::new(*archive.identity_key_pair(), archive.registration_id())?;
+        import(&archive, &mut new_alice_store)?;
+        // Importing the same archive again loses nothing.
+        import(&archive, &mut new_alice_store)?;
+
+        // Once the new device has moved on, the archive is out of date.
+        let message = encrypt(
+            &mut conversation.bob_store,
+            &conversation.alice_address,
+            "moving on",
+        )
+        .await?;
+        decrypt(&mut new_alice_store, &conversation.bob_address, &message).await?;
+        let session_before = new_alice_store
+            .load_session(&conversation.bob_address)
+            .await?
+            .expect("has session")
+            .serialize()?;
+
+        assert_matches!(
+            import(&archive, &mut new_alice_store),
+            Err(SignalProtocolError::StaleSessionArchive(address)) if address == conversation.bob_address
+        );
+        assert_eq!(
+            new_alice_store
+                .load_session(&conversation.bob_address)
+                .await?
+                .expect("has session")
+                .serialize()?,
+            session_before
+        );
+
+        // The same goes for sender keys.
+        let mut new_alice_store =
+            InMemSignalProtocolStore::new(*archive.identity_key_pair(), archive.registration_id())?;
+        import(&archive, &mut new_alice_store)?;
+        group_encrypt(
+            &mut new_alice_store,
+            &conversation.alice_address,
+            DISTRIBUTION_ID,
+            b"moving on",
+            &mut OsRng.unwrap_err(),
+        )
+        .await?;
+        assert_matches!(
+            import(&archive, &mut new_alice_store),
+            Err(SignalProtocolError::StaleSessionArchive(address)) if address == conversation.alice_address
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+/// Fails the first `store_sender_key` call, passing everything else through.
+struct FailingOnceSenderKeyStore<'a> {
+    inner: &'a mut InMemSenderKeyStore,
+    failed: bool,
+}
+
+#[async_trait(?Send)]
+impl SenderKeyStore for FailingOnceSenderKeyStore<'_> {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<(), SignalProtocolError> {
+        if !self.failed {
+            self.failed = true;
+            return Err(SignalProtocolError::InvalidState(
+                "store_sender_key",
+                "simulated failure".to_owned(),
+            ));
+        }
+        self.inner
+            .store_sender_key(sender, distribution_id, record)
+            .await
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>, SignalProtocolError> {
+        self.inner.load_sender_key(sender, distribution_id).await
+    }
+}
+
+#[test]
+fn import_can_be_retried_after_a_failed_write() -> Result<(), SignalProtocolError> {
+    async {
+        let mut conversation = Conversation::new().await?;
+        let archive_key = OsRng.unwrap_err().random();
+        let archive = SessionArchive::decrypt(
+            &archive_key,
+            &conversation.export_alice(&archive_key).await?,
+        )?;
+
+        let mut new_alice_store =
+            InMemSignalProtocolStore::new(*archive.identity_key_pair(), archive.registration_id())?;
+        let mut failing_sender_key_store = FailingOnceSenderKeyStore {
+            inner: &mut new_alice_store.sender_key_store,
+            failed: false,
+        };
+        assert_matches!(
+            archive
+                .import(
+                    &mut new_alice_store.session_store,
+                    &mut new_alice_store.identity_store,
+                    &mut failing_sender_key_store,
+                )
+                .await,
+            Err(SignalProtocolError::InvalidState(..))
+        );
+        // The session made it in, but the sender key didn't.
+        assert!(
+            new_alice_store
+                .load_session(&conversation.bob_address)
+                .await?
+                .is_some()
+        );
+        assert!(
+            new_alice_store
+                .load_sender_key(&conversation.alice_address, DISTRIBUTION_ID)
+                .await?
+                .is_none()
+        );
+
+        import(&archive, &mut new_alice_store)?;
+        let message = encrypt(
+            &mut conversation.bob_store,
+            &conversation.alice_address,
+            "welcome to your new device",
+        )
+        .await?;
+        assert_eq!(
+            decrypt(&mut new_alice_store, &conversation.bob_address, &message).await?,
+            b"welcome to your new device"
+        );
+        let group_message = group_encrypt(
+            &mut new_alice_store,
+            &conversation.alice_address,
+            DISTRIBUTION_ID,
+            b"still me",
+            &mut OsRng.unwrap_err(),
+        )
+        .await?;
+        assert_eq!(group_message.iteration(), 1);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn import_checks_identity() -> Result<(), SignalProtocolError> {
+    async {
+        let mut conversation = Conversation::new().await?;
+        let archive_key = OsRng.unwrap_err().random();
+        let archive = SessionArchive::decrypt(
+            &archive_key,
+            &conversation.export_alice(&archive_key).await?,
+        )?;
+
+        let mut unrelated_store = test_in_memory_protocol_store()?;
+        assert_matches!(
+            import(&archive, &mut unrelated_store),
+            Err(SignalProtocolError::InvalidSessionArchive(_))
+        );
+        assert!(
+            unrelated_store
+                .load_session(&conversation.bob_address)
+                .await?
+                .is_none()
+        );
+
+        // A store that already trusts a different key for Bob is newer than the archive.
+        let mut new_alice_store =
+            InMemSignalProtocolStore::new(*archive.identity_key_pair(), archive.registration_id())?;
+        let other_identity = IdentityKeyPair::generate(&mut OsRng.unwrap_err());
+        new_alice_store
+            .save_identity(&conversation.bob_address, other_identity.identity_key())
+            .await?;
+        assert_matches!(
+            import(&archive, &mut new_alice_store),
+            Err(SignalProtocolError::StaleSessionArchive(_))
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn decrypt_rejects_damaged_archives() -> Result<(), SignalProtocolError> {
+    async {
+        let mut conversation = Conversation::new().await?;
+        let archive_key = OsRng.unwrap_err().random();
+        let archive = conversation.export_alice(&archive_key).await?;
+
+        let wrong_key = OsRng.unwrap_err().random();
+        assert_matches!(
+            SessionArchive::decrypt(&wrong_key, &archive).err(),
+            Some(SignalProtocolError::InvalidSessionArchive(_))
+        );
+
+        for i in [0, 1, archive.len() / 2, archive.len() - 1] {
+            let mut modified = archive.clone();
+            modified[i] ^= 1;
+            assert_matches!(
+                SessionArchive::decrypt(&archive_key, &modified).err(),
+                Some(SignalProtocolError::InvalidSessionArchive(_)),
+                "modified byte {i}"
+            );
+        }
+        assert_matches!(
+            SessionArchive::decrypt(&archive_key, &archive[..archive.len() - 1]).err(),
+            Some(SignalProtocolError::InvalidSessionArchive(_))
+        );
+        assert_matches!(
+            SessionArchive::decrypt(&archive_key, &archive[..10]).err(),
+            Some(SignalProtocolError::InvalidSessionArchive(_))
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -0,0 +1,806 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use assert_matches::assert_matches;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+use rand::{Rng, TryRngCore as _};
+use support::*;
+use uuid::Uuid;
+
+type TestResult = Result<(), SignalProtocolError>;
+
+/// A database file that is deleted when dropped.
+struct TempDatabase(PathBuf);
+
+impl TempDatabase {
+    fn new() -> Self {
+        let name = format!(
+            "libsignal-protocol-test-{:016x}.sqlite",
+            OsRng.unwrap_err().random::<u64>()
+        );
+        Self(std::env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn in_mem_store(key_pair: IdentityKeyPair, registration_id: u32) -> InMemSignalProtocolStore {
+    InMemSignalProtocolStore::new(key_pair, registration_id).expect("can create store")
+}
+
+fn sqlite_store(key_pair: IdentityKeyPair, registration_id: u32) -> SqliteSignalProtocolStore {
+    SqliteSignalProtocolStore::open_in_memory(key_pair, registration_id).expect("can open store")
+}
+
+/// Runs each of the given store checks against both the in-memory and the SQLite stores, so that
+/// the two are held to the same behavior.
+macro_rules! store_tests {
+    ($($check:ident),* $(,)?) => {
+        mod in_mem {
+            $(
+                #[test]
+                fn $check() -> super::TestResult {
+                    super::$check(super::in_mem_store)
+                }
+            )*
+        }
+        mod sqlite {
+            $(
+                #[test]
+                fn $check() -> super::TestResult {
+                    super::$check(super::sqlite_store)
+                }
+            )*
+        }
+    };
+}
+
+store_tests!(
+    identity_keys_are_trusted_on_first_use,
+    identity_history_keeps_every_key,
+    pre_keys_round_trip,
+    signed_pre_keys_round_trip,
+    kyber_pre_keys_round_trip,
+    sessions_are_stored_per_address,
+    sender_keys_are_stored_per_distribution_id,
+    transactions_are_all_or_nothing,
+    pre_key_messages_set_up_sessions,
+    sender_key_messages_round_trip,
+);
+
+fn identity_keys_are_trusted_on_first_use<S: ProtocolStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let key_pair = IdentityKeyPair::generate(&mut csprng);
+        let mut store = new_store(key_pair, 1234);
+        assert_eq!(store.get_local_registration_id().await?, 1234);
+        assert_eq!(
+            store.get_identity_key_pair().await?.serialize(),
+            key_pair.serialize()
+        );
+
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let other_device =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(2).unwrap());
+        let first = *IdentityKeyPair::generate(&mut csprng).identity_key();
+        let second = *IdentityKeyPair::generate(&mut csprng).identity_key();
+
+        assert_eq!(store.get_identity(&address).await?, None);
+        assert!(
+            store
+                .is_trusted_identity(&address, &first, Direction::Sending)
+                .await?
+        );
+
+        assert_eq!(
+            store.save_identity(&address, &first).await?,
+            IdentityChange::NewOrUnchanged
+        );
+        assert_eq!(
+            store.save_identity(&address, &first).await?,
+            IdentityChange::NewOrUnchanged
+        );
+        assert_eq!(store.get_identity(&address).await?, Some(first));
+        assert_eq!(store.get_identity(&other_device).await?, None);
+        assert!(
+            !store
+                .is_trusted_identity(&address, &second, Direction::Receiving)
+                .await?
+        );
+
+        assert_eq!(
+            store.save_identity(&address, &second).await?,
+            IdentityChange::ReplacedExisting
+        );
+        assert_eq!(store.get_identity(&address).await?, Some(second));
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn identity_history_keeps_every_key<S: ProtocolStore + IdentityHistoryStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = new_store(IdentityKeyPair::generate(&mut csprng), 1);
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let other_device =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(2).unwrap());
+        let first = *IdentityKeyPair::generate(&mut csprng).identity_key();
+        let second = *IdentityKeyPair::generate(&mut csprng).identity_key();
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+
+        assert_eq!(store.identity_history(&address).await?, []);
+        assert!(!store.was_identity_seen(&address, &first).await?);
+        assert!(!store.set_identity_verified(&address, &first, true).await?);
+
+        store
+            .observe_identity(&address, &first, IdentitySource::PreKeyBundle, at(100))
+            .await?;
+        store
+            .observe_identity(&address, &first, IdentitySource::Message, at(200))
+            .await?;
+        store
+            .observe_identity(&address, &second, IdentitySource::Message, at(300))
+            .await?;
+        // Out-of-order observations don't move last_seen backwards.
+        store
+            .observe_identity(&address, &first, IdentitySource::KeyTransparency, at(150))
+            .await?;
+
+        assert!(store.was_identity_seen(&address, &first).await?);
+        assert!(store.was_identity_seen(&address, &second).await?);
+        assert!(!store.was_identity_seen(&other_device, &first).await?);
+
+        let history = store.identity_history(&address).await?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].identity(), &first);
+        assert_eq!(history[0].first_seen(), at(100));
+        assert_eq!(history[0].last_seen(), at(200));
+        assert_eq!(
+            history[0].sources().collect::<Vec<_>>(),
+            IdentitySource::ALL
+        );
+        assert_eq!(history[1].identity(), &second);
+        assert_eq!(history[1].first_seen(), at(300));
+        assert_eq!(history[1].last_seen(), at(300));
+        assert_eq!(
+            history[1].sources().collect::<Vec<_>>(),
+            [IdentitySource::Message]
+        );
+        assert!(!history[0].is_verified() && !history[1].is_verified());
+
+        assert!(store.set_identity_verified(&address, &second, true).await?);
+        let entry = store
+            .identity_history_entry(&address, &second)
+            .await?
+            .expect("seen");
+        assert!(entry.is_verified());
+        assert!(
+            !store
+                .identity_history_entry(&address, &first)
+                .await?
+                .expect("seen")
+                .is_verified()
+        );
+
+        // Seeing a verified key again keeps it verified.
+        store
+            .observe_identity(&address, &second, IdentitySource::Message, at(400))
+            .await?;
+        let entry = store
+            .identity_history_entry(&address, &second)
+            .await?
+            .expect("seen");
+        assert!(entry.is_verified());
+        assert_eq!(entry.last_seen(), at(400));
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn pre_keys_round_trip<S: ProtocolStore>(new_store: fn(IdentityKeyPair, u32) -> S) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = new_store(IdentityKeyPair::generate(&mut csprng), 1);
+
+        assert_matches!(
+            store.get_pre_key(1.into()).await,
+            Err(SignalProtocolError::InvalidPreKeyId)
+        );
+
+        let record = PreKeyRecord::new(1.into(), &KeyPair::generate(&mut csprng));
+        store.save_pre_key(1.into(), &record).await?;
+        assert_eq!(
+            store.get_pre_key(1.into()).await?.serialize()?,
+            record.serialize()?
+        );
+
+        store.remove_pre_key(1.into()).await?;
+        assert_matches!(
+            store.get_pre_key(1.into()).await,
+            Err(SignalProtocolError::InvalidPreKeyId)
+        );
+        // Removing a missing key is fine.
+        store.remove_pre_key(1.into()).await?;
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn signed_pre_keys_round_trip<S: ProtocolStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let identity_key = IdentityKeyPair::generate(&mut csprng);
+        let mut store = new_store(identity_key, 1);
+
+        assert_matches!(
+            store.get_signed_pre_key(7.into()).await,
+            Err(SignalProtocolError::InvalidSignedPreKeyId)
+        );
+
+        let key_pair = KeyPair::generate(&mut csprng);
+        let signature = identity_key
+            .private_key()
+            .calculate_signature(&key_pair.public_key.serialize(), &mut csprng)?;
+        let record = SignedPreKeyRecord::new(
+            7.into(),
+            Timestamp::from_epoch_millis(42),
+            &key_pair,
+            &signature,
+        );
+        store.save_signed_pre_key(7.into(), &record).await?;
+        assert_eq!(
+            store.get_signed_pre_key(7.into()).await?.serialize()?,
+            record.serialize()?
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn kyber_pre_keys_round_trip<S: ProtocolStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let identity_key = IdentityKeyPair::generate(&mut csprng);
+        let mut store = new_store(identity_key, 1);
+
+        assert_matches!(
+            store.get_kyber_pre_key(3.into()).await,
+            Err(SignalProtocolError::InvalidKyberPreKeyId)
+        );
+
+        let record = KyberPreKeyRecord::generate(
+            kem::KeyType::Kyber1024,
+            3.into(),
+            identity_key.private_key(),
+        )?;
+        store.save_kyber_pre_key(3.into(), &record).await?;
+        assert_eq!(
+            store.get_kyber_pre_key(3.into()).await?.serialize()?,
+            record.serialize()?
+        );
+
+        let base_key = KeyPair::generate(&mut csprng).public_key;
+        store
+            .mark_kyber_pre_key_used(3.into(), 7.into(), &base_key)
+            .await?;
+        // The same base key with a different signed pre-key is a different combination.
+        store
+            .mark_kyber_pre_key_used(3.into(), 8.into(), &base_key)
+            .await?;
+        assert_matches!(
+            store
+                .mark_kyber_pre_key_used(3.into(), 7.into(), &base_key)
+                .await,
+            Err(SignalProtocolError::ReusedBaseKey(..))
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn sessions_are_stored_per_address<S: ProtocolStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = new_store(IdentityKeyPair::generate(&mut csprng), 1);
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let other_device =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(2).unwrap());
+
+        assert!(store.load_session(&address).await?.is_none());
+
+        let (record, _) = initialize_sessions_v4()?;
+        store.store_session(&address, &record).await?;
+        assert_eq!(
+            store
+                .load_session(&address)
+                .await?
+                .expect("stored")
+                .serialize()?,
+            record.serialize()?
+        );
+        assert!(store.load_session(&other_device).await?.is_none());
+
+        let (replacement, _) = initialize_sessions_v4()?;
+        store.store_session(&address, &replacement).await?;
+        assert_eq!(
+            store
+                .load_session(&address)
+                .await?
+                .expect("stored")
+                .serialize()?,
+            replacement.serialize()?
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn transactions_are_all_or_nothing<S: TransactionalProtocolStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = new_store(IdentityKeyPair::generate(&mut csprng), 1);
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let identity = *IdentityKeyPair::generate(&mut csprng).identity_key();
+        let (record, _) = initialize_sessions_v4()?;
+        let pre_key = PreKeyRecord::new(5.into(), &KeyPair::generate(&mut csprng));
+        store.save_pre_key(5.into(), &pre_key).await?;
+
+        let used_base_key = KeyPair::generate(&mut csprng).public_key;
+        let mut earlier = ProtocolStoreTransaction::new(address.clone());
+        earlier.stage_kyber_pre_key_used(3.into(), 7.into(), used_base_key);
+        store.commit_transaction(earlier).await?;
+
+        let transaction_with_base_key = |base_key| {
+            let mut transaction = ProtocolStoreTransaction::new(address.clone());
+            transaction.stage_identity(identity);
+            transaction.stage_kyber_pre_key_used(3.into(), 7.into(), base_key);
+            transaction.stage_pre_key_removal(5.into());
+            transaction.stage_session(record.clone());
+            transaction
+        };
+
+        // The identity is saved before the Kyber pre-key is checked, and must be rolled back.
+        assert_matches!(
+            store
+                .commit_transaction(transaction_with_base_key(used_base_key))
+                .await,
+            Err(SignalProtocolError::ReusedBaseKey(..))
+        );
+        assert_eq!(store.get_identity(&address).await?, None);
+        assert!(store.load_session(&address).await?.is_none());
+        assert_eq!(
+            store.get_pre_key(5.into()).await?.serialize()?,
+            pre_key.serialize()?
+        );
+
+        let fresh_base_key = KeyPair::generate(&mut csprng).public_key;
+        store
+            .commit_transaction(transaction_with_base_key(fresh_base_key))
+            .await?;
+        assert_eq!(store.get_identity(&address).await?, Some(identity));
+        assert_eq!(
+            store
+                .load_session(&address)
+                .await?
+                .expect("stored")
+                .serialize()?,
+            record.serialize()?
+        );
+        assert_matches!(
+            store.get_pre_key(5.into()).await,
+            Err(SignalProtocolError::InvalidPreKeyId)
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn sender_keys_are_stored_per_distribution_id<S: SenderKeyStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = new_store(IdentityKeyPair::generate(&mut csprng), 1);
+        let sender = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+        let other_distribution_id = Uuid::from_u128(0xd2d2d2d2_7000_11eb_b32a_33b8a8a487a6);
+
+        assert!(
+            store
+                .load_sender_key(&sender, distribution_id)
+                .await?
+                .is_none()
+        );
+
+        create_sender_key_distribution_message(&sender, distribution_id, &mut store, &mut csprng)
+            .await?;
+        let record = store
+            .load_sender_key(&sender, distribution_id)
+            .await?
+            .expect("created");
+        assert!(
+            store
+                .load_sender_key(&sender, other_distribution_id)
+                .await?
+                .is_none()
+        );
+
+        store
+            .store_sender_key(&sender, other_distribution_id, &record)
+            .await?;
+        assert_eq!(
+            store
+                .load_sender_key(&sender, other_distribution_id)
+                .await?
+                .expect("stored")
+                .serialize()?,
+            record.serialize()?
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+async fn send(
+    from: &mut SqliteSignalProtocolStore,
+    to: &ProtocolAddress,
+    message: &str,
+) -> Result<CiphertextMessage, SignalProtocolError> {
+    let mut csprng = OsRng.unwrap_err();
+    message_encrypt(
+        message.as_bytes(),
+        to,
+        &mut from.session_store,
+        &mut from.identity_store,
+        SystemTime::now(),
+        &mut csprng,
+    )
+    .await
+}
+
+async fn receive(
+    to: &mut SqliteSignalProtocolStore,
+    from: &ProtocolAddress,
+    message: &CiphertextMessage,
+) -> Result<String, SignalProtocolError> {
+    let mut csprng = OsRng.unwrap_err();
+    let plaintext = message_decrypt(
+        message,
+        from,
+        &mut to.session_store,
+        &mut to.identity_store,
+        &mut to.pre_key_store,
+        &to.signed_pre_key_store,
+        &mut to.kyber_pre_key_store,
+        &mut csprng,
+    )
+    .await?;
+    Ok(String::from_utf8(plaintext).expect("valid UTF-8"))
+}
+
+#[test]
+fn sessions_survive_reopening_the_database() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let alice_database = TempDatabase::new();
+        let bob_database = TempDatabase::new();
+        let mut alice_store = SqliteSignalProtocolStore::open(
+            &alice_database.0,
+            IdentityKeyPair::generate(&mut csprng),
+            1,
+        )?;
+        let mut bob_store = SqliteSignalProtocolStore::open(
+            &bob_database.0,
+            IdentityKeyPair::generate(&mut csprng),
+            2,
+        )?;
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let message = send(&mut alice_store, &bob_address, "hello").await?;
+        assert_eq!(message.message_type(), CiphertextMessageType::PreKey);
+        assert_eq!(
+            receive(&mut bob_store, &alice_address, &message).await?,
+            "hello"
+        );
+
+        // Both sides restart.
+        drop((alice_store, bob_store));
+        let mut alice_store = SqliteSignalProtocolStore::open_existing(&alice_database.0)?;
+        let mut bob_store = SqliteSignalProtocolStore::open_existing(&bob_database.0)?;
+        assert_eq!(bob_store.get_local_registration_id().await?, 2);
+        assert_eq!(
+            bob_store
+                .session_store
+                .device_ids_with_sessions(alice_address.name())?,
+            vec![alice_address.device_id()]
+        );
+
+        let reply = send(&mut bob_store, &alice_address, "hi there").await?;
+        assert_eq!(reply.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            receive(&mut alice_store, &bob_address, &reply).await?,
+            "hi there"
+        );
+
+        let message = send(&mut alice_store, &bob_address, "still here").await?;
+        assert_eq!(message.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            receive(&mut bob_store, &alice_address, &message).await?,
+            "still here"
+        );
+
+        // Replaying a message is still caught after the restart.
+        assert_matches!(
+            receive(&mut bob_store, &alice_address, &message).await,
+            Err(SignalProtocolError::DuplicatedMessage(..))
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_messages_with_sqlite_sender_key_stores() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let sender = ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(4).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = sqlite_store(IdentityKeyPair::generate(&mut csprng), 1);
+        let mut bob_store = sqlite_store(IdentityKeyPair::generate(&mut csprng), 2);
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &sender,
+            distribution_id,
+            &mut alice_store.sender_key_store,
+            &mut csprng,
+        )
+        .await?;
+        let recv_distribution_message =
+            SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+        process_sender_key_distribution_message(
+            &sender,
+            &recv_distribution_message,
+            &mut bob_store.sender_key_store,
+        )
+        .await?;
+
+        let ciphertext = group_encrypt(
+            &mut alice_store.sender_key_store,
+            &sender,
+            distribution_id,
+            "space camp?".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+        let plaintext = group_decrypt(
+            ciphertext.serialized(),
+            &mut bob_store.sender_key_store,
+            &sender,
+        )
+        .await?;
+        assert_eq!(plaintext, b"space camp?");
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn database_is_tied_to_one_identity() -> TestResult {
+    let mut csprng = OsRng.unwrap_err();
+    let database = TempDatabase::new();
+    let key_pair = IdentityKeyPair::generate(&mut csprng);
+
+    assert_matches!(
+        SqliteSignalProtocolStore::open_existing(&database.0).err(),
+        Some(SignalProtocolError::InvalidState("open_existing", _))
+    );
+
+    let store = SqliteSignalProtocolStore::open(&database.0, key_pair, 1)?;
+    assert_eq!(store.schema_version()?, 2);
+    drop(store);
+
+    // Reopening with the same identity is fine...
+    drop(SqliteSignalProtocolStore::open(&database.0, key_pair, 1)?);
+    // ...but not with a different one.
+    assert_matches!(
+        SqliteSignalProtocolStore::open(&database.0, IdentityKeyPair::generate(&mut csprng), 1)
+            .err(),
+        Some(SignalProtocolError::InvalidState("open", _))
+    );
+    assert_matches!(
+        SqliteSignalProtocolStore::open(&database.0, key_pair, 2).err(),
+        Some(SignalProtocolError::InvalidState("open", _))
+    );
+    Ok(())
+}
+
+fn pre_key_messages_set_up_sessions<S: TransactionalProtocolStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = new_store(IdentityKeyPair::generate(&mut csprng), 2);
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+
+        let messages = [
+            encrypt(&mut alice_store, &bob_address, "first").await?,
+            encrypt(&mut alice_store, &bob_address, "second").await?,
+            encrypt(&mut alice_store, &bob_address, "third").await?,
+        ];
+        // Out of order, to leave a skipped message key in the stored session.
+        for (index, expected) in [(0, "first"), (2, "third"), (1, "second")] {
+            let ptext = message_decrypt_transactional(
+                &messages[index],
+                &alice_address,
+                &mut bob_store,
+                &mut csprng,
+            )
+            .await?;
+            assert_eq!(ptext, expected.as_bytes());
+        }
+        assert_matches!(
+            message_decrypt_transactional(
+                &messages[0],
+                &alice_address,
+                &mut bob_store,
+                &mut csprng
+            )
+            .await,
+            Err(SignalProtocolError::DuplicatedMessage(..))
+        );
+
+        let pre_key_id = bob_pre_key_bundle
+            .pre_key_id()?
+            .expect("has a one-time pre-key");
+        assert_matches!(
+            bob_store.get_pre_key(pre_key_id).await,
+            Err(SignalProtocolError::InvalidPreKeyId)
+        );
+        assert_eq!(
+            bob_store.get_identity(&alice_address).await?,
+            Some(*alice_store.get_identity_key_pair().await?.identity_key())
+        );
+
+        let reply = message_encrypt_transactional(
+            b"reply",
+            &alice_address,
+            &mut bob_store,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(reply.message_type(), CiphertextMessageType::Whisper);
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply).await?,
+            b"reply"
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+fn sender_key_messages_round_trip<S: SenderKeyStore>(
+    new_store: fn(IdentityKeyPair, u32) -> S,
+) -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+        let mut alice_store = new_store(IdentityKeyPair::generate(&mut csprng), 1);
+        let mut bob_store = new_store(IdentityKeyPair::generate(&mut csprng), 2);
+
+        let distribution_message = create_sender_key_distribution_message(
+            &alice_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &alice_address,
+            &distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let mut messages = vec![];
+        for ptext in ["first", "second", "third"] {
+            messages.push(
+                group_encrypt(
+                    &mut alice_store,
+                    &alice_address,
+                    distribution_id,
+                    ptext.as_bytes(),
+                    &mut csprng,
+                )
+                .await?,
+            );
+        }
+        // Out of order, to leave a skipped message key in the stored record.
+        for (index, expected) in [(2, "third"), (0, "first"), (1, "second")] {
+            let ptext =
+                group_decrypt(messages[index].serialized(), &mut bob_store, &alice_address).await?;
+            assert_eq!(ptext, expected.as_bytes());
+        }
+        assert_matches!(
+            group_decrypt(messages[0].serialized(), &mut bob_store, &alice_address).await,
+            Err(SignalProtocolError::DuplicatedMessage(..))
+        );
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -0,0 +1,619 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! A deterministic, in-process simulation of key transparency gossip spreading between clients.
+//!
+//! Each simulated client has its own [`InMemSignalProtocolStore`] and [`GossipService`], and
+//! exchanges messages encrypted with [`message_encrypt_with_gossip`] with its contacts in a random
+//! communication graph. Each client periodically runs a monitor round against a [`FakeKtLog`],
+//! which makes the fetched head its new distinguished head, so clients that last fetched at
+//! different times gossip from different distinguished heads. The log can be told to start
+//! equivocating, showing a subset of the clients a different history than the rest. The
+//! simulation reports how many rounds pass before some client notices.
+//!
+//! Everything random is drawn from a single seeded RNG, and time only advances between rounds, so
+//! a given [`SimulationConfig`] always produces the same [`SimulationReport`].
+
+use std::time::{Duration, SystemTime};
+
+use libsignal_gossip::{Gossip, GossipOutcome, GossipService, InMemGossipStore};
+use libsignal_keytrans::{FullTreeHead, LastTreeHead, TreeHead, TreeRoot};
+use libsignal_protocol::*;
+use rand::seq::IndexedRandom as _;
+use rand::{Rng, SeedableRng as _};
+use rand_chacha::ChaCha8Rng;
+use sha2::{Digest, Sha256};
+
+/// When the first round of a simulation takes place, relative to the Unix epoch.
+const SIMULATION_START: Duration = Duration::from_secs(1_700_000_000);
+/// How much simulated time passes between rounds.
+const ROUND_DURATION: Duration = Duration::from_secs(60 * 60);
+
+fn simulated_time(round: usize) -> SystemTime {
+    SystemTime::UNIX_EPOCH
+        + SIMULATION_START
+        + ROUND_DURATION * u32::try_from(round).expect("not that many rounds")
+}
+
+/// Which history a [`FakeKtLog`] shows a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogView {
+    Honest,
+    Forked,
+}
+
+/// A key transparency log that can show different clients different histories.
+///
+/// Leaves are derived from their position, so the log only needs to remember where the forked
+/// view diverged. Both views agree on every leaf before that point, so heads published before the
+/// log started equivocating are valid in both.
+///
+/// Roots and consistency proofs are computed the same way as for a real log, so clients verify
+/// the heads it publishes exactly as they would a real log's.
+pub struct FakeKtLog {
+    size: u64,
+    fork_size: Option<u64>,
+    initial: LastTreeHead,
+    published: [LastTreeHead; 2],
+}
+
+impl FakeKtLog {
+    /// Creates a log with `initial_size` entries, whose head at that size is the distinguished
+    /// head all clients start from.
+    pub fn new(initial_size: u64, now: SystemTime) -> Self {
+        assert!(
+            initial_size > 0,
+            "the log must start with at least one entry"
+        );
+        let head = head_at(
+            initial_size,
+            root_of(|i| leaf(LogView::Honest, i), initial_size),
+            now,
+        );
+        Self {
+            size: initial_size,
+            fork_size: None,
+            initial: head.clone(),
+            published: [head.clone(), head],
+        }
+    }
+
+    /// Shows [`LogView::Forked`] clients different entries from those appended next onwards.
+    pub fn equivocate(&mut self) {
+        self.fork_size.get_or_insert(self.size);
+    }
+
+    /// Appends `count` entries to both views, and publishes heads for them timestamped `now`.
+    pub fn append(&mut self, count: u64, now: SystemTime) {
+        self.size += count;
+        for view in [LogView::Honest, LogView::Forked] {
+            let root = self.root(view, self.size);
+            self.published[view as usize] = head_at(self.size, root, now);
+        }
+    }
+
+    /// The head the log was created with, which both views share.
+    pub fn initial_head(&self) -> &LastTreeHead {
+        &self.initial
+    }
+
+    /// The most recently published head in `view`.
+    pub fn head(&self, view: LogView) -> &LastTreeHead {
+        &self.published[view as usize]
+    }
+
+    /// The most recently published head in `view`, encoded as gossip from the log itself, with
+    /// the consistency proof from the client's distinguished head that a monitor response would
+    /// carry.
+    pub fn monitor(&self, view: LogView, distinguished_size: u64, now: SystemTime) -> Vec<u8> {
+        let (tree_head, tree_root) = self.head(view);
+        let full_tree_head = FullTreeHead {
+            tree_head: Some(tree_head.clone()),
+            distinguished: self.consistency_proof(view, distinguished_size, self.size),
+            ..Default::default()
+        };
+        Gossip::new(full_tree_head, *tree_root, now)
+            .with_distinguished_tree_size(distinguished_size)
+            .encode()
+            .expect("valid gossip")
+    }
+
+    fn leaf(&self, view: LogView, index: u64) -> [u8; 32] {
+        match self.fork_size {
+            Some(fork_size) if index >= fork_size => leaf(view, index),
+            _ => leaf(LogView::Honest, index),
+        }
+    }
+
+    fn root(&self, view: LogView, size: u64) -> TreeRoot {
+        root_of(|i| self.leaf(view, i), size)
+    }
+
+    /// A consistency proof from the head of size `from` to the head of size `to`, in the order
+    /// key transparency's log verification expects.
+    fn consistency_proof(&self, view: LogView, from: u64, to: u64) -> Vec<Vec<u8>> {
+        let mut proof = vec![];
+        if from < to {
+            self.sub_proof(view, 0, from, to, true, &mut proof);
+        }
+        proof
+    }
+
+    // RFC 6962's SUBPROOF, over the `n` leaves starting at `start`.
+    fn sub_proof(
+        &self,
+        view: LogView,
+        start: u64,
+        m: u64,
+        n: u64,
+        complete: bool,
+        proof: &mut Vec<Vec<u8>>,
+    ) {
+        let leaf = |i| self.leaf(view, i);
+        if m == n {
+            if !complete {
+                proof.push(subtree(&leaf, start, start + m).1.to_vec());
+            }
+            return;
+        }
+        let k = largest_power_of_two_below(n);
+        if m <= k {
+            self.sub_proof(view, start, m, k, complete, proof);
+            proof.push(subtree(&leaf, start + k, start + n).1.to_vec());
+        } else {
+            proof.push(subtree(&leaf, start, start + k).1.to_vec());
+            self.sub_proof(view, start + k, m - k, n - k, false, proof);
+        }
+    }
+}
+
+fn head_at(tree_size: u64, root: TreeRoot, timestamp: SystemTime) -> LastTreeHead {
+    let timestamp = timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("valid simulated time")
+        .as_millis()
+        .try_into()
+        .expect("valid timestamp");
+    let mut tree_head = TreeHead {
+        tree_size,
+        timestamp,
+        signatures: vec![],
+    };
+    libsignal_gossip::gossip_test::sign_test_tree_head(&mut tree_head, &root);
+    (tree_head, root)
+}
+
+fn leaf(view: LogView, index: u64) -> [u8; 32] {
+    let label: &[u8] = match view {
+        LogView::Honest => b"honest",
+        LogView::Forked => b"forked",
+    };
+    Sha256::new()
+        .chain_update(label)
+        .chain_update(index.to_be_bytes())
+        .finalize()
+        .into()
+}
+
+fn root_of(leaf: impl Fn(u64) -> [u8; 32], size: u64) -> TreeRoot {
+    subtree(&leaf, 0, size).1
+}
+
+fn largest_power_of_two_below(n: u64) -> u64 {
+    1 << (n - 1).ilog2()
+}
+
+/// Hashes leaves `start..end` the way the key transparency log tree does, returning whether the
+/// node is an interior one along with its hash.
+fn subtree(leaf: &impl Fn(u64) -> [u8; 32], start: u64, end: u64) -> (bool, [u8; 32]) {
+    if end - start == 1 {
+        return (false, leaf(start));
+    }
+    let split = start + largest_power_of_two_below(end - start);
+    let (left_interior, left) = subtree(leaf, start, split);
+    let (right_interior, right) = subtree(leaf, split, end);
+    let hash = Sha256::new()
+        .chain_update([u8::from(left_interior)])
+        .chain_update(left)
+        .chain_update([u8::from(right_interior)])
+        .chain_update(right)
+        .finalize()
+        .into();
+    (true, hash)
+}
+
+/// When, and against whom, the [`FakeKtLog`] starts equivocating.
+#[derive(Clone, Debug)]
+pub struct Fork {
+    /// The round in which the log first appends entries that differ between its views.
+    pub round: usize,
+    /// How many clients, chosen at random, are shown the forked view.
+    pub victims: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// Seeds every random choice made by the simulation.
+    pub seed: u64,
+    pub clients: usize,
+    /// How many random contacts each client picks; contacts are mutual, so most clients end up
+    /// with more.
+    pub contacts_per_client: usize,
+    /// How many messages each client sends per round, each to a random contact.
+    pub messages_per_round: usize,
+    /// The chance that a message carries gossip, between 0 and 1.
+    pub gossip_probability: f64,
+    /// How many rounds pass between a client's fetches from the log; clients are spread out
+    /// evenly over the interval.
+    pub fetch_interval: usize,
+    /// How many entries the log appends per round.
+    pub entries_per_round: u64,
+    pub fork: Option<Fork>,
+    /// The simulation stops after this many rounds, or at the end of the first round in which a
+    /// fork is detected.
+    pub max_rounds: usize,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            clients: 20,
+            contacts_per_client: 3,
+            messages_per_round: 2,
+            gossip_probability: 1.0,
+            fetch_interval: 4,
+            entries_per_round: 3,
+            fork: None,
+            max_rounds: 20,
+        }
+    }
+}
+
+/// A client noticing that a peer's tree head is inconsistent with its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Detection {
+    pub round: usize,
+    pub client: ProtocolAddress,
+    /// The peer whose gossip gave the fork away; this is the log itself if the client noticed
+    /// while fetching a head.
+    pub peer: ProtocolAddress,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub rounds: usize,
+    pub fork_round: Option<usize>,
+    pub first_detection: Option<Detection>,
+    /// How many times a client was sent an inconsistent head, over all rounds run.
+    pub detections: usize,
+    pub messages: usize,
+    pub messages_with_gossip: usize,
+    /// The size of each client's distinguished tree head when the simulation stopped.
+    pub distinguished_tree_sizes: Vec<u64>,
+}
+
+impl SimulationReport {
+    /// How many rounds it took to detect the fork, counting the round the log started
+    /// equivocating in as the first.
+    ///
+    /// Returns `None` if there was no fork, or it went undetected.
+    pub fn rounds_to_detection(&self) -> Option<usize> {
+        let fork_round = self.fork_round?;
+        let detection = self.first_detection.as_ref()?;
+        Some(detection.round + 1 - fork_round)
+    }
+}
+
+struct Client {
+    address: ProtocolAddress,
+    store: InMemSignalProtocolStore,
+    gossip: GossipService,
+    view: LogView,
+    contacts: Vec<usize>,
+}
+
+/// Runs a simulation to completion.
+pub async fn simulate(config: &SimulationConfig) -> Result<SimulationReport, SignalProtocolError> {
+    Simulation::new(config).await?.run().await
+}
+
+struct Simulation<'a> {
+    config: &'a SimulationConfig,
+    rng: ChaCha8Rng,
+    log: FakeKtLog,
+    log_address: ProtocolAddress,
+    clients: Vec<Client>,
+    report: SimulationReport,
+}
+
+impl<'a> Simulation<'a> {
+    async fn new(config: &'a SimulationConfig) -> Result<Self, SignalProtocolError> {
+        assert!(config.clients >= 2, "need at least two clients to gossip");
+        assert!(config.fetch_interval > 0, "clients must fetch from the log");
+        assert!(
+            config.entries_per_round > 0,
+            "the log must grow every round"
+        );
+
+        let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+        let log = FakeKtLog::new(16, simulated_time(0));
+
+        let mut clients = Vec::with_capacity(config.clients);
+        for i in 0..config.clients {
+            let identity_key = IdentityKeyPair::generate(&mut rng);
+            // Valid registration IDs fit in 14 bits.
+            let registration_id = rng.random_range(1..0x4000);
+            let mut gossip = GossipService::new(
+                libsignal_gossip::gossip_test::create_test_key_transparency(),
+                Box::new(InMemGossipStore::new()),
+            )
+            .await
+            .expect("in-memory store cannot fail");
+            gossip
+                .set_distinguished_tree_head(log.initial_head().clone())
+                .await
+                .expect("in-memory store cannot fail");
+            clients.push(Client {
+                address: ProtocolAddress::new(format!("client-{i}"), DeviceId::new(1).unwrap()),
+                store: InMemSignalProtocolStore::new(identity_key, registration_id)?,
+                gossip,
+                view: LogView::Honest,
+                contacts: vec![],
+            });
+        }
+
+        if let Some(fork) = &config.fork {
+            assert!(fork.victims <= config.clients, "too many victims");
+            let victims = rand::seq::index::sample(&mut rng, config.clients, fork.victims);
+            for victim in victims {
+                clients[victim].view = LogView::Forked;
+            }
+        }
+
+        let mut simulation = Self {
+            config,
+            rng,
+            log,
+            log_address: ProtocolAddress::new("kt-log".to_owned(), DeviceId::new(1).unwrap()),
+            clients,
+            report: SimulationReport {
+                rounds: 0,
+                fork_round: config.fork.as_ref().map(|fork| fork.round),
+                first_detection: None,
+                detections: 0,
+                messages: 0,
+                messages_with_gossip: 0,
+                distinguished_tree_sizes: vec![],
+            },
+        };
+        simulation.connect_contacts().await?;
+        Ok(simulation)
+    }
+
+    /// Builds the random communication graph, setting up a session along each edge.
+    async fn connect_contacts(&mut self) -> Result<(), SignalProtocolError> {
+        for i in 0..self.clients.len() {
+            for _ in 0..self.config.contacts_per_client {
+                let j = self.rng.random_range(0..self.clients.len() - 1);
+                // Skip over ourselves.
+                let j = if j >= i { j + 1 } else { j };
+                if self.clients[i].contacts.contains(&j) {
+                    continue;
+                }
+                self.clients[i].contacts.push(j);
+                self.clients[j].contacts.push(i);
+                self.start_session(i, j).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn start_session(&mut self, from: usize, to: usize) -> Result<(), SignalProtocolError> {
+        let now = simulated_time(0);
+        let (sender, recipient) = pair_mut(&mut self.clients, from, to);
+        let bundle = super::create_pre_key_bundle(&mut recipient.store, &mut self.rng).await?;
+        process_prekey_bundle(
+            &recipient.address,
+            &mut sender.store.session_store,
+            &mut sender.store.identity_store,
+            &bundle,
+            now,
+            &mut self.rng,
+        )
+        .await?;
+        // Send a first message so that both sides have a session.
+        let message = message_encrypt(
+            b"hello",
+            &recipient.address,
+            &mut sender.store.session_store,
+            &mut sender.store.identity_store,
+            now,
+            &mut self.rng,
+        )
+        .await?;
+        message_decrypt(
+            &message,
+            &sender.address,
+            &mut recipient.store.session_store,
+            &mut recipient.store.identity_store,
+            &mut recipient.store.pre_key_store,
+            &recipient.store.signed_pre_key_store,
+            &mut recipient.store.kyber_pre_key_store,
+            &mut self.rng,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn run(mut self) -> Result<SimulationReport, SignalProtocolError> {
+        for round in 0..self.config.max_rounds {
+            self.run_round(round).await?;
+            self.report.rounds = round + 1;
+            if self.report.first_detection.is_some() {
+                break;
+            }
+        }
+        self.report.distinguished_tree_sizes = self
+            .clients
+            .iter()
+            .map(|client| distinguished_tree_size(&client.gossip))
+            .collect();
+        Ok(self.report)
+    }
+
+    async fn run_round(&mut self, round: usize) -> Result<(), SignalProtocolError> {
+        let now = simulated_time(round);
+
+        if self.report.fork_round == Some(round) {
+            self.log.equivocate();
+        }
+        self.log.append(self.config.entries_per_round, now);
+
+        for i in 0..self.clients.len() {
+            if (round + i) % self.config.fetch_interval != 0 {
+                continue;
+            }
+            let client = &mut self.clients[i];
+            let gossip =
+                self.log
+                    .monitor(client.view, distinguished_tree_size(&client.gossip), now);
+            let status =
+                process_incoming_gossip(&mut client.gossip, &self.log_address, &gossip, now)
+                    .await?;
+            if !matches!(status, GossipStatus::Inconsistent(_)) {
+                // As after a successful monitor round, the fetched head is now distinguished.
+                client
+                    .gossip
+                    .set_distinguished_tree_head(self.log.head(client.view).clone())
+                    .await
+                    .expect("in-memory store cannot fail");
+            }
+            let detector = client.address.clone();
+            self.record(round, detector, self.log_address.clone(), &status);
+        }
+
+        for i in 0..self.clients.len() {
+            for _ in 0..self.config.messages_per_round {
+                let Some(&j) = self.clients[i].contacts.choose(&mut self.rng) else {
+                    break;
+                };
+                let with_gossip = self.rng.random_bool(self.config.gossip_probability);
+                self.send_message(round, i, j, with_gossip, now).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_message(
+        &mut self,
+        round: usize,
+        from: usize,
+        to: usize,
+        with_gossip: bool,
+        now: SystemTime,
+    ) -> Result<(), SignalProtocolError> {
+        let (sender, recipient) = pair_mut(&mut self.clients, from, to);
+        let message = if with_gossip {
+            message_encrypt_with_gossip(
+                b"ping",
+                &recipient.address,
+                &mut sender.store.session_store,
+                &mut sender.store.identity_store,
+                &sender.gossip,
+                now,
+                &mut self.rng,
+            )
+            .await?
+        } else {
+            message_encrypt(
+                b"ping",
+                &recipient.address,
+                &mut sender.store.session_store,
+                &mut sender.store.identity_store,
+                now,
+                &mut self.rng,
+            )
+            .await?
+        };
+        let (_, status) = message_decrypt_with_gossip(
+            &message,
+            &sender.address,
+            &mut recipient.store.session_store,
+            &mut recipient.store.identity_store,
+            &mut recipient.store.pre_key_store,
+            &recipient.store.signed_pre_key_store,
+            &mut recipient.store.kyber_pre_key_store,
+            &mut recipient.gossip,
+            now,
+            &mut self.rng,
+        )
+        .await?;
+
+        // A recipient that is ahead answers with its own head, as a client would on its reply.
+        let catch_up_status = match &status {
+            GossipStatus::Verified(GossipOutcome::PeerBehind { catch_up }) => Some(
+                process_incoming_gossip(&mut sender.gossip, &recipient.address, catch_up, now)
+                    .await?,
+            ),
+            _ => None,
+        };
+
+        let (sender_address, recipient_address) =
+            (sender.address.clone(), recipient.address.clone());
+        self.report.messages += 1;
+        if with_gossip {
+            self.report.messages_with_gossip += 1;
+        }
+        self.record(
+            round,
+            recipient_address.clone(),
+            sender_address.clone(),
+            &status,
+        );
+        if let Some(status) = catch_up_status {
+            self.record(round, sender_address, recipient_address, &status);
+        }
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        round: usize,
+        client: ProtocolAddress,
+        peer: ProtocolAddress,
+        status: &GossipStatus,
+    ) {
+        if !matches!(status, GossipStatus::Inconsistent(_)) {
+            return;
+        }
+        self.report.detections += 1;
+        self.report.first_detection.get_or_insert(Detection {
+            round,
+            client,
+            peer,
+        });
+    }
+}
+
+fn distinguished_tree_size(gossip: &GossipService) -> u64 {
+    gossip
+        .state()
+        .last_distinguished_tree_head()
+        .expect("clients start with a distinguished head")
+        .0
+        .tree_size
+}
+
+fn pair_mut<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert_ne!(a, b);
+    if a < b {
+        let (left, right) = items.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = items.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
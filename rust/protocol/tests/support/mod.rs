@@ -7,10 +7,14 @@
 // APIs will always be considered dead code.
 #![allow(dead_code)]
 
+pub mod gossip_simulation;
+
 use std::ops::RangeFrom;
 use std::time::SystemTime;
 
 use futures_util::FutureExt;
+use libsignal_gossip::{GossipService, GossipStore, InMemGossipStore, KtState};
+use libsignal_keytrans::TreeHead;
 use libsignal_protocol::*;
 use rand::rngs::OsRng;
 use rand::{CryptoRng, Rng, TryRngCore as _};
@@ -64,6 +68,82 @@ pub async fn decrypt(
     .await
 }
 
+pub async fn encrypt_with_gossip(
+    store: &mut InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    gossip_service: &GossipService,
+    msg: &str,
+) -> Result<CiphertextMessage, SignalProtocolError> {
+    let mut csprng = OsRng.unwrap_err();
+    message_encrypt_with_gossip(
+        msg.as_bytes(),
+        remote_address,
+        &mut store.session_store,
+        &mut store.identity_store,
+        gossip_service,
+        SystemTime::now(),
+        &mut csprng,
+    )
+    .await
+}
+
+pub async fn decrypt_with_gossip(
+    store: &mut InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    gossip_service: &mut GossipService,
+    msg: &CiphertextMessage,
+) -> Result<(Vec<u8>, GossipStatus), SignalProtocolError> {
+    let mut csprng = OsRng.unwrap_err();
+    message_decrypt_with_gossip(
+        msg,
+        remote_address,
+        &mut store.session_store,
+        &mut store.identity_store,
+        &mut store.pre_key_store,
+        &store.signed_pre_key_store,
+        &mut store.kyber_pre_key_store,
+        gossip_service,
+        SystemTime::now(),
+        &mut csprng,
+    )
+    .await
+}
+
+/// Creates a [`GossipService`] whose trusted (and distinguished) tree head has the given root, or
+/// which trusts nothing yet if `trusted_root` is `None`.
+pub fn test_gossip_service(trusted_root: Option<[u8; 32]>) -> GossipService {
+    let mut state = KtState::empty();
+    if let Some(root) = trusted_root {
+        let mut head = TreeHead {
+            tree_size: 10,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("valid system time")
+                .as_millis()
+                .try_into()
+                .expect("valid timestamp"),
+            signatures: vec![],
+        };
+        libsignal_gossip::gossip_test::sign_test_tree_head(&mut head, &root);
+        state.set_last_tree_head((head.clone(), root));
+        state.set_last_distinguished_tree_head((head, root));
+    }
+
+    let mut store = InMemGossipStore::new();
+    store
+        .save_state(&state)
+        .now_or_never()
+        .expect("sync")
+        .expect("can save");
+    GossipService::new(
+        libsignal_gossip::gossip_test::create_test_key_transparency(),
+        Box::new(store),
+    )
+    .now_or_never()
+    .expect("sync")
+    .expect("can load")
+}
+
 pub async fn create_pre_key_bundle<R: Rng + CryptoRng>(
     store: &mut dyn ProtocolStore,
     mut csprng: &mut R,
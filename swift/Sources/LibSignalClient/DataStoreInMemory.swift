@@ -161,3 +161,17 @@ open class InMemorySignalProtocolStore: IdentityKeyStore, PreKeyStore, SignedPre
         return self.senderKeyMap[SenderKeyName(sender: sender, distributionId: distributionId)]
     }
 }
+
+open class InMemoryGossipStore: GossipStore {
+    private var state: KtState?
+
+    public init() {}
+
+    open func loadGossipState(context: StoreContext) throws -> KtState? {
+        return self.state
+    }
+
+    open func saveGossipState(_ state: KtState, context: StoreContext) throws {
+        self.state = state
+    }
+}
@@ -74,3 +74,11 @@ public protocol SenderKeyStore: AnyObject {
         context: StoreContext
     ) throws -> SenderKeyRecord?
 }
+
+/// Persists the ``KtState`` used to gossip key transparency tree heads with peers.
+public protocol GossipStore: AnyObject {
+    /// Returns the stored state, or `nil` if none has been saved yet.
+    func loadGossipState(context: StoreContext) throws -> KtState?
+    /// Replaces the stored state. It is only saved when it changes.
+    func saveGossipState(_ state: KtState, context: StoreContext) throws
+}
@@ -401,3 +401,48 @@ internal func withSenderKeyStore<Result>(
         }
     }
 }
+
+internal func withGossipStore<Result>(
+    _ store: GossipStore,
+    _ context: StoreContext,
+    _ body: (SignalConstPointerFfiGossipStoreStruct) throws -> Result
+) rethrows -> Result {
+    func ffiShimLoadGossipState(
+        storeCtx: UnsafeMutableRawPointer?,
+        statep: UnsafeMutablePointer<SignalMutPointerKtState>?
+    ) -> Int32 {
+        let storeContext = storeCtx!.assumingMemoryBound(to: ErrorHandlingContext<(GossipStore, StoreContext)>.self)
+        return storeContext.pointee.catchCallbackErrors { store, context in
+            if var state = try store.loadGossipState(context: context) {
+                statep!.pointee = try cloneOrTakeHandle(from: &state)
+            } else {
+                statep!.pointee = SignalMutPointerKtState()
+            }
+            return 0
+        }
+    }
+
+    func ffiShimSaveGossipState(
+        storeCtx: UnsafeMutableRawPointer?,
+        state: SignalConstPointerKtState
+    ) -> Int32 {
+        let storeContext = storeCtx!.assumingMemoryBound(to: ErrorHandlingContext<(GossipStore, StoreContext)>.self)
+        return storeContext.pointee.catchCallbackErrors { store, context in
+            var state = KtState(borrowing: state)
+            defer { cloneOrForgetAsNeeded(&state) }
+            try store.saveGossipState(state, context: context)
+            return 0
+        }
+    }
+
+    return try rethrowCallbackErrors((store, context)) {
+        var ffiStore = SignalGossipStore(
+            ctx: $0,
+            load_state: ffiShimLoadGossipState,
+            save_state: ffiShimSaveGossipState
+        )
+        return try withUnsafePointer(to: &ffiStore) {
+            try body(SignalConstPointerFfiGossipStoreStruct(raw: $0))
+        }
+    }
+}
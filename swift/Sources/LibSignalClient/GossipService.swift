@@ -0,0 +1,250 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import Foundation
+import SignalFfi
+
+/// Outcome of checking key transparency gossip received from a peer.
+public class GossipStatus: ClonableHandleOwner<SignalMutPointerGossipStatus> {
+    public enum Kind: UInt8, Sendable {
+        // This needs to be kept in sync with the Rust version of the enum.
+
+        /// The peer's tree head is newer than ours, and has been adopted.
+        case advanced = 0
+        /// The peer's tree head is the same as ours.
+        case upToDate = 1
+        /// The peer is behind us; see ``GossipStatus/catchUp``.
+        case peerBehind = 2
+        /// The peer's tree head is inconsistent with ours; see ``GossipStatus/equivocationProof``.
+        case inconsistent = 3
+        /// The gossip could not be parsed, or its tree head wasn't signed by the service.
+        case invalid = 4
+        /// The gossip was too old or too far in the future, and was not adopted.
+        case stale = 5
+        /// The gossip could not be checked against our distinguished tree head.
+        case unverified = 6
+        /// There was no gossip.
+        case missing = 7
+        /// The gossip was checked, but our updated tree head could not be saved.
+        case notSaved = 8
+    }
+
+    override internal class func destroyNativeHandle(
+        _ handle: NonNull<SignalMutPointerGossipStatus>
+    ) -> SignalFfiErrorRef? {
+        return signal_gossip_status_destroy(handle.pointer)
+    }
+
+    override internal class func cloneNativeHandle(
+        _ newHandle: inout SignalMutPointerGossipStatus,
+        currentHandle: SignalConstPointerGossipStatus
+    ) -> SignalFfiErrorRef? {
+        return signal_gossip_status_clone(&newHandle, currentHandle)
+    }
+
+    public var kind: Kind {
+        let rawValue = withNativeHandle { nativeHandle in
+            failOnError {
+                try invokeFnReturningInteger {
+                    signal_gossip_status_get_kind($0, nativeHandle.const())
+                }
+            }
+        }
+        return Kind(rawValue: rawValue)!
+    }
+
+    /// Gossip to send back to a peer that is behind, if the kind is ``Kind/peerBehind``.
+    public var catchUp: Data? {
+        let catchUp = withNativeHandle { nativeHandle in
+            failOnError {
+                try invokeFnReturningData {
+                    signal_gossip_status_get_catch_up($0, nativeHandle.const())
+                }
+            }
+        }
+        return catchUp.isEmpty ? nil : catchUp
+    }
+
+    /// Evidence that the service showed the peer a tree inconsistent with ours, if the kind is
+    /// ``Kind/inconsistent``.
+    ///
+    /// It can be reported to an auditor or shown to the user.
+    public var equivocationProof: Data? {
+        let proof = withNativeHandle { nativeHandle in
+            failOnError {
+                try invokeFnReturningData {
+                    signal_gossip_status_get_equivocation_proof($0, nativeHandle.const())
+                }
+            }
+        }
+        return proof.isEmpty ? nil : proof
+    }
+}
+
+extension SignalMutPointerGossipStatus: SignalMutPointer {
+    public typealias ConstPointer = SignalConstPointerGossipStatus
+
+    public init(untyped: OpaquePointer?) {
+        self.init(raw: untyped)
+    }
+
+    public func toOpaque() -> OpaquePointer? {
+        self.raw
+    }
+
+    public func const() -> Self.ConstPointer {
+        Self.ConstPointer(raw: self.raw)
+    }
+}
+
+extension SignalConstPointerGossipStatus: SignalConstPointer {
+    public func toOpaque() -> OpaquePointer? {
+        self.raw
+    }
+}
+
+/// Gossips key transparency tree heads with peers, to detect the service showing different clients
+/// inconsistent trees.
+///
+/// The state is kept in a ``GossipStore``, which is read and, if anything changed, written back on
+/// every call.
+///
+/// The distinguished tree head is only ever advanced by ``setDistinguishedTreeHead(_:context:)``,
+/// with the head a ``KeyTransparency/Client`` saved to its ``KeyTransparency/Store``.
+public class GossipService {
+    private let environment: Net.Environment
+    private let store: GossipStore
+
+    public init(environment: Net.Environment, store: GossipStore) {
+        self.environment = environment
+        self.store = store
+    }
+
+    /// Adopts a distinguished tree head obtained from the key transparency service.
+    ///
+    /// Throws ``SignalError/invalidArgument(_:)`` if the head is malformed or was not signed by the
+    /// service.
+    public func setDistinguishedTreeHead<Bytes: ContiguousBytes>(
+        _ distinguishedTreeHead: Bytes,
+        context: StoreContext
+    ) throws {
+        try distinguishedTreeHead.withUnsafeBorrowedBuffer { head in
+            try withGossipStore(self.store, context) { ffiStore in
+                try checkError(
+                    signal_gossip_service_set_distinguished_tree_head(
+                        self.environment.rawValue,
+                        ffiStore,
+                        head
+                    )
+                )
+            }
+        }
+    }
+
+    /// Gossip to attach to outgoing messages, if there is a tree head to gossip about.
+    public func outgoingGossip(now: Date = Date(), context: StoreContext) throws -> Data? {
+        let gossip = try withGossipStore(self.store, context) { ffiStore in
+            try invokeFnReturningData {
+                signal_gossip_service_outgoing_gossip(
+                    $0,
+                    self.environment.rawValue,
+                    ffiStore,
+                    UInt64(now.timeIntervalSince1970 * 1000)
+                )
+            }
+        }
+        return gossip.isEmpty ? nil : gossip
+    }
+
+    /// Checks gossip received out of band from `peer` against our tree heads.
+    public func processIncomingGossip<Bytes: ContiguousBytes>(
+        _ gossip: Bytes,
+        from peer: ProtocolAddress,
+        now: Date = Date(),
+        context: StoreContext
+    ) throws -> GossipStatus {
+        return try withAllBorrowed(peer, .bytes(gossip)) { peerHandle, gossipBuffer in
+            try withGossipStore(self.store, context) { ffiStore in
+                try invokeFnReturningNativeHandle {
+                    signal_gossip_service_process_incoming_gossip(
+                        $0,
+                        self.environment.rawValue,
+                        ffiStore,
+                        peerHandle.const(),
+                        gossipBuffer,
+                        UInt64(now.timeIntervalSince1970 * 1000)
+                    )
+                }
+            }
+        }
+    }
+
+    /// Like ``signalEncrypt(message:for:sessionStore:identityStore:now:context:)``, but attaches
+    /// gossip for the recipient to the message.
+    public func encrypt<Bytes: ContiguousBytes>(
+        message: Bytes,
+        for address: ProtocolAddress,
+        sessionStore: SessionStore,
+        identityStore: IdentityKeyStore,
+        now: Date = Date(),
+        context: StoreContext
+    ) throws -> CiphertextMessage {
+        return try withAllBorrowed(address, .bytes(message)) { addressHandle, messageBuffer in
+            try withSessionStore(sessionStore, context) { ffiSessionStore in
+                try withIdentityKeyStore(identityStore, context) { ffiIdentityStore in
+                    try withGossipStore(self.store, context) { ffiGossipStore in
+                        try invokeFnReturningNativeHandle {
+                            signal_encrypt_message_with_gossip(
+                                $0,
+                                messageBuffer,
+                                addressHandle.const(),
+                                ffiSessionStore,
+                                ffiIdentityStore,
+                                self.environment.rawValue,
+                                ffiGossipStore,
+                                UInt64(now.timeIntervalSince1970 * 1000)
+                            )
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like ``signalDecrypt(message:from:sessionStore:identityStore:context:)``, but also processes
+    /// any gossip the message carries.
+    ///
+    /// The outcome of processing the gossip is not reported; use
+    /// ``processIncomingGossip(_:from:now:context:)`` for that.
+    public func decrypt(
+        message: SignalMessage,
+        from address: ProtocolAddress,
+        sessionStore: SessionStore,
+        identityStore: IdentityKeyStore,
+        now: Date = Date(),
+        context: StoreContext
+    ) throws -> Data {
+        return try withAllBorrowed(message, address) { messageHandle, addressHandle in
+            try withSessionStore(sessionStore, context) { ffiSessionStore in
+                try withIdentityKeyStore(identityStore, context) { ffiIdentityStore in
+                    try withGossipStore(self.store, context) { ffiGossipStore in
+                        try invokeFnReturningData {
+                            signal_decrypt_message_with_gossip(
+                                $0,
+                                messageHandle.const(),
+                                addressHandle.const(),
+                                ffiSessionStore,
+                                ffiIdentityStore,
+                                self.environment.rawValue,
+                                ffiGossipStore,
+                                UInt64(now.timeIntervalSince1970 * 1000)
+                            )
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import Foundation
+import SignalFfi
+
+/// The key transparency tree heads a client has verified or learned from its peers through gossip.
+///
+/// Its contents are opaque to the app; it only needs to be persisted through a ``GossipStore``.
+public class KtState: ClonableHandleOwner<SignalMutPointerKtState> {
+    override internal class func destroyNativeHandle(
+        _ handle: NonNull<SignalMutPointerKtState>
+    ) -> SignalFfiErrorRef? {
+        return signal_kt_state_destroy(handle.pointer)
+    }
+
+    override internal class func cloneNativeHandle(
+        _ newHandle: inout SignalMutPointerKtState,
+        currentHandle: SignalConstPointerKtState
+    ) -> SignalFfiErrorRef? {
+        return signal_kt_state_clone(&newHandle, currentHandle)
+    }
+
+    /// Creates a state with no tree heads, as used before the first distinguished tree head.
+    public convenience init() {
+        let handle = failOnError {
+            try invokeFnReturningValueByPointer(.init()) {
+                signal_kt_state_new($0)
+            }
+        }
+        self.init(owned: NonNull(handle)!)
+    }
+
+    public convenience init<Bytes: ContiguousBytes>(bytes: Bytes) throws {
+        let handle = try bytes.withUnsafeBorrowedBuffer { bytes in
+            try invokeFnReturningValueByPointer(.init()) {
+                signal_kt_state_deserialize($0, bytes)
+            }
+        }
+        self.init(owned: NonNull(handle)!)
+    }
+
+    public func serialize() -> Data {
+        return withNativeHandle { nativeHandle in
+            failOnError {
+                try invokeFnReturningData {
+                    signal_kt_state_serialize($0, nativeHandle.const())
+                }
+            }
+        }
+    }
+
+    /// The newest tree head this client has verified, if any.
+    public var lastTreeHead: Data? {
+        let head = withNativeHandle { nativeHandle in
+            failOnError {
+                try invokeFnReturningData {
+                    signal_kt_state_get_last_tree_head($0, nativeHandle.const())
+                }
+            }
+        }
+        return head.isEmpty ? nil : head
+    }
+
+    /// The tree head this client last got from the key transparency service, if any.
+    public var lastDistinguishedTreeHead: Data? {
+        let head = withNativeHandle { nativeHandle in
+            failOnError {
+                try invokeFnReturningData {
+                    signal_kt_state_get_last_distinguished_tree_head($0, nativeHandle.const())
+                }
+            }
+        }
+        return head.isEmpty ? nil : head
+    }
+}
+
+extension SignalMutPointerKtState: SignalMutPointer {
+    public typealias ConstPointer = SignalConstPointerKtState
+
+    public init(untyped: OpaquePointer?) {
+        self.init(raw: untyped)
+    }
+
+    public func toOpaque() -> OpaquePointer? {
+        self.raw
+    }
+
+    public func const() -> Self.ConstPointer {
+        Self.ConstPointer(raw: self.raw)
+    }
+}
+
+extension SignalConstPointerKtState: SignalConstPointer {
+    public func toOpaque() -> OpaquePointer? {
+        self.raw
+    }
+}
@@ -171,6 +171,18 @@ typedef enum {
   SignalContentHintImplicit = 2,
 } SignalContentHint;
 
+typedef enum {
+  SignalGossipStatusKindAdvanced = 0,
+  SignalGossipStatusKindUpToDate = 1,
+  SignalGossipStatusKindPeerBehind = 2,
+  SignalGossipStatusKindInconsistent = 3,
+  SignalGossipStatusKindInvalid = 4,
+  SignalGossipStatusKindStale = 5,
+  SignalGossipStatusKindUnverified = 6,
+  SignalGossipStatusKindMissing = 7,
+  SignalGossipStatusKindNotSaved = 8,
+} SignalGossipStatusKind;
+
 /**
  * The result of saving a new identity key for a protocol address.
  */
@@ -312,6 +324,8 @@ typedef struct SignalDecryptionErrorMessage SignalDecryptionErrorMessage;
 
 typedef struct SignalFingerprint SignalFingerprint;
 
+typedef struct SignalGossipStatus SignalGossipStatus;
+
 typedef struct SignalHsmEnclaveClient SignalHsmEnclaveClient;
 
 typedef struct SignalHttpRequest SignalHttpRequest;
@@ -322,6 +336,8 @@ typedef struct SignalKeyPair SignalKeyPair;
 
 typedef struct SignalKeySecret SignalKeySecret;
 
+typedef struct SignalKtState SignalKtState;
+
 typedef struct SignalKyberPreKeyRecord SignalKyberPreKeyRecord;
 
 typedef struct SignalLookupRequest SignalLookupRequest;
@@ -1013,6 +1029,36 @@ typedef struct {
   const SignalSenderKeyStore *raw;
 } SignalConstPointerFfiSenderKeyStoreStruct;
 
+typedef struct {
+  SignalGossipStatus *raw;
+} SignalMutPointerGossipStatus;
+
+typedef struct {
+  const SignalGossipStatus *raw;
+} SignalConstPointerGossipStatus;
+
+typedef struct {
+  SignalKtState *raw;
+} SignalMutPointerKtState;
+
+typedef int (*SignalLoadGossipState)(void *store_ctx, SignalMutPointerKtState*);
+
+typedef struct {
+  const SignalKtState *raw;
+} SignalConstPointerKtState;
+
+typedef int (*SignalSaveGossipState)(void *store_ctx, SignalConstPointerKtState);
+
+typedef struct {
+  void *ctx;
+  SignalLoadGossipState load_state;
+  SignalSaveGossipState save_state;
+} SignalGossipStore;
+
+typedef struct {
+  const SignalGossipStore *raw;
+} SignalConstPointerFfiGossipStoreStruct;
+
 typedef struct {
   const SignalServerSecretParams *raw;
 } SignalConstPointerServerSecretParams;
@@ -1756,6 +1802,8 @@ SignalFfiError *signal_create_call_link_credential_response_check_valid_contents
 
 SignalFfiError *signal_decrypt_message(SignalOwnedBuffer *out, SignalConstPointerSignalMessage message, SignalConstPointerProtocolAddress protocol_address, SignalConstPointerFfiSessionStoreStruct session_store, SignalConstPointerFfiIdentityKeyStoreStruct identity_key_store);
 
+SignalFfiError *signal_decrypt_message_with_gossip(SignalOwnedBuffer *out, SignalConstPointerSignalMessage message, SignalConstPointerProtocolAddress protocol_address, SignalConstPointerFfiSessionStoreStruct session_store, SignalConstPointerFfiIdentityKeyStoreStruct identity_key_store, uint8_t environment, SignalConstPointerFfiGossipStoreStruct gossip_store, uint64_t now);
+
 SignalFfiError *signal_decrypt_pre_key_message(SignalOwnedBuffer *out, SignalConstPointerPreKeySignalMessage message, SignalConstPointerProtocolAddress protocol_address, SignalConstPointerFfiSessionStoreStruct session_store, SignalConstPointerFfiIdentityKeyStoreStruct identity_key_store, SignalConstPointerFfiPreKeyStoreStruct prekey_store, SignalConstPointerFfiSignedPreKeyStoreStruct signed_prekey_store, SignalConstPointerFfiKyberPreKeyStoreStruct kyber_prekey_store);
 
 SignalFfiError *signal_decryption_error_message_clone(SignalMutPointerDecryptionErrorMessage *new_obj, SignalConstPointerDecryptionErrorMessage obj);
@@ -1784,6 +1832,8 @@ SignalFfiError *signal_device_transfer_generate_private_key_with_format(SignalOw
 
 SignalFfiError *signal_encrypt_message(SignalMutPointerCiphertextMessage *out, SignalBorrowedBuffer ptext, SignalConstPointerProtocolAddress protocol_address, SignalConstPointerFfiSessionStoreStruct session_store, SignalConstPointerFfiIdentityKeyStoreStruct identity_key_store, uint64_t now);
 
+SignalFfiError *signal_encrypt_message_with_gossip(SignalMutPointerCiphertextMessage *out, SignalBorrowedBuffer ptext, SignalConstPointerProtocolAddress protocol_address, SignalConstPointerFfiSessionStoreStruct session_store, SignalConstPointerFfiIdentityKeyStoreStruct identity_key_store, uint8_t environment, SignalConstPointerFfiGossipStoreStruct gossip_store, uint64_t now);
+
 void signal_error_free(SignalFfiError *err);
 
 SignalFfiError *signal_error_get_address(SignalMutPointerProtocolAddress *out, SignalUnwindSafeArgSignalFfiError err);
@@ -1856,6 +1906,22 @@ SignalFfiError *signal_generic_server_secret_params_generate_deterministic(Signa
 
 SignalFfiError *signal_generic_server_secret_params_get_public_params(SignalOwnedBuffer *out, SignalBorrowedBuffer params_bytes);
 
+SignalFfiError *signal_gossip_service_outgoing_gossip(SignalOwnedBuffer *out, uint8_t environment, SignalConstPointerFfiGossipStoreStruct store, uint64_t now);
+
+SignalFfiError *signal_gossip_service_process_incoming_gossip(SignalMutPointerGossipStatus *out, uint8_t environment, SignalConstPointerFfiGossipStoreStruct store, SignalConstPointerProtocolAddress peer, SignalBorrowedBuffer gossip, uint64_t now);
+
+SignalFfiError *signal_gossip_service_set_distinguished_tree_head(uint8_t environment, SignalConstPointerFfiGossipStoreStruct store, SignalBorrowedBuffer distinguished_tree_head);
+
+SignalFfiError *signal_gossip_status_clone(SignalMutPointerGossipStatus *new_obj, SignalConstPointerGossipStatus obj);
+
+SignalFfiError *signal_gossip_status_destroy(SignalMutPointerGossipStatus p);
+
+SignalFfiError *signal_gossip_status_get_catch_up(SignalOwnedBuffer *out, SignalConstPointerGossipStatus status);
+
+SignalFfiError *signal_gossip_status_get_equivocation_proof(SignalOwnedBuffer *out, SignalConstPointerGossipStatus status);
+
+SignalFfiError *signal_gossip_status_get_kind(uint8_t *out, SignalConstPointerGossipStatus status);
+
 SignalFfiError *signal_group_decrypt_message(SignalOwnedBuffer *out, SignalConstPointerProtocolAddress sender, SignalBorrowedBuffer message, SignalConstPointerFfiSenderKeyStoreStruct store);
 
 SignalFfiError *signal_group_encrypt_message(SignalMutPointerCiphertextMessage *out, SignalConstPointerProtocolAddress sender, const uint8_t (*distribution_id)[16], SignalBorrowedBuffer message, SignalConstPointerFfiSenderKeyStoreStruct store);
@@ -1978,6 +2044,20 @@ SignalFfiError *signal_key_transparency_search(SignalCPromiseOwnedBufferOfc_ucha
 
 SignalFfiError *signal_key_transparency_username_hash_search_key(SignalOwnedBuffer *out, SignalBorrowedBuffer hash);
 
+SignalFfiError *signal_kt_state_clone(SignalMutPointerKtState *new_obj, SignalConstPointerKtState obj);
+
+SignalFfiError *signal_kt_state_deserialize(SignalMutPointerKtState *out, SignalBorrowedBuffer data);
+
+SignalFfiError *signal_kt_state_destroy(SignalMutPointerKtState p);
+
+SignalFfiError *signal_kt_state_get_last_distinguished_tree_head(SignalOwnedBuffer *out, SignalConstPointerKtState state);
+
+SignalFfiError *signal_kt_state_get_last_tree_head(SignalOwnedBuffer *out, SignalConstPointerKtState state);
+
+SignalFfiError *signal_kt_state_new(SignalMutPointerKtState *out);
+
+SignalFfiError *signal_kt_state_serialize(SignalOwnedBuffer *out, SignalConstPointerKtState obj);
+
 SignalFfiError *signal_kyber_key_pair_clone(SignalMutPointerKyberKeyPair *new_obj, SignalConstPointerKyberKeyPair obj);
 
 SignalFfiError *signal_kyber_key_pair_destroy(SignalMutPointerKyberKeyPair p);
@@ -0,0 +1,37 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+import LibSignalClient
+import XCTest
+
+class GossipServiceTests: TestCaseBase {
+    func testFreshStateHasNoTreeHeads() throws {
+        let state = KtState()
+        XCTAssertNil(state.lastTreeHead)
+        XCTAssertNil(state.lastDistinguishedTreeHead)
+
+        let roundTripped = try KtState(bytes: state.serialize())
+        XCTAssertEqual(state.serialize(), roundTripped.serialize())
+    }
+
+    func testNothingToGossipWithoutTreeHead() throws {
+        let store = InMemoryGossipStore()
+        let service = GossipService(environment: .staging, store: store)
+        XCTAssertNil(try service.outgoingGossip(context: NullContext()))
+        XCTAssertNil(try store.loadGossipState(context: NullContext()))
+    }
+
+    func testRejectsMalformedDistinguishedTreeHead() throws {
+        let store = InMemoryGossipStore()
+        let service = GossipService(environment: .staging, store: store)
+        XCTAssertThrowsError(try service.setDistinguishedTreeHead(Data([1, 2]), context: NullContext())) {
+            guard case SignalError.invalidArgument(_) = $0 else {
+                XCTFail("wrong error: \($0)")
+                return
+            }
+        }
+        XCTAssertNil(try store.loadGossipState(context: NullContext()))
+    }
+}